@@ -0,0 +1,64 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+use log::{debug, error};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+/// A reader with `tail -f` semantics. Reaching the end of the file blocks until more data is
+/// appended instead of returning EOF. Waking up is triggered by file system notifications with a
+/// polling fallback for file systems that do not support them.
+pub struct FollowReader {
+  file: File,
+  changes: Receiver<notify::Result<notify::Event>>,
+  _watcher: Option<RecommendedWatcher>,
+}
+
+impl FollowReader {
+  const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+  /// Opens the file for following.
+  ///
+  /// # Errors
+  /// If the file cannot be opened.
+  pub fn open(path: &Path) -> std::io::Result<Self> {
+    let file = File::open(path)?;
+    let (tx, changes) = channel();
+    let watcher = notify::recommended_watcher(tx)
+      .and_then(|mut w| w.watch(path, RecursiveMode::NonRecursive).map(|()| w))
+      .map_err(|e| error!("Cannot watch {path:?}, falling back to polling: {e}"))
+      .ok();
+    Ok(Self {
+      file,
+      changes,
+      _watcher: watcher,
+    })
+  }
+
+  /// Starts from the beginning if the file was truncated, e.g. by log rotation.
+  fn handle_truncation(&mut self) -> std::io::Result<()> {
+    let position = self.file.stream_position()?;
+    if self.file.metadata()?.len() < position {
+      debug!("File was truncated. Reading from the start.");
+      self.file.seek(SeekFrom::Start(0))?;
+    }
+    Ok(())
+  }
+}
+
+impl Read for FollowReader {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    loop {
+      let read = self.file.read(buf)?;
+      if read > 0 || buf.is_empty() {
+        return Ok(read);
+      }
+      // Wait for a change and drain further queued notifications.
+      let _ = self.changes.recv_timeout(Self::POLL_INTERVAL);
+      while self.changes.try_recv().is_ok() {}
+      self.handle_truncation()?;
+    }
+  }
+}
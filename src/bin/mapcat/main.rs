@@ -1,24 +1,68 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::Duration;
 
 use clap::Parser as CliParser;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbaImage};
 use log::error;
-use mapvas::map::map_event::{Color, MapEvent};
-use mapvas::parser::{FileParser, GrepParser, RandomParser, TTJsonParser};
+use mapvas::map::coordinates::Coordinate;
+use mapvas::map::map_event::{
+  BlendMode, CacheKind, Color, Dash, Direction, ExportFormat, Icon, ImageOverlay, Layer, MapEvent,
+  Marker, RenderMode, Style,
+};
+use mapvas::map::overlay::georeferenced_overlay;
+use mapvas::map::render::{HeadlessRenderer, Playback};
+use mapvas::map::style_rule::StyleRule;
+use mapvas::map::theme::Theme;
+use mapvas::parser::{
+  detect, parser_for_path, CommandParser, FileParser, Format, GeoJsonParser, GeoParquetParser,
+  GrepParser, KmlParser, NmeaParser, OsmParser, PluginParser, PolylineParser, RandomParser,
+  TTJsonParser, TagFilter,
+};
+use mapvas::recent::RecentFiles;
+use mapvas::remote::RemoteConfig;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Cursor};
 use tokio::time::sleep;
 
+use follow::FollowReader;
+
+mod follow;
+mod mcp;
+mod overpass;
+mod postgis;
+mod replay;
+mod search;
 mod sender;
+mod source;
+mod stats;
+mod watch;
 
 #[derive(clap::Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-  /// Which parser to use. Values: grep, random, ttjson.
-  #[arg(short, long, default_value = "grep")]
+  /// Which parser to use. Values: auto, geojson, grep, kml, nmea, polyline, random, ttjson, or the
+  /// name of a plugin. auto recognizes the format by the start of the input and reads other text
+  /// with grep.
+  #[arg(short, long, default_value = "auto")]
   parser: String,
 
+  /// The format --parser auto chooses if the input could be several, e.g. grep for logs with NMEA
+  /// sentences.
+  #[arg(long, value_parser = parse_format)]
+  prefer: Option<Format>,
+
+  /// Prints the number of points, lines, and polygons per layer, the number of errors, and if the
+  /// map was reached as JSON to stderr.
+  #[arg(long)]
+  stats: bool,
+
+  /// Pipes the input through the command, which has to print GeoJSON, e.g. a converter for a format
+  /// without a parser. It replaces --parser.
+  #[arg(long)]
+  parser_cmd: Option<String>,
+
   /// Inverts the normal lat/lon when using grep as parser.
   #[arg(short, long, default_value_t = false)]
   invert_coordinates: bool,
@@ -28,91 +72,871 @@ struct Args {
   #[arg(short, long, default_value = "blue")]
   color: String,
 
-  /// Clears the map before drawing new stuff.
-  #[arg(short, long)]
-  reset: bool,
+  /// Gives each input file its own color, like `--color cycle`. The colors are printed to stderr.
+  #[arg(long)]
+  color_per_file: bool,
+
+  /// Decimal places of encoded polylines, 5 for Google, 6 for Valhalla and OSRM polyline6.
+  #[arg(long, default_value_t = 5)]
+  polyline_precision: u32,
+
+  /// Lets the grep parser also draw encoded polylines it finds in the lines.
+  #[arg(long)]
+  grep_polylines: bool,
+
+  /// Clears the map before drawing new stuff. `--reset=<layer>` only clears that layer.
+  #[arg(short, long, num_args = 0..=1, require_equals = true)]
+  reset: Option<Option<String>>,
 
   /// Zooms to the bounding box of drawn stuff.
   #[arg(short, long)]
   focus: bool,
 
-  /// Defines a regex with one capture group labels.
+  /// Defines a regex with capture groups for labels. A named group `time` gives the shapes of the
+  /// line a timestamp.
   #[arg(short, long, default_value = "(.*)")]
   label_pattern: String,
 
+  /// Composes labels of the named groups of --label-pattern, e.g. `{vehicle} @ {speed}km/h`.
+  #[arg(long)]
+  label: Option<String>,
+
   /// Path to take a screenshot.
   #[arg(short, long, default_value = "")]
   screenshot: String,
 
+  /// Path to write the current map content to.
+  /// The format is chosen by the extension: geojson, gpx, or kml.
+  #[arg(short, long, default_value = "")]
+  export: String,
+
+  /// How the parsed shapes are drawn. Values: shapes, heatmap.
+  #[arg(long, default_value = "shapes")]
+  style: String,
+
+  /// A rule styling the matching shapes of the layers, e.g. `label ~ "error" => color red`.
+  /// Can be given several times, later rules win.
+  #[arg(long = "rule")]
+  rules: Vec<StyleRule>,
+
+  /// The opacity of the drawn layers from 0 to 1.
+  #[arg(long, value_parser = parse_opacity)]
+  layer_opacity: Option<f32>,
+
+  /// How the layers blend with the map below. Values: normal, add, multiply.
+  #[arg(long, value_parser = parse_blend)]
+  blend: Option<BlendMode>,
+
+  /// Aggregates nearby points of the drawn layers into markers with their count.
+  #[arg(long)]
+  cluster: bool,
+
+  /// The width of lines in pixels.
+  #[arg(long)]
+  width: Option<f32>,
+
+  /// Draws lines dashed. Values: dashed, dotted, or the lengths of dash and gap like 10/5.
+  #[arg(long, value_parser = parse_dash)]
+  dash: Option<Dash>,
+
+  /// The radius of points in pixels.
+  #[arg(long)]
+  point_radius: Option<f32>,
+
+  /// How points are drawn. Values: circle, square, triangle, cross.
+  #[arg(long, value_parser = parse_marker)]
+  marker: Option<Marker>,
+
+  /// Shows the direction of lines. Values: arrow for an arrowhead at the end, ticks for chevrons
+  /// along the line.
+  #[arg(long, value_parser = parse_direction)]
+  direction: Option<Direction>,
+
+  /// An icon drawn instead of the marker of points: an image file, an icon of the sprite, a
+  /// bundled icon like car, bus, truck, or pin, or an emoji. Options follow after commas, e.g.
+  /// `bus,size=24,anchor=bottom,zoom=15` to scale it with the map from zoom level 15.
+  #[arg(long)]
+  icon: Option<Icon>,
+
+  /// Draws lines as great circles, e.g. flight routes. They are split at the antimeridian.
+  #[arg(long)]
+  geodesic: bool,
+
+  /// Renders the parsed input into this image file without a map window.
+  #[arg(long, default_value = "")]
+  render: String,
+
+  /// Renders this many frames of the input growing along its timestamps instead of one image.
+  /// A `.gif` file gets an animation, other files a numbered image per frame.
+  #[arg(long)]
+  frames: Option<usize>,
+
+  /// Frames per second of the animation rendered with --frames.
+  #[arg(long, default_value_t = 10)]
+  fps: u32,
+
+  /// Draws a point moving along timed lines in the frames of --frames.
+  #[arg(long)]
+  moving_marker: bool,
+
+  /// Only draws the last minutes of timed lines in the frames of --frames, fading out.
+  #[arg(long)]
+  trail: Option<f32>,
+
+  /// Size of the rendered image as WIDTHxHEIGHT.
+  #[arg(long, default_value = "1920x1080", value_parser = parse_size)]
+  size: (u32, u32),
+
+  /// The area to render as lat1,lon1,lat2,lon2. Defaults to the bounding box of the input.
+  /// It is also the `{{bbox}}` of --overpass and the corners of --overlay.
+  #[arg(long, value_parser = parse_bbox)]
+  bbox: Option<(Coordinate, Coordinate)>,
+
+  /// Only draws elements of osm files with this tag, e.g. `highway=*` or `amenity=cafe`.
+  #[arg(long)]
+  osm_filter: Option<TagFilter>,
+
+  /// Comma separated columns of GeoParquet and Arrow files that are shown in the label.
+  /// All columns are shown by default.
+  #[arg(long, value_delimiter = ',')]
+  columns: Option<Vec<String>>,
+
+  /// Reads at most this many rows of GeoParquet and Arrow files.
+  #[arg(long)]
+  max_rows: Option<usize>,
+
+  /// An Overpass QL query whose result is drawn instead of reading files.
+  /// `{{bbox}}` is replaced by the --bbox.
+  #[arg(long, default_value = "")]
+  overpass: String,
+
+  /// Connection string of a PostGIS database for --sql, e.g. `host=localhost user=postgres`.
+  /// Defaults to the environment variable `MAPVAS_POSTGIS`.
+  #[arg(long, default_value = "")]
+  postgis: String,
+
+  /// An SQL query whose result is drawn as layer instead of reading files.
+  /// The first geometry column is drawn, the others are used as label.
+  #[arg(long, default_value = "")]
+  sql: String,
+
+  /// Runs the --sql query again every this many seconds and replaces the layer.
+  #[arg(long)]
+  refresh: Option<u64>,
+
+  /// An image shown on the map between the corners given by --bbox.
+  /// Without --bbox the corners are read from a world file next to the image or GeoTIFF tags.
+  #[arg(long, default_value = "")]
+  overlay: String,
+
+  /// The opacity of the --overlay between 0 and 1.
+  #[arg(long, default_value_t = 0.7)]
+  opacity: f32,
+
+  /// Switches the colors of the map window. Values: light, dark.
+  #[arg(long)]
+  theme: Option<String>,
+
+  /// Removes a tile cache of mapvas from disk. Values: tiles, rendered.
+  #[arg(long)]
+  clear_cache: Option<String>,
+
+  /// Centers the map on a position like `52.5,13.4`, with zoom level `52.5,13.4,z14`, or on
+  /// the first search result for a place name like `Berlin`. A bounding box like
+  /// `52.6,13.3,52.4,13.5` is shown completely.
+  #[arg(long)]
+  goto: Option<String>,
+
+  /// Stores the current view of the map under this name.
+  #[arg(long)]
+  add_bookmark: Option<String>,
+
+  /// Shows the view stored under this name.
+  #[arg(long)]
+  bookmark: Option<String>,
+
+  /// Lists the recently drawn files with their options.
+  #[arg(long)]
+  recent: bool,
+
+  /// Draws the n-th file of --recent again with the same parser and style options.
+  #[arg(long)]
+  reopen: Option<usize>,
+
+  /// Keeps the files open and draws lines that are appended to them like `tail -f`.
+  #[arg(long)]
+  follow: bool,
+
+  /// Draws the files of the directory and keeps them up to date: new and changed files are drawn
+  /// again and deleted ones removed. Each file is a layer named after it.
+  #[arg(long)]
+  watch: Option<PathBuf>,
+
+  /// The files of --watch, `*` matches any text and `?` any character, e.g. `*.geojson`.
+  #[arg(long, default_value = "*")]
+  glob: String,
+
+  /// Sends shapes with timestamps as they were recorded, the optional value is the speed, e.g.
+  /// `--replay=60` replays an hour in a minute.
+  #[arg(long, num_args = 0..=1, default_missing_value = "1", require_equals = true)]
+  replay: Option<f32>,
+
+  /// Runs a Model Context Protocol server on stdin and stdout, so agents can draw on the map with
+  /// the tools add_geojson, focus_bbox, search_location, and take_screenshot.
+  #[arg(long)]
+  mcp: bool,
+
+  /// Address of the map like `mapvas.local:12345`. Overrides `MAPVAS_ADDRESS`.
+  /// Mapvas is only started if the address is on this machine.
+  #[arg(long)]
+  address: Option<String>,
+
+  /// Token the map requires. Overrides `MAPVAS_TOKEN`.
+  #[arg(long)]
+  token: Option<String>,
+
+  /// Name of the mapvas instance to draw on, it is started if it does not run.
+  /// Overrides `MAPVAS_INSTANCE`.
+  #[arg(long)]
+  instance: Option<String>,
+
+  /// How the events are sent to the map. Values: json, msgpack. msgpack is much faster for huge
+  /// inputs. Overrides `MAPVAS_ENCODING`.
+  #[arg(long)]
+  encoding: Option<String>,
+
+  /// Compresses the events sent to the map. Values: gzip, zstd. Overrides `MAPVAS_COMPRESSION`.
+  #[arg(long)]
+  compression: Option<String>,
+
+  /// Sends to the map over this Unix socket or Windows named pipe instead of the address.
+  /// Overrides `MAPVAS_SOCKET`.
+  #[arg(long)]
+  socket: Option<PathBuf>,
+
   /// A file to parse. stdin is used if this is not provided.
   files: Vec<std::path::PathBuf>,
 }
 
-fn readers(paths: &[std::path::PathBuf]) -> Vec<Box<dyn BufRead>> {
-  let mut res: Vec<Box<dyn BufRead>> = Vec::new();
+/// Opens the files and urls, or stdin if none are given, with the path their parser is chosen by.
+/// Inputs that cannot be read are skipped.
+async fn readers(paths: &[PathBuf]) -> Vec<(Option<PathBuf>, Box<dyn BufRead>)> {
   if paths.is_empty() {
-    res.push(Box::new(std::io::stdin().lock()));
-  } else {
-    for f in paths {
-      res.push(Box::new(BufReader::new(
-        File::open(f).expect("File exists"),
-      )));
+    return vec![(None, Box::new(std::io::stdin().lock()))];
+  }
+  let mut res = vec![];
+  for path in paths {
+    match source::open(path).await {
+      Ok(reader) => res.push((Some(source::format_path(path)), reader)),
+      Err(e) => error!("Cannot read {path:?}: {e}"),
     }
-  };
+  }
   res
 }
 
-#[tokio::main]
-async fn main() {
-  let args = Args::parse();
-  let color = Color::from_str(&args.color).unwrap_or(Color::Green);
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+  let (w, h) = s
+    .split_once('x')
+    .ok_or(format!("Size {s} is not of the form WIDTHxHEIGHT."))?;
+  Ok((
+    w.trim().parse().map_err(|e| format!("{e}"))?,
+    h.trim().parse().map_err(|e| format!("{e}"))?,
+  ))
+}
 
-  env_logger::init();
+fn parse_format(s: &str) -> Result<Format, String> {
+  s.parse()
+    .map_err(|()| format!("Unknown format {s}, use geojson, grep, kml, nmea, polyline, or ttjson."))
+}
 
-  let sender = sender::MapSender::new().await;
-  if args.reset {
-    sender.send_event(MapEvent::Clear);
+fn parse_opacity(s: &str) -> Result<f32, String> {
+  match s.trim().parse::<f32>() {
+    Ok(opacity) if (0. ..=1.).contains(&opacity) => Ok(opacity),
+    _ => Err(format!("Opacity {s} is not between 0 and 1.")),
   }
-  sender.finalize().await;
+}
 
-  let sender = sender::MapSender::new().await;
-
-  let parser = || -> Box<dyn FileParser> {
-    match args.parser.as_str() {
-      "random" => Box::new(RandomParser::new()),
-      "ttjson" => Box::new(TTJsonParser::new().with_color(color)),
-      "grep" => Box::new(
-        GrepParser::new(args.invert_coordinates)
-          .with_color(color)
-          .with_label_pattern(&args.label_pattern),
-      ),
-      _ => {
+fn parse_blend(s: &str) -> Result<BlendMode, String> {
+  s.parse()
+    .map_err(|()| format!("Unknown blend mode {s}, use normal, add, or multiply."))
+}
+
+fn parse_dash(s: &str) -> Result<Dash, String> {
+  s.parse()
+    .map_err(|()| format!("Unknown dash {s}, use dashed, dotted, or lengths like 10/5."))
+}
+
+fn parse_marker(s: &str) -> Result<Marker, String> {
+  s.parse()
+    .map_err(|()| format!("Unknown marker {s}, use circle, square, triangle, or cross."))
+}
+
+fn parse_direction(s: &str) -> Result<Direction, String> {
+  s.parse()
+    .map_err(|()| format!("Unknown direction {s}, use arrow or ticks."))
+}
+
+fn parse_bbox(s: &str) -> Result<(Coordinate, Coordinate), String> {
+  let values = s
+    .split(',')
+    .map(|v| v.trim().parse::<f32>().map_err(|e| format!("{e}")))
+    .collect::<Result<Vec<_>, _>>()?;
+  match values[..] {
+    [lat1, lon1, lat2, lon2] => Ok((
+      Coordinate {
+        lat: lat1,
+        lon: lon1,
+      },
+      Coordinate {
+        lat: lat2,
+        lon: lon2,
+      },
+    )),
+    _ => Err(format!(
+      "Bounding box {s} is not of the form lat1,lon1,lat2,lon2."
+    )),
+  }
+}
+
+/// Chooses the parser for a file. Files with a known format like `.shp` get their own parser.
+/// `start` is the beginning of the input, which `--parser auto` recognizes the format by.
+fn parser(
+  args: &Args,
+  color: Color,
+  path: Option<&Path>,
+  start: Option<&[u8]>,
+) -> Box<dyn FileParser> {
+  if let Some(command) = &args.parser_cmd {
+    return Box::new(CommandParser::new(command.clone()).with_color(color));
+  }
+  if let Some(osm) = path.and_then(OsmParser::for_path) {
+    return Box::new(osm.with_color(color).with_filter(args.osm_filter.clone()));
+  }
+  if let Some(geoparquet) = path.and_then(GeoParquetParser::for_path) {
+    return Box::new(
+      geoparquet
+        .with_color(color)
+        .with_columns(args.columns.clone())
+        .with_max_rows(args.max_rows),
+    );
+  }
+  if let Some(parser) = path.and_then(|p| parser_for_path(p, color)) {
+    return parser;
+  }
+  let grep = || -> Box<dyn FileParser> {
+    Box::new(
+      GrepParser::new(args.invert_coordinates)
+        .with_color(color)
+        .with_label_pattern(&args.label_pattern)
+        .with_label_template(args.label.clone())
+        .with_polyline_precision(args.grep_polylines.then_some(args.polyline_precision)),
+    )
+  };
+  let name = match args.parser.as_str() {
+    "auto" => start
+      .and_then(|start| detect(start, args.prefer))
+      .map_or("grep", Format::name),
+    name => name,
+  };
+  match name {
+    "random" => Box::new(RandomParser::new()),
+    "kml" => Box::new(KmlParser::new().with_color(color)),
+    "ttjson" => Box::new(TTJsonParser::new().with_color(color)),
+    "geojson" => Box::new(GeoJsonParser::new().with_color(color)),
+    "nmea" => Box::new(NmeaParser::new().with_color(color)),
+    "polyline" => Box::new(
+      PolylineParser::new()
+        .with_precision(args.polyline_precision)
+        .with_color(color),
+    ),
+    "grep" => grep(),
+    name => match PluginParser::named(name) {
+      Some(plugin) => Box::new(plugin),
+      None => {
         error!("Unkown parser: {}. Falling back to grep.", args.parser);
         Box::new(GrepParser::new(args.invert_coordinates))
       }
+    },
+  }
+}
+
+/// The color of the n-th input. With --color cycle or --color-per-file each file gets the next
+/// color of the palette, which is printed to stderr.
+fn input_color(args: &Args, color: Color, n: usize, path: Option<&Path>) -> Color {
+  if !args.color_per_file && args.color != "cycle" {
+    return color;
+  }
+  let color = Color::cycled(n);
+  if let Some(path) = path {
+    eprintln!("{}: {color:?}", path.display());
+  }
+  color
+}
+
+/// Pairs the input readers with their parsers. The result of an Overpass query replaces the files.
+async fn inputs(
+  args: &Args,
+  color: Color,
+  overpass: Option<Vec<u8>>,
+) -> Vec<(Box<dyn BufRead>, Box<dyn FileParser>)> {
+  if let Some(data) = overpass {
+    let reader: Box<dyn BufRead> = Box::new(Cursor::new(data));
+    let parser: Box<dyn FileParser> = Box::new(
+      OsmParser::new()
+        .with_color(color)
+        .with_filter(args.osm_filter.clone())
+        .with_layer("Overpass"),
+    );
+    return vec![(reader, parser)];
+  }
+  readers(&args.files)
+    .await
+    .into_iter()
+    .enumerate()
+    .map(|(i, (path, mut reader))| {
+      // Peeking at the buffer keeps the input for the parser, so stdin still streams.
+      let start = reader.fill_buf().ok();
+      let color = input_color(args, color, i, path.as_deref());
+      let parser = parser(args, color, path.as_deref(), start);
+      (reader, parser)
+    })
+    .collect()
+}
+
+/// The image overlay with the corners from --bbox or the georeference of the image.
+fn overlay(args: &Args) -> anyhow::Result<ImageOverlay> {
+  let path = std::path::absolute(Path::new(args.overlay.trim()))?;
+  let overlay = match args.bbox {
+    Some((c1, c2)) => ImageOverlay::new(
+      path,
+      Coordinate {
+        lat: c1.lat.max(c2.lat),
+        lon: c1.lon.min(c2.lon),
+      },
+      Coordinate {
+        lat: c1.lat.min(c2.lat),
+        lon: c1.lon.max(c2.lon),
+      },
+    ),
+    None => georeferenced_overlay(&path)?,
+  };
+  Ok(overlay.with_opacity(args.opacity))
+}
+
+/// Input is read unless mapcat is only used for commands like --overlay, --theme, or --goto.
+fn reads_input(args: &Args) -> bool {
+  !args.files.is_empty()
+    || (args.overlay.is_empty()
+      && args.theme.is_none()
+      && args.clear_cache.is_none()
+      && args.add_bookmark.is_none()
+      && args.bookmark.is_none()
+      && args.goto.is_none())
+}
+
+fn with_layer_style(event: MapEvent, render_mode: RenderMode, args: &Args) -> MapEvent {
+  match event {
+    MapEvent::Layer(layer) => MapEvent::Layer(
+      layer
+        .with_render_mode(render_mode)
+        .with_geodesic(args.geodesic)
+        .with_rules(args.rules.clone())
+        .with_opacity(args.layer_opacity)
+        .with_blend(args.blend)
+        .with_cluster(args.cluster.then_some(true))
+        .with_default_style(Style {
+          width: args.width,
+          dash: args.dash,
+          radius: args.point_radius,
+          marker: args.marker.unwrap_or_default(),
+          direction: args.direction,
+          ..Style::default()
+        })
+        .with_default_icon(args.icon.as_ref()),
+    ),
+    e => e,
+  }
+}
+
+/// The options that change how a file is parsed and drawn, to draw it again the same way.
+fn parse_options(args: &Args) -> Vec<String> {
+  let defaults = Args::parse_from(["mapcat"]);
+  let mut options = vec![];
+  let mut option = |name: &str, value: &str, default: &str| {
+    if value != default {
+      options.extend([name.to_string(), value.to_string()]);
+    }
+  };
+  option("--parser", &args.parser, &defaults.parser);
+  option("--color", &args.color, &defaults.color);
+  option("--style", &args.style, &defaults.style);
+  option(
+    "--label-pattern",
+    &args.label_pattern,
+    &defaults.label_pattern,
+  );
+  option(
+    "--polyline-precision",
+    &args.polyline_precision.to_string(),
+    &defaults.polyline_precision.to_string(),
+  );
+  if let Some(command) = &args.parser_cmd {
+    options.extend(["--parser-cmd".to_string(), command.clone()]);
+  }
+  if let Some(label) = &args.label {
+    options.extend(["--label".to_string(), label.clone()]);
+  }
+  if let Some(prefer) = args.prefer {
+    options.extend(["--prefer".to_string(), prefer.to_string()]);
+  }
+  if args.invert_coordinates {
+    options.push("--invert-coordinates".to_string());
+  }
+  if args.grep_polylines {
+    options.push("--grep-polylines".to_string());
+  }
+  if args.geodesic {
+    options.push("--geodesic".to_string());
+  }
+  options
+}
+
+/// Remembers the files for --recent.
+fn add_recent_files(args: &Args) {
+  let mut recent = RecentFiles::load();
+  for file in &args.files {
+    let path = if source::is_url(file) {
+      Ok(file.clone())
+    } else {
+      std::path::absolute(file)
+    };
+    if let Ok(path) = path {
+      recent.add(path, parse_options(args));
+    }
+  }
+  if let Err(e) = recent.save() {
+    error!("Cannot save the recent files: {e}");
+  }
+}
+
+/// Replaces the arguments by the recent file and its options for --reopen.
+fn reopened(args: Args) -> Option<Args> {
+  let Some(n) = args.reopen else {
+    return Some(args);
+  };
+  let recent = RecentFiles::load();
+  let Some(file) = recent.get(n) else {
+    error!("There is no recent file {n}.");
+    return None;
+  };
+  let mut reopened = Args::parse_from(
+    std::iter::once("mapcat".into())
+      .chain(file.options.iter().map(Into::into))
+      .chain(std::iter::once(file.path.clone().into_os_string())),
+  );
+  reopened.reset = args.reset;
+  reopened.focus = args.focus;
+  Some(reopened)
+}
+
+/// Renders all parsed layers into an image file instead of sending them to mapvas.
+#[allow(clippy::cast_possible_truncation)]
+async fn render(args: &Args, events: impl Iterator<Item = MapEvent>) {
+  let mut layers: Vec<Layer> = vec![];
+  for event in events {
+    match event {
+      MapEvent::Layer(layer) => layers.push(layer),
+      MapEvent::Clear => layers.clear(),
+      _ => (),
+    }
+  }
+  let (width, height) = args.size;
+  let renderer = HeadlessRenderer::new(width, height);
+  if let Some(frames) = args.frames {
+    let playback = Playback {
+      marker: args.moving_marker,
+      trail: args.trail.map(|minutes| (minutes * 60_000.) as i64),
+    };
+    let written = match renderer
+      .render_frames(&layers, args.bbox, frames, playback)
+      .await
+    {
+      Ok(frames) => write_frames(Path::new(args.render.trim()), frames, args.fps),
+      Err(e) => Err(e),
+    };
+    if let Err(e) = written {
+      error!("Cannot render frames: {e}");
+    }
+    return;
+  }
+  match renderer.render(&layers, args.bbox).await {
+    Ok(image) => {
+      if let Err(e) = image.save(args.render.trim()) {
+        error!("Cannot write {}: {e}", args.render);
+      }
+    }
+    Err(e) => error!("Cannot render: {e}"),
+  }
+}
+
+/// Writes the frames as animated GIF or as numbered images like `track_0001.png`.
+fn write_frames(path: &Path, frames: Vec<RgbaImage>, fps: u32) -> anyhow::Result<()> {
+  if path
+    .extension()
+    .is_some_and(|e| e.eq_ignore_ascii_case("gif"))
+  {
+    let mut encoder = GifEncoder::new(File::create(path)?);
+    encoder.set_repeat(Repeat::Infinite)?;
+    let delay = Delay::from_numer_denom_ms(1000, fps.max(1));
+    encoder.encode_frames(
+      frames
+        .into_iter()
+        .map(|image| Frame::from_parts(image, 0, 0, delay)),
+    )?;
+    return Ok(());
+  }
+  let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+  let extension = path
+    .extension()
+    .map_or("png".into(), |e| e.to_string_lossy());
+  for (i, frame) in frames.iter().enumerate() {
+    frame.save(path.with_file_name(format!("{stem}_{:04}.{extension}", i + 1)))?;
+  }
+  Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+  stats::init_logging();
+
+  let args = Args::parse();
+  if args.recent {
+    for line in RecentFiles::load().numbered() {
+      println!("{line}");
     }
+    return;
+  }
+  let Some(args) = reopened(args) else {
+    return;
   };
+  if !args.files.is_empty() {
+    add_recent_files(&args);
+  }
+  let color = Color::from_str(&args.color).unwrap_or(Color::Green);
+  let render_mode = RenderMode::from_str(&args.style).unwrap_or_else(|()| {
+    error!("Unknown style: {}. Falling back to shapes.", args.style);
+    RenderMode::Shapes
+  });
 
-  let readers = readers(&args.files);
-  for reader in readers {
-    let mut parser = parser();
-    parser.parse(reader).for_each(|e| sender.send_event(e));
-    // Waiting for all tasks to finish.
+  let overpass = if args.overpass.is_empty() {
+    None
+  } else {
+    match overpass::query(&args.overpass, args.bbox).await {
+      Ok(data) => Some(data),
+      Err(e) => {
+        error!("{e}");
+        return;
+      }
+    }
+  };
+
+  if !args.render.is_empty() {
+    let mut events = vec![];
+    if args.sql.is_empty() {
+      for (reader, mut parser) in inputs(&args, color, overpass).await {
+        events.extend(
+          parser
+            .parse(reader)
+            .map(|e| with_layer_style(e, render_mode, &args)),
+        );
+      }
+    } else {
+      match postgis::query(&args.postgis, &args.sql, color).await {
+        Ok(layer) => events.push(with_layer_style(MapEvent::Layer(layer), render_mode, &args)),
+        Err(e) => error!("PostGIS query failed: {e}"),
+      }
+    }
+    render(&args, events.into_iter()).await;
+    return;
+  }
+
+  let remote = RemoteConfig::from_env()
+    .with_address(args.address.clone())
+    .with_token(args.token.clone())
+    .with_socket(args.socket.clone())
+    .with_instance(args.instance.clone())
+    .with_encoding(args.encoding.clone())
+    .with_compression(args.compression.clone());
+  if args.mcp {
+    mcp::serve(&remote).await;
+    return;
+  }
+  let sender = sender::MapSender::new(&remote).await;
+  match &args.reset {
+    Some(Some(layer)) => sender.send_event(MapEvent::ClearLayer(layer.clone())),
+    Some(None) => sender.send_event(MapEvent::Clear),
+    None => (),
   }
   sender.finalize().await;
 
+  let sender = sender::MapSender::new(&remote).await;
+
+  if let Some(theme) = &args.theme {
+    match Theme::from_str(theme) {
+      Ok(theme) => sender.send_event(MapEvent::Theme(theme)),
+      Err(()) => error!("Unknown theme: {theme}."),
+    }
+  }
+
+  if let Some(kind) = &args.clear_cache {
+    match CacheKind::from_str(kind) {
+      Ok(kind) => sender.send_event(MapEvent::ClearCache(kind)),
+      Err(()) => error!("Unknown cache: {kind}."),
+    }
+  }
+
+  if let Some(name) = &args.add_bookmark {
+    sender.send_event(MapEvent::AddBookmark(name.clone()));
+  }
+
+  if let Some(name) = &args.bookmark {
+    sender.send_event(MapEvent::GoToBookmark(name.clone()));
+  }
+
+  if let Some(target) = &args.goto {
+    match search::view(target).await {
+      Ok(view) => sender.send_event(MapEvent::SetView(view)),
+      Err(e) => error!("Cannot go to {target}: {e}"),
+    }
+  }
+
+  if !args.overlay.is_empty() {
+    match overlay(&args) {
+      Ok(overlay) => sender.send_event(MapEvent::ImageOverlay(overlay)),
+      Err(e) => error!("Cannot show overlay: {e}"),
+    }
+  }
+
+  if !args.sql.is_empty() {
+    loop {
+      match postgis::query(&args.postgis, &args.sql, color).await {
+        Ok(layer) => sender.send_event(with_layer_style(
+          MapEvent::Layer(layer.with_replace(true)),
+          render_mode,
+          &args,
+        )),
+        Err(e) => error!("PostGIS query failed: {e}"),
+      }
+      match args.refresh {
+        Some(seconds) => sleep(Duration::from_secs(seconds)).await,
+        None => break,
+      }
+    }
+  } else if let Some(dir) = &args.watch {
+    watch::watch(dir, &args.glob, &sender, |path| {
+      let mut reader = match source::open_file(path) {
+        Ok(reader) => reader,
+        Err(e) => {
+          error!("Cannot read {path:?}: {e}");
+          return vec![];
+        }
+      };
+      let format = source::format_path(path);
+      let mut parser = parser(&args, color, Some(&format), reader.fill_buf().ok());
+      let events = parser
+        .parse(reader)
+        .map(|e| with_layer_style(e, render_mode, &args))
+        .collect();
+      events
+    });
+  } else if args.follow && !args.files.is_empty() && overpass.is_none() {
+    // Followed files never end, so each one is parsed in its own thread.
+    std::thread::scope(|scope| {
+      for (i, file) in args.files.iter().enumerate() {
+        let sender = &sender;
+        let args = &args;
+        let color = input_color(args, color, i, Some(file));
+        scope.spawn(move || {
+          let reader = match FollowReader::open(file) {
+            Ok(reader) => BufReader::new(reader),
+            Err(e) => {
+              error!("Cannot follow {file:?}: {e}");
+              return;
+            }
+          };
+          parser(args, color, Some(file), None)
+            .parse(Box::new(reader))
+            .for_each(|e| sender.send_event(with_layer_style(e, render_mode, &args)));
+        });
+      }
+    });
+  } else if let Some(speed) = args.replay.filter(|_| reads_input(&args)) {
+    let mut events = vec![];
+    for (reader, mut parser) in inputs(&args, color, overpass).await {
+      events.extend(
+        parser
+          .parse(reader)
+          .map(|e| with_layer_style(e, render_mode, &args)),
+      );
+    }
+    replay::replay(&sender, events, speed).await;
+  } else if reads_input(&args) {
+    for (reader, mut parser) in inputs(&args, color, overpass).await {
+      parser
+        .parse(reader)
+        .for_each(|e| sender.send_event(with_layer_style(e, render_mode, &args)));
+      // Waiting for all tasks to finish.
+    }
+  }
+  let mut stats = sender.finalize().await;
+
   if args.focus {
-    let sender = sender::MapSender::new().await;
+    let sender = sender::MapSender::new(&remote).await;
     sender.send_event(MapEvent::Focus);
     sender.finalize().await;
   }
 
   if !args.screenshot.is_empty() {
     sleep(Duration::from_millis(300)).await;
-    let sender = sender::MapSender::new().await;
+    let sender = sender::MapSender::new(&remote).await;
     sender.send_event(MapEvent::Screenshot(
       std::path::absolute(Path::new(&args.screenshot.trim())).unwrap(),
     ));
     sender.finalize().await;
   }
+
+  if !args.export.is_empty() {
+    let sender = sender::MapSender::new(&remote).await;
+    let path = std::path::absolute(Path::new(args.export.trim())).unwrap();
+    sender.send_event(MapEvent::Export {
+      format: ExportFormat::from_path(&path).unwrap_or_default(),
+      path,
+    });
+    sender.finalize().await;
+  }
+
+  stats.errors = stats::errors();
+  if args.stats {
+    eprintln!("{}", serde_json::to_string(&stats).unwrap_or_default());
+  }
+  if !stats.delivered {
+    std::process::exit(2);
+  }
+  if reads_input(&args) && stats.geometries() == 0 {
+    std::process::exit(1);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn opacities() {
+    let args = Args::try_parse_from(["mapcat", "--opacity", "0.5", "--layer-opacity", "0.2"])
+      .expect("The options parse.");
+    assert!((args.opacity - 0.5).abs() < f32::EPSILON);
+    assert_eq!(args.layer_opacity, Some(0.2));
+    assert!(Args::try_parse_from(["mapcat", "--layer-opacity", "2"]).is_err());
+  }
 }
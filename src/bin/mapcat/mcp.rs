@@ -0,0 +1,229 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use log::{debug, error};
+use mapvas::map::map_event::{Color, Layer, MapEvent, View};
+use mapvas::parser::GeoJsonParser;
+use mapvas::remote::RemoteConfig;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use crate::search;
+use crate::sender::MapSender;
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+fn tools() -> Value {
+  json!([
+    {
+      "name": "add_geojson",
+      "description": "Draws a GeoJSON FeatureCollection, Feature, or geometry on the map.",
+      "inputSchema": {
+        "type": "object",
+        "properties": {
+          "geojson": {"description": "The GeoJSON as object or string."},
+          "layer": {"type": "string", "description": "Layer to draw into, `mcp` by default."},
+          "color": {"type": "string", "description": "Color of features without style, e.g. red."},
+          "focus": {"type": "boolean", "description": "Shows everything on the map afterwards."}
+        },
+        "required": ["geojson"]
+      }
+    },
+    {
+      "name": "focus_bbox",
+      "description": "Moves the map to show a bounding box.",
+      "inputSchema": {
+        "type": "object",
+        "properties": {
+          "south": {"type": "number"},
+          "west": {"type": "number"},
+          "north": {"type": "number"},
+          "east": {"type": "number"}
+        },
+        "required": ["south", "west", "north", "east"]
+      }
+    },
+    {
+      "name": "search_location",
+      "description": "Moves the map to a place name, a position like `52.5,13.4,z14`, or a bounding box.",
+      "inputSchema": {
+        "type": "object",
+        "properties": {"query": {"type": "string"}},
+        "required": ["query"]
+      }
+    },
+    {
+      "name": "take_screenshot",
+      "description": "Returns the current map as png image.",
+      "inputSchema": {
+        "type": "object",
+        "properties": {
+          "width": {"type": "integer", "description": "Scales the image to this width."}
+        }
+      }
+    }
+  ])
+}
+
+fn text(text: &str) -> Value {
+  json!({"type": "text", "text": text})
+}
+
+/// Sends the event and waits until it reached the map, so a following screenshot shows it.
+async fn send(sender: &MapSender, event: MapEvent) -> Result<()> {
+  sender.send_event(event);
+  if sender.flush().await {
+    Ok(())
+  } else {
+    Err(anyhow!("The map is not reachable."))
+  }
+}
+
+async fn add_geojson(sender: &MapSender, arguments: &Value) -> Result<Vec<Value>> {
+  let geojson = match &arguments["geojson"] {
+    Value::String(geojson) => serde_json::from_str(geojson)?,
+    geojson => geojson.clone(),
+  };
+  let color = match arguments["color"].as_str() {
+    Some(color) => Color::from_str(color).map_err(|()| anyhow!("Unknown color: {color}"))?,
+    None => Color::default(),
+  };
+  let mut layer = Layer::new(arguments["layer"].as_str().unwrap_or("mcp").to_string());
+  layer.shapes = GeoJsonParser::new().with_color(color).shapes(&geojson);
+  if layer.shapes.is_empty() {
+    return Err(anyhow!("The GeoJSON contains no geometries."));
+  }
+  let answer = format!("Drew {} shapes in layer {}.", layer.shapes.len(), layer.id);
+  send(sender, MapEvent::Layer(layer)).await?;
+  if arguments["focus"].as_bool().unwrap_or(false) {
+    send(sender, MapEvent::Focus).await?;
+  }
+  Ok(vec![text(&answer)])
+}
+
+#[allow(clippy::cast_possible_truncation)]
+async fn focus_bbox(sender: &MapSender, arguments: &Value) -> Result<Vec<Value>> {
+  let value = |key: &str| {
+    arguments[key]
+      .as_f64()
+      .map(|v| v as f32)
+      .ok_or(anyhow!("{key} is missing."))
+  };
+  let bbox = [
+    value("north")?,
+    value("west")?,
+    value("south")?,
+    value("east")?,
+  ];
+  send(sender, MapEvent::SetView(View::BoundingBox { bbox })).await?;
+  Ok(vec![text("The map shows the bounding box.")])
+}
+
+async fn search_location(sender: &MapSender, arguments: &Value) -> Result<Vec<Value>> {
+  let query = arguments["query"]
+    .as_str()
+    .ok_or(anyhow!("query is missing."))?;
+  let view = search::view(query).await?;
+  let answer = match &view {
+    View::Center { center, .. } => {
+      format!("The map shows {query} at {}, {}.", center.lat, center.lon)
+    }
+    View::BoundingBox { .. } => format!("The map shows {query}."),
+  };
+  send(sender, MapEvent::SetView(view)).await?;
+  Ok(vec![text(&answer)])
+}
+
+/// Gets the png of `GET /screenshot` of the map.
+async fn take_screenshot(sender: &MapSender, arguments: &Value) -> Result<Vec<Value>> {
+  let config = sender.config();
+  if config.socket.is_some() {
+    return Err(anyhow!(
+      "Screenshots need the http api of the map, not a socket."
+    ));
+  }
+  let mut url = config.url("/screenshot");
+  if let Some(width) = arguments["width"].as_u64() {
+    url.push_str(&format!("?width={width}"));
+  }
+  let mut request = surf::get(url);
+  if let Some(authorization) = config.authorization() {
+    request = request.header("Authorization", authorization);
+  }
+  let mut response = request.await.map_err(|e| anyhow!("{e}"))?;
+  if !response.status().is_success() {
+    return Err(anyhow!("The map answered {}.", response.status()));
+  }
+  let png = response.body_bytes().await.map_err(|e| anyhow!("{e}"))?;
+  Ok(vec![
+    json!({"type": "image", "data": base64::encode(png), "mimeType": "image/png"}),
+  ])
+}
+
+async fn call_tool(sender: &MapSender, params: &Value) -> Value {
+  let arguments = &params["arguments"];
+  let result = match params["name"].as_str().unwrap_or_default() {
+    "add_geojson" => add_geojson(sender, arguments).await,
+    "focus_bbox" => focus_bbox(sender, arguments).await,
+    "search_location" => search_location(sender, arguments).await,
+    "take_screenshot" => take_screenshot(sender, arguments).await,
+    name => Err(anyhow!("Unknown tool: {name}")),
+  };
+  match result {
+    Ok(content) => json!({"content": content, "isError": false}),
+    Err(e) => json!({"content": [text(&e.to_string())], "isError": true}),
+  }
+}
+
+/// The answer to a json-rpc message, notifications without id get none.
+async fn handle(sender: &MapSender, message: &Value) -> Option<Value> {
+  let id = message.get("id")?.clone();
+  let result = match message["method"].as_str().unwrap_or_default() {
+    "initialize" => Ok(json!({
+      "protocolVersion": PROTOCOL_VERSION,
+      "capabilities": {"tools": {}},
+      "serverInfo": {"name": "mapvas", "version": env!("CARGO_PKG_VERSION")}
+    })),
+    "ping" => Ok(json!({})),
+    "tools/list" => Ok(json!({"tools": tools()})),
+    "tools/call" => Ok(call_tool(sender, &message["params"]).await),
+    method => Err(json!({"code": -32601, "message": format!("Unknown method: {method}")})),
+  };
+  Some(match result {
+    Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+    Err(error) => json!({"jsonrpc": "2.0", "id": id, "error": error}),
+  })
+}
+
+/// Runs a Model Context Protocol server on stdin and stdout whose tools draw on the map.
+pub async fn serve(config: &RemoteConfig) {
+  let sender = MapSender::new(config).await;
+  let mut lines = BufReader::new(tokio::io::stdin()).lines();
+  let mut stdout = tokio::io::stdout();
+  while let Ok(Some(line)) = lines.next_line().await {
+    if line.trim().is_empty() {
+      continue;
+    }
+    debug!("MCP request {line}");
+    let answer = match serde_json::from_str::<Value>(&line) {
+      Ok(message) => handle(&sender, &message).await,
+      Err(e) => Some(json!({
+        "jsonrpc": "2.0",
+        "id": null,
+        "error": {"code": -32700, "message": e.to_string()}
+      })),
+    };
+    if let Some(answer) = answer {
+      if stdout
+        .write_all(format!("{answer}\n").as_bytes())
+        .await
+        .and(stdout.flush().await)
+        .is_err()
+      {
+        error!("Cannot answer on stdout.");
+        break;
+      }
+    }
+  }
+  sender.finalize().await;
+}
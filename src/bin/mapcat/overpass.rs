@@ -0,0 +1,47 @@
+use anyhow::{anyhow, Result};
+use log::debug;
+use mapvas::map::coordinates::Coordinate;
+
+const DEFAULT_URL: &str = "https://overpass-api.de/api/interpreter";
+
+/// Replaces `{{bbox}}` in an Overpass QL query with the bounding box as south,west,north,east.
+fn bind_bbox(query: &str, bbox: Option<(Coordinate, Coordinate)>) -> Result<String> {
+  if !query.contains("{{bbox}}") {
+    return Ok(query.to_string());
+  }
+  let (c1, c2) = bbox.ok_or(anyhow!(
+    "The query uses {{{{bbox}}}}, but no --bbox is given."
+  ))?;
+  let bbox = format!(
+    "{},{},{},{}",
+    c1.lat.min(c2.lat),
+    c1.lon.min(c2.lon),
+    c1.lat.max(c2.lat),
+    c1.lon.max(c2.lon)
+  );
+  Ok(query.replace("{{bbox}}", &bbox))
+}
+
+/// Runs an Overpass QL query and returns the response as OSM XML or JSON.
+/// The endpoint can be changed with the environment variable `MAPVAS_OVERPASS_URL`.
+pub async fn query(query: &str, bbox: Option<(Coordinate, Coordinate)>) -> Result<Vec<u8>> {
+  let query = bind_bbox(query, bbox)?;
+  let url = std::env::var("MAPVAS_OVERPASS_URL").unwrap_or_else(|_| DEFAULT_URL.to_string());
+  debug!("Querying {url}: {query}");
+  let body = surf::Body::from_form(&[("data", query.as_str())]).map_err(|e| anyhow!("{e}"))?;
+  let mut response = surf::post(&url)
+    .body(body)
+    .await
+    .map_err(|e| anyhow!("Overpass request failed: {e}"))?;
+  if !response.status().is_success() {
+    return Err(anyhow!(
+      "Overpass returned {}: {}",
+      response.status(),
+      response.body_string().await.unwrap_or_default()
+    ));
+  }
+  response
+    .body_bytes()
+    .await
+    .map_err(|e| anyhow!("Cannot read Overpass response: {e}"))
+}
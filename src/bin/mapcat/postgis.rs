@@ -0,0 +1,54 @@
+use anyhow::{anyhow, Result};
+use log::error;
+use mapvas::map::map_event::{Color, Layer};
+use mapvas::parser::wkb::parse_hex_wkb;
+use tokio_postgres::{NoTls, SimpleQueryMessage};
+
+/// Hex encoded WKB of a point has 21 bytes. Shorter values are never geometries.
+const MIN_HEX_WKB_LENGTH: usize = 42;
+
+/// Runs the query and converts the rows into the layer `PostGIS`.
+/// The first column with a geometry is drawn, the other columns are used as label.
+/// The connection string is taken from `--postgis` or the environment variable `MAPVAS_POSTGIS`.
+pub async fn query(connection: &str, sql: &str, color: Color) -> Result<Layer> {
+  let connection = if connection.is_empty() {
+    std::env::var("MAPVAS_POSTGIS")
+      .map_err(|_| anyhow!("Neither --postgis nor MAPVAS_POSTGIS is set."))?
+  } else {
+    connection.to_string()
+  };
+  let (client, connection) = tokio_postgres::connect(&connection, NoTls).await?;
+  tokio::spawn(async move {
+    if let Err(e) = connection.await {
+      error!("PostGIS connection failed: {e}");
+    }
+  });
+
+  let mut layer = Layer::new("PostGIS".to_string());
+  for message in client.simple_query(sql).await? {
+    let SimpleQueryMessage::Row(row) = message else {
+      continue;
+    };
+    let mut parts = vec![];
+    let mut attributes = vec![];
+    for (i, column) in row.columns().iter().enumerate() {
+      let Some(value) = row.get(i) else {
+        continue;
+      };
+      if parts.is_empty() && value.len() >= MIN_HEX_WKB_LENGTH {
+        if let Ok(p) = parse_hex_wkb(value) {
+          parts = p;
+          continue;
+        }
+      }
+      attributes.push(format!("{}: {value}", column.name()));
+    }
+    let label = (!attributes.is_empty()).then(|| attributes.join(", "));
+    layer.shapes.extend(
+      parts
+        .into_iter()
+        .map(|part| part.into_shape(color, label.clone())),
+    );
+  }
+  Ok(layer)
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use mapvas::map::map_event::{Layer, MapEvent, Shape};
+use tokio::time::sleep;
+
+use crate::sender::MapSender;
+
+/// Splits the shapes of the layer into the parts that appear at their timestamps: lines segment by
+/// segment and points at once. Shapes without timestamps have no time.
+fn timed_parts(mut layer: Layer) -> Vec<(Option<i64>, Layer)> {
+  let shapes = std::mem::take(&mut layer.shapes);
+  let part = |shapes: Vec<Shape>| Layer {
+    shapes,
+    replace: false,
+    ..layer.clone()
+  };
+  let mut parts = vec![];
+  for shape in &shapes {
+    let timed = shape.timestamps.len() == shape.coordinates.len();
+    match shape.timestamps[..] {
+      [time] if timed => parts.push((Some(time), part(vec![shape.clone()]))),
+      [_, _, ..] if timed => {
+        for i in 1..shape.coordinates.len() {
+          let segment = Shape {
+            coordinates: shape.coordinates[i - 1..=i].to_vec(),
+            timestamps: shape.timestamps[i - 1..=i].to_vec(),
+            elevations: shape.elevations.get(i - 1..=i).unwrap_or_default().to_vec(),
+            ..shape.clone()
+          };
+          parts.push((Some(shape.timestamps[i]), part(vec![segment])));
+        }
+      }
+      _ => parts.push((None, part(vec![shape.clone()]))),
+    }
+  }
+  parts
+}
+
+/// Sends the events paced by the timestamps of their shapes, `speed` times faster than recorded.
+/// Shapes without timestamps and other events are sent first.
+#[allow(clippy::cast_precision_loss)]
+pub async fn replay(sender: &MapSender, events: Vec<MapEvent>, speed: f32) {
+  let mut parts = vec![];
+  for event in events {
+    match event {
+      MapEvent::Layer(layer) => parts.extend(timed_parts(layer)),
+      event => sender.send_event(event),
+    }
+  }
+  // Untimed parts sort first, the order of parts with the same time stays.
+  parts.sort_by_key(|(time, _)| *time);
+  let mut previous = None;
+  for (time, layer) in parts {
+    if let (Some(previous), Some(time)) = (previous, time) {
+      let delay = (time - previous) as f32 / 1000. / speed.max(0.001);
+      sleep(Duration::from_secs_f32(delay.max(0.))).await;
+    }
+    previous = time.or(previous);
+    sender.send_event(MapEvent::Layer(layer));
+  }
+}
@@ -0,0 +1,51 @@
+use anyhow::{anyhow, Result};
+use log::debug;
+use mapvas::map::{coordinates::Coordinate, map_event::View};
+use serde::Deserialize;
+
+const DEFAULT_URL: &str = "https://nominatim.openstreetmap.org/search?format=json&limit=1";
+
+#[derive(Deserialize)]
+struct Place {
+  lat: String,
+  lon: String,
+}
+
+/// The position of the first result for the place name.
+/// The search provider can be changed with the environment variable `MAPVAS_SEARCH_URL`, it has to
+/// answer like Nominatim.
+async fn search(place: &str) -> Result<Coordinate> {
+  let url = std::env::var("MAPVAS_SEARCH_URL").unwrap_or_else(|_| DEFAULT_URL.to_string());
+  let mut url = surf::Url::parse(&url)?;
+  url.query_pairs_mut().append_pair("q", place);
+  debug!("Searching {url}");
+  let mut response = surf::get(url)
+    .header("User-Agent", "mapvas")
+    .await
+    .map_err(|e| anyhow!("Search request failed: {e}"))?;
+  if !response.status().is_success() {
+    return Err(anyhow!("Search returned {}", response.status()));
+  }
+  let places: Vec<Place> = response
+    .body_json()
+    .await
+    .map_err(|e| anyhow!("Cannot read search response: {e}"))?;
+  let place = places
+    .first()
+    .ok_or(anyhow!("Nothing found for {place}."))?;
+  Ok(Coordinate {
+    lat: place.lat.parse()?,
+    lon: place.lon.parse()?,
+  })
+}
+
+/// The view for a position, bounding box, or place name.
+pub async fn view(target: &str) -> Result<View> {
+  if let Ok(view) = target.parse() {
+    return Ok(view);
+  }
+  Ok(View::Center {
+    center: search(target).await?,
+    zoom: None,
+  })
+}
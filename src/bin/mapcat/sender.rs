@@ -1,36 +1,61 @@
-use log::debug;
-use mapvas::map::map_event::{Layer, MapEvent, Shape};
-use mapvas::remote::DEFAULT_PORT;
+use log::{debug, error};
+use mapvas::map::map_event::{Layer, MapEvent};
+use mapvas::remote::{socket, RemoteConfig};
 use std::process::Stdio;
 
 use async_std::task::block_on;
 use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
 use std::time::Duration;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+
+use crate::stats::Stats;
 
 /// Creates a sender that spawns a mapvas instance and queues requests and summarizes layers for
 /// performance speedup with some parsers. The events are send from another thread to not block the
 /// parsing.
 /// To guarantee that the events are send to the map the `finalize` method has to be used in the end.
 pub struct MapSender {
-  sender: UnboundedSender<Option<MapEvent>>,
+  sender: UnboundedSender<Message>,
   inner_join_handle: tokio::task::JoinHandle<()>,
+  stats: Mutex<Stats>,
+  /// Set if a request did not reach the map.
+  failed: Arc<AtomicBool>,
+  /// The config with the address of the running map.
+  config: RemoteConfig,
+}
+
+enum Message {
+  Event(MapEvent),
+  /// Sends the queue and answers once it was sent.
+  Flush(oneshot::Sender<()>),
+  /// Sends the queue and stops.
+  Finish,
 }
 
 struct SenderInner {
-  receiver: UnboundedReceiver<Option<MapEvent>>,
+  receiver: UnboundedReceiver<Message>,
   queue: VecDeque<MapEvent>,
   send_mutex: Arc<(std::sync::Mutex<usize>, Condvar)>,
+  config: Arc<RemoteConfig>,
+  failed: Arc<AtomicBool>,
 }
 
 impl SenderInner {
-  pub fn start(receiver: UnboundedReceiver<Option<MapEvent>>) -> tokio::task::JoinHandle<()> {
+  pub fn start(
+    receiver: UnboundedReceiver<Message>,
+    config: RemoteConfig,
+    failed: Arc<AtomicBool>,
+  ) -> tokio::task::JoinHandle<()> {
     tokio::spawn({
       Self {
         receiver,
         queue: VecDeque::new(),
         send_mutex: Arc::new((Mutex::new(0), Condvar::new())),
+        config: Arc::new(config),
+        failed,
       }
       .run()
     })
@@ -40,15 +65,24 @@ impl SenderInner {
     let mut interval = tokio::time::interval(Duration::from_millis(100));
     loop {
       tokio::select! {
-        Some(event) = self.receiver.recv() => {
-           if let Some(event) = event {self.receive(event);} else {
-                 self.send_queue();
-                 drop(self.send_mutex.1.wait_while(
-                   self.send_mutex.0.lock().unwrap(), |count| { *count != 0 })
-                 );
-                 return;
-              }
-            }
+        Some(message) = self.receiver.recv() => match message {
+          Message::Event(event) => self.receive(event),
+          Message::Flush(done) => {
+            self.send_queue();
+            let send_mutex = self.send_mutex.clone();
+            tokio::task::spawn_blocking(move || {
+              drop(send_mutex.1.wait_while(send_mutex.0.lock().unwrap(), |count| *count != 0));
+              let _ = done.send(());
+            });
+          }
+          Message::Finish => {
+            self.send_queue();
+            drop(self.send_mutex.1.wait_while(
+              self.send_mutex.0.lock().unwrap(), |count| { *count != 0 })
+            );
+            return;
+          }
+        },
         _ = interval.tick() => {
             self.send_queue();
           },
@@ -71,8 +105,12 @@ impl SenderInner {
     std::mem::swap(&mut queue, &mut self.queue);
 
     let send_mut_condv = self.send_mutex.clone();
+    let config = self.config.clone();
+    let failed = self.failed.clone();
     rayon::spawn(move || {
-      block_on(Self::compact_and_send(queue));
+      if !block_on(Self::compact_and_send(&config, queue)) {
+        failed.store(true, Ordering::Relaxed);
+      }
       let lock_stuff = send_mut_condv;
       let mut count = lock_stuff.0.lock().expect("can aquire lock");
       *count -= 1;
@@ -81,76 +119,169 @@ impl SenderInner {
     });
   }
 
-  async fn compact_and_send(queue: VecDeque<MapEvent>) {
-    let mut layers: BTreeMap<String, Vec<Shape>> = BTreeMap::new();
+  /// Returns if the events reached the map.
+  async fn compact_and_send(config: &RemoteConfig, queue: VecDeque<MapEvent>) -> bool {
+    let mut layers: BTreeMap<String, Layer> = BTreeMap::new();
+    let mut events = vec![];
 
     for event in queue {
       match event {
-        MapEvent::Layer(Layer { id, mut shapes }) => {
-          layers
-            .entry(id)
-            .and_modify(|e| e.append(&mut shapes))
-            .or_insert(shapes);
-        }
-        e => Self::send_event(&e).await,
+        MapEvent::Layer(mut layer) => match layers.get_mut(&layer.id) {
+          Some(compacted) => {
+            if layer.replace {
+              compacted.shapes.clear();
+              compacted.replace = true;
+            }
+            compacted.shapes.append(&mut layer.shapes);
+            compacted.render_mode = layer.render_mode;
+            if !layer.rules.is_empty() {
+              compacted.rules = layer.rules;
+            }
+            compacted.opacity = layer.opacity.or(compacted.opacity);
+            compacted.blend = layer.blend.or(compacted.blend);
+            compacted.cluster = layer.cluster.or(compacted.cluster);
+          }
+          None => {
+            layers.insert(layer.id.clone(), layer);
+          }
+        },
+        e => events.push(e),
       }
     }
 
-    for (id, shapes) in layers {
-      Self::send_event(&MapEvent::Layer(Layer { id, shapes })).await;
-    }
+    events.extend(layers.into_values().map(MapEvent::Layer));
+    events.is_empty() || Self::send_events(config, &events).await
   }
 
-  async fn send_event(event: &MapEvent) {
-    let _ = surf::post(format!("http://localhost:{DEFAULT_PORT}/"))
-      .body_json(&event)
-      .expect("cannot serialize json")
-      .await;
+  /// Sends all events in one request and returns if they reached the map.
+  async fn send_events(config: &RemoteConfig, events: &[MapEvent]) -> bool {
+    if let Some(path) = &config.socket {
+      return socket::send(path, events)
+        .await
+        .inspect_err(|e| error!("{e}"))
+        .is_ok();
+    }
+    let mut body = config
+      .encoding
+      .encode(events)
+      .expect("cannot serialize events");
+    if let Some(compression) = config.compression {
+      body = compression.compress(&body).expect("cannot compress events");
+    }
+    let mut request = surf::post(config.url("/batch"))
+      .body_bytes(body)
+      .content_type(config.encoding.content_type());
+    if let Some(compression) = config.compression {
+      request = request.header("Content-Encoding", compression.content_encoding());
+    }
+    if let Some(authorization) = config.authorization() {
+      request = request.header("Authorization", authorization);
+    }
+    match request.await {
+      Ok(response) if response.status() == surf::StatusCode::Unauthorized => {
+        error!("The map rejected the token, see MAPVAS_TOKEN.");
+        false
+      }
+      Ok(response) => response.status().is_success(),
+      Err(e) => {
+        debug!("Sending failed: {e}");
+        false
+      }
+    }
   }
 }
 
 impl MapSender {
-  /// Creates a new sender and spawns a mapvas instance if none is running.
-  pub async fn new() -> MapSender {
+  /// Creates a new sender for the map at the configured address or instance and spawns a mapvas
+  /// instance if none is running on this machine.
+  pub async fn new(config: &RemoteConfig) -> MapSender {
+    let config = Self::spawn_mapvas_if_needed(config).await;
     let (rx, tx) = unbounded_channel();
-    let sender = Self {
+    let failed = Arc::new(AtomicBool::new(false));
+    Self {
       sender: rx,
-      inner_join_handle: SenderInner::start(tx),
-    };
-    sender.spawn_mapvas_if_needed().await;
+      inner_join_handle: SenderInner::start(tx, config.clone(), failed.clone()),
+      stats: Mutex::new(Stats::default()),
+      failed,
+      config,
+    }
+  }
+
+  /// The config with the address of the map the events are sent to.
+  pub fn config(&self) -> &RemoteConfig {
+    &self.config
+  }
+
+  async fn is_map_reachable(config: &RemoteConfig) -> bool {
+    match &config.socket {
+      Some(path) => socket::is_reachable(path).await,
+      None => surf::get(config.url("/healthcheck"))
+        .send()
+        .await
+        .inspect_err(|e| debug!("Healthcheck {e}"))
+        .is_ok(),
+    }
+  }
 
-    sender
+  /// The config with the address of the map if it is running.
+  async fn running_map(config: &RemoteConfig) -> Option<RemoteConfig> {
+    let resolved = config.resolved()?;
+    Self::is_map_reachable(&resolved).await.then_some(resolved)
   }
 
-  async fn spawn_mapvas_if_needed(&self) {
-    if surf::get(format!("http://localhost:{DEFAULT_PORT}/healthcheck"))
-      .send()
-      .await
-      .is_ok()
-    {
-      return;
+  /// Returns the config with the address of the running map.
+  async fn spawn_mapvas_if_needed(config: &RemoteConfig) -> RemoteConfig {
+    if let Some(running) = Self::running_map(config).await {
+      return running;
+    }
+    if config.socket.is_none() && !config.is_local() {
+      error!("No map is reachable at {}.", config.address);
+      return config.clone();
     }
 
-    let _ = std::process::Command::new("mapvas")
-      .stderr(Stdio::null())
-      .stdout(Stdio::null())
-      .spawn();
-    while let Err(e) = surf::get(format!("http://localhost:{DEFAULT_PORT}/healthcheck",))
-      .send()
-      .await
-    {
-      debug!("Healthcheck {}", e);
+    let mut command = std::process::Command::new("mapvas");
+    if let Some(path) = &config.socket {
+      command.arg("--socket").arg(path);
+    } else {
+      command.arg("--address").arg(&config.address);
+    }
+    // Arguments show up in the process list, the environment only for the same user.
+    if let Some(token) = &config.token {
+      command.env("MAPVAS_TOKEN", token);
+    }
+    if let Some(name) = &config.instance {
+      command.arg("--instance").arg(name);
+    }
+    let _ = command.stderr(Stdio::null()).stdout(Stdio::null()).spawn();
+    loop {
+      // A new instance may register another address.
+      if let Some(running) = Self::running_map(config).await {
+        return running;
+      }
+      tokio::time::sleep(Duration::from_millis(10)).await;
     }
   }
 
   /// Queues an event for sending.
   pub fn send_event(&self, event: MapEvent) {
-    let _ = self.sender.send(Some(event));
+    self.stats.lock().expect("can aquire lock").count(&event);
+    let _ = self.sender.send(Message::Event(event));
+  }
+
+  /// Sends the queued events and returns if all events so far reached the map.
+  pub async fn flush(&self) -> bool {
+    let (done, sent) = oneshot::channel();
+    let _ = self.sender.send(Message::Flush(done));
+    let _ = sent.await;
+    !self.failed.load(Ordering::Relaxed)
   }
 
-  /// Sends the events that are still in the queue.
-  pub async fn finalize(self) {
-    let _ = self.sender.send(None);
+  /// Sends the events that are still in the queue and returns what was sent.
+  pub async fn finalize(self) -> Stats {
+    let _ = self.sender.send(Message::Finish);
     let _ = self.inner_join_handle.await;
+    let mut stats = self.stats.into_inner().expect("can aquire lock");
+    stats.delivered = !self.failed.load(Ordering::Relaxed);
+    stats
   }
 }
@@ -0,0 +1,78 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use flate2::read::MultiGzDecoder;
+use log::debug;
+
+fn url(path: &Path) -> Option<&str> {
+  path
+    .to_str()
+    .filter(|p| p.starts_with("http://") || p.starts_with("https://"))
+}
+
+/// The compression of a file by its extension, `gz` or `zst`.
+fn compression(path: &Path) -> Option<String> {
+  let extension = path.extension()?.to_str()?.to_lowercase();
+  ["gz", "zst"]
+    .contains(&extension.as_str())
+    .then_some(extension)
+}
+
+fn decompressed(read: impl Read + 'static, compression: Option<&str>) -> Result<Box<dyn BufRead>> {
+  Ok(match compression {
+    Some("gz") => Box::new(BufReader::new(MultiGzDecoder::new(read))),
+    Some("zst") => Box::new(BufReader::new(zstd::Decoder::new(read)?)),
+    _ => Box::new(BufReader::new(read)),
+  })
+}
+
+/// The path the parser is chosen by: the file name without `.gz` or `.zst` and for urls the last
+/// segment of the url path, e.g. `roads.geojson` for `https://example.com/roads.geojson.gz?v=2`.
+pub fn format_path(path: &Path) -> PathBuf {
+  let path = match url(path) {
+    Some(url) => {
+      let url = url.split(['?', '#']).next().unwrap_or_default();
+      PathBuf::from(url.rsplit('/').next().unwrap_or_default())
+    }
+    None => path.to_path_buf(),
+  };
+  match compression(&path) {
+    Some(_) => path.with_extension(""),
+    None => path,
+  }
+}
+
+/// If the input is a url instead of a file.
+pub fn is_url(path: &Path) -> bool {
+  url(path).is_some()
+}
+
+/// Opens a file, decompressing files ending in `.gz` or `.zst` while they are read.
+pub fn open_file(path: &Path) -> Result<Box<dyn BufRead>> {
+  decompressed(File::open(path)?, compression(path).as_deref())
+}
+
+/// Opens a file or downloads a url, both are decompressed if they end in `.gz` or `.zst`.
+pub async fn open(path: &Path) -> Result<Box<dyn BufRead>> {
+  let Some(url) = url(path) else {
+    return open_file(path);
+  };
+  debug!("Downloading {url}");
+  let client = surf::client().with(surf::middleware::Redirect::default());
+  let mut response = client
+    .get(url)
+    .header("User-Agent", "mapvas")
+    .await
+    .map_err(|e| anyhow!("Download of {url} failed: {e}"))?;
+  if !response.status().is_success() {
+    return Err(anyhow!("Download of {url} returned {}", response.status()));
+  }
+  let data = response
+    .body_bytes()
+    .await
+    .map_err(|e| anyhow!("Cannot read {url}: {e}"))?;
+  let plain = Path::new(url.split(['?', '#']).next().unwrap_or_default());
+  decompressed(Cursor::new(data), compression(plain).as_deref())
+}
@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use mapvas::map::map_event::{FillStyle, MapEvent};
+use serde::Serialize;
+
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+/// Logs like `env_logger` and counts the errors, e.g. of parsers, for `--stats`.
+struct CountingLogger {
+  inner: env_logger::Logger,
+}
+
+impl Log for CountingLogger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    metadata.level() == Level::Error || self.inner.enabled(metadata)
+  }
+
+  fn log(&self, record: &Record) {
+    if record.level() == Level::Error {
+      ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
+    if self.inner.matches(record) {
+      self.inner.log(record);
+    }
+  }
+
+  fn flush(&self) {
+    self.inner.flush();
+  }
+}
+
+/// Sets up logging with `RUST_LOG` like `env_logger::init`. Errors are counted even if they are
+/// not shown.
+pub fn init_logging() {
+  let inner = env_logger::Builder::from_default_env().build();
+  log::set_max_level(inner.filter().max(LevelFilter::Error));
+  if let Err(e) = log::set_boxed_logger(Box::new(CountingLogger { inner })) {
+    eprintln!("Cannot set up logging: {e}");
+  }
+}
+
+/// The number of errors logged so far.
+pub fn errors() -> usize {
+  ERRORS.load(Ordering::Relaxed)
+}
+
+/// The number of geometries of a layer by type.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct Counts {
+  pub points: usize,
+  pub lines: usize,
+  pub polygons: usize,
+}
+
+/// What mapcat sent to the map, printed as JSON by `--stats`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Stats {
+  pub layers: BTreeMap<String, Counts>,
+  pub errors: usize,
+  /// If all events reached the map.
+  pub delivered: bool,
+}
+
+impl Stats {
+  pub fn count(&mut self, event: &MapEvent) {
+    let MapEvent::Layer(layer) = event else {
+      return;
+    };
+    let counts = self.layers.entry(layer.id.clone()).or_default();
+    for shape in &layer.shapes {
+      match (shape.coordinates.len(), shape.style.fill) {
+        (0, _) => {}
+        (1, _) => counts.points += 1,
+        (_, FillStyle::NoFill) => counts.lines += 1,
+        _ => counts.polygons += 1,
+      }
+    }
+  }
+
+  /// The number of all geometries.
+  pub fn geometries(&self) -> usize {
+    self
+      .layers
+      .values()
+      .map(|c| c.points + c.lines + c.polygons)
+      .sum()
+  }
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+use log::{error, info};
+use mapvas::map::map_event::{Layer, MapEvent};
+use notify::{RecursiveMode, Watcher};
+
+use crate::sender::MapSender;
+
+/// How long changes are collected before the files are read, so a file written in several steps
+/// is read once.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// If the name matches the pattern with `*` for any text and `?` for any character.
+fn matches(pattern: &[char], name: &[char]) -> bool {
+  match (pattern.first(), name.first()) {
+    (None, None) => true,
+    (Some('*'), _) => {
+      matches(&pattern[1..], name) || (!name.is_empty() && matches(pattern, &name[1..]))
+    }
+    (Some('?'), Some(_)) => matches(&pattern[1..], &name[1..]),
+    (Some(p), Some(n)) if p == n => matches(&pattern[1..], &name[1..]),
+    _ => false,
+  }
+}
+
+fn layer_name(path: &Path) -> Option<String> {
+  Some(path.file_name()?.to_string_lossy().to_string())
+}
+
+/// Draws the file as one layer named after it, replacing what was drawn for it before.
+fn draw(sender: &MapSender, path: &Path, events: Vec<MapEvent>) {
+  let Some(name) = layer_name(path) else {
+    return;
+  };
+  let mut drawn: Option<Layer> = None;
+  for event in events {
+    match (event, &mut drawn) {
+      (MapEvent::Layer(layer), Some(drawn)) => drawn.shapes.extend(layer.shapes),
+      (MapEvent::Layer(layer), None) => drawn = Some(layer),
+      (event, _) => sender.send_event(event),
+    }
+  }
+  match drawn {
+    Some(mut layer) => {
+      layer.id = name;
+      sender.send_event(MapEvent::Layer(layer.with_replace(true)));
+    }
+    None => sender.send_event(MapEvent::ClearLayer(name)),
+  }
+}
+
+/// Draws the files of the directory whose names match the pattern and keeps them up to date until
+/// mapcat is stopped. New and changed files are drawn again, the layers of deleted files are
+/// removed. `load` parses a file.
+pub fn watch(dir: &Path, pattern: &str, sender: &MapSender, load: impl Fn(&Path) -> Vec<MapEvent>) {
+  let pattern: Vec<char> = pattern.chars().collect();
+  let wanted = |path: &Path| {
+    layer_name(path).is_some_and(|name| matches(&pattern, &name.chars().collect::<Vec<_>>()))
+  };
+
+  let (tx, changes) = channel();
+  let watcher = notify::recommended_watcher(tx)
+    .and_then(|mut w| w.watch(dir, RecursiveMode::NonRecursive).map(|()| w));
+  let _watcher = match watcher {
+    Ok(watcher) => watcher,
+    Err(e) => {
+      error!("Cannot watch {dir:?}: {e}");
+      return;
+    }
+  };
+
+  let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+    .map(|entries| entries.filter_map(|e| e.ok().map(|e| e.path())).collect())
+    .unwrap_or_default();
+  files.sort();
+  for file in files.iter().filter(|f| f.is_file() && wanted(f)) {
+    draw(sender, file, load(file));
+  }
+  info!("Watching {dir:?} for changes.");
+
+  while let Ok(change) = changes.recv() {
+    let mut changed = BTreeSet::new();
+    let mut change = Some(change);
+    while let Some(next) = change {
+      match next {
+        Ok(event) => changed.extend(event.paths.into_iter().filter(|p| wanted(p))),
+        Err(e) => error!("Watching {dir:?} failed: {e}"),
+      }
+      change = changes.recv_timeout(SETTLE_TIME).ok();
+    }
+    for path in changed {
+      if path.is_file() {
+        draw(sender, &path, load(&path));
+      } else if let Some(name) = layer_name(&path) {
+        sender.send_event(MapEvent::ClearLayer(name));
+      }
+    }
+  }
+}
@@ -1,14 +1,23 @@
 use mapvas::{
   map::{map_event::MapEvent, mapvas::MapVas},
-  remote::{serve_axum, DEFAULT_PORT},
+  parser::plugin_layers,
+  remote::{
+    authorize, batch, delete_layer, events, fly_to, instances::Instances, layer_geometries, layers,
+    screenshot, serve_axum, set_view, socket, Remote, RemoteConfig,
+  },
 };
 
-use std::net::SocketAddr;
+use std::{net::SocketAddr, path::PathBuf};
 
 use axum::extract::DefaultBodyLimit;
-use axum::{routing::get, routing::post, Router};
+use axum::{middleware, routing::delete, routing::get, routing::post, Router};
+use clap::Parser;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc::Sender;
-use tower_http::trace::{self, TraceLayer};
+use tower_http::{
+  decompression::RequestDecompressionLayer,
+  trace::{self, TraceLayer},
+};
 use tracing_subscriber::EnvFilter;
 
 async fn shutdown_signal(sender: Sender<MapEvent>) {
@@ -39,6 +48,64 @@ async fn shutdown_signal(sender: Sender<MapEvent>) {
 
 async fn healthcheck() {}
 
+/// Listens on the configured address. A named instance takes a free port on the host of the
+/// address, mapcat finds it by its name.
+async fn listen(config: &RemoteConfig) -> std::io::Result<TcpListener> {
+  if config.instance.is_none() {
+    return TcpListener::bind(&config.address).await;
+  }
+  let host = config
+    .address
+    .rsplit_once(':')
+    .map_or("127.0.0.1", |(host, _)| host);
+  TcpListener::bind(format!("{host}:0")).await
+}
+
+/// Stores the address of the named instance for mapcat.
+fn register(name: &str, mut address: SocketAddr) {
+  if address.ip().is_unspecified() {
+    address.set_ip(std::net::Ipv4Addr::LOCALHOST.into());
+  }
+  if let Err(e) = Instances::update(|instances| instances.register(name, address.to_string())) {
+    tracing::error!("Cannot register instance {name}: {e}");
+  }
+}
+
+fn unregister(name: &str) {
+  if let Err(e) = Instances::update(|instances| instances.unregister(name)) {
+    tracing::error!("Cannot unregister instance {name}: {e}");
+  }
+}
+
+#[derive(clap::Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  /// Address of the remote api, e.g. `0.0.0.0:12345` to be reachable in the network.
+  /// Overrides `MAPVAS_ADDRESS`.
+  #[arg(long)]
+  address: Option<String>,
+
+  /// Token that requests to the remote api need as `Authorization: Bearer <token>`.
+  /// Overrides `MAPVAS_TOKEN`.
+  #[arg(long)]
+  token: Option<String>,
+
+  /// Name of this instance, so several maps can run side by side and mapcat can choose one with
+  /// `--instance`. Overrides `MAPVAS_INSTANCE`.
+  #[arg(long)]
+  instance: Option<String>,
+
+  /// Also serves the gRPC api of `proto/mapvas.proto` on this address, e.g. `127.0.0.1:12346`.
+  #[cfg(feature = "grpc")]
+  #[arg(long)]
+  grpc_address: Option<SocketAddr>,
+
+  /// Receives events from mapcat on this Unix socket or Windows named pipe instead of the address,
+  /// which is only served as well if given with `--address`. Overrides `MAPVAS_SOCKET`.
+  #[arg(long)]
+  socket: Option<PathBuf>,
+}
+
 #[tokio::main]
 async fn main() {
   tracing_subscriber::fmt()
@@ -47,25 +114,100 @@ async fn main() {
     .compact()
     .init();
 
+  let args = Args::parse();
+  let address_given = args.address.is_some();
+  let config = RemoteConfig::from_env()
+    .with_address(args.address)
+    .with_token(args.token)
+    .with_socket(args.socket)
+    .with_instance(args.instance);
+  if !config.is_local() && config.token.is_none() {
+    tracing::warn!(
+      "The remote api listens on {} without a token, set one with --token.",
+      config.address
+    );
+  }
+
+  if let Some(name) = &config.instance {
+    if let Some(address) = Instances::load().address(name) {
+      if TcpStream::connect(address).await.is_ok() {
+        tracing::error!("An instance named {name} already runs at {address}.");
+        std::process::exit(1);
+      }
+    }
+  }
+
   let widget: MapVas = MapVas::new();
   let sender = widget.get_event_sender();
+  let plugin_sender = sender.clone();
+  tokio::task::spawn_blocking(move || {
+    for event in plugin_layers() {
+      let _ = plugin_sender.blocking_send(event);
+    }
+  });
+  if let Some(path) = config.socket.clone() {
+    let sender = sender.clone();
+    tokio::spawn(async move {
+      if let Err(e) = socket::serve(&path, sender).await {
+        tracing::error!("Cannot receive events on {}: {e}", path.display());
+      }
+    });
+  }
+  let remote = Remote {
+    sender: sender.clone(),
+    updates: widget.get_update_sender(),
+    max_batch_size: config.max_batch_size,
+  };
+  #[cfg(feature = "grpc")]
+  if let Some(address) = args.grpc_address {
+    let (remote, config) = (remote.clone(), config.clone());
+    tokio::spawn(async move { mapvas::remote::grpc::serve(address, remote, &config).await });
+  }
+  // With a socket the map does not take the port, so several users can run a map each.
+  if config.socket.is_some() && !address_given {
+    tokio::spawn(shutdown_signal(sender));
+    widget.run();
+    return;
+  }
   let app = Router::new()
     .route("/", post(serve_axum))
+    .route("/batch", post(batch))
     .route("/healtcheck", get(healthcheck))
-    .with_state(sender.clone())
+    .route("/screenshot", get(screenshot))
+    .route("/flyto", get(fly_to))
+    .route("/view", post(set_view))
+    .route("/layers", get(layers))
+    .route("/layers/:id", delete(delete_layer))
+    .route("/layers/:id/geometries", get(layer_geometries))
+    .route("/events", get(events))
+    .with_state(remote)
+    .layer(middleware::from_fn_with_state(config.clone(), authorize))
     .layer(DefaultBodyLimit::max(10_000_000_000_000))
+    .layer(RequestDecompressionLayer::new())
     .layer(
       TraceLayer::new_for_http()
         .make_span_with(trace::DefaultMakeSpan::new().level(tracing::Level::INFO))
         .on_response(trace::DefaultOnResponse::new().level(tracing::Level::INFO)),
     );
 
-  tokio::spawn(async {
-    let addr = SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT));
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+  tokio::spawn(async move {
+    let listener = match listen(&config).await {
+      Ok(listener) => listener,
+      Err(e) => {
+        tracing::error!("Cannot listen on {}: {e}", config.address);
+        return;
+      }
+    };
+    if let (Some(name), Ok(address)) = (&config.instance, listener.local_addr()) {
+      tracing::info!("Instance {name} listens on {address}.");
+      register(name, address);
+    }
     let _ = axum::serve(listener, app)
       .with_graceful_shutdown(shutdown_signal(sender))
       .await;
+    if let Some(name) = &config.instance {
+      unregister(name);
+    }
   });
 
   widget.run();
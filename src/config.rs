@@ -0,0 +1,118 @@
+use std::{
+  fs::OpenOptions,
+  path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use fs2::FileExt;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The directory mapvas keeps its files in, given by `MAPVAS_CONFIG_DIR`, by default `mapvas` in
+/// the config directory of the platform like `~/.config/mapvas`.
+#[must_use]
+pub fn dir() -> Option<PathBuf> {
+  std::env::var_os("MAPVAS_CONFIG_DIR")
+    .filter(|dir| !dir.is_empty())
+    .map(PathBuf::from)
+    .or_else(|| dirs::config_dir().map(|dir| dir.join("mapvas")))
+}
+
+/// The file or directory given by the environment variable, by default the one with the name in
+/// [`dir`].
+#[must_use]
+pub fn path(var: &str, name: &str) -> Option<PathBuf> {
+  std::env::var_os(var)
+    .filter(|path| !path.is_empty())
+    .map(PathBuf::from)
+    .or_else(|| dir().map(|dir| dir.join(name)))
+}
+
+/// Reads a value stored as JSON. A missing or broken file reads as the default.
+#[must_use]
+pub fn load<T: DeserializeOwned + Default>(path: Option<&Path>) -> T {
+  path
+    .and_then(|p| std::fs::read_to_string(p).ok())
+    .and_then(|json| serde_json::from_str(&json).ok())
+    .unwrap_or_default()
+}
+
+/// Replaces the file with the contents, so readers never see a half written file. The directory
+/// is created if needed.
+///
+/// # Errors
+/// If the file cannot be written.
+pub fn write(path: &Path, contents: &str) -> Result<()> {
+  if let Some(dir) = path.parent() {
+    std::fs::create_dir_all(dir)?;
+  }
+  let written = path.with_extension("tmp");
+  std::fs::write(&written, contents)?;
+  std::fs::rename(written, path)?;
+  Ok(())
+}
+
+/// Stores a value as JSON.
+///
+/// # Errors
+/// If the file cannot be written.
+pub fn save<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+  write(path, &serde_json::to_string_pretty(value)?)
+}
+
+/// Changes a value stored as JSON while holding a lock on the file, so processes changing it at
+/// the same time keep the changes of each other. Returns the stored value.
+///
+/// # Errors
+/// If the file cannot be locked or written.
+pub fn update<T: Serialize + DeserializeOwned + Default>(
+  path: &Path,
+  change: impl FnOnce(&mut T),
+) -> Result<T> {
+  if let Some(dir) = path.parent() {
+    std::fs::create_dir_all(dir)?;
+  }
+  // The stored file is replaced on every write, so the lock is held on a file next to it.
+  let lock = OpenOptions::new()
+    .create(true)
+    .truncate(false)
+    .write(true)
+    .open(path.with_extension("lock"))?;
+  lock.lock_exclusive()?;
+  let mut value = load(Some(path));
+  change(&mut value);
+  save(path, &value)?;
+  // Closing the file releases the lock.
+  drop(lock);
+  Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+
+  use super::*;
+
+  #[test]
+  fn concurrent_updates() {
+    let dir = std::env::temp_dir().join(format!("mapvas_config_{}", std::process::id()));
+    let path = dir.join("counts.json");
+    let threads: Vec<_> = (0..8)
+      .map(|i| {
+        let path = path.clone();
+        std::thread::spawn(move || {
+          update(&path, |counts: &mut BTreeMap<String, usize>| {
+            counts.insert(format!("thread {i}"), i);
+          })
+          .unwrap();
+        })
+      })
+      .collect();
+    for thread in threads {
+      thread.join().unwrap();
+    }
+    let counts: BTreeMap<String, usize> = load(Some(&path));
+    assert_eq!(counts.len(), 8);
+    assert!(load::<Vec<String>>(Some(&dir.join("missing.json"))).is_empty());
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,301 @@
+use std::io::Write;
+
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+
+use crate::map::{
+  coordinates::{Coordinate, PixelPosition},
+  map_event::{FillStyle, Layer, Shape},
+};
+use crate::parser::GeoJsonParser;
+
+use super::Exporter;
+
+/// Writes layers as a GeoJSON `FeatureCollection`.
+/// Styles are stored in the properties following the simplestyle spec, which geojson.io and uMap
+/// show. Labels are written as `label` and `title`, the layer id as `layer`, and the meters around
+/// the point of a circle as `radius`. The properties of the shapes are written as strings, the
+/// style wins over properties of the same name.
+/// Shapes read from GeoJSON keep the id, the properties, and, while their coordinates are
+/// unchanged, the geometry of their feature as read. Only the style and label are written over
+/// the properties if they changed, and properties that changed as strings.
+#[derive(Debug, Default)]
+pub struct GeoJsonExporter {}
+
+impl GeoJsonExporter {
+  fn position(coord: &Coordinate) -> Value {
+    json!([coord.lon, coord.lat])
+  }
+
+  fn geometry(shape: &Shape) -> Value {
+    let positions: Vec<Value> = shape.coordinates.iter().map(Self::position).collect();
+    match (shape.coordinates.len(), shape.style.fill) {
+      (1, _) => json!({"type": "Point", "coordinates": positions[0]}),
+      (_, FillStyle::NoFill) => json!({"type": "LineString", "coordinates": positions}),
+      _ => {
+        let mut ring = positions;
+        if shape.coordinates.first() != shape.coordinates.last() {
+          ring.push(ring[0].clone());
+        }
+        json!({"type": "Polygon", "coordinates": [ring]})
+      }
+    }
+  }
+
+  /// The geometry of the parts, the geometry of their feature if they were read from GeoJSON and
+  /// are unchanged.
+  fn geometry_of(parts: &[&Shape]) -> Option<Value> {
+    let source = parts[0].source.as_ref()?;
+    let read = GeoJsonParser::new().shapes(&source.geometry);
+    let same_position = |coordinate: &Coordinate, read: &Coordinate| {
+      // Shapes shown on the map went through its canvas.
+      coordinate == read || *coordinate == Coordinate::from(PixelPosition::from(*read))
+    };
+    let unchanged = read.len() == parts.len()
+      && parts.iter().zip(&read).all(|(shape, read)| {
+        (shape.style.fill == FillStyle::NoFill) == (read.style.fill == FillStyle::NoFill)
+          && shape.coordinates.len() == read.coordinates.len()
+          && (shape.coordinates.iter())
+            .zip(&read.coordinates)
+            .all(|(c, r)| same_position(c, r))
+      });
+    unchanged.then(|| source.geometry.clone())
+  }
+
+  /// The properties that describe the style, label, and properties of the shape.
+  pub(crate) fn style_properties(shape: &Shape) -> Map<String, Value> {
+    let mut properties: Map<String, Value> = shape
+      .properties
+      .iter()
+      .map(|(key, value)| (key.clone(), json!(value)))
+      .collect();
+    let color = shape.style.color.to_hex();
+    if shape.coordinates.len() == 1 {
+      properties.insert("marker-color".into(), json!(color));
+      let size = match shape.style.radius {
+        Some(radius) if radius < 2.5 => Some("small"),
+        Some(radius) if radius > 4. => Some("large"),
+        Some(_) => Some("medium"),
+        None => None,
+      };
+      if let Some(size) = size {
+        properties.insert("marker-size".into(), json!(size));
+      }
+      if let Some(icon) = &shape.icon {
+        properties.insert("marker-symbol".into(), json!(icon.name));
+      }
+      if let Some(radius) = shape.radius {
+        properties.insert("radius".into(), json!(radius));
+      }
+    } else {
+      properties.insert("stroke".into(), json!(color));
+      if let Some(width) = shape.style.width {
+        properties.insert("stroke-width".into(), json!(width));
+      }
+    }
+    match shape.style.fill {
+      FillStyle::NoFill => (),
+      FillStyle::Transparent => {
+        properties.insert("fill".into(), json!(color));
+        properties.insert("fill-opacity".into(), json!(0.2));
+      }
+      FillStyle::Solid => {
+        properties.insert("fill".into(), json!(color));
+        properties.insert("fill-opacity".into(), json!(1.0));
+      }
+    }
+    if let Some(label) = &shape.label {
+      properties.insert("label".into(), json!(label));
+      properties.insert("title".into(), json!(label));
+    }
+    if let Some(description) = &shape.description {
+      properties.insert("description".into(), json!(description));
+    }
+    properties
+  }
+
+  fn properties(layer_id: &str, shape: &Shape) -> Value {
+    let written = Self::style_properties(shape);
+    let Some(source) = &shape.source else {
+      let mut properties = written;
+      properties.insert("layer".into(), json!(layer_id));
+      return Value::Object(properties);
+    };
+    let mut properties = source.properties.clone();
+    for (key, value) in written {
+      if source.read_as.get(&key) != Some(&value) {
+        properties.insert(key, value);
+      }
+    }
+    Value::Object(properties)
+  }
+
+  /// A `Feature` with the id of the source of the first part.
+  fn with_id(parts: &[&Shape], geometry: Value, properties: Value) -> Value {
+    let mut feature = json!({
+      "type": "Feature",
+      "geometry": geometry,
+      "properties": properties,
+    });
+    if let Some(id) = parts[0]
+      .source
+      .as_ref()
+      .and_then(|source| source.id.clone())
+    {
+      feature["id"] = id;
+    }
+    feature
+  }
+
+  /// Converts the layers into a `FeatureCollection`. Invisible and empty shapes are skipped, the
+  /// consecutive parts of a multi geometry become one feature.
+  #[must_use]
+  pub fn feature_collection(&self, layers: &[Layer]) -> Value {
+    let features: Vec<Value> = layers
+      .iter()
+      .flat_map(|layer| {
+        layer
+          .shapes
+          .chunk_by(|a, b| a.part_of.is_some() && a.part_of == b.part_of)
+          .map(move |parts| (&layer.id, parts))
+      })
+      .filter_map(|(id, parts)| {
+        let parts: Vec<&Shape> = parts
+          .iter()
+          .filter(|shape| shape.visible && !shape.coordinates.is_empty())
+          .collect();
+        match parts[..] {
+          [] => None,
+          [shape] => Some(Self::feature(id, shape)),
+          _ => Some(Self::multi_feature(id, &parts)),
+        }
+      })
+      .collect();
+    json!({"type": "FeatureCollection", "features": features})
+  }
+
+  /// Converts a single shape of the layer into a `Feature`.
+  #[must_use]
+  pub fn feature(layer_id: &str, shape: &Shape) -> Value {
+    let parts = [shape];
+    let geometry = Self::geometry_of(&parts).unwrap_or_else(|| Self::geometry(shape));
+    Self::with_id(&parts, geometry, Self::properties(layer_id, shape))
+  }
+
+  /// Converts the parts of a multi geometry into one `Feature` with the properties of the first
+  /// part. Parts of different types become a `GeometryCollection`.
+  fn multi_feature(layer_id: &str, parts: &[&Shape]) -> Value {
+    let properties = Self::properties(layer_id, parts[0]);
+    if let Some(geometry) = Self::geometry_of(parts) {
+      return Self::with_id(parts, geometry, properties);
+    }
+    let geometries: Vec<Value> = parts.iter().map(|shape| Self::geometry(shape)).collect();
+    let kind = geometries[0]["type"].as_str().unwrap_or_default();
+    let geometry = if geometries.iter().all(|g| g["type"] == kind) {
+      let coordinates: Vec<&Value> = geometries.iter().map(|g| &g["coordinates"]).collect();
+      json!({"type": format!("Multi{kind}"), "coordinates": coordinates})
+    } else {
+      json!({"type": "GeometryCollection", "geometries": geometries})
+    };
+    Self::with_id(parts, geometry, properties)
+  }
+}
+
+impl Exporter for GeoJsonExporter {
+  fn export(&self, layers: &[Layer], write: &mut dyn Write) -> Result<()> {
+    serde_json::to_writer(write, &self.feature_collection(layers))?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::map::map_event::Color;
+
+  #[test]
+  fn export_shapes() {
+    let mut layer = Layer::new("test".to_string());
+    layer.shapes = vec![
+      Shape::new(vec![Coordinate {
+        lat: 52.0,
+        lon: 10.0,
+      }])
+      .with_color(Color::Red)
+      .with_label(Some("point".to_string())),
+      Shape::new(vec![
+        Coordinate {
+          lat: 52.0,
+          lon: 10.0,
+        },
+        Coordinate {
+          lat: 53.0,
+          lon: 11.0,
+        },
+      ]),
+      Shape::new(vec![
+        Coordinate {
+          lat: 52.0,
+          lon: 10.0,
+        },
+        Coordinate {
+          lat: 53.0,
+          lon: 11.0,
+        },
+        Coordinate {
+          lat: 53.0,
+          lon: 10.0,
+        },
+      ])
+      .with_fill(FillStyle::Transparent),
+    ];
+    let mut hidden = Shape::new(vec![Coordinate { lat: 1.0, lon: 1.0 }]);
+    hidden.visible = false;
+    layer.shapes.push(hidden);
+
+    let collection = GeoJsonExporter::default().feature_collection(&[layer]);
+    let features = collection["features"].as_array().unwrap();
+    assert_eq!(features.len(), 3);
+    assert_eq!(features[0]["geometry"]["type"], "Point");
+    assert_eq!(features[0]["geometry"]["coordinates"], json!([10.0, 52.0]));
+    assert_eq!(features[0]["properties"]["marker-color"], "#ff0000");
+    assert_eq!(features[0]["properties"]["label"], "point");
+    assert_eq!(features[1]["geometry"]["type"], "LineString");
+    assert_eq!(features[2]["geometry"]["type"], "Polygon");
+    assert_eq!(
+      features[2]["geometry"]["coordinates"][0]
+        .as_array()
+        .unwrap()
+        .len(),
+      4
+    );
+    assert_eq!(features[2]["properties"]["fill-opacity"], 0.2);
+  }
+
+  #[test]
+  fn multi_geometries() {
+    let line = |lat| {
+      Shape::new(vec![
+        Coordinate { lat, lon: 10.0 },
+        Coordinate { lat, lon: 11.0 },
+      ])
+    };
+    let mut layer = Layer::new("test".to_string());
+    layer.shapes = vec![
+      line(1.).with_part_of(Some(0)),
+      line(2.).with_part_of(Some(0)),
+      line(3.).with_part_of(Some(2)),
+      line(4.),
+      line(5.),
+    ];
+    let collection = GeoJsonExporter::default().feature_collection(&[layer]);
+    let features = collection["features"].as_array().unwrap();
+    assert_eq!(features.len(), 4);
+    assert_eq!(features[0]["geometry"]["type"], "MultiLineString");
+    assert_eq!(
+      features[0]["geometry"]["coordinates"][1],
+      json!([[10.0, 2.0], [11.0, 2.0]])
+    );
+    assert_eq!(features[1]["geometry"]["type"], "LineString");
+  }
+}
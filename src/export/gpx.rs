@@ -0,0 +1,133 @@
+use std::io::Write;
+
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::map::{
+  coordinates::Coordinate,
+  map_event::{FillStyle, Layer, Shape},
+};
+
+use super::{escape_xml, Exporter};
+
+/// Writes layers as GPX 1.1. Points become waypoints, lines and polygons become tracks. The
+/// elevations and timestamps of the coordinates are kept.
+#[derive(Debug, Default)]
+pub struct GpxExporter {}
+
+impl GpxExporter {
+  fn name(write: &mut dyn Write, shape: &Shape, indent: &str) -> Result<()> {
+    if let Some(label) = &shape.label {
+      writeln!(write, "{indent}<name>{}</name>", escape_xml(label))?;
+    }
+    Ok(())
+  }
+
+  /// The elevation and time of the n-th coordinate, which come first in a waypoint or trackpoint.
+  fn elevation_and_time(
+    write: &mut dyn Write,
+    shape: &Shape,
+    n: usize,
+    indent: &str,
+  ) -> Result<()> {
+    if let Some(elevation) = shape.elevations.get(n) {
+      writeln!(write, "{indent}<ele>{elevation}</ele>")?;
+    }
+    let time = shape
+      .timestamps
+      .get(n)
+      .and_then(|t| DateTime::<Utc>::from_timestamp_millis(*t));
+    if let Some(time) = time {
+      let time = time.to_rfc3339_opts(SecondsFormat::Millis, true);
+      writeln!(write, "{indent}<time>{time}</time>")?;
+    }
+    Ok(())
+  }
+
+  fn waypoint(write: &mut dyn Write, shape: &Shape) -> Result<()> {
+    let Coordinate { lat, lon } = shape.coordinates[0];
+    writeln!(write, r#"  <wpt lat="{lat}" lon="{lon}">"#)?;
+    Self::elevation_and_time(write, shape, 0, "    ")?;
+    Self::name(write, shape, "    ")?;
+    writeln!(write, "  </wpt>")?;
+    Ok(())
+  }
+
+  fn track(write: &mut dyn Write, shape: &Shape) -> Result<()> {
+    writeln!(write, "  <trk>")?;
+    Self::name(write, shape, "    ")?;
+    writeln!(write, "    <trkseg>")?;
+    let closing = (shape.style.fill != FillStyle::NoFill
+      && shape.coordinates.first() != shape.coordinates.last())
+    .then_some(shape.coordinates[0]);
+    for (n, Coordinate { lat, lon }) in shape.coordinates.iter().chain(closing.iter()).enumerate() {
+      if shape.elevations.get(n).is_none() && shape.timestamps.get(n).is_none() {
+        writeln!(write, r#"      <trkpt lat="{lat}" lon="{lon}"/>"#)?;
+        continue;
+      }
+      writeln!(write, r#"      <trkpt lat="{lat}" lon="{lon}">"#)?;
+      Self::elevation_and_time(write, shape, n, "        ")?;
+      writeln!(write, "      </trkpt>")?;
+    }
+    writeln!(write, "    </trkseg>")?;
+    writeln!(write, "  </trk>")?;
+    Ok(())
+  }
+}
+
+impl Exporter for GpxExporter {
+  fn export(&self, layers: &[Layer], write: &mut dyn Write) -> Result<()> {
+    let shapes: Vec<&Shape> = layers
+      .iter()
+      .flat_map(|l| l.shapes.iter())
+      .filter(|s| s.visible && !s.coordinates.is_empty())
+      .collect();
+
+    writeln!(write, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+      write,
+      r#"<gpx version="1.1" creator="mapvas" xmlns="http://www.topografix.com/GPX/1/1">"#
+    )?;
+    // The schema requires all waypoints before the tracks.
+    for shape in shapes.iter().filter(|s| s.coordinates.len() == 1) {
+      Self::waypoint(write, shape)?;
+    }
+    for shape in shapes.iter().filter(|s| s.coordinates.len() > 1) {
+      Self::track(write, shape)?;
+    }
+    writeln!(write, "</gpx>")?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn track_times() {
+    let c = |lat, lon| Coordinate { lat, lon };
+    let mut layer = Layer::new("track".to_string());
+    layer.shapes = vec![
+      Shape::new(vec![c(52.0, 10.0), c(52.1, 10.1)])
+        .with_timestamps(vec![1_700_000_000_000, 1_700_000_001_500])
+        .with_elevations(vec![35., 36.5]),
+      Shape::new(vec![c(52.0, 10.0), c(52.1, 10.1), c(52.0, 10.1)])
+        .with_fill(FillStyle::Transparent),
+    ];
+    let mut written = vec![];
+    GpxExporter::default()
+      .export(&[layer], &mut written)
+      .unwrap();
+    let gpx = String::from_utf8(written).unwrap();
+    assert!(gpx.contains(concat!(
+      "      <trkpt lat=\"52\" lon=\"10\">\n",
+      "        <ele>35</ele>\n",
+      "        <time>2023-11-14T22:13:20.000Z</time>\n",
+      "      </trkpt>\n"
+    )));
+    assert!(gpx.contains("<time>2023-11-14T22:13:21.500Z</time>"));
+    assert_eq!(gpx.matches("<trkpt").count(), 6);
+    assert_eq!(gpx.matches("<time>").count(), 2);
+  }
+}
@@ -0,0 +1,133 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::map::{
+  coordinates::Coordinate,
+  map_event::{Color, FillStyle, Layer, Shape},
+};
+
+use super::{escape_xml, Exporter};
+
+/// Writes layers as KML document with one folder per layer and the style of each shape inlined.
+#[derive(Debug, Default)]
+pub struct KmlExporter {}
+
+impl KmlExporter {
+  /// KML colors are written as aabbggrr.
+  fn color(color: Color, alpha: u8) -> String {
+    let hex = color.to_hex();
+    format!("{alpha:02x}{}{}{}", &hex[5..7], &hex[3..5], &hex[1..3])
+  }
+
+  fn coordinates(coordinates: &[Coordinate]) -> String {
+    coordinates
+      .iter()
+      .map(|c| format!("{},{}", c.lon, c.lat))
+      .collect::<Vec<_>>()
+      .join(" ")
+  }
+
+  /// The properties and the radius of circles as `Data`.
+  fn extended_data(write: &mut dyn Write, shape: &Shape) -> Result<()> {
+    let radius = shape
+      .radius
+      .map(|radius| ("radius".to_string(), radius.to_string()));
+    let data: Vec<(String, String)> = shape.properties.iter().cloned().chain(radius).collect();
+    if data.is_empty() {
+      return Ok(());
+    }
+    writeln!(write, "        <ExtendedData>")?;
+    for (name, value) in data {
+      writeln!(
+        write,
+        r#"          <Data name="{}"><value>{}</value></Data>"#,
+        escape_xml(&name),
+        escape_xml(&value)
+      )?;
+    }
+    writeln!(write, "        </ExtendedData>")?;
+    Ok(())
+  }
+
+  fn placemark(write: &mut dyn Write, shape: &Shape) -> Result<()> {
+    writeln!(write, "      <Placemark>")?;
+    if let Some(label) = &shape.label {
+      writeln!(write, "        <name>{}</name>", escape_xml(label))?;
+    }
+    let color = shape.style.color;
+    let (fill_alpha, fill) = match shape.style.fill {
+      FillStyle::NoFill => (0, 0),
+      FillStyle::Transparent => (50, 1),
+      FillStyle::Solid => (255, 1),
+    };
+    writeln!(
+      write,
+      "        <Style><IconStyle><color>{c}</color></IconStyle><LineStyle><color>{c}</color><width>3</width></LineStyle><PolyStyle><color>{}</color><fill>{fill}</fill></PolyStyle></Style>",
+      Self::color(color, fill_alpha),
+      c = Self::color(color, 255),
+    )?;
+
+    Self::extended_data(write, shape)?;
+
+    let mut coordinates = shape.coordinates.clone();
+    match (coordinates.len(), shape.style.fill) {
+      (1, _) => writeln!(
+        write,
+        "        <Point><coordinates>{}</coordinates></Point>",
+        Self::coordinates(&coordinates)
+      )?,
+      (_, FillStyle::NoFill) => writeln!(
+        write,
+        "        <LineString><coordinates>{}</coordinates></LineString>",
+        Self::coordinates(&coordinates)
+      )?,
+      _ => {
+        if coordinates.first() != coordinates.last() {
+          coordinates.push(coordinates[0]);
+        }
+        writeln!(
+          write,
+          "        <Polygon><outerBoundaryIs><LinearRing><coordinates>{}</coordinates></LinearRing></outerBoundaryIs></Polygon>",
+          Self::coordinates(&coordinates)
+        )?;
+      }
+    }
+    writeln!(write, "      </Placemark>")?;
+    Ok(())
+  }
+}
+
+impl Exporter for KmlExporter {
+  fn export(&self, layers: &[Layer], write: &mut dyn Write) -> Result<()> {
+    writeln!(write, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(write, r#"<kml xmlns="http://www.opengis.net/kml/2.2">"#)?;
+    writeln!(write, "  <Document>")?;
+    for layer in layers {
+      writeln!(write, "    <Folder>")?;
+      writeln!(write, "      <name>{}</name>", escape_xml(&layer.id))?;
+      for shape in layer
+        .shapes
+        .iter()
+        .filter(|s| s.visible && !s.coordinates.is_empty())
+      {
+        Self::placemark(write, shape)?;
+      }
+      writeln!(write, "    </Folder>")?;
+    }
+    writeln!(write, "  </Document>")?;
+    writeln!(write, "</kml>")?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn kml_colors() {
+    assert_eq!(KmlExporter::color(Color::Red, 255), "ff0000ff");
+    assert_eq!(KmlExporter::color(Color::Brown, 50), "32004c99");
+  }
+}
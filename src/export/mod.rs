@@ -0,0 +1,56 @@
+mod geojson;
+mod gpx;
+mod kml;
+mod wkt;
+use std::{
+  fs::File,
+  io::{BufWriter, Write},
+  path::Path,
+};
+
+use anyhow::Result;
+pub use geojson::GeoJsonExporter;
+pub use gpx::GpxExporter;
+pub use kml::KmlExporter;
+pub use wkt::shape_to_wkt;
+
+use crate::map::map_event::{ExportFormat, Layer};
+
+/// An interface for writers that serialize layers into a file format.
+pub trait Exporter {
+  /// Writes the given layers to `write`.
+  /// * `layers` - The layers to export in drawing order.
+  fn export(&self, layers: &[Layer], write: &mut dyn Write) -> Result<()>;
+}
+
+/// Chooses the exporter for the given format and writes the layers to `path`.
+///
+/// # Errors
+/// If the file cannot be created or written.
+pub fn export_to_file(format: ExportFormat, layers: &[Layer], path: &Path) -> Result<()> {
+  let exporter: Box<dyn Exporter> = match format {
+    ExportFormat::GeoJson => Box::new(GeoJsonExporter::default()),
+    ExportFormat::Gpx => Box::new(GpxExporter::default()),
+    ExportFormat::Kml => Box::new(KmlExporter::default()),
+  };
+  let mut writer = BufWriter::new(File::create(path)?);
+  exporter.export(layers, &mut writer)?;
+  writer.flush()?;
+  Ok(())
+}
+
+/// Escapes the characters with a special meaning in xml text and attributes.
+fn escape_xml(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&apos;"),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
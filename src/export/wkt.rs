@@ -0,0 +1,53 @@
+use crate::map::{
+  coordinates::Coordinate,
+  map_event::{FillStyle, Shape},
+};
+
+fn positions(coordinates: &[Coordinate]) -> String {
+  coordinates
+    .iter()
+    .map(|c| format!("{} {}", c.lon, c.lat))
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+/// The well-known text of a shape. Filled shapes are polygons like in the GeoJSON export.
+#[must_use]
+pub fn shape_to_wkt(shape: &Shape) -> String {
+  match (shape.coordinates.len(), shape.style.fill) {
+    (0, _) => "GEOMETRYCOLLECTION EMPTY".to_string(),
+    (1, _) => format!("POINT ({})", positions(&shape.coordinates)),
+    (_, FillStyle::NoFill) => format!("LINESTRING ({})", positions(&shape.coordinates)),
+    _ => {
+      let mut ring = shape.coordinates.clone();
+      if ring.first() != ring.last() {
+        ring.push(ring[0]);
+      }
+      format!("POLYGON (({}))", positions(&ring))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn shapes() {
+    let a = Coordinate {
+      lat: 52.5,
+      lon: 13.25,
+    };
+    let b = Coordinate { lat: 53., lon: 14. };
+    let c = Coordinate { lat: 53., lon: 13. };
+    assert_eq!(shape_to_wkt(&Shape::new(vec![a])), "POINT (13.25 52.5)");
+    assert_eq!(
+      shape_to_wkt(&Shape::new(vec![a, b])),
+      "LINESTRING (13.25 52.5, 14 53)"
+    );
+    assert_eq!(
+      shape_to_wkt(&Shape::new(vec![a, b, c]).with_fill(FillStyle::Solid)),
+      "POLYGON ((13.25 52.5, 14 53, 13 53, 13.25 52.5))"
+    );
+  }
+}
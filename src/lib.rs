@@ -1,3 +1,6 @@
+pub mod config;
+pub mod export;
 pub mod map;
 pub mod parser;
+pub mod recent;
 pub mod remote;
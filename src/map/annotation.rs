@@ -0,0 +1,148 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+use super::coordinates::{simplify, PixelPosition};
+
+/// The layer annotations are drawn in.
+pub const LAYER: &str = "Annotations";
+/// The vertices of a drawn circle.
+const CIRCLE_VERTICES: usize = 64;
+
+/// The tools of `:draw`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+  /// Follows the mouse while it is dragged.
+  Pen,
+  /// Dragged from the center to the radius.
+  Circle,
+  /// Dragged from one corner to the other.
+  Rectangle,
+}
+
+impl FromStr for Tool {
+  type Err = ();
+  fn from_str(input: &str) -> Result<Self, Self::Err> {
+    match input {
+      "pen" | "freehand" => Ok(Tool::Pen),
+      "circle" => Ok(Tool::Circle),
+      "rectangle" | "rect" => Ok(Tool::Rectangle),
+      _ => Err(()),
+    }
+  }
+}
+
+/// An annotation while it is drawn, in canvas coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sketch {
+  tool: Tool,
+  positions: Vec<PixelPosition>,
+}
+
+impl Sketch {
+  #[must_use]
+  pub fn new(tool: Tool, start: PixelPosition) -> Self {
+    Self {
+      tool,
+      positions: vec![start],
+    }
+  }
+
+  /// Adds the position of the mouse to a pen stroke or moves the second point of the others.
+  pub fn extend(&mut self, position: PixelPosition) {
+    match self.tool {
+      Tool::Pen => self.positions.push(position),
+      Tool::Circle | Tool::Rectangle => {
+        self.positions.truncate(1);
+        self.positions.push(position);
+      }
+    }
+  }
+
+  /// The outline of the annotation and if it is closed, `None` while it has no extent.
+  #[must_use]
+  pub fn outline(&self) -> Option<(Vec<PixelPosition>, bool)> {
+    let (&start, &end) = (self.positions.first()?, self.positions.last()?);
+    if start == end && self.positions.len() < 3 {
+      return None;
+    }
+    Some(match self.tool {
+      Tool::Pen => (self.positions.clone(), false),
+      Tool::Circle => {
+        let radius = start.sq_dist(&end).sqrt();
+        #[allow(clippy::cast_precision_loss)]
+        let circle = (0..CIRCLE_VERTICES)
+          .map(|i| {
+            let angle = 2. * PI * i as f32 / CIRCLE_VERTICES as f32;
+            PixelPosition {
+              x: start.x + radius * angle.cos(),
+              y: start.y + radius * angle.sin(),
+            }
+          })
+          .collect();
+        (circle, true)
+      }
+      Tool::Rectangle => (
+        vec![
+          start,
+          PixelPosition {
+            x: end.x,
+            y: start.y,
+          },
+          end,
+          PixelPosition {
+            x: start.x,
+            y: end.y,
+          },
+        ],
+        true,
+      ),
+    })
+  }
+
+  /// The outline of the finished annotation. Pen strokes are simplified by the tolerance, so
+  /// every mouse event does not become a vertex.
+  #[must_use]
+  pub fn finish(&self, tolerance: f32) -> Option<(Vec<PixelPosition>, bool)> {
+    let (outline, closed) = self.outline()?;
+    Some(match self.tool {
+      Tool::Pen => (simplify(&outline, tolerance), closed),
+      Tool::Circle | Tool::Rectangle => (outline, closed),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn p(x: f32, y: f32) -> PixelPosition {
+    PixelPosition { x, y }
+  }
+
+  #[test]
+  fn sketches() {
+    let mut pen = Sketch::new(Tool::Pen, p(0., 0.));
+    assert_eq!(pen.finish(0.1), None);
+    for x in 1..=10 {
+      #[allow(clippy::cast_precision_loss)]
+      pen.extend(p(x as f32, 0.));
+    }
+    assert_eq!(pen.finish(0.1), Some((vec![p(0., 0.), p(10., 0.)], false)));
+
+    let mut rectangle = Sketch::new(Tool::Rectangle, p(0., 0.));
+    rectangle.extend(p(5., 5.));
+    rectangle.extend(p(2., 3.));
+    let (corners, closed) = rectangle.finish(0.1).unwrap();
+    assert!(closed);
+    assert_eq!(corners, vec![p(0., 0.), p(2., 0.), p(2., 3.), p(0., 3.)]);
+
+    let mut circle = Sketch::new(Tool::Circle, p(1., 1.));
+    circle.extend(p(4., 5.));
+    let (outline, _) = circle.finish(0.1).unwrap();
+    assert_eq!(outline.len(), CIRCLE_VERTICES);
+    assert!(outline
+      .iter()
+      .all(|v| (v.sq_dist(&p(1., 1.)) - 25.).abs() < 1e-3));
+    assert_eq!("rect".parse(), Ok(Tool::Rectangle));
+  }
+}
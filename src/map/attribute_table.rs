@@ -0,0 +1,163 @@
+use std::cmp::Ordering;
+
+use anyhow::{anyhow, Result};
+
+/// The column of the label in front of the properties.
+const LABEL: &str = "label";
+
+/// A row of the attribute table: the index of the element in its layer and a value per column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+  pub index: usize,
+  pub values: Vec<String>,
+}
+
+/// The properties of the elements of a layer as table for `:table`, one row per element and one
+/// column per property.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeTable {
+  pub layer: String,
+  pub columns: Vec<String>,
+  rows: Vec<Row>,
+  /// The first shown row.
+  offset: usize,
+}
+
+impl AttributeTable {
+  /// The table of elements given as index, label, and properties. The columns are the label and
+  /// the properties in the order they first appear.
+  #[must_use]
+  pub fn new<'a>(
+    layer: String,
+    elements: impl IntoIterator<Item = (usize, Option<&'a str>, &'a [(String, String)])>,
+  ) -> Self {
+    let elements: Vec<_> = elements.into_iter().collect();
+    let mut columns = vec![LABEL.to_string()];
+    for (key, _) in elements
+      .iter()
+      .flat_map(|(_, _, properties)| properties.iter())
+    {
+      if !columns.contains(key) {
+        columns.push(key.clone());
+      }
+    }
+    let rows = elements
+      .into_iter()
+      .map(|(index, label, properties)| Row {
+        index,
+        values: columns
+          .iter()
+          .map(|column| match column.as_str() {
+            LABEL => label
+              .and_then(|l| l.lines().next())
+              .unwrap_or_default()
+              .to_string(),
+            _ => properties
+              .iter()
+              .find(|(key, _)| key == column)
+              .map(|(_, value)| value.clone())
+              .unwrap_or_default(),
+          })
+          .collect(),
+      })
+      .collect();
+    Self {
+      layer,
+      columns,
+      rows,
+      offset: 0,
+    }
+  }
+
+  /// Sorts the rows by the column, numbers by their value and before other text.
+  ///
+  /// # Errors
+  /// If the table has no such column.
+  pub fn sort(&mut self, column: &str, descending: bool) -> Result<()> {
+    let i = self
+      .columns
+      .iter()
+      .position(|c| c.eq_ignore_ascii_case(column))
+      .ok_or_else(|| anyhow!("No column {column} in {}", self.layer))?;
+    let compare = |a: &str, b: &str| match (a.parse::<f64>(), b.parse::<f64>()) {
+      (Ok(a), Ok(b)) => a.total_cmp(&b),
+      (Ok(_), Err(_)) => Ordering::Less,
+      (Err(_), Ok(_)) => Ordering::Greater,
+      (Err(_), Err(_)) => a.to_lowercase().cmp(&b.to_lowercase()),
+    };
+    self.rows.sort_by(|a, b| {
+      let order = compare(&a.values[i], &b.values[i]);
+      if descending {
+        order.reverse()
+      } else {
+        order
+      }
+    });
+    self.offset = 0;
+    Ok(())
+  }
+
+  #[must_use]
+  pub fn len(&self) -> usize {
+    self.rows.len()
+  }
+
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.rows.is_empty()
+  }
+
+  /// The shown rows, at most `count` from the first shown one.
+  #[must_use]
+  pub fn shown(&self, count: usize) -> &[Row] {
+    let end = (self.offset + count).min(self.rows.len());
+    &self.rows[self.offset..end]
+  }
+
+  /// Moves the shown rows by `by` rows while `count` rows are shown.
+  pub fn scroll(&mut self, by: isize, count: usize) {
+    let last = self.rows.len().saturating_sub(count);
+    self.offset = self.offset.saturating_add_signed(by).min(last);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn properties(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+      .iter()
+      .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+      .collect()
+  }
+
+  #[test]
+  fn table() {
+    let a = properties(&[("speed", "80"), ("kind", "bus")]);
+    let b = properties(&[("speed", "9.5")]);
+    let c = properties(&[("kind", "tram"), ("speed", "n/a")]);
+    let mut table = AttributeTable::new(
+      "vehicles".to_string(),
+      [
+        (0, Some("Bus 7\nlate"), a.as_slice()),
+        (1, None, b.as_slice()),
+        (2, Some("Tram"), c.as_slice()),
+      ],
+    );
+    assert_eq!(table.columns, vec!["label", "speed", "kind"]);
+    assert_eq!(table.shown(1)[0].values, vec!["Bus 7", "80", "bus"]);
+    table.sort("Speed", false).unwrap();
+    let order =
+      |table: &AttributeTable| table.shown(10).iter().map(|r| r.index).collect::<Vec<_>>();
+    assert_eq!(order(&table), vec![1, 0, 2]);
+    table.sort("kind", true).unwrap();
+    assert_eq!(order(&table), vec![2, 0, 1]);
+    assert!(table.sort("missing", false).is_err());
+    table.scroll(5, 2);
+    assert_eq!(table.shown(2).len(), 2);
+    assert_eq!(table.shown(2)[0].index, 0);
+    table.scroll(-9, 2);
+    assert_eq!(table.shown(2)[0].index, 2);
+  }
+}
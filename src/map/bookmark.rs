@@ -0,0 +1,155 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+use super::map_event::Camera;
+
+/// A named view of the map.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+  pub name: String,
+  pub camera: Camera,
+}
+
+/// The bookmarks in the order they were added. They are stored in the file given by
+/// `MAPVAS_BOOKMARKS`, by default `bookmarks.json` in the [config directory](config::dir).
+#[derive(Debug, Default)]
+pub struct Bookmarks {
+  path: Option<PathBuf>,
+  pub bookmarks: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+  /// Reads the stored bookmarks. Missing or broken bookmarks are empty.
+  #[must_use]
+  pub fn load() -> Self {
+    let path = config::path("MAPVAS_BOOKMARKS", "bookmarks.json");
+    let bookmarks = config::load(path.as_deref());
+    Self { path, bookmarks }
+  }
+
+  /// Adds the bookmark or moves the one with the same name.
+  pub fn add(&mut self, name: String, camera: Camera) {
+    match self.bookmarks.iter_mut().find(|b| b.name == name) {
+      Some(bookmark) => bookmark.camera = camera,
+      None => self.bookmarks.push(Bookmark { name, camera }),
+    }
+  }
+
+  #[must_use]
+  pub fn get(&self, name: &str) -> Option<&Bookmark> {
+    self.bookmarks.iter().find(|b| b.name == name)
+  }
+
+  /// The names with their number for the keys 1 to 9.
+  #[must_use]
+  pub fn numbered(&self) -> Vec<String> {
+    self
+      .bookmarks
+      .iter()
+      .enumerate()
+      .map(|(i, b)| format!("{} {}", i + 1, b.name))
+      .collect()
+  }
+
+  /// Writes the bookmarks back.
+  ///
+  /// # Errors
+  /// If the bookmarks cannot be written.
+  pub fn save(&self) -> Result<()> {
+    if let Some(path) = &self.path {
+      config::save(path, &self.bookmarks)?;
+    }
+    Ok(())
+  }
+}
+
+/// Views stored under the letters a to z like marks in vim. `'` is the view before the last jump.
+/// Marks only live as long as the map.
+#[derive(Debug, Default)]
+pub struct Marks {
+  marks: BTreeMap<char, Camera>,
+}
+
+impl Marks {
+  /// The mark before the last jump.
+  pub const PREVIOUS: char = '\'';
+
+  /// Stores the view under the letter. Returns false for other characters.
+  pub fn set(&mut self, letter: char, camera: Camera) -> bool {
+    let valid = letter.is_ascii_lowercase() || letter == Self::PREVIOUS;
+    if valid {
+      self.marks.insert(letter, camera);
+    }
+    valid
+  }
+
+  /// The view of the mark. The current view becomes the previous mark.
+  pub fn jump(&mut self, letter: char, current: Camera) -> Option<Camera> {
+    let target = self.marks.get(&letter).copied()?;
+    self.marks.insert(Self::PREVIOUS, current);
+    Some(target)
+  }
+
+  /// One line per mark with its position and zoom level.
+  #[must_use]
+  pub fn list(&self) -> Vec<String> {
+    self
+      .marks
+      .iter()
+      .map(|(letter, camera)| {
+        format!(
+          "{letter} {:.5}, {:.5} z{:.0}",
+          camera.center.lat, camera.center.lon, camera.zoom
+        )
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::map::coordinates::Coordinate;
+
+  #[test]
+  fn replace_by_name() {
+    let camera = |lat| Camera {
+      center: Coordinate { lat, lon: 13.4 },
+      zoom: 12.,
+      rotation: 0.,
+    };
+    let mut bookmarks = Bookmarks::default();
+    bookmarks.add("home".to_string(), camera(52.5));
+    bookmarks.add("work".to_string(), camera(48.1));
+    bookmarks.add("home".to_string(), camera(52.4));
+    assert_eq!(bookmarks.numbered(), vec!["1 home", "2 work"]);
+    assert_eq!(bookmarks.get("home").unwrap().camera, camera(52.4));
+    assert!(bookmarks.get("garden").is_none());
+  }
+
+  #[test]
+  fn marks() {
+    let camera = |lat| Camera {
+      center: Coordinate { lat, lon: 13.4 },
+      zoom: 12.,
+      rotation: 0.,
+    };
+    let mut marks = Marks::default();
+    assert!(marks.set('a', camera(52.5)));
+    assert!(!marks.set('A', camera(52.5)));
+    assert!(marks.jump('b', camera(48.1)).is_none());
+    assert_eq!(marks.jump('a', camera(48.1)), Some(camera(52.5)));
+    assert_eq!(
+      marks.jump(Marks::PREVIOUS, camera(52.5)),
+      Some(camera(48.1))
+    );
+    assert_eq!(
+      marks.list(),
+      vec!["' 52.50000, 13.40000 z12", "a 52.50000, 13.40000 z12"]
+    );
+  }
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+use super::coordinates::PixelPosition;
+
+/// Points that are drawn as one marker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cluster {
+  /// The mean of all points in the cluster.
+  pub center: PixelPosition,
+  pub count: usize,
+}
+
+/// Aggregates points that fall into the same grid cell.
+/// * `cell_size` - The edge length of a grid cell in the unit of the points.
+#[must_use]
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+pub fn grid_clusters<I: IntoIterator<Item = PixelPosition>>(
+  points: I,
+  cell_size: f32,
+) -> Vec<Cluster> {
+  let mut cells: HashMap<(i64, i64), (f64, f64, usize)> = HashMap::new();
+  for p in points {
+    let cell = (
+      (p.x / cell_size).floor() as i64,
+      (p.y / cell_size).floor() as i64,
+    );
+    let entry = cells.entry(cell).or_insert((0., 0., 0));
+    entry.0 += f64::from(p.x);
+    entry.1 += f64::from(p.y);
+    entry.2 += 1;
+  }
+
+  let mut clusters: Vec<Cluster> = cells
+    .into_values()
+    .map(|(x, y, count)| Cluster {
+      center: PixelPosition {
+        x: (x / count as f64) as f32,
+        y: (y / count as f64) as f32,
+      },
+      count,
+    })
+    .collect();
+  clusters.sort_by(|a, b| b.count.cmp(&a.count));
+  clusters
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn clusters_by_cell() {
+    let points = [
+      PixelPosition { x: 1., y: 1. },
+      PixelPosition { x: 3., y: 3. },
+      PixelPosition { x: 9., y: 9. },
+      PixelPosition { x: 15., y: 1. },
+    ];
+    let clusters = grid_clusters(points, 10.);
+    assert_eq!(clusters.len(), 2);
+    assert_eq!(
+      clusters[0],
+      Cluster {
+        center: PixelPosition {
+          x: 13. / 3.,
+          y: 13. / 3.
+        },
+        count: 3
+      }
+    );
+    assert_eq!(clusters[1].count, 1);
+    assert_eq!(grid_clusters(points, 1.).len(), 4);
+  }
+}
@@ -0,0 +1,280 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::{config, recent::RecentFiles};
+
+/// How many commands the history keeps.
+const MAX_HISTORY: usize = 1000;
+
+/// The command line opened with `:`. Executed commands are kept in the file given by
+/// `MAPVAS_HISTORY`, by default `history` in the [config directory](config::dir), one command
+/// per line. Up and down recall them and ctrl+R searches them backwards like in a shell. Tab
+/// completes the file of `e <file>`.
+#[derive(Debug, Default)]
+pub struct CommandLine {
+  path: Option<PathBuf>,
+  history: Vec<String>,
+  input: String,
+  /// The index of the recalled command in the history.
+  recalled: Option<usize>,
+  /// The text searched with ctrl+R.
+  search: Option<String>,
+}
+
+impl CommandLine {
+  /// An empty command line with the stored history. A missing history is empty.
+  #[must_use]
+  pub fn load() -> Self {
+    let path = config::path("MAPVAS_HISTORY", "history");
+    let history = path
+      .as_ref()
+      .and_then(|p| std::fs::read_to_string(p).ok())
+      .map(|history| history.lines().map(String::from).collect())
+      .unwrap_or_default();
+    Self {
+      path,
+      history,
+      ..Self::default()
+    }
+  }
+
+  /// The text shown in the text bar.
+  #[must_use]
+  pub fn prompt(&self) -> String {
+    match &self.search {
+      Some(search) => format!("(reverse-i-search)`{search}': {}", self.input),
+      None => format!(":{}", self.input),
+    }
+  }
+
+  pub fn push(&mut self, c: char) {
+    match &mut self.search {
+      Some(search) => {
+        search.push(c);
+        self.find(self.recalled.map_or(self.history.len(), |i| i + 1));
+      }
+      None => {
+        self.input.push(c);
+        self.recalled = None;
+      }
+    }
+  }
+
+  pub fn backspace(&mut self) {
+    match &mut self.search {
+      Some(search) => {
+        search.pop();
+        self.find(self.history.len());
+      }
+      None => {
+        self.input.pop();
+        self.recalled = None;
+      }
+    }
+  }
+
+  /// Shows the command before the recalled one.
+  pub fn previous(&mut self) {
+    self.search = None;
+    let index = match self.recalled {
+      Some(0) => return,
+      Some(i) => i - 1,
+      None => match self.history.len().checked_sub(1) {
+        Some(last) => last,
+        None => return,
+      },
+    };
+    self.recall(Some(index));
+  }
+
+  /// Shows the command after the recalled one, after the last one the input is empty again.
+  pub fn next(&mut self) {
+    self.search = None;
+    match self.recalled {
+      Some(i) if i + 1 < self.history.len() => self.recall(Some(i + 1)),
+      Some(_) => self.recall(None),
+      None => (),
+    }
+  }
+
+  /// Starts the search or goes to the next older match.
+  pub fn search_backwards(&mut self) {
+    if self.search.is_none() {
+      self.search = Some(String::new());
+      return;
+    }
+    self.find(self.recalled.unwrap_or(self.history.len()));
+  }
+
+  /// Recalls the newest command before the index containing the searched text.
+  fn find(&mut self, before: usize) {
+    let Some(search) = &self.search else {
+      return;
+    };
+    let found = self.history[..before.min(self.history.len())]
+      .iter()
+      .rposition(|command| command.contains(search.as_str()));
+    if let Some(index) = found {
+      self.recall(Some(index));
+    }
+  }
+
+  fn recall(&mut self, index: Option<usize>) {
+    self.recalled = index;
+    self.input = index
+      .and_then(|i| self.history.get(i))
+      .cloned()
+      .unwrap_or_default();
+  }
+
+  /// Completes the file of `e <file>` with the recent files and the files in its directory as far
+  /// as they agree.
+  pub fn complete(&mut self) {
+    if self.search.is_some() {
+      return;
+    }
+    let Some(start) = self.input.strip_prefix("e ").map(str::trim_start) else {
+      return;
+    };
+    let recent = RecentFiles::load().files.into_iter();
+    let paths = recent.map(|file| file.path.to_string_lossy().into_owned());
+    if let Some(completed) = common_start(&completions(start, paths)) {
+      if completed.len() > start.len() {
+        self.input = format!("e {completed}");
+        self.recalled = None;
+      }
+    }
+  }
+
+  /// Takes the command and adds it to the history unless it repeats the last one.
+  pub fn submit(&mut self) -> String {
+    let command = std::mem::take(&mut self.input).trim().to_string();
+    self.recalled = None;
+    self.search = None;
+    if !command.is_empty() && self.history.last() != Some(&command) {
+      self.history.push(command.clone());
+      let excess = self.history.len().saturating_sub(MAX_HISTORY);
+      self.history.drain(..excess);
+    }
+    command
+  }
+
+  /// Writes the history back.
+  ///
+  /// # Errors
+  /// If the history cannot be written.
+  pub fn save(&self) -> Result<()> {
+    if let Some(path) = &self.path {
+      let mut history = self.history.join("\n");
+      history.push('\n');
+      config::write(path, &history)?;
+    }
+    Ok(())
+  }
+}
+
+/// The recent files and the files in the directory of the start that begin with it. Directories
+/// end with a separator.
+fn completions(start: &str, recent: impl Iterator<Item = String>) -> Vec<String> {
+  let mut completions: Vec<String> = recent.filter(|path| path.starts_with(start)).collect();
+  let (dir, name) = match start.rfind(std::path::is_separator) {
+    Some(i) => start.split_at(i + 1),
+    None => ("", start),
+  };
+  let entries = std::fs::read_dir(if dir.is_empty() { "." } else { dir });
+  for entry in entries.into_iter().flatten().flatten() {
+    let file_name = entry.file_name().to_string_lossy().into_owned();
+    if file_name.starts_with(name) {
+      let separator = if entry.path().is_dir() {
+        std::path::MAIN_SEPARATOR_STR
+      } else {
+        ""
+      };
+      completions.push(format!("{dir}{file_name}{separator}"));
+    }
+  }
+  completions
+}
+
+/// The longest start all the texts share.
+fn common_start(texts: &[String]) -> Option<String> {
+  let (first, rest) = texts.split_first()?;
+  let mut common = first.as_str();
+  for text in rest {
+    let len = common
+      .char_indices()
+      .zip(text.chars())
+      .find(|((_, a), b)| a != b)
+      .map_or(common.len().min(text.len()), |((i, _), _)| i);
+    common = &common[..len];
+  }
+  Some(common.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn command_line(history: &[&str]) -> CommandLine {
+    CommandLine {
+      history: history.iter().map(ToString::to_string).collect(),
+      ..CommandLine::default()
+    }
+  }
+
+  #[test]
+  fn recall() {
+    let mut line = command_line(&["goto 52.5,13.4", "marks"]);
+    line.previous();
+    line.previous();
+    line.previous();
+    assert_eq!(line.prompt(), ":goto 52.5,13.4");
+    line.next();
+    line.next();
+    assert_eq!(line.prompt(), ":");
+    line.previous();
+    assert_eq!(line.submit(), "marks");
+    assert_eq!(line.history.len(), 2);
+  }
+
+  #[test]
+  fn complete_files() {
+    let dir = std::env::temp_dir().join(format!("mapvas_complete_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("tracks")).unwrap();
+    for name in ["track_a.gpx", "track_b.gpx", "notes.txt"] {
+      std::fs::write(dir.join(name), "").unwrap();
+    }
+    let start = format!("{}/tr", dir.display());
+    let mut found = completions(&start, std::iter::empty());
+    found.sort();
+    assert_eq!(
+      found,
+      ["track_a.gpx", "track_b.gpx", "tracks/"].map(|name| format!("{}/{name}", dir.display()))
+    );
+    assert_eq!(
+      common_start(&found),
+      Some(format!("{}/track", dir.display()))
+    );
+    let start = format!("{}/n", dir.display());
+    let recent = std::iter::once(format!("{}/nmea.log", dir.display()));
+    assert_eq!(completions(&start, recent).len(), 2);
+    assert_eq!(common_start(&[]), None);
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn reverse_search() {
+    let mut line = command_line(&["goto 48.1,11.6", "marks", "goto 52.5,13.4"]);
+    line.search_backwards();
+    line.push('g');
+    line.push('o');
+    assert_eq!(line.prompt(), "(reverse-i-search)`go': goto 52.5,13.4");
+    line.search_backwards();
+    assert_eq!(line.prompt(), "(reverse-i-search)`go': goto 48.1,11.6");
+    line.search_backwards();
+    assert_eq!(line.prompt(), "(reverse-i-search)`go': goto 48.1,11.6");
+    assert_eq!(line.submit(), "goto 48.1,11.6");
+    assert_eq!(line.history.last().unwrap(), "goto 48.1,11.6");
+  }
+}
@@ -0,0 +1,107 @@
+/// Screen pixels from the divider within which the mouse drags it.
+const GRAB_DISTANCE: f32 = 6.;
+
+/// What the right side of `:compare` shows differently from the left one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Other {
+  /// The tile layer with the id instead of the other tile layers.
+  TileLayer(usize),
+  /// The layers at the time, `None` without time cursor.
+  Time(Option<i64>),
+}
+
+/// How the two sides share the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+  /// Both sides show the same area, split by a divider that can be dragged.
+  Swipe,
+  /// Each half of the window shows the area around the center of the map.
+  Split,
+}
+
+/// Two states of the map shown side by side with the same camera, set by `:compare`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Compare {
+  pub other: Other,
+  pub mode: Mode,
+  /// The position of the divider of `Swipe` as fraction of the width of the window.
+  divider: f32,
+}
+
+impl Compare {
+  #[must_use]
+  pub fn new(other: Other, mode: Mode) -> Self {
+    Self {
+      other,
+      mode,
+      divider: 0.5,
+    }
+  }
+
+  /// The left and the right side in a window of the width as start, width, and how far the map
+  /// is moved horizontally in screen pixels.
+  #[must_use]
+  pub fn sides(&self, width: f32) -> [(f32, f32, f32); 2] {
+    let x = self.divider_x(width);
+    match self.mode {
+      Mode::Swipe => [(0., x, 0.), (x, width - x, 0.)],
+      Mode::Split => [(0., x, -width / 4.), (x, width - x, width / 4.)],
+    }
+  }
+
+  /// The x of the line between the sides.
+  #[must_use]
+  pub fn divider_x(&self, width: f32) -> f32 {
+    match self.mode {
+      Mode::Swipe => self.divider * width,
+      Mode::Split => width / 2.,
+    }
+  }
+
+  /// If the divider can be dragged from the x of the mouse.
+  #[must_use]
+  pub fn grabs(&self, x: f32, width: f32) -> bool {
+    self.mode == Mode::Swipe && (x - self.divider_x(width)).abs() <= GRAB_DISTANCE
+  }
+
+  /// Moves the divider to the x of the mouse.
+  pub fn drag(&mut self, x: f32, width: f32) {
+    if width > 0. {
+      self.divider = (x / width).clamp(0., 1.);
+    }
+  }
+
+  /// If a side draws the tile layer with the id.
+  #[must_use]
+  pub fn shows_tile_layer(&self, right: bool, id: usize) -> bool {
+    match self.other {
+      Other::TileLayer(other) => (id == other) == right,
+      Other::Time(_) => true,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn sides() {
+    let mut swipe = Compare::new(Other::TileLayer(1), Mode::Swipe);
+    assert_eq!(swipe.sides(800.), [(0., 400., 0.), (400., 400., 0.)]);
+    assert!(swipe.grabs(404., 800.));
+    assert!(!swipe.grabs(420., 800.));
+    swipe.drag(200., 800.);
+    assert_eq!(swipe.sides(800.), [(0., 200., 0.), (200., 600., 0.)]);
+    swipe.drag(900., 800.);
+    assert_eq!(swipe.divider_x(800.), 800.);
+    assert!(swipe.shows_tile_layer(true, 1));
+    assert!(!swipe.shows_tile_layer(true, 0));
+    assert!(swipe.shows_tile_layer(false, 0));
+
+    let split = Compare::new(Other::Time(Some(0)), Mode::Split);
+    assert_eq!(split.sides(800.), [(0., 400., -200.), (400., 400., 200.)]);
+    assert!(!split.grabs(400., 800.));
+    assert!(split.shows_tile_layer(true, 0));
+  }
+}
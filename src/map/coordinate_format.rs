@@ -0,0 +1,489 @@
+use std::str::FromStr;
+
+use regex::Regex;
+
+use super::coordinates::Coordinate;
+
+/// The semi-major axis of the WGS84 ellipsoid in meters.
+const A: f64 = 6_378_137.;
+/// The flattening of the WGS84 ellipsoid.
+const F: f64 = 1. / 298.257_223_563;
+/// The scale factor on the central meridian of a UTM zone.
+const K0: f64 = 0.9996;
+const FALSE_EASTING: f64 = 500_000.;
+const FALSE_NORTHING: f64 = 10_000_000.;
+
+/// The latitude bands from 80° south to 84° north, 8° each except X with 12°.
+const BANDS: &[u8; 20] = b"CDEFGHJKLMNPQRSTUVWX";
+/// The column letters of 100 km squares, one set for every third zone.
+const COLUMN_LETTERS: [&[u8; 8]; 3] = [b"STUVWXYZ", b"ABCDEFGH", b"JKLMNPQR"];
+/// The row letters of 100 km squares. They repeat every 2000 km.
+const ROW_LETTERS: &[u8; 20] = b"ABCDEFGHJKLMNPQRSTUV";
+
+/// How coordinates are shown in the text bar and copied.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum CoordinateFormat {
+  /// Decimal degrees, e.g. `52.520000, 13.410000`.
+  #[default]
+  Decimal,
+  /// Degrees, minutes, and seconds, e.g. `52°31'12.0"N 13°24'36.0"E`.
+  Dms,
+  /// Zone, latitude band, easting, and northing, e.g. `33U 391776 5820073`.
+  Utm,
+  /// The military grid reference system, e.g. `33U UU 91776 20073`.
+  Mgrs,
+}
+
+impl CoordinateFormat {
+  #[must_use]
+  pub fn next(self) -> Self {
+    match self {
+      Self::Decimal => Self::Dms,
+      Self::Dms => Self::Utm,
+      Self::Utm => Self::Mgrs,
+      Self::Mgrs => Self::Decimal,
+    }
+  }
+
+  #[must_use]
+  pub fn format(self, coordinate: Coordinate) -> String {
+    match self {
+      Self::Decimal => format!("{:.6}, {:.6}", coordinate.lat, coordinate.lon),
+      Self::Dms => format!(
+        "{} {}",
+        dms(coordinate.lat, if coordinate.lat < 0. { 'S' } else { 'N' }),
+        dms(coordinate.lon, if coordinate.lon < 0. { 'W' } else { 'E' })
+      ),
+      Self::Utm => {
+        let utm = Utm::from(coordinate);
+        format!(
+          "{}{} {:.0} {:.0}",
+          utm.zone,
+          char::from(utm.band),
+          utm.easting.floor(),
+          utm.northing.floor()
+        )
+      }
+      Self::Mgrs => Utm::from(coordinate).mgrs(),
+    }
+  }
+}
+
+impl FromStr for CoordinateFormat {
+  type Err = ();
+  fn from_str(input: &str) -> Result<CoordinateFormat, Self::Err> {
+    let lowercase = input.to_lowercase();
+    match lowercase.as_str() {
+      "decimal" => Ok(CoordinateFormat::Decimal),
+      "dms" => Ok(CoordinateFormat::Dms),
+      "utm" => Ok(CoordinateFormat::Utm),
+      "mgrs" => Ok(CoordinateFormat::Mgrs),
+      _ => Err(()),
+    }
+  }
+}
+
+/// The parts of the status bar: the position in the format, the zoom level, and the visible area
+/// from its north west to its south east corner as `lat1,lon1,lat2,lon2` like `mapcat --bbox`.
+#[must_use]
+pub fn status(
+  format: CoordinateFormat,
+  position: Coordinate,
+  zoom: f32,
+  nw: Coordinate,
+  se: Coordinate,
+) -> [String; 3] {
+  [
+    format.format(position),
+    format!("z{zoom:.1}"),
+    format!("{:.6},{:.6},{:.6},{:.6}", nw.lat, nw.lon, se.lat, se.lon),
+  ]
+}
+
+fn dms(degrees: f32, hemisphere: char) -> String {
+  let seconds = (f64::from(degrees).abs() * 3600. * 10.).round() / 10.;
+  let d = (seconds / 3600.).floor();
+  let m = ((seconds - d * 3600.) / 60.).floor();
+  let s = seconds - d * 3600. - m * 60.;
+  format!("{d:.0}°{m:.0}'{s:.1}\"{hemisphere}")
+}
+
+/// A position in the universal transverse mercator projection.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Utm {
+  pub zone: u8,
+  /// The latitude band letter, `N` and after are on the northern hemisphere.
+  pub band: u8,
+  pub easting: f64,
+  /// The northing in meters, on the southern hemisphere from 10000 km south of the equator.
+  pub northing: f64,
+}
+
+fn central_meridian(zone: u8) -> f64 {
+  (f64::from(zone) * 6. - 183.).to_radians()
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn band(lat: f64) -> u8 {
+  BANDS[(((lat + 80.) / 8.).floor() as usize).min(BANDS.len() - 1)]
+}
+
+/// The distance along the meridian from the equator to the latitude.
+fn meridian_arc(lat: f64) -> f64 {
+  let e2 = F * (2. - F);
+  let (e4, e6) = (e2 * e2, e2 * e2 * e2);
+  A * ((1. - e2 / 4. - 3. * e4 / 64. - 5. * e6 / 256.) * lat
+    - (3. * e2 / 8. + 3. * e4 / 32. + 45. * e6 / 1024.) * (2. * lat).sin()
+    + (15. * e4 / 256. + 45. * e6 / 1024.) * (4. * lat).sin()
+    - (35. * e6 / 3072.) * (6. * lat).sin())
+}
+
+impl Utm {
+  /// Projects a coordinate into the given zone.
+  #[must_use]
+  pub fn in_zone(coordinate: Coordinate, zone: u8) -> Self {
+    let e2 = F * (2. - F);
+    let ep2 = e2 / (1. - e2);
+    let lat = f64::from(coordinate.lat).to_radians();
+    let lon = f64::from(coordinate.lon).to_radians();
+
+    let n = A / (1. - e2 * lat.sin().powi(2)).sqrt();
+    let t = lat.tan().powi(2);
+    let c = ep2 * lat.cos().powi(2);
+    let a = lat.cos() * (lon - central_meridian(zone));
+
+    let easting = K0
+      * n
+      * (a
+        + (1. - t + c) * a.powi(3) / 6.
+        + (5. - 18. * t + t * t + 72. * c - 58. * ep2) * a.powi(5) / 120.)
+      + FALSE_EASTING;
+    let mut northing = K0
+      * (meridian_arc(lat)
+        + n
+          * lat.tan()
+          * (a * a / 2.
+            + (5. - t + 9. * c + 4. * c * c) * a.powi(4) / 24.
+            + (61. - 58. * t + t * t + 600. * c - 330. * ep2) * a.powi(6) / 720.));
+    if coordinate.lat < 0. {
+      northing += FALSE_NORTHING;
+    }
+    Self {
+      zone,
+      band: band(f64::from(coordinate.lat)),
+      easting,
+      northing,
+    }
+  }
+
+  fn is_north(&self) -> bool {
+    self.band >= b'N'
+  }
+
+  /// The grid reference with 1 m precision.
+  #[must_use]
+  pub fn mgrs(&self) -> String {
+    let (column, row) = self.square();
+    format!(
+      "{}{} {}{} {:05.0} {:05.0}",
+      self.zone,
+      char::from(self.band),
+      char::from(column),
+      char::from(row),
+      (self.easting % 100_000.).floor(),
+      (self.northing % 100_000.).floor()
+    )
+  }
+
+  /// The letters of the 100 km square.
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn square(&self) -> (u8, u8) {
+    let columns = COLUMN_LETTERS[usize::from(self.zone % 3)];
+    let column = ((self.easting / 100_000.).floor() as usize).clamp(1, 8) - 1;
+    let offset = if self.zone % 2 == 0 { 5 } else { 0 };
+    let row = ((self.northing / 100_000.).floor() as usize + offset) % ROW_LETTERS.len();
+    (columns[column], ROW_LETTERS[row])
+  }
+
+  /// The position of a grid reference like `32U NV 123 456` within the 100 km square.
+  #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+  fn from_mgrs(
+    zone: u8,
+    band: u8,
+    column: u8,
+    row: u8,
+    easting: f64,
+    northing: f64,
+  ) -> Option<Self> {
+    if !(1..=60).contains(&zone) {
+      return None;
+    }
+    let columns = COLUMN_LETTERS[usize::from(zone % 3)];
+    let column = columns.iter().position(|c| *c == column)?;
+    let row = ROW_LETTERS.iter().position(|r| *r == row)?;
+    let offset = if zone % 2 == 0 { 5 } else { 0 };
+    let mut northing =
+      ((row + ROW_LETTERS.len() - offset) % ROW_LETTERS.len()) as f64 * 100_000. + northing;
+    // The row letters repeat every 2000 km, the band tells which repetition is meant.
+    let band_index = BANDS.iter().position(|b| *b == band)?;
+    let band_south = Coordinate {
+      lat: band_index as f32 * 8. - 80.,
+      lon: central_meridian(zone).to_degrees() as f32,
+    };
+    let min_northing = Self::in_zone(band_south, zone).northing - 100_000.;
+    while northing < min_northing {
+      northing += 2_000_000.;
+    }
+    Some(Self {
+      zone,
+      band,
+      easting: (column + 1) as f64 * 100_000. + easting,
+      northing,
+    })
+  }
+}
+
+impl From<Coordinate> for Utm {
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn from(coordinate: Coordinate) -> Self {
+    let (lat, lon) = (coordinate.lat, coordinate.lon);
+    let zone = if (56. ..64.).contains(&lat) && (3. ..12.).contains(&lon) {
+      // South western Norway has a wider zone.
+      32
+    } else if (72. ..84.).contains(&lat) && (0. ..42.).contains(&lon) {
+      // Svalbard only has the odd zones.
+      ((lon + 3.) / 12.).floor() as u8 * 2 + 31
+    } else {
+      ((lon + 180.) / 6.).floor() as u8 % 60 + 1
+    };
+    Self::in_zone(coordinate, zone)
+  }
+}
+
+impl From<Utm> for Coordinate {
+  #[allow(clippy::cast_possible_truncation)]
+  fn from(utm: Utm) -> Self {
+    let e2 = F * (2. - F);
+    let ep2 = e2 / (1. - e2);
+    let x = utm.easting - FALSE_EASTING;
+    let y = if utm.is_north() {
+      utm.northing
+    } else {
+      utm.northing - FALSE_NORTHING
+    };
+
+    let (e4, e6) = (e2 * e2, e2 * e2 * e2);
+    let mu = y / K0 / (A * (1. - e2 / 4. - 3. * e4 / 64. - 5. * e6 / 256.));
+    let e1 = (1. - (1. - e2).sqrt()) / (1. + (1. - e2).sqrt());
+    let phi = mu
+      + (3. * e1 / 2. - 27. * e1.powi(3) / 32.) * (2. * mu).sin()
+      + (21. * e1 * e1 / 16. - 55. * e1.powi(4) / 32.) * (4. * mu).sin()
+      + (151. * e1.powi(3) / 96.) * (6. * mu).sin()
+      + (1097. * e1.powi(4) / 512.) * (8. * mu).sin();
+
+    let n = A / (1. - e2 * phi.sin().powi(2)).sqrt();
+    let t = phi.tan().powi(2);
+    let c = ep2 * phi.cos().powi(2);
+    let r = A * (1. - e2) / (1. - e2 * phi.sin().powi(2)).powf(1.5);
+    let d = x / (n * K0);
+
+    let lat = phi
+      - (n * phi.tan() / r)
+        * (d * d / 2. - (5. + 3. * t + 10. * c - 4. * c * c - 9. * ep2) * d.powi(4) / 24.
+          + (61. + 90. * t + 298. * c + 45. * t * t - 252. * ep2 - 3. * c * c) * d.powi(6) / 720.);
+    let lon = central_meridian(utm.zone)
+      + (d - (1. + 2. * t + c) * d.powi(3) / 6.
+        + (5. - 2. * c + 28. * t - 3. * c * c + 8. * ep2 + 24. * t * t) * d.powi(5) / 120.)
+        / phi.cos();
+    Coordinate {
+      lat: lat.to_degrees() as f32,
+      lon: lon.to_degrees() as f32,
+    }
+  }
+}
+
+/// Finds coordinates given as MGRS, UTM, or degrees, minutes, and seconds in text.
+#[derive(Clone, Debug)]
+pub struct GridCoordinateFinder {
+  mgrs_re: Regex,
+  utm_re: Regex,
+  dms_re: Regex,
+}
+
+impl Default for GridCoordinateFinder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl GridCoordinateFinder {
+  /// # Panics
+  /// If there is a typo in some regex.
+  #[must_use]
+  pub fn new() -> Self {
+    let mgrs_re =
+      Regex::new(r"\b(\d{1,2})([C-HJ-NP-X]) ?([A-HJ-NP-Z])([A-HJ-NP-V]) ?(\d{1,5}) ?(\d{1,5})\b")
+        .unwrap();
+    let utm_re =
+      Regex::new(r"\b(\d{1,2})([C-HJ-NP-X]) (\d{6}(?:\.\d+)?)m?E? (\d{1,8}(?:\.\d+)?)m?N?")
+        .unwrap();
+    let dms_re = Regex::new(
+      r#"(\d{1,2})°\s*(\d{1,2})['′]\s*(\d{1,2}(?:\.\d+)?)["″]?\s*([NS])[,\s]*(\d{1,3})°\s*(\d{1,2})['′]\s*(\d{1,2}(?:\.\d+)?)["″]?\s*([EW])"#,
+    )
+    .unwrap();
+    Self {
+      mgrs_re,
+      utm_re,
+      dms_re,
+    }
+  }
+
+  /// All coordinates in the text, the formats are tried one after the other.
+  #[must_use]
+  pub fn find(&self, text: &str) -> Vec<Coordinate> {
+    let mut coordinates = self.find_mgrs(text);
+    if coordinates.is_empty() {
+      coordinates = self.find_utm(text);
+    }
+    if coordinates.is_empty() {
+      coordinates = self.find_dms(text);
+    }
+    coordinates.retain(Coordinate::is_valid);
+    coordinates
+  }
+
+  fn find_mgrs(&self, text: &str) -> Vec<Coordinate> {
+    self
+      .mgrs_re
+      .captures_iter(text)
+      .map(|c| c.extract())
+      .filter_map(|(_, [zone, band, column, row, easting, northing])| {
+        // Easting and northing have the same number of digits, fewer digits are less precise.
+        if easting.len() != northing.len() {
+          return None;
+        }
+        let scale = 10f64.powi(5 - i32::try_from(easting.len()).ok()?);
+        let utm = Utm::from_mgrs(
+          zone.parse().ok()?,
+          band.as_bytes()[0],
+          column.as_bytes()[0],
+          row.as_bytes()[0],
+          easting.parse::<f64>().ok()? * scale,
+          northing.parse::<f64>().ok()? * scale,
+        )?;
+        Some(Coordinate::from(utm))
+      })
+      .collect()
+  }
+
+  fn find_utm(&self, text: &str) -> Vec<Coordinate> {
+    self
+      .utm_re
+      .captures_iter(text)
+      .map(|c| c.extract())
+      .filter_map(|(_, [zone, band, easting, northing])| {
+        let zone: u8 = zone.parse().ok()?;
+        if !(1..=60).contains(&zone) {
+          return None;
+        }
+        Some(Coordinate::from(Utm {
+          zone,
+          band: band.as_bytes()[0],
+          easting: easting.parse().ok()?,
+          northing: northing.parse().ok()?,
+        }))
+      })
+      .collect()
+  }
+
+  fn find_dms(&self, text: &str) -> Vec<Coordinate> {
+    let degrees = |d: &str, m: &str, s: &str, hemisphere: &str| -> Option<f32> {
+      let value =
+        d.parse::<f32>().ok()? + m.parse::<f32>().ok()? / 60. + s.parse::<f32>().ok()? / 3600.;
+      Some(if hemisphere == "S" || hemisphere == "W" {
+        -value
+      } else {
+        value
+      })
+    };
+    self
+      .dms_re
+      .captures_iter(text)
+      .map(|c| c.extract())
+      .filter_map(|(_, [lat_d, lat_m, lat_s, ns, lon_d, lon_m, lon_s, ew])| {
+        Some(Coordinate {
+          lat: degrees(lat_d, lat_m, lat_s, ns)?,
+          lon: degrees(lon_d, lon_m, lon_s, ew)?,
+        })
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const EIFFEL_TOWER: Coordinate = Coordinate {
+    lat: 48.858_37,
+    lon: 2.294_481,
+  };
+
+  fn assert_close(a: Coordinate, b: Coordinate) {
+    assert!(
+      (a.lat - b.lat).abs() < 0.000_1 && (a.lon - b.lon).abs() < 0.000_1,
+      "{a:?} != {b:?}"
+    );
+  }
+
+  #[test]
+  fn utm_round_trip() {
+    let utm = Utm::from(EIFFEL_TOWER);
+    assert_eq!((utm.zone, utm.band), (31, b'U'));
+    assert!((utm.easting - 448_251.).abs() < 2.);
+    assert!((utm.northing - 5_411_952.).abs() < 2.);
+    assert_close(Coordinate::from(utm), EIFFEL_TOWER);
+
+    let sydney = Coordinate {
+      lat: -33.856_8,
+      lon: 151.215_3,
+    };
+    assert_close(Coordinate::from(Utm::from(sydney)), sydney);
+  }
+
+  #[test]
+  fn find_in_text() {
+    let finder = GridCoordinateFinder::new();
+    let mgrs = CoordinateFormat::Mgrs.format(EIFFEL_TOWER);
+    assert!(mgrs.starts_with("31U DQ 4825"), "{mgrs}");
+    assert_close(finder.find(&mgrs)[0], EIFFEL_TOWER);
+    assert_close(finder.find("Position: 31U 448251 5411952")[0], EIFFEL_TOWER);
+    let dms = CoordinateFormat::Dms.format(EIFFEL_TOWER);
+    assert_eq!(dms, "48°51'30.1\"N 2°17'40.1\"E");
+    assert!((finder.find(&dms)[0].lat - EIFFEL_TOWER.lat).abs() < 0.001);
+
+    // Fewer digits are the south west corner of a larger square.
+    let coarse = finder.find("32U NV 123 456")[0];
+    let utm = Utm::from(coarse);
+    assert_eq!(utm.zone, 32);
+    assert!((utm.easting - 512_300.).abs() < 2.);
+    assert!((utm.northing - 5_445_600.).abs() < 2.);
+  }
+
+  #[test]
+  fn status_bar() {
+    let nw = Coordinate { lat: 49., lon: 2. };
+    let se = Coordinate {
+      lat: 48.5,
+      lon: 2.5,
+    };
+    assert_eq!(
+      status(CoordinateFormat::Utm, EIFFEL_TOWER, 12.345, nw, se),
+      [
+        CoordinateFormat::Utm.format(EIFFEL_TOWER),
+        "z12.3".to_string(),
+        "49.000000,2.000000,48.500000,2.500000".to_string()
+      ]
+    );
+  }
+}
@@ -42,6 +42,49 @@ pub fn tiles_in_box(nw: TileCoordinate, se: TileCoordinate) -> impl Iterator<Ite
     .filter(Tile::exists)
 }
 
+/// Tiles that are likely needed next for the visible tiles: the ring around them with the ones
+/// in pan direction first, the tiles one zoom level up, and the ones one zoom level down.
+/// * `direction` - The recent panning in canvas coordinates.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn prefetch_tiles(visible: &[Tile], direction: (f32, f32)) -> Vec<Tile> {
+  let Some(first) = visible.first() else {
+    return vec![];
+  };
+  let zoom = first.zoom;
+  let min_x = visible.iter().map(|t| t.x).min().unwrap_or(0);
+  let max_x = visible.iter().map(|t| t.x).max().unwrap_or(0);
+  let min_y = visible.iter().map(|t| t.y).min().unwrap_or(0);
+  let max_y = visible.iter().map(|t| t.y).max().unwrap_or(0);
+  let center = ((min_x + max_x) as f32 / 2., (min_y + max_y) as f32 / 2.);
+
+  let mut ring: Vec<Tile> = (min_x.saturating_sub(1)..=max_x + 1)
+    .flat_map(|x| (min_y.saturating_sub(1)..=max_y + 1).map(move |y| Tile { x, y, zoom }))
+    .filter(|t| t.x < min_x || t.x > max_x || t.y < min_y || t.y > max_y)
+    .filter(Tile::exists)
+    .collect();
+  let ahead =
+    |t: &Tile| (t.x as f32 - center.0) * direction.0 + (t.y as f32 - center.1) * direction.1;
+  ring.sort_by(|a, b| ahead(b).total_cmp(&ahead(a)));
+
+  let mut parents: Vec<Tile> = visible.iter().filter_map(Tile::parent).collect();
+  parents.sort_by_key(|t| (t.x, t.y));
+  parents.dedup();
+
+  let children = visible.iter().flat_map(|t| {
+    [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| Tile {
+      x: 2 * t.x + dx,
+      y: 2 * t.y + dy,
+      zoom: t.zoom + 1,
+    })
+  });
+  ring
+    .into_iter()
+    .chain(parents)
+    .chain(children.filter(|_| zoom < 19))
+    .collect()
+}
+
 pub const CANVAS_SIZE: f32 = 1000.;
 pub const TILE_SIZE: f32 = 250.;
 
@@ -77,6 +120,12 @@ impl PixelPosition {
 
   #[must_use]
   pub fn sq_distance_line_segment(&self, l1: &PixelPosition, l2: &PixelPosition) -> f32 {
+    self.closest_on_line_segment(l1, l2).sq_dist(self)
+  }
+
+  /// The point of the line segment closest to this one.
+  #[must_use]
+  pub fn closest_on_line_segment(&self, l1: &PixelPosition, l2: &PixelPosition) -> PixelPosition {
     let dbx = l2.x - l1.x;
     let dby = l2.y - l1.y;
     let dpx = self.x - l1.x;
@@ -85,17 +134,55 @@ impl PixelPosition {
     let len_sq = dbx * dbx + dby * dby;
 
     if len_sq < 0.000_000_1 {
-      return self.sq_dist(l1);
+      return *l1;
     }
     let param = (dot / len_sq).clamp(0., 1.);
     PixelPosition {
       x: l1.x + param * dbx,
       y: l1.y + param * dby,
     }
-    .sq_dist(self)
   }
 }
 
+/// Simplifies a polyline with the Douglas-Peucker algorithm.
+/// The result keeps the first and last point and deviates at most `epsilon` from the input.
+#[must_use]
+pub fn simplify(points: &[PixelPosition], epsilon: f32) -> Vec<PixelPosition> {
+  if points.len() < 3 {
+    return points.to_vec();
+  }
+  let sq_epsilon = epsilon * epsilon;
+  let mut keep = vec![false; points.len()];
+  keep[0] = true;
+  keep[points.len() - 1] = true;
+
+  let mut stack = vec![(0, points.len() - 1)];
+  while let Some((first, last)) = stack.pop() {
+    let (index, sq_dist) = (first + 1..last)
+      .map(|i| {
+        (
+          i,
+          points[i].sq_distance_line_segment(&points[first], &points[last]),
+        )
+      })
+      .fold(
+        (first, 0.),
+        |max, next| if next.1 > max.1 { next } else { max },
+      );
+    if sq_dist > sq_epsilon {
+      keep[index] = true;
+      stack.push((first, index));
+      stack.push((index, last));
+    }
+  }
+
+  points
+    .iter()
+    .zip(keep)
+    .filter_map(|(p, k)| k.then_some(*p))
+    .collect()
+}
+
 impl From<PixelPosition> for Coordinate {
   fn from(pp: PixelPosition) -> Self {
     Coordinate::from(TileCoordinate::from_pixel_position(pp, 2))
@@ -266,11 +353,34 @@ impl BoundingBox {
     }
   }
 
+  #[must_use]
+  pub fn nw(&self) -> PixelPosition {
+    PixelPosition {
+      x: self.min_x,
+      y: self.min_y,
+    }
+  }
+
+  #[must_use]
+  pub fn se(&self) -> PixelPosition {
+    PixelPosition {
+      x: self.max_x,
+      y: self.max_y,
+    }
+  }
+
   #[must_use]
   pub fn width(&self) -> f32 {
     self.max_x - self.min_x
   }
 
+  /// If the position is inside of the box or at most the distance away from it.
+  #[must_use]
+  pub fn is_near(&self, p: PixelPosition, distance: f32) -> bool {
+    (self.min_x - distance..=self.max_x + distance).contains(&p.x)
+      && (self.min_y - distance..=self.max_y + distance).contains(&p.y)
+  }
+
   #[must_use]
   pub fn height(&self) -> f32 {
     self.max_y - self.min_y
@@ -281,6 +391,44 @@ impl BoundingBox {
 mod tests {
   use super::*;
 
+  #[test]
+  fn prefetch() {
+    let visible = [
+      Tile {
+        x: 4,
+        y: 4,
+        zoom: 4,
+      },
+      Tile {
+        x: 5,
+        y: 4,
+        zoom: 4,
+      },
+    ];
+    let tiles = prefetch_tiles(&visible, (0., 1.));
+    // 10 ring tiles, 1 parent, and 8 children.
+    assert_eq!(tiles.len(), 19);
+    assert_eq!(tiles[0].y, 5);
+    assert_eq!(tiles[9].y, 3);
+    assert_eq!(
+      tiles[10],
+      Tile {
+        x: 2,
+        y: 2,
+        zoom: 3
+      }
+    );
+    assert_eq!(
+      tiles[11],
+      Tile {
+        x: 8,
+        y: 8,
+        zoom: 5
+      }
+    );
+    assert!(prefetch_tiles(&[], (0., 0.)).is_empty());
+  }
+
   #[test]
   fn coordinate_tile_conversions() {
     let coord = Coordinate {
@@ -340,6 +488,26 @@ mod tests {
     assert_eq!(Coordinate::from(tc3), Coordinate::from(pp));
   }
 
+  #[test]
+  #[allow(clippy::cast_precision_loss)]
+  fn simplify_test() {
+    // A noisy triangle with its peak at x = 5.
+    let points: Vec<_> = (0..=10)
+      .map(|i: i32| PixelPosition {
+        x: i as f32,
+        y: 1. - (i - 5).abs() as f32 / 5. + if i % 2 == 0 { 0.01 } else { -0.01 },
+      })
+      .collect();
+
+    assert_eq!(
+      simplify(&points, 0.1),
+      vec![points[0], points[5], points[10]]
+    );
+    assert_eq!(simplify(&points, 2.), vec![points[0], points[10]]);
+    assert_eq!(simplify(&points, 0.001), points);
+    assert_eq!(simplify(&points[..2], 10.), points[..2].to_vec());
+  }
+
   #[test]
   fn tile_box_test() {
     let nw = TileCoordinate {
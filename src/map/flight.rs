@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use super::{
+  coordinates::{PixelPosition, CANVAS_SIZE},
+  map_event::Camera,
+};
+
+/// The window size in pixels assumed to decide how far a flight zooms out.
+const VIEW_SIZE: f32 = 1000.;
+
+/// The duration of camera flights given by `MAPVAS_FLY_DURATION_MS`, 0 jumps without animation.
+#[must_use]
+pub fn flight_duration() -> Duration {
+  std::env::var("MAPVAS_FLY_DURATION_MS")
+    .ok()
+    .and_then(|ms| ms.parse().ok())
+    .map_or(Duration::from_millis(800), Duration::from_millis)
+}
+
+fn ease_in_out(t: f32) -> f32 {
+  if t < 0.5 {
+    4. * t * t * t
+  } else {
+    1. - (-2. * t + 2.).powi(3) / 2.
+  }
+}
+
+/// An animated move of the camera. Far flights zoom out on the way so start and destination
+/// are both in view in the middle of the flight.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flight {
+  from: Camera,
+  to: Camera,
+  duration: Duration,
+  /// How many zoom levels the camera goes out in the middle.
+  hop: f32,
+}
+
+impl Flight {
+  #[must_use]
+  pub fn new(from: Camera, to: Camera, duration: Duration) -> Self {
+    let (a, b) = (
+      PixelPosition::from(from.center),
+      PixelPosition::from(to.center),
+    );
+    let distance = a.sq_dist(&b).sqrt() / CANVAS_SIZE;
+    let hop = if distance > 0. {
+      let zoom_showing_both = (VIEW_SIZE / (256. * distance)).log2();
+      (from.zoom.min(to.zoom) - zoom_showing_both).max(0.)
+    } else {
+      0.
+    };
+    Self {
+      from,
+      to,
+      duration,
+      hop,
+    }
+  }
+
+  #[must_use]
+  pub fn is_done(&self, elapsed: Duration) -> bool {
+    elapsed >= self.duration
+  }
+
+  /// The camera after the elapsed time of the flight.
+  #[must_use]
+  pub fn camera(&self, elapsed: Duration) -> Camera {
+    if self.is_done(elapsed) {
+      return self.to;
+    }
+    let t = ease_in_out(elapsed.as_secs_f32() / self.duration.as_secs_f32());
+    let (a, b) = (
+      PixelPosition::from(self.from.center),
+      PixelPosition::from(self.to.center),
+    );
+    let center = PixelPosition {
+      x: a.x + (b.x - a.x) * t,
+      y: a.y + (b.y - a.y) * t,
+    };
+    let mut turn = (self.to.rotation - self.from.rotation).rem_euclid(360.);
+    if turn > 180. {
+      turn -= 360.;
+    }
+    Camera {
+      center: center.into(),
+      zoom: self.from.zoom + (self.to.zoom - self.from.zoom) * t
+        - self.hop * (t * std::f32::consts::PI).sin(),
+      rotation: self.from.rotation + turn * t,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::map::coordinates::Coordinate;
+
+  fn camera(lat: f32, lon: f32, zoom: f32) -> Camera {
+    Camera {
+      center: Coordinate { lat, lon },
+      zoom,
+      rotation: 0.,
+    }
+  }
+
+  #[test]
+  fn fly() {
+    let (from, to) = (camera(52.5, 13.4, 12.), camera(48.1, 11.6, 14.));
+    let flight = Flight::new(from, to, Duration::from_secs(1));
+    let start = flight.camera(Duration::ZERO);
+    assert!((start.center.lat - from.center.lat).abs() < 0.000_1);
+    assert!((start.zoom - from.zoom).abs() < 0.000_1);
+    assert_eq!(flight.camera(Duration::from_secs(2)), to);
+    assert!(flight.is_done(Duration::from_secs(1)));
+
+    let middle = flight.camera(Duration::from_millis(500));
+    assert!((middle.center.lat - 50.3).abs() < 0.5);
+    // Berlin and Munich are not both visible at zoom level 12.
+    assert!(middle.zoom < 9.);
+
+    let near = Flight::new(from, camera(52.5, 13.41, 12.), Duration::from_secs(1));
+    assert!((near.camera(Duration::from_millis(500)).zoom - 12.).abs() < 0.001);
+  }
+}
@@ -0,0 +1,101 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// The frames whose times are kept.
+const FRAMES: usize = 60;
+
+/// The times of the last frames and the elements drawn per layer in the current one, shown by
+/// `:set debug_overlay`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameStats {
+  times: VecDeque<Duration>,
+  drawn: Vec<(String, usize)>,
+}
+
+impl FrameStats {
+  /// Keeps the time a frame took to draw.
+  pub fn record(&mut self, time: Duration) {
+    if self.times.len() == FRAMES {
+      self.times.pop_front();
+    }
+    self.times.push_back(time);
+  }
+
+  /// Forgets the elements drawn in the last frame.
+  pub fn start_frame(&mut self) {
+    self.drawn.clear();
+  }
+
+  /// Adds elements drawn of the layer, which can be drawn more than once in a frame.
+  pub fn count(&mut self, layer: &str, drawn: usize) {
+    match self.drawn.iter_mut().find(|(id, _)| id == layer) {
+      Some((_, count)) => *count += drawn,
+      None => self.drawn.push((layer.to_string(), drawn)),
+    }
+  }
+
+  /// The average and the longest time of the last frames.
+  #[must_use]
+  pub fn times(&self) -> Option<(Duration, Duration)> {
+    let count = u32::try_from(self.times.len()).ok().filter(|c| *c > 0)?;
+    let average = self.times.iter().sum::<Duration>() / count;
+    Some((average, self.times.iter().max().copied()?))
+  }
+
+  /// The frame times and the number of drawn elements of each layer as lines of text.
+  #[must_use]
+  pub fn lines(&self) -> Vec<String> {
+    let mut lines = vec![];
+    if let Some((average, longest)) = self.times() {
+      lines.push(format!(
+        "frame {:.1} ms, max {:.1} ms, {:.0} fps possible",
+        average.as_secs_f64() * 1000.,
+        longest.as_secs_f64() * 1000.,
+        1. / average.as_secs_f64().max(1e-6)
+      ));
+    }
+    for (layer, drawn) in &self.drawn {
+      lines.push(format!("{layer}: {drawn} elements drawn"));
+    }
+    lines
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn frames() {
+    let mut stats = FrameStats::default();
+    assert_eq!(stats.times(), None);
+    for ms in [2, 4, 6] {
+      stats.record(Duration::from_millis(ms));
+    }
+    assert_eq!(
+      stats.times(),
+      Some((Duration::from_millis(4), Duration::from_millis(6)))
+    );
+    for _ in 0..FRAMES {
+      stats.record(Duration::from_millis(1));
+    }
+    assert_eq!(
+      stats.times(),
+      Some((Duration::from_millis(1), Duration::from_millis(1)))
+    );
+
+    stats.count("roads", 3);
+    stats.count("roads", 2);
+    stats.count("pois", 1);
+    assert_eq!(
+      stats.lines(),
+      vec![
+        "frame 1.0 ms, max 1.0 ms, 1000 fps possible",
+        "roads: 5 elements drawn",
+        "pois: 1 elements drawn"
+      ]
+    );
+    stats.start_frame();
+    assert_eq!(stats.lines().len(), 1);
+  }
+}
@@ -0,0 +1,189 @@
+use super::coordinates::Coordinate;
+
+/// The length in kilometers after which great circle segments get an intermediate point.
+const SEGMENT_LENGTH: f32 = 100.;
+const EARTH_RADIUS: f32 = 6371.;
+/// The vertices of the outline of a circle.
+const CIRCLE_VERTICES: usize = 72;
+
+fn to_vector(c: Coordinate) -> [f32; 3] {
+  let (lat, lon) = (c.lat.to_radians(), c.lon.to_radians());
+  [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()]
+}
+
+fn to_coordinate(v: [f32; 3]) -> Coordinate {
+  Coordinate {
+    lat: v[2].atan2(v[0].hypot(v[1])).to_degrees(),
+    lon: v[1].atan2(v[0]).to_degrees(),
+  }
+}
+
+/// The great circle distance in kilometers.
+#[must_use]
+pub fn distance(a: Coordinate, b: Coordinate) -> f32 {
+  angle(to_vector(a), to_vector(b)) * EARTH_RADIUS
+}
+
+fn angle(a: [f32; 3], b: [f32; 3]) -> f32 {
+  let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+  let cross = [
+    a[1] * b[2] - a[2] * b[1],
+    a[2] * b[0] - a[0] * b[2],
+    a[0] * b[1] - a[1] * b[0],
+  ];
+  cross.iter().map(|c| c * c).sum::<f32>().sqrt().atan2(dot)
+}
+
+/// Adds points on the great circle between `a` and `b` so no segment is longer than
+/// `SEGMENT_LENGTH`, with where they lie after `start`, the index of `a`. `b` is not part of the
+/// result.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn densify_segment(a: Coordinate, b: Coordinate, start: f32, result: &mut Vec<(Coordinate, f32)>) {
+  let (va, vb) = (to_vector(a), to_vector(b));
+  let omega = angle(va, vb);
+  let steps = (omega * EARTH_RADIUS / SEGMENT_LENGTH).ceil().max(1.) as usize;
+  result.push((a, start));
+  if omega.sin().abs() < f32::EPSILON {
+    return;
+  }
+  for step in 1..steps {
+    #[allow(clippy::cast_precision_loss)]
+    let t = step as f32 / steps as f32;
+    let (wa, wb) = (
+      ((1. - t) * omega).sin() / omega.sin(),
+      (t * omega).sin() / omega.sin(),
+    );
+    let c = to_coordinate([
+      wa * va[0] + wb * vb[0],
+      wa * va[1] + wb * vb[1],
+      wa * va[2] + wb * vb[2],
+    ]);
+    result.push((c, start + t));
+  }
+}
+
+/// Follows the great circles between the coordinates instead of straight lines on the map.
+/// Lines crossing the antimeridian are split into one part on each side.
+#[must_use]
+pub fn great_circle(coordinates: &[Coordinate]) -> Vec<Vec<Coordinate>> {
+  great_circle_along(coordinates)
+    .into_iter()
+    .map(|part| part.into_iter().map(|(c, _)| c).collect())
+    .collect()
+}
+
+/// The parts of [`great_circle`] with where each point lies along the coordinates: the index of
+/// the coordinate before it plus how far it is towards the next one. Values given per coordinate
+/// like timestamps are interpolated with it.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn great_circle_along(coordinates: &[Coordinate]) -> Vec<Vec<(Coordinate, f32)>> {
+  let Some(last) = coordinates.last() else {
+    return vec![];
+  };
+  let mut dense = Vec::with_capacity(coordinates.len());
+  for (i, pair) in coordinates.windows(2).enumerate() {
+    densify_segment(pair[0], pair[1], i as f32, &mut dense);
+  }
+  dense.push((*last, (coordinates.len() - 1) as f32));
+
+  let mut parts = vec![];
+  let mut part = vec![dense[0]];
+  for pair in dense.windows(2) {
+    let ((a, from), (b, to)) = (pair[0], pair[1]);
+    if (b.lon - a.lon).abs() > 180. {
+      // The longitude of b on the side of a.
+      let b_lon = if a.lon > 0. {
+        b.lon + 360.
+      } else {
+        b.lon - 360.
+      };
+      let border = if a.lon > 0. { 180. } else { -180. };
+      let t = (border - a.lon) / (b_lon - a.lon);
+      let lat = a.lat + t * (b.lat - a.lat);
+      let along = from + t * (to - from);
+      part.push((Coordinate { lat, lon: border }, along));
+      parts.push(std::mem::take(&mut part));
+      part.push((Coordinate { lat, lon: -border }, along));
+    }
+    part.push(pair[1]);
+  }
+  parts.push(part);
+  parts
+}
+
+/// The outline of the circle with the radius in meters around the center on the sphere. Projected
+/// to the map it becomes taller than wide away from the equator like the land around it.
+#[must_use]
+pub fn circle(center: Coordinate, meters: f32) -> Vec<Coordinate> {
+  let c = to_vector(center);
+  let (lat, lon) = (center.lat.to_radians(), center.lon.to_radians());
+  let north = [-lat.sin() * lon.cos(), -lat.sin() * lon.sin(), lat.cos()];
+  let east = [-lon.sin(), lon.cos(), 0.];
+  let angle = meters / 1000. / EARTH_RADIUS;
+  (0..CIRCLE_VERTICES)
+    .map(|i| {
+      #[allow(clippy::cast_precision_loss)]
+      let bearing = 2. * std::f32::consts::PI * i as f32 / CIRCLE_VERTICES as f32;
+      let direction = |k: usize| north[k] * bearing.cos() + east[k] * bearing.sin();
+      to_coordinate([0, 1, 2].map(|k| c[k] * angle.cos() + direction(k) * angle.sin()))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn densify() {
+    let berlin = Coordinate {
+      lat: 52.52,
+      lon: 13.41,
+    };
+    let new_york = Coordinate {
+      lat: 40.71,
+      lon: -74.01,
+    };
+    assert!((distance(berlin, new_york) - 6385.).abs() < 10.);
+    let parts = great_circle(&[berlin, new_york]);
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].len(), 65);
+    // The great circle goes further north than both ends.
+    assert!(parts[0].iter().any(|c| c.lat > 56.));
+    assert!((parts[0][64].lon - new_york.lon).abs() < 0.001);
+  }
+
+  #[test]
+  fn split_at_antimeridian() {
+    let tokyo = Coordinate {
+      lat: 35.68,
+      lon: 139.69,
+    };
+    let san_francisco = Coordinate {
+      lat: 37.77,
+      lon: -122.42,
+    };
+    let parts = great_circle(&[tokyo, san_francisco]);
+    assert_eq!(parts.len(), 2);
+    assert!((parts[0].last().unwrap().lon - 180.).abs() < 0.001);
+    assert!((parts[1][0].lon + 180.).abs() < 0.001);
+    assert!((parts[0].last().unwrap().lat - parts[1][0].lat).abs() < 0.001);
+    assert!(parts[0].iter().all(|c| c.lon > 0.));
+    assert!(parts[1].iter().all(|c| c.lon < 0.));
+  }
+
+  #[test]
+  fn circles() {
+    let center = Coordinate { lat: 60., lon: 10. };
+    let outline = circle(center, 10_000.);
+    assert_eq!(outline.len(), CIRCLE_VERTICES);
+    assert!(outline
+      .iter()
+      .all(|c| (distance(center, *c) - 10.).abs() < 0.01));
+    // At 60 degrees a degree of longitude is half as long as one of latitude.
+    let height = outline[0].lat - center.lat;
+    let width = outline[CIRCLE_VERTICES / 4].lon - center.lon;
+    assert!((width / height - 2.).abs() < 0.01);
+  }
+}
@@ -0,0 +1,364 @@
+use std::f64::consts::PI;
+
+use anyhow::{anyhow, Result};
+use geo::{BooleanOps, Coord, LineString, MultiLineString, MultiPolygon, Polygon};
+
+use super::{
+  coordinates::Coordinate,
+  map_event::{FillStyle, Shape},
+};
+
+/// Meters per degree of latitude.
+const METERS_PER_DEGREE: f64 = 111_320.;
+/// The segments of a full circle around the vertices of a buffer.
+const CIRCLE_SEGMENTS: usize = 32;
+
+/// An operation on the selected shapes whose result is drawn as new layer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+  /// The area within the meters of the shapes.
+  Buffer(f64),
+  /// The area covered by any of the areas.
+  Union,
+  /// The area covered by all areas, or the parts of the lines inside of it.
+  Intersection,
+  /// The first area without the others, or the parts of the lines outside of the areas.
+  Difference,
+}
+
+impl Operation {
+  /// The operation of a command like `buffer 500m` or `union`.
+  pub fn parse(name: &str, argument: &str) -> Result<Self> {
+    match name {
+      "buffer" => distance(argument)
+        .map(Operation::Buffer)
+        .ok_or(anyhow!("Usage: buffer <distance like 500m or 2km>")),
+      "union" => Ok(Operation::Union),
+      "intersection" => Ok(Operation::Intersection),
+      "difference" => Ok(Operation::Difference),
+      _ => Err(anyhow!("Unknown operation: {name}")),
+    }
+  }
+}
+
+/// Meters of a distance like `500`, `500m`, or `2km`.
+fn distance(text: &str) -> Option<f64> {
+  let text = text.trim();
+  let (number, factor) = match text.strip_suffix("km") {
+    Some(number) => (number, 1000.),
+    None => (text.strip_suffix('m').unwrap_or(text), 1.),
+  };
+  let meters = number.trim().parse::<f64>().ok()? * factor;
+  (meters > 0.).then_some(meters)
+}
+
+fn coord(c: Coordinate) -> Coord {
+  Coord {
+    x: f64::from(c.lon),
+    y: f64::from(c.lat),
+  }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn coordinate(c: Coord) -> Coordinate {
+  Coordinate {
+    lat: c.y as f32,
+    lon: c.x as f32,
+  }
+}
+
+/// The area of a closed shape.
+fn area(shape: &Shape) -> Option<MultiPolygon> {
+  (shape.style.fill != FillStyle::NoFill && shape.coordinates.len() > 2).then(|| {
+    let ring = shape.coordinates.iter().copied().map(coord).collect();
+    MultiPolygon::new(vec![Polygon::new(LineString::new(ring), vec![])])
+  })
+}
+
+fn line(shape: &Shape) -> Option<LineString> {
+  (shape.style.fill == FillStyle::NoFill && shape.coordinates.len() > 1)
+    .then(|| shape.coordinates.iter().copied().map(coord).collect())
+}
+
+fn union_all(areas: impl IntoIterator<Item = MultiPolygon>) -> MultiPolygon {
+  areas
+    .into_iter()
+    .reduce(|a, b| a.union(&b))
+    .unwrap_or_else(|| MultiPolygon::new(vec![]))
+}
+
+/// Converts between degrees and meters around a latitude, precise enough for buffers of a few
+/// kilometers.
+struct LocalProjection {
+  meters_per_degree_lon: f64,
+}
+
+impl LocalProjection {
+  fn around(shapes: &[Shape]) -> Self {
+    let coordinates = shapes.iter().flat_map(|s| &s.coordinates);
+    let (sum, count) = coordinates.fold((0., 0.), |(sum, count), c| {
+      (sum + f64::from(c.lat), count + 1.)
+    });
+    let lat = if count > 0. { sum / count } else { 0. };
+    Self {
+      meters_per_degree_lon: METERS_PER_DEGREE * lat.to_radians().cos().max(0.01),
+    }
+  }
+
+  fn to_meters(&self, c: Coord) -> Coord {
+    Coord {
+      x: c.x * self.meters_per_degree_lon,
+      y: c.y * METERS_PER_DEGREE,
+    }
+  }
+
+  fn to_degrees(&self, c: Coord) -> Coord {
+    Coord {
+      x: c.x / self.meters_per_degree_lon,
+      y: c.y / METERS_PER_DEGREE,
+    }
+  }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn circle(center: Coord, radius: f64) -> MultiPolygon {
+  let ring = (0..CIRCLE_SEGMENTS)
+    .map(|i| {
+      let angle = 2. * PI * i as f64 / CIRCLE_SEGMENTS as f64;
+      Coord {
+        x: center.x + radius * angle.cos(),
+        y: center.y + radius * angle.sin(),
+      }
+    })
+    .collect();
+  MultiPolygon::new(vec![Polygon::new(LineString::new(ring), vec![])])
+}
+
+/// The rectangle around a segment reaching the radius to both sides.
+fn segment_band(a: Coord, b: Coord, radius: f64) -> Option<MultiPolygon> {
+  let (dx, dy) = (b.x - a.x, b.y - a.y);
+  let length = dx.hypot(dy);
+  if length <= f64::EPSILON {
+    return None;
+  }
+  let (nx, ny) = (-dy / length * radius, dx / length * radius);
+  let ring = vec![
+    Coord {
+      x: a.x + nx,
+      y: a.y + ny,
+    },
+    Coord {
+      x: b.x + nx,
+      y: b.y + ny,
+    },
+    Coord {
+      x: b.x - nx,
+      y: b.y - ny,
+    },
+    Coord {
+      x: a.x - nx,
+      y: a.y - ny,
+    },
+  ];
+  Some(MultiPolygon::new(vec![Polygon::new(
+    LineString::new(ring),
+    vec![],
+  )]))
+}
+
+/// The area within the meters of the shapes: circles around the vertices, bands along the
+/// segments, and the closed shapes themselves.
+fn buffer(shapes: &[Shape], meters: f64) -> MultiPolygon {
+  let projection = LocalProjection::around(shapes);
+  let mut parts = vec![];
+  for shape in shapes {
+    let points: Vec<Coord> = shape
+      .coordinates
+      .iter()
+      .map(|c| projection.to_meters(coord(*c)))
+      .collect();
+    let closed = shape.style.fill != FillStyle::NoFill && points.len() > 2;
+    let closing = closed.then(|| (points[points.len() - 1], points[0]));
+    parts.extend(points.iter().map(|p| circle(*p, meters)));
+    parts.extend(
+      points
+        .windows(2)
+        .map(|w| (w[0], w[1]))
+        .chain(closing)
+        .filter_map(|(a, b)| segment_band(a, b, meters)),
+    );
+    if closed {
+      parts.push(MultiPolygon::new(vec![Polygon::new(
+        LineString::new(points),
+        vec![],
+      )]));
+    }
+  }
+  let mut result = union_all(parts);
+  result.iter_mut().for_each(|polygon| {
+    polygon.exterior_mut(|ring| {
+      ring
+        .0
+        .iter_mut()
+        .for_each(|c| *c = projection.to_degrees(*c))
+    });
+    polygon.interiors_mut(|rings| {
+      for ring in rings {
+        ring
+          .0
+          .iter_mut()
+          .for_each(|c| *c = projection.to_degrees(*c));
+      }
+    });
+  });
+  result
+}
+
+/// The outlines of the polygons as filled shapes, holes as outlines without fill.
+fn area_shapes(areas: &MultiPolygon) -> Vec<Shape> {
+  let ring = |ring: &LineString, fill: FillStyle| {
+    // The closing coordinate is implied by the fill.
+    let mut coordinates: Vec<Coordinate> = ring.coords().copied().map(coordinate).collect();
+    if fill != FillStyle::NoFill && coordinates.len() > 1 && ring.is_closed() {
+      coordinates.pop();
+    }
+    Shape::new(coordinates).with_fill(fill)
+  };
+  areas
+    .iter()
+    .flat_map(|polygon| {
+      std::iter::once(ring(polygon.exterior(), FillStyle::Transparent)).chain(
+        polygon
+          .interiors()
+          .iter()
+          .map(|hole| ring(hole, FillStyle::NoFill)),
+      )
+    })
+    .collect()
+}
+
+fn line_shapes(lines: &MultiLineString) -> Vec<Shape> {
+  lines
+    .iter()
+    .map(|line| Shape::new(line.coords().copied().map(coordinate).collect()))
+    .collect()
+}
+
+/// Applies the operation to the shapes. Lines take part in buffers, intersections, and
+/// differences, where they are cut by the areas.
+pub fn apply(operation: Operation, shapes: &[Shape]) -> Result<Vec<Shape>> {
+  if shapes.is_empty() {
+    return Err(anyhow!("Nothing is selected."));
+  }
+  let areas: Vec<MultiPolygon> = shapes.iter().filter_map(area).collect();
+  let lines = MultiLineString::new(shapes.iter().filter_map(line).collect());
+  let needs_areas = |count: usize| {
+    if areas.len() < count {
+      Err(anyhow!(
+        "The selection needs at least {count} closed shapes."
+      ))
+    } else {
+      Ok(())
+    }
+  };
+  let result = match operation {
+    Operation::Buffer(meters) => area_shapes(&buffer(shapes, meters)),
+    Operation::Union => {
+      needs_areas(1)?;
+      area_shapes(&union_all(areas))
+    }
+    Operation::Intersection if lines.0.is_empty() => {
+      needs_areas(2)?;
+      let first = areas[0].clone();
+      area_shapes(&areas[1..].iter().fold(first, |a, b| a.intersection(b)))
+    }
+    Operation::Intersection => {
+      needs_areas(1)?;
+      let first = areas[0].clone();
+      let zone = areas[1..].iter().fold(first, |a, b| a.intersection(b));
+      line_shapes(&zone.clip(&lines, false))
+    }
+    Operation::Difference if lines.0.is_empty() => {
+      needs_areas(2)?;
+      area_shapes(&areas[0].difference(&union_all(areas[1..].iter().cloned())))
+    }
+    Operation::Difference => {
+      needs_areas(1)?;
+      line_shapes(&union_all(areas).clip(&lines, true))
+    }
+  };
+  if result.is_empty() {
+    return Err(anyhow!("The result is empty."));
+  }
+  Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn square(lat: f32, lon: f32, size: f32) -> Shape {
+    let c = |lat, lon| Coordinate { lat, lon };
+    Shape::new(vec![
+      c(lat, lon),
+      c(lat, lon + size),
+      c(lat + size, lon + size),
+      c(lat + size, lon),
+    ])
+    .with_fill(FillStyle::Transparent)
+  }
+
+  #[test]
+  fn distances() {
+    assert_eq!(distance("500m"), Some(500.));
+    assert_eq!(distance("2km"), Some(2000.));
+    assert_eq!(distance("30"), Some(30.));
+    assert_eq!(distance("-3m"), None);
+    assert_eq!(distance("far"), None);
+  }
+
+  #[test]
+  fn boolean_operations() {
+    let shapes = [square(0., 0., 2.), square(1., 1., 2.)];
+    let union = apply(Operation::Union, &shapes).unwrap();
+    assert_eq!(union.len(), 1);
+    assert_eq!(union[0].coordinates.len(), 8);
+    let intersection = apply(Operation::Intersection, &shapes).unwrap();
+    assert_eq!(intersection[0].coordinates.len(), 4);
+    let difference = apply(Operation::Difference, &shapes).unwrap();
+    assert_eq!(difference[0].coordinates.len(), 6);
+    assert!(apply(Operation::Intersection, &shapes[..1]).is_err());
+    assert!(apply(
+      Operation::Intersection,
+      &[square(0., 0., 1.), square(5., 5., 1.)]
+    )
+    .is_err());
+  }
+
+  #[test]
+  fn lines_and_buffers() {
+    let route = Shape::new(vec![
+      Coordinate { lat: 1., lon: -1. },
+      Coordinate { lat: 1., lon: 3. },
+    ]);
+    let zone = square(0., 0., 2.);
+    let inside = apply(Operation::Intersection, &[route.clone(), zone.clone()]).unwrap();
+    let mut lons: Vec<f32> = inside[0].coordinates.iter().map(|c| c.lon).collect();
+    lons.sort_by(f32::total_cmp);
+    assert_eq!(lons, vec![0., 2.]);
+    let outside = apply(Operation::Difference, &[route, zone]).unwrap();
+    assert_eq!(outside.len(), 2);
+
+    let point = Shape::new(vec![Coordinate {
+      lat: 52.5,
+      lon: 13.4,
+    }]);
+    let buffer = apply(Operation::Buffer(1000.), &[point]).unwrap();
+    let north = buffer[0]
+      .coordinates
+      .iter()
+      .map(|c| c.lat)
+      .fold(f32::MIN, f32::max);
+    assert!((f64::from(north - 52.5) * METERS_PER_DEGREE - 1000.).abs() < 10.);
+  }
+}
@@ -0,0 +1,173 @@
+use femtovg::rgb::RGBA8;
+
+/// Colors from low to high density. The alpha of low densities is reduced further.
+const RAMP: [(u8, u8, u8); 5] = [
+  (0, 0, 255),
+  (0, 255, 255),
+  (0, 255, 0),
+  (255, 255, 0),
+  (255, 0, 0),
+];
+
+/// The maximal opacity of the heatmap overlay.
+const MAX_ALPHA: f32 = 180.;
+
+/// Screen pixels per raster cell.
+pub const CELL: f32 = 4.;
+
+/// How heatmap layers are drawn, changed by `:set heatmap_radius=<pixels>` and
+/// `:set heatmap_intensity=<factor>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeatmapSettings {
+  /// The kernel radius in screen pixels.
+  pub radius: f32,
+  /// Multiplies the density relative to the maximum, so sparse points show up stronger.
+  pub intensity: f32,
+}
+
+impl Default for HeatmapSettings {
+  fn default() -> Self {
+    Self {
+      radius: 24.,
+      intensity: 1.,
+    }
+  }
+}
+
+/// What a heatmap was computed from. The raster is only computed again if the points of the
+/// heatmap layers, the window, or the settings change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeatmapInput {
+  /// Positions in raster cells.
+  pub points: Vec<(f32, f32)>,
+  pub width: usize,
+  pub height: usize,
+  pub settings: HeatmapSettings,
+}
+
+impl HeatmapInput {
+  /// The raster with the colors of the density.
+  #[must_use]
+  pub fn colorize(&self) -> Vec<RGBA8> {
+    let radius = self.settings.radius / CELL;
+    Heatmap::new(self.points.iter().copied(), self.width, self.height, radius)
+      .colorize(self.settings.intensity)
+  }
+}
+
+/// A kernel density raster of points.
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+  width: usize,
+  height: usize,
+  density: Vec<f32>,
+}
+
+impl Heatmap {
+  /// Accumulates a quartic kernel for each point.
+  /// * `points` - Positions in raster cells. Points outside the raster still contribute to cells in
+  ///   range.
+  /// * `radius` - The kernel radius in raster cells.
+  #[must_use]
+  #[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_possible_wrap,
+    clippy::cast_precision_loss
+  )]
+  pub fn new<I: IntoIterator<Item = (f32, f32)>>(
+    points: I,
+    width: usize,
+    height: usize,
+    radius: f32,
+  ) -> Self {
+    let mut density = vec![0.; width * height];
+    let r = radius.ceil() as i64;
+    for (px, py) in points {
+      if px < -radius || py < -radius || px > width as f32 + radius || py > height as f32 + radius {
+        continue;
+      }
+      let (cx, cy) = (px.round() as i64, py.round() as i64);
+      for y in (cy - r).max(0)..=(cy + r).min(height as i64 - 1) {
+        for x in (cx - r).max(0)..=(cx + r).min(width as i64 - 1) {
+          let dx = x as f32 - px;
+          let dy = y as f32 - py;
+          let d = (dx * dx + dy * dy) / (radius * radius);
+          if d < 1. {
+            density[y as usize * width + x as usize] += (1. - d) * (1. - d);
+          }
+        }
+      }
+    }
+    Self {
+      width,
+      height,
+      density,
+    }
+  }
+
+  #[must_use]
+  pub fn width(&self) -> usize {
+    self.width
+  }
+
+  #[must_use]
+  pub fn height(&self) -> usize {
+    self.height
+  }
+
+  fn max_density(&self) -> f32 {
+    self.density.iter().copied().fold(0., f32::max)
+  }
+
+  fn color(value: f32) -> RGBA8 {
+    if value <= 0. {
+      return RGBA8::new(0, 0, 0, 0);
+    }
+    let scaled = value.min(1.) * (RAMP.len() - 1) as f32;
+    let index = (scaled.floor() as usize).min(RAMP.len() - 2);
+    let t = scaled - index as f32;
+    let (r0, g0, b0) = RAMP[index];
+    let (r1, g1, b1) = RAMP[index + 1];
+    let lerp = |a: u8, b: u8| (f32::from(a) + (f32::from(b) - f32::from(a)) * t).round() as u8;
+    RGBA8::new(
+      lerp(r0, r1),
+      lerp(g0, g1),
+      lerp(b0, b1),
+      (MAX_ALPHA * value.min(1.).sqrt()).round() as u8,
+    )
+  }
+
+  /// Maps the density relative to the maximum times the intensity onto the color ramp.
+  #[must_use]
+  pub fn colorize(&self, intensity: f32) -> Vec<RGBA8> {
+    let max = self.max_density();
+    if max <= 0. {
+      return vec![RGBA8::new(0, 0, 0, 0); self.density.len()];
+    }
+    self
+      .density
+      .iter()
+      .map(|d| Self::color(d / max * intensity))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn density_peaks_at_points() {
+    let heatmap = Heatmap::new([(2., 2.), (2., 2.), (7., 2.), (100., 100.)], 10, 5, 2.);
+    assert_eq!(heatmap.density.len(), 50);
+    assert!((heatmap.density[2 * 10 + 2] - 2.).abs() < 1e-6);
+    assert!((heatmap.density[2 * 10 + 7] - 1.).abs() < 1e-6);
+    assert!(heatmap.density[0].abs() < 1e-6);
+
+    let colors = heatmap.colorize(1.);
+    assert_eq!(colors[2 * 10 + 2], RGBA8::new(255, 0, 0, 180));
+    assert_eq!(colors[0].a, 0);
+    assert_eq!(heatmap.colorize(2.)[2 * 10 + 7], colors[2 * 10 + 2]);
+  }
+}
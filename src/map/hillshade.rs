@@ -0,0 +1,154 @@
+use std::io::Cursor;
+
+use anyhow::Result;
+use image::{ImageOutputFormat, Rgba, RgbaImage};
+
+use super::{
+  coordinates::{Coordinate, PixelPosition, Tile},
+  scale::meters_per_pixel,
+};
+
+/// The direction of the light in degrees clockwise from north.
+const SUN_AZIMUTH: f32 = 315.;
+/// The height of the light in degrees above the horizon.
+const SUN_ALTITUDE: f32 = 45.;
+
+/// How the elevation is encoded in the colors of a terrain tile.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DemEncoding {
+  /// <https://github.com/tilezen/joerd/blob/master/docs/formats.md#terrarium>
+  Terrarium,
+  /// The terrain-RGB tiles of Mapbox and MapTiler.
+  TerrainRgb,
+}
+
+impl DemEncoding {
+  /// Splits a prefix `terrarium+` or `terrainrgb+` from a tile url.
+  #[must_use]
+  pub fn split_prefix(url_template: &str) -> (Option<Self>, &str) {
+    if let Some(url) = url_template.strip_prefix("terrarium+") {
+      (Some(Self::Terrarium), url)
+    } else if let Some(url) = url_template.strip_prefix("terrainrgb+") {
+      (Some(Self::TerrainRgb), url)
+    } else {
+      (None, url_template)
+    }
+  }
+
+  /// The elevation in meters.
+  #[must_use]
+  pub fn elevation(self, pixel: Rgba<u8>) -> f32 {
+    let [r, g, b, _] = pixel.0.map(f32::from);
+    match self {
+      Self::Terrarium => r * 256. + g + b / 256. - 32768.,
+      Self::TerrainRgb => -10000. + (r * 65536. + g * 256. + b) * 0.1,
+    }
+  }
+}
+
+/// Identifies the hillshading of terrain tiles with this encoding and light in the cache of
+/// rendered tiles.
+#[must_use]
+pub fn style_key(encoding: DemEncoding) -> String {
+  format!("hillshade_{encoding:?}_{SUN_AZIMUTH}_{SUN_ALTITUDE}").to_lowercase()
+}
+
+/// The brightness of a surface with the given slopes to the east and to the south,
+/// 1 if it faces the sun.
+fn shade(dzdx: f32, dzdy: f32) -> f32 {
+  let (azimuth, altitude) = (SUN_AZIMUTH.to_radians(), SUN_ALTITUDE.to_radians());
+  // Normal and sun direction with the axes east, north, and up.
+  let normal = [-dzdx, dzdy, 1.];
+  let sun = [
+    azimuth.sin() * altitude.cos(),
+    azimuth.cos() * altitude.cos(),
+    altitude.sin(),
+  ];
+  let length = normal.iter().map(|v| v * v).sum::<f32>().sqrt();
+  let dot: f32 = normal.iter().zip(sun).map(|(n, s)| n * s).sum();
+  (dot / length).max(0.)
+}
+
+/// Computes the hillshading of a terrain tile. Shadows are darkened and slopes facing the sun
+/// are lightened, flat areas are transparent.
+///
+/// # Errors
+/// If the tile is no image.
+#[allow(
+  clippy::cast_possible_truncation,
+  clippy::cast_possible_wrap,
+  clippy::cast_precision_loss,
+  clippy::cast_sign_loss
+)]
+pub fn hillshade(data: &[u8], encoding: DemEncoding, tile: &Tile) -> Result<Vec<u8>> {
+  let dem = image::load_from_memory(data)?.to_rgba8();
+  let (width, height) = dem.dimensions();
+  let (nw, se) = tile.position();
+  let center = Coordinate::from(PixelPosition {
+    x: (nw.x + se.x) / 2.,
+    y: (nw.y + se.y) / 2.,
+  });
+  let resolution = meters_per_pixel(center.lat, width as f32 * 2f32.powi(tile.zoom.into()));
+
+  let elevation = |x: i64, y: i64| {
+    let x = x.clamp(0, i64::from(width) - 1) as u32;
+    let y = y.clamp(0, i64::from(height) - 1) as u32;
+    encoding.elevation(*dem.get_pixel(x, y))
+  };
+  let flat = shade(0., 0.);
+  let mut shading = RgbaImage::new(width, height);
+  for (x, y, pixel) in shading.enumerate_pixels_mut() {
+    let (x, y) = (i64::from(x), i64::from(y));
+    // Horn's method with the eight neighbours.
+    let z = |dx, dy| elevation(x + dx, y + dy);
+    let dzdx = ((z(1, -1) + 2. * z(1, 0) + z(1, 1)) - (z(-1, -1) + 2. * z(-1, 0) + z(-1, 1)))
+      / (8. * resolution);
+    let dzdy = ((z(-1, 1) + 2. * z(0, 1) + z(1, 1)) - (z(-1, -1) + 2. * z(0, -1) + z(1, -1)))
+      / (8. * resolution);
+    let brightness = shade(dzdx, dzdy) - flat;
+    *pixel = if brightness < 0. {
+      Rgba([0, 0, 0, (-brightness / flat * 255.).min(255.) as u8])
+    } else {
+      Rgba([
+        255,
+        255,
+        255,
+        (brightness / (1. - flat) * 160.).min(255.) as u8,
+      ])
+    };
+  }
+
+  let mut png = Cursor::new(vec![]);
+  shading.write_to(&mut png, ImageOutputFormat::Png)?;
+  Ok(png.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decode_elevation() {
+    assert!((DemEncoding::Terrarium.elevation(Rgba([128, 0, 0, 255]))).abs() < 0.01);
+    assert!((DemEncoding::Terrarium.elevation(Rgba([128, 100, 128, 255])) - 100.5).abs() < 0.01);
+    assert!((DemEncoding::TerrainRgb.elevation(Rgba([1, 134, 160, 255]))).abs() < 0.01);
+    assert_eq!(
+      DemEncoding::split_prefix("terrarium+https://example.com/{zoom}/{x}/{y}.png"),
+      (
+        Some(DemEncoding::Terrarium),
+        "https://example.com/{zoom}/{x}/{y}.png"
+      )
+    );
+  }
+
+  #[test]
+  fn shade_slopes() {
+    let flat = shade(0., 0.);
+    assert!((flat - SUN_ALTITUDE.to_radians().sin()).abs() < 0.001);
+    // The sun is in the north west, so slopes rising to the east and south face it.
+    assert!(shade(1., 0.) > flat);
+    assert!(shade(-1., 0.) < flat);
+    assert!(shade(0., 1.) > flat);
+    assert!(shade(0., -1.) < flat);
+  }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+/// A rectangle on the screen occupied by a label.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LabelBox {
+  pub x: f32,
+  pub y: f32,
+  pub width: f32,
+  pub height: f32,
+}
+
+impl LabelBox {
+  fn intersects(&self, other: &LabelBox) -> bool {
+    self.x < other.x + other.width
+      && other.x < self.x + self.width
+      && self.y < other.y + other.height
+      && other.y < self.y + self.height
+  }
+}
+
+/// Screen-space index of placed labels. Labels are placed in order of insertion and a label
+/// overlapping an already placed one is rejected.
+#[derive(Debug)]
+pub struct CollisionIndex {
+  cell_size: f32,
+  cells: HashMap<(i32, i32), Vec<usize>>,
+  boxes: Vec<LabelBox>,
+}
+
+impl CollisionIndex {
+  /// * `cell_size` - The edge length of the grid cells in screen pixels. It should be about the
+  ///   size of a typical label.
+  #[must_use]
+  pub fn new(cell_size: f32) -> Self {
+    Self {
+      cell_size,
+      cells: HashMap::new(),
+      boxes: vec![],
+    }
+  }
+
+  #[allow(clippy::cast_possible_truncation)]
+  fn cells(&self, b: &LabelBox) -> impl Iterator<Item = (i32, i32)> {
+    let x0 = (b.x / self.cell_size).floor() as i32;
+    let x1 = ((b.x + b.width) / self.cell_size).floor() as i32;
+    let y0 = (b.y / self.cell_size).floor() as i32;
+    let y1 = ((b.y + b.height) / self.cell_size).floor() as i32;
+    (x0..=x1).flat_map(move |x| (y0..=y1).map(move |y| (x, y)))
+  }
+
+  /// Places the label if it does not overlap a placed one.
+  /// Returns if the label was placed.
+  pub fn try_insert(&mut self, label: LabelBox) -> bool {
+    let collides = self.cells(&label).any(|cell| {
+      self
+        .cells
+        .get(&cell)
+        .is_some_and(|ids| ids.iter().any(|i| self.boxes[*i].intersects(&label)))
+    });
+    if collides {
+      return false;
+    }
+    let id = self.boxes.len();
+    let cells: Vec<_> = self.cells(&label).collect();
+    for cell in cells {
+      self.cells.entry(cell).or_default().push(id);
+    }
+    self.boxes.push(label);
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rejects_overlapping_labels() {
+    let mut index = CollisionIndex::new(50.);
+    let label = |x, y| LabelBox {
+      x,
+      y,
+      width: 80.,
+      height: 14.,
+    };
+    assert!(index.try_insert(label(10., 10.)));
+    assert!(!index.try_insert(label(60., 20.)));
+    assert!(index.try_insert(label(10., 24.)));
+    assert!(index.try_insert(label(95., 10.)));
+    assert!(!index.try_insert(label(-60., 30.)));
+    assert!(index.try_insert(label(-80., 30.)));
+  }
+}
@@ -0,0 +1,97 @@
+use std::str::FromStr;
+
+/// Where `:layer move` puts a layer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Move {
+  /// Drawn one later, so it covers the layer above it.
+  Up,
+  /// Drawn one earlier.
+  Down,
+  Top,
+  Bottom,
+}
+
+impl FromStr for Move {
+  type Err = ();
+  fn from_str(input: &str) -> Result<Move, Self::Err> {
+    match input.to_lowercase().as_str() {
+      "up" => Ok(Move::Up),
+      "down" => Ok(Move::Down),
+      "top" => Ok(Move::Top),
+      "bottom" => Ok(Move::Bottom),
+      _ => Err(()),
+    }
+  }
+}
+
+/// The order in which shape layers and image overlays are drawn, bottom first. Layers are drawn
+/// in the order they arrived until they are moved.
+#[derive(Debug, Default)]
+pub struct LayerOrder {
+  ids: Vec<String>,
+}
+
+impl LayerOrder {
+  /// Puts a new layer on top, known layers keep their place.
+  pub fn add(&mut self, id: &str) {
+    if !self.ids.iter().any(|i| i == id) {
+      self.ids.push(id.to_string());
+    }
+  }
+
+  pub fn remove(&mut self, id: &str) {
+    self.ids.retain(|i| i != id);
+  }
+
+  pub fn clear(&mut self) {
+    self.ids.clear();
+  }
+
+  /// Moves the layer and returns if it is known.
+  pub fn move_layer(&mut self, id: &str, to: Move) -> bool {
+    let Some(i) = self.ids.iter().position(|i| i == id) else {
+      return false;
+    };
+    let layer = self.ids.remove(i);
+    let j = match to {
+      Move::Up => (i + 1).min(self.ids.len()),
+      Move::Down => i.saturating_sub(1),
+      Move::Top => self.ids.len(),
+      Move::Bottom => 0,
+    };
+    self.ids.insert(j, layer);
+    true
+  }
+
+  /// The layer ids from bottom to top.
+  #[must_use]
+  pub fn ids(&self) -> &[String] {
+    &self.ids
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn move_layers() {
+    let mut order = LayerOrder::default();
+    for id in ["a", "b", "c", "b"] {
+      order.add(id);
+    }
+    assert_eq!(order.ids(), ["a", "b", "c"]);
+    assert!(order.move_layer("a", Move::Up));
+    assert_eq!(order.ids(), ["b", "a", "c"]);
+    assert!(order.move_layer("c", Move::Up));
+    assert!(order.move_layer("c", Move::Bottom));
+    assert_eq!(order.ids(), ["c", "b", "a"]);
+    assert!(order.move_layer("c", Move::Down));
+    assert!(order.move_layer("b", Move::Top));
+    assert_eq!(order.ids(), ["c", "a", "b"]);
+    assert!(!order.move_layer("d", Move::Up));
+    order.remove("a");
+    assert_eq!(order.ids(), ["c", "b"]);
+    assert_eq!("TOP".parse(), Ok(Move::Top));
+  }
+}
@@ -1,4 +1,8 @@
+use super::coordinate_format::GridCoordinateFinder;
 use super::coordinates::{Coordinate, Tile};
+use super::geodesic::{circle, great_circle_along};
+use super::style_rule::StyleRule;
+use super::theme::Theme;
 use serde::{Deserialize, Serialize};
 use std::{path::PathBuf, str::FromStr};
 
@@ -16,6 +20,18 @@ static ALL_COLORS: [Color; 11] = [
   Color::Brown,
 ];
 
+/// Colors that are easy to tell apart on the map, in the order they are given to several inputs.
+static PALETTE: [Color; 8] = [
+  Color::Red,
+  Color::Blue,
+  Color::DarkGreen,
+  Color::Brown,
+  Color::DarkRed,
+  Color::DarkBlue,
+  Color::DarkYellow,
+  Color::Black,
+];
+
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub enum Color {
   #[default]
@@ -57,10 +73,53 @@ impl Color {
     self.to_rgba(255)
   }
 
+  /// The color as css hex string, e.g. `#0000ff`.
+  #[must_use]
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  pub fn to_hex(self) -> String {
+    let c = self.to_rgb();
+    format!(
+      "#{:02x}{:02x}{:02x}",
+      (c.r * 255.).round() as u8,
+      (c.g * 255.).round() as u8,
+      (c.b * 255.).round() as u8
+    )
+  }
+
+  /// The color closest to a css hex string like `#7e7e7e` or `#f00`.
+  #[must_use]
+  pub fn from_hex(hex: &str) -> Option<Color> {
+    let digits = hex.strip_prefix('#')?;
+    let channel = |i: usize, len: usize| {
+      let value = u8::from_str_radix(digits.get(i * len..(i + 1) * len)?, 16).ok()?;
+      Some(f32::from(if len == 1 { value * 17 } else { value }) / 255.)
+    };
+    let len = match digits.len() {
+      3 => 1,
+      6 => 2,
+      _ => return None,
+    };
+    let (r, g, b) = (channel(0, len)?, channel(1, len)?, channel(2, len)?);
+    let distance = |c: Color| {
+      let c = c.to_rgb();
+      (c.r - r).powi(2) + (c.g - g).powi(2) + (c.b - b).powi(2)
+    };
+    ALL_COLORS
+      .iter()
+      .copied()
+      .min_by(|x, y| distance(*x).total_cmp(&distance(*y)))
+  }
+
   #[must_use]
   pub fn all() -> &'static [Color] {
     &ALL_COLORS
   }
+
+  /// The color of the n-th of several inputs, repeating after the colors of the palette.
+  #[must_use]
+  pub fn cycled(n: usize) -> Color {
+    PALETTE[n % PALETTE.len()]
+  }
 }
 
 impl FromStr for Color {
@@ -93,10 +152,206 @@ pub enum FillStyle {
   Solid,
 }
 
+/// The lengths of the drawn and the left out parts of dashed lines in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dash {
+  pub on: f32,
+  pub off: f32,
+}
+
+impl FromStr for Dash {
+  type Err = ();
+  /// Reads `dashed`, `dotted`, or the lengths like `10/5`.
+  fn from_str(input: &str) -> Result<Dash, Self::Err> {
+    match input.to_lowercase().as_str() {
+      "dashed" => Ok(Dash { on: 8., off: 6. }),
+      "dotted" => Ok(Dash { on: 2., off: 4. }),
+      lengths => {
+        let (on, off) = lengths.split_once('/').ok_or(())?;
+        let (on, off) = (
+          on.trim().parse().map_err(|_| ())?,
+          off.trim().parse().map_err(|_| ())?,
+        );
+        if on > 0. && off >= 0. {
+          Ok(Dash { on, off })
+        } else {
+          Err(())
+        }
+      }
+    }
+  }
+}
+
+impl Dash {
+  /// Splits the line into its dashes. `scale` converts pixels into the units of the points.
+  #[must_use]
+  pub fn split(self, points: &[(f32, f32)], scale: f32) -> Vec<Vec<(f32, f32)>> {
+    let (on, off) = (self.on * scale, self.off * scale);
+    let mut dashes = vec![];
+    let mut dash = points.first().map(|p| vec![*p]).unwrap_or_default();
+    // The distance left in the current dash or gap.
+    let (mut drawing, mut left) = (true, on);
+    for w in points.windows(2) {
+      let ((x0, y0), (x1, y1)) = (w[0], w[1]);
+      let length = (x1 - x0).hypot(y1 - y0);
+      let mut done = 0.;
+      while length - done > left {
+        done += left;
+        let t = done / length;
+        let point = (x0 + (x1 - x0) * t, y0 + (y1 - y0) * t);
+        if drawing {
+          dash.push(point);
+          dashes.push(std::mem::take(&mut dash));
+        } else {
+          dash.push(point);
+        }
+        drawing = !drawing;
+        left = if drawing { on } else { off };
+      }
+      left -= length - done;
+      if drawing {
+        dash.push((x1, y1));
+      }
+    }
+    if dash.len() > 1 {
+      dashes.push(dash);
+    }
+    dashes
+  }
+}
+
+/// Marks along lines showing the direction from their first to their last coordinate.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Direction {
+  /// An arrowhead at the end.
+  Arrow,
+  /// Chevrons in regular distances along the line.
+  Ticks,
+}
+
+impl FromStr for Direction {
+  type Err = ();
+  fn from_str(input: &str) -> Result<Direction, Self::Err> {
+    match input.to_lowercase().as_str() {
+      "arrow" | "arrows" => Ok(Direction::Arrow),
+      "ticks" => Ok(Direction::Ticks),
+      _ => Err(()),
+    }
+  }
+}
+
+impl Direction {
+  /// The distance between ticks in pixels.
+  const TICK_DISTANCE: f32 = 80.;
+
+  /// The marks for a line of the width in pixels as left wing, tip, and right wing.
+  /// `scale` converts pixels into the units of the points.
+  #[must_use]
+  pub fn marks(self, points: &[(f32, f32)], width: f32, scale: f32) -> Vec<[(f32, f32); 3]> {
+    let size = (2. * width + 6.) * scale;
+    let chevron = |(x, y): (f32, f32), (dx, dy): (f32, f32)| {
+      let (back, side) = ((-dx * size, -dy * size), (-dy * size / 2., dx * size / 2.));
+      [
+        (x + back.0 + side.0, y + back.1 + side.1),
+        (x, y),
+        (x + back.0 - side.0, y + back.1 - side.1),
+      ]
+    };
+    let segments = points.windows(2).filter_map(|w| {
+      let ((x0, y0), (x1, y1)) = (w[0], w[1]);
+      let length = (x1 - x0).hypot(y1 - y0);
+      (length > 0.).then_some((w[0], ((x1 - x0) / length, (y1 - y0) / length), length))
+    });
+    match self {
+      Direction::Arrow => segments
+        .last()
+        .map(|((x, y), direction, length)| {
+          chevron(
+            (x + direction.0 * length, y + direction.1 * length),
+            direction,
+          )
+        })
+        .into_iter()
+        .collect(),
+      Direction::Ticks => {
+        let distance = Self::TICK_DISTANCE * scale;
+        let mut marks = vec![];
+        let mut next = distance / 2.;
+        for ((x, y), direction, length) in segments {
+          while next <= length {
+            marks.push(chevron(
+              (x + direction.0 * next, y + direction.1 * next),
+              direction,
+            ));
+            next += distance;
+          }
+          next -= length;
+        }
+        marks
+      }
+    }
+  }
+}
+
+/// How points are drawn.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub enum Marker {
+  #[default]
+  Circle,
+  Square,
+  Triangle,
+  Cross,
+}
+
+impl FromStr for Marker {
+  type Err = ();
+  fn from_str(input: &str) -> Result<Marker, Self::Err> {
+    match input.to_lowercase().as_str() {
+      "circle" => Ok(Marker::Circle),
+      "square" => Ok(Marker::Square),
+      "triangle" => Ok(Marker::Triangle),
+      "cross" => Ok(Marker::Cross),
+      _ => Err(()),
+    }
+  }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct Style {
   pub color: Color,
   pub fill: FillStyle,
+  /// Lines follow great circles instead of straight lines on the map.
+  #[serde(default)]
+  pub geodesic: bool,
+  /// The line width in pixels, [`Style::DEFAULT_WIDTH`] if not given.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub width: Option<f32>,
+  /// Lines are solid if not given.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub dash: Option<Dash>,
+  /// The radius of points in pixels, [`Style::DEFAULT_RADIUS`] if not given.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub radius: Option<f32>,
+  #[serde(default)]
+  pub marker: Marker,
+  /// Lines are drawn without direction if not given.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub direction: Option<Direction>,
+}
+
+impl Style {
+  pub const DEFAULT_WIDTH: f32 = 3.;
+  pub const DEFAULT_RADIUS: f32 = 3.;
+
+  #[must_use]
+  pub fn line_width(&self) -> f32 {
+    self.width.unwrap_or(Self::DEFAULT_WIDTH)
+  }
+
+  #[must_use]
+  pub fn point_radius(&self) -> f32 {
+    self.radius.unwrap_or(Self::DEFAULT_RADIUS)
+  }
 }
 
 impl FromStr for FillStyle {
@@ -112,12 +367,131 @@ impl FromStr for FillStyle {
   }
 }
 
+/// Where an icon sits on its point.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub enum Anchor {
+  #[default]
+  Center,
+  /// The point is at the bottom of the icon like for a pin.
+  Bottom,
+}
+
+/// An icon drawn instead of the marker of a point.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Icon {
+  /// An image file, an icon of the sprite, a bundled icon like `car`, or a text like an emoji.
+  pub name: String,
+  /// The height in pixels.
+  #[serde(default = "Icon::default_size")]
+  pub size: f32,
+  #[serde(default)]
+  pub anchor: Anchor,
+  /// The zoom level at which the icon has its size. If given, the icon scales with the map.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub zoom: Option<f32>,
+}
+
+impl Icon {
+  fn default_size() -> f32 {
+    20.
+  }
+
+  #[must_use]
+  pub fn new(name: String) -> Self {
+    Self {
+      name,
+      size: Self::default_size(),
+      anchor: Anchor::default(),
+      zoom: None,
+    }
+  }
+
+  /// The icon is read from this image file.
+  #[must_use]
+  pub fn image(&self) -> Option<PathBuf> {
+    let extension = std::path::Path::new(&self.name).extension()?.to_str()?;
+    ["png", "jpg", "jpeg"]
+      .contains(&extension.to_lowercase().as_str())
+      .then(|| PathBuf::from(&self.name))
+  }
+
+  /// The height in pixels at the zoom level.
+  #[must_use]
+  pub fn height(&self, zoom: f32) -> f32 {
+    match self.zoom {
+      Some(icon_zoom) => self.size * 2f32.powf(zoom - icon_zoom).clamp(0.25, 4.),
+      None => self.size,
+    }
+  }
+}
+
+impl FromStr for Icon {
+  type Err = anyhow::Error;
+  /// Reads the name followed by optional `size=<pixels>`, `anchor=center|bottom`, and
+  /// `zoom=<level>` separated by commas, e.g. `bus,size=24,anchor=bottom`.
+  fn from_str(input: &str) -> anyhow::Result<Self> {
+    let mut parts = input.split(',');
+    let name = parts.next().unwrap_or_default().trim();
+    if name.is_empty() {
+      return Err(anyhow::anyhow!("An icon needs a name: {input}"));
+    }
+    let mut icon = Icon::new(name.to_string());
+    for option in parts {
+      match option.trim().split_once('=') {
+        Some(("size", size)) => icon.size = size.trim().parse()?,
+        Some(("anchor", "center")) => icon.anchor = Anchor::Center,
+        Some(("anchor", "bottom")) => icon.anchor = Anchor::Bottom,
+        Some(("zoom", zoom)) => icon.zoom = Some(zoom.trim().parse()?),
+        _ => return Err(anyhow::anyhow!("Unknown icon option: {option}")),
+      }
+    }
+    Ok(icon)
+  }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Shape {
   pub coordinates: Vec<Coordinate>,
   pub style: Style,
   pub visible: bool,
   pub label: Option<String>,
+  /// A longer text about the shape, e.g. the simplestyle `description` of GeoJSON.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  /// Drawn instead of the marker if the shape is a point.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub icon: Option<Icon>,
+  /// Milliseconds since the unix epoch for each coordinate. Empty if the time is unknown.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub timestamps: Vec<i64>,
+  /// Meters above sea level for each coordinate. Empty if the elevation is unknown.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub elevations: Vec<f32>,
+  /// Meters around the single coordinate if the shape is a circle.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub radius: Option<f32>,
+  /// The number of the multi geometry the shape is a part of. Shapes of a layer with the same
+  /// number are selected, counted, and exported as one feature.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub part_of: Option<usize>,
+  /// The attributes of the source, e.g. the properties of a GeoJSON feature.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub properties: Vec<(String, String)>,
+  /// The GeoJSON feature the shape was read from, so it is written again without losing what is
+  /// not read.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source: Option<Box<GeoJsonSource>>,
+}
+
+/// The parts of a GeoJSON feature as they were read.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeoJsonSource {
+  pub id: Option<serde_json::Value>,
+  pub geometry: serde_json::Value,
+  pub properties: serde_json::Map<String, serde_json::Value>,
+  /// The properties the exporter writes for the shape as read. Only those that changed since
+  /// replace the original properties.
+  pub read_as: serde_json::Map<String, serde_json::Value>,
 }
 
 impl Shape {
@@ -147,30 +521,709 @@ impl Shape {
     self.label = label;
     self
   }
+
+  #[must_use]
+  pub fn with_description(mut self, description: Option<String>) -> Self {
+    self.description = description;
+    self
+  }
+
+  #[must_use]
+  pub fn with_icon(mut self, icon: Option<Icon>) -> Self {
+    self.icon = icon;
+    self
+  }
+
+  #[must_use]
+  pub fn with_timestamps(mut self, timestamps: Vec<i64>) -> Self {
+    self.timestamps = timestamps;
+    self
+  }
+
+  #[must_use]
+  pub fn with_elevations(mut self, elevations: Vec<f32>) -> Self {
+    self.elevations = elevations;
+    self
+  }
+
+  #[must_use]
+  pub fn with_radius(mut self, radius: Option<f32>) -> Self {
+    self.radius = radius;
+    self
+  }
+
+  #[must_use]
+  pub fn with_part_of(mut self, part_of: Option<usize>) -> Self {
+    self.part_of = part_of;
+    self
+  }
+
+  #[must_use]
+  pub fn with_properties(mut self, properties: Vec<(String, String)>) -> Self {
+    self.properties = properties;
+    self
+  }
+
+  #[must_use]
+  pub fn with_source(mut self, source: Option<Box<GeoJsonSource>>) -> Self {
+    self.source = source;
+    self
+  }
+
+  /// The center and the radius in meters of a circle.
+  #[must_use]
+  pub fn circle(&self) -> Option<(Coordinate, f32)> {
+    match self.coordinates[..] {
+      [center] => self.radius.filter(|r| *r > 0.).map(|r| (center, r)),
+      _ => None,
+    }
+  }
+
+  #[must_use]
+  pub fn with_geodesic(mut self, geodesic: bool) -> Self {
+    self.style.geodesic = geodesic;
+    self
+  }
+
+  /// The part of the shape between the times with positions interpolated at both ends, `None` if
+  /// it starts later or ends earlier. Shapes without timestamps are always complete.
+  #[must_use]
+  pub fn between(&self, from: i64, to: i64) -> Option<Shape> {
+    if self.timestamps.len() != self.coordinates.len() {
+      return Some(self.clone());
+    }
+    let (first, last) = (*self.timestamps.first()?, *self.timestamps.last()?);
+    if first > to || last < from {
+      return None;
+    }
+    let (from, to) = (from.max(first), to.min(last));
+    let mut times = vec![from];
+    times.extend(
+      self
+        .timestamps
+        .iter()
+        .copied()
+        .filter(|t| from < *t && *t < to),
+    );
+    if to > from {
+      times.push(to);
+    }
+    let points: Vec<_> = times.iter().map(|t| self.at(*t)).collect();
+    Some(Shape {
+      coordinates: points.iter().map(|p| p.0).collect(),
+      elevations: points
+        .iter()
+        .map(|p| p.1)
+        .collect::<Option<Vec<_>>>()
+        .unwrap_or_default(),
+      timestamps: times,
+      ..self.clone()
+    })
+  }
+
+  /// The position and elevation at the time, interpolated between the timestamps around it.
+  #[allow(clippy::cast_precision_loss)]
+  fn at(&self, time: i64) -> (Coordinate, Option<f32>) {
+    let i = self
+      .timestamps
+      .partition_point(|t| *t <= time)
+      .saturating_sub(1);
+    let j = (i + 1).min(self.coordinates.len() - 1);
+    let (t0, t1) = (self.timestamps[i], self.timestamps[j]);
+    let f = if t1 > t0 {
+      (time - t0) as f32 / (t1 - t0) as f32
+    } else {
+      0.
+    };
+    let lerp = |a: f32, b: f32| a + (b - a) * f;
+    let (a, b) = (self.coordinates[i], self.coordinates[j]);
+    let elevation = self
+      .elevations
+      .get(i)
+      .zip(self.elevations.get(j))
+      .map(|(a, b)| lerp(*a, *b));
+    (
+      Coordinate {
+        lat: lerp(a.lat, b.lat),
+        lon: lerp(a.lon, b.lon),
+      },
+      elevation,
+    )
+  }
+
+  /// The parts of a geodesic shape along great circles, split at the antimeridian, and the outline
+  /// of a circle. Other shapes are returned as they are.
+  #[must_use]
+  pub fn geodesic_parts(&self) -> Vec<Shape> {
+    if let Some((center, radius)) = self.circle() {
+      let mut outline = circle(center, radius);
+      if self.style.fill == FillStyle::NoFill {
+        // Only filled shapes are closed.
+        outline.extend(outline.first().copied());
+      }
+      return vec![Shape {
+        coordinates: outline,
+        timestamps: vec![],
+        elevations: vec![],
+        radius: None,
+        ..self.clone()
+      }];
+    }
+    if !self.style.geodesic || self.coordinates.len() < 2 {
+      return vec![self.clone()];
+    }
+    // Filled shapes are closed, so the edge back to the first coordinate is followed as well.
+    let filled = self.style.fill != FillStyle::NoFill;
+    let mut coordinates = self.coordinates.clone();
+    if filled && coordinates.first() != coordinates.last() {
+      coordinates.push(coordinates[0]);
+    }
+    let mut parts = great_circle_along(&coordinates);
+    // A ring crossing the antimeridian an even number of times starts and ends on the same side.
+    // Joining its first and last part lets every part be closed along the antimeridian.
+    if filled && parts.len() > 1 && parts.len() % 2 == 1 {
+      let last = parts.pop().unwrap_or_default();
+      parts[0].splice(0..1, last);
+    }
+    parts
+      .into_iter()
+      .map(|part| {
+        let (coordinates, positions): (Vec<_>, Vec<_>) = part.into_iter().unzip();
+        Shape {
+          coordinates,
+          timestamps: self.along(&self.timestamps, &positions, |a, b, t| {
+            a + ((b - a) as f64 * f64::from(t)).round() as i64
+          }),
+          elevations: self.along(&self.elevations, &positions, |a, b, t| a + (b - a) * t),
+          ..self.clone()
+        }
+      })
+      .collect()
+  }
+
+  /// The values given per coordinate at the positions along the coordinates, see
+  /// [`great_circle_along`]. Empty if not every coordinate has a value.
+  #[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+  )]
+  fn along<T: Copy>(
+    &self,
+    values: &[T],
+    positions: &[f32],
+    lerp: impl Fn(T, T, f32) -> T,
+  ) -> Vec<T> {
+    if values.len() != self.coordinates.len() {
+      return vec![];
+    }
+    positions
+      .iter()
+      .map(|position| {
+        let i = (position.floor() as usize).min(values.len() - 1);
+        let next = values.get(i + 1).copied().unwrap_or(values[i]);
+        lerp(values[i], next, position - i as f32)
+      })
+      .collect()
+  }
+}
+
+/// How the shapes of a layer are shown on the map.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub enum RenderMode {
+  #[default]
+  Shapes,
+  /// The points of the layer are drawn as density raster.
+  Heatmap,
+}
+
+impl FromStr for RenderMode {
+  type Err = ();
+  fn from_str(input: &str) -> Result<RenderMode, Self::Err> {
+    let lowercase = input.to_lowercase();
+    match lowercase.as_str() {
+      "shapes" => Ok(RenderMode::Shapes),
+      "heatmap" => Ok(RenderMode::Heatmap),
+      _ => Err(()),
+    }
+  }
+}
+
+/// How the shapes of a layer are combined with what is drawn below them.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub enum BlendMode {
+  #[default]
+  Normal,
+  /// Adds the colors, so overlapping shapes get brighter.
+  Add,
+  /// Multiplies the colors, so the map below stays visible.
+  Multiply,
+}
+
+impl FromStr for BlendMode {
+  type Err = ();
+  fn from_str(input: &str) -> Result<BlendMode, Self::Err> {
+    match input.to_lowercase().as_str() {
+      "normal" => Ok(BlendMode::Normal),
+      "add" | "additive" => Ok(BlendMode::Add),
+      "multiply" => Ok(BlendMode::Multiply),
+      _ => Err(()),
+    }
+  }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Layer {
   pub id: String,
   pub shapes: Vec<Shape>,
+  #[serde(default)]
+  pub render_mode: RenderMode,
+  /// Replaces the shapes of the layer with the same id instead of adding to them.
+  #[serde(default)]
+  pub replace: bool,
+  /// Rules changing the style of the shapes when they are drawn. They replace the rules of the
+  /// layer unless they are empty.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub rules: Vec<StyleRule>,
+  /// The opacity of the layer from 0 to 1, kept from before if not given.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub opacity: Option<f32>,
+  /// The blend mode of the layer, kept from before if not given.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub blend: Option<BlendMode>,
+  /// If the points of the layer are aggregated into count markers, kept from before if not given.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub cluster: Option<bool>,
 }
 
 impl Layer {
   #[must_use]
   pub fn new(id: String) -> Self {
-    Layer { id, shapes: vec![] }
+    Layer {
+      id,
+      shapes: vec![],
+      render_mode: RenderMode::default(),
+      replace: false,
+      rules: vec![],
+      opacity: None,
+      blend: None,
+      cluster: None,
+    }
+  }
+
+  #[must_use]
+  pub fn with_render_mode(mut self, render_mode: RenderMode) -> Self {
+    self.render_mode = render_mode;
+    self
+  }
+
+  #[must_use]
+  pub fn with_replace(mut self, replace: bool) -> Self {
+    self.replace = replace;
+    self
+  }
+
+  #[must_use]
+  pub fn with_rules(mut self, rules: Vec<StyleRule>) -> Self {
+    self.rules = rules;
+    self
+  }
+
+  #[must_use]
+  pub fn with_opacity(mut self, opacity: Option<f32>) -> Self {
+    self.opacity = opacity.map(|o| o.clamp(0., 1.));
+    self
+  }
+
+  #[must_use]
+  pub fn with_blend(mut self, blend: Option<BlendMode>) -> Self {
+    self.blend = blend;
+    self
+  }
+
+  #[must_use]
+  pub fn with_cluster(mut self, cluster: Option<bool>) -> Self {
+    self.cluster = cluster;
+    self
+  }
+
+  /// Draws all lines of the layer as great circles.
+  #[must_use]
+  pub fn with_geodesic(mut self, geodesic: bool) -> Self {
+    if geodesic {
+      self.shapes = self
+        .shapes
+        .into_iter()
+        .map(|s| s.with_geodesic(true))
+        .collect();
+    }
+    self
+  }
+
+  /// Gives the shapes without a width, dash, point radius, marker, or direction of their own the
+  /// ones of the style.
+  #[must_use]
+  pub fn with_default_style(mut self, defaults: Style) -> Self {
+    for shape in &mut self.shapes {
+      let style = &mut shape.style;
+      style.width = style.width.or(defaults.width);
+      style.dash = style.dash.or(defaults.dash);
+      style.radius = style.radius.or(defaults.radius);
+      if style.marker == Marker::default() {
+        style.marker = defaults.marker;
+      }
+      style.direction = style.direction.or(defaults.direction);
+    }
+    self
+  }
+
+  /// Gives the points without an icon of their own the icon.
+  #[must_use]
+  pub fn with_default_icon(mut self, icon: Option<&Icon>) -> Self {
+    if let Some(icon) = icon {
+      for shape in &mut self.shapes {
+        if shape.coordinates.len() == 1 && shape.icon.is_none() {
+          shape.icon = Some(icon.clone());
+        }
+      }
+    }
+    self
+  }
+}
+
+/// An image stretched between two corners on the map, e.g. a floor plan or a scanned map.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageOverlay {
+  /// An overlay replaces the one with the same id.
+  pub id: String,
+  /// The png or jpeg file. It has to be readable by mapvas.
+  pub path: PathBuf,
+  pub nw: Coordinate,
+  pub se: Coordinate,
+  #[serde(default = "ImageOverlay::default_opacity")]
+  pub opacity: f32,
+}
+
+impl ImageOverlay {
+  #[must_use]
+  pub fn new(path: PathBuf, nw: Coordinate, se: Coordinate) -> Self {
+    Self {
+      id: path.to_string_lossy().to_string(),
+      path,
+      nw,
+      se,
+      opacity: Self::default_opacity(),
+    }
+  }
+
+  #[must_use]
+  pub fn with_opacity(mut self, opacity: f32) -> Self {
+    self.opacity = opacity.clamp(0., 1.);
+    self
+  }
+
+  fn default_opacity() -> f32 {
+    0.7
   }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Focus {}
 
+/// A view of the map.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Camera {
+  pub center: Coordinate,
+  /// The zoom level as in tile urls, 0 shows the whole world in one tile.
+  pub zoom: f32,
+  /// The rotation in degrees clockwise, 0 is north up.
+  #[serde(default)]
+  pub rotation: f32,
+}
+
+/// A change of the map that is sent to the subscribers of the remote api.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MapUpdate {
+  LayerAdded {
+    id: String,
+    shapes: usize,
+  },
+  Cleared,
+  LayerCleared {
+    id: String,
+  },
+  Focus,
+  /// The map was moved, zoomed, or rotated.
+  View {
+    camera: Camera,
+  },
+  /// A screenshot was written to the file, or failed to be.
+  Screenshot {
+    path: PathBuf,
+  },
+  /// The layers were exported to the file, or failed to be.
+  Exported {
+    path: PathBuf,
+  },
+}
+
+/// Where the map should go, given by the remote api and `mapcat --goto`.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum View {
+  /// Centers the map on the position. The zoom level is kept if it is not given.
+  Center {
+    center: Coordinate,
+    zoom: Option<f32>,
+  },
+  /// Shows the area given as `[lat1, lon1, lat2, lon2]`.
+  BoundingBox { bbox: [f32; 4] },
+}
+
+/// Reads a position like `52.5,13.4` or `32U NV 123 456` with an optional zoom level like
+/// `52.5,13.4,z14`, or a bounding box like `52.6,13.3,52.4,13.5`.
+impl FromStr for View {
+  type Err = ();
+  fn from_str(target: &str) -> Result<View, Self::Err> {
+    let values: Option<Vec<f32>> = target.split(',').map(|v| v.trim().parse().ok()).collect();
+    if let Some(&[lat1, lon1, lat2, lon2]) = values.as_deref() {
+      return Ok(View::BoundingBox {
+        bbox: [lat1, lon1, lat2, lon2],
+      });
+    }
+    let (position, zoom) = match target.rsplit_once(',') {
+      Some((position, zoom)) if zoom.trim().starts_with('z') => {
+        (position, zoom.trim()[1..].parse().ok())
+      }
+      _ => (target, None),
+    };
+    let center = match position.split_once(',') {
+      Some((lat, lon)) => Coordinate {
+        lat: lat.trim().parse().map_err(|_| ())?,
+        lon: lon.trim().parse().map_err(|_| ())?,
+      },
+      None => *GridCoordinateFinder::new()
+        .find(position)
+        .first()
+        .ok_or(())?,
+    };
+    if center.is_valid() {
+      Ok(View::Center { center, zoom })
+    } else {
+      Err(())
+    }
+  }
+}
+
+/// The file formats the current layers can be written to.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub enum ExportFormat {
+  #[default]
+  GeoJson,
+  Gpx,
+  Kml,
+}
+
+impl ExportFormat {
+  /// Guesses the format from the file extension.
+  #[must_use]
+  pub fn from_path(path: &std::path::Path) -> Option<Self> {
+    path
+      .extension()
+      .and_then(|e| e.to_str())
+      .and_then(|e| Self::from_str(e).ok())
+  }
+}
+
+impl FromStr for ExportFormat {
+  type Err = ();
+  fn from_str(input: &str) -> Result<ExportFormat, Self::Err> {
+    let lowercase = input.to_lowercase();
+    match lowercase.as_str() {
+      "geojson" | "json" => Ok(ExportFormat::GeoJson),
+      "gpx" => Ok(ExportFormat::Gpx),
+      "kml" => Ok(ExportFormat::Kml),
+      _ => Err(()),
+    }
+  }
+}
+
+/// The tile caches on disk.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CacheKind {
+  /// The downloaded tiles.
+  Tiles,
+  /// Tiles computed from downloaded ones like hillshading.
+  Rendered,
+}
+
+impl FromStr for CacheKind {
+  type Err = ();
+  fn from_str(input: &str) -> Result<CacheKind, Self::Err> {
+    let lowercase = input.to_lowercase();
+    match lowercase.as_str() {
+      "tiles" | "raster" => Ok(CacheKind::Tiles),
+      "rendered" | "hillshade" => Ok(CacheKind::Rendered),
+      _ => Err(()),
+    }
+  }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum MapEvent {
   Shutdown,
   Clear,
-  TileDataArrived { tile: Tile, data: Vec<u8> },
+  TileDataArrived {
+    /// The tile layer the tile belongs to.
+    #[serde(default)]
+    layer: usize,
+    tile: Tile,
+    width: u32,
+    height: u32,
+    /// The decoded rgba pixels.
+    data: Vec<u8>,
+  },
   Layer(Layer),
   Focus,
   Screenshot(PathBuf),
+  Export {
+    format: ExportFormat,
+    path: PathBuf,
+  },
+  /// Rotates the map to the given angle in degrees clockwise. 0 is north up.
+  Rotation(f32),
+  ImageOverlay(ImageOverlay),
+  Theme(Theme),
+  /// Removes the tiles of a cache from disk and loads the visible tiles again.
+  ClearCache(CacheKind),
+  /// Stores the current view under the name.
+  AddBookmark(String),
+  /// Shows the view stored under the name.
+  GoToBookmark(String),
+  /// Moves the map to the view in an animation.
+  SetView(View),
+  /// Removes the layer with the id and keeps the others.
+  ClearLayer(String),
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn dashes() {
+    let dash: Dash = "2/1".parse().unwrap();
+    let dashes = dash.split(&[(0., 0.), (4., 0.), (4., 3.)], 1.);
+    assert_eq!(
+      dashes,
+      vec![
+        vec![(0., 0.), (2., 0.)],
+        vec![(3., 0.), (4., 0.), (4., 1.)],
+        vec![(4., 2.), (4., 3.)],
+      ]
+    );
+    assert_eq!("dotted".parse(), Ok(Dash { on: 2., off: 4. }));
+    assert!("0/2".parse::<Dash>().is_err());
+  }
+
+  #[test]
+  fn direction_marks() {
+    let line = [(0., 0.), (100., 0.), (100., 90.)];
+    let arrow = Direction::Arrow.marks(&line, 2., 1.);
+    assert_eq!(arrow, vec![[(95., 80.), (100., 90.), (105., 80.)]]);
+    let ticks = Direction::Ticks.marks(&line, 2., 1.);
+    assert_eq!(ticks.len(), 2);
+    assert_eq!(ticks[0][1], (40., 0.));
+    assert_eq!(ticks[1][1], (100., 20.));
+  }
+
+  #[test]
+  fn icons() {
+    let icon: Icon = "bus,size=24,anchor=bottom,zoom=15".parse().unwrap();
+    assert_eq!(icon.name, "bus");
+    assert_eq!(icon.anchor, Anchor::Bottom);
+    assert!((icon.height(16.) - 48.).abs() < 0.001);
+    assert!(icon.image().is_none());
+    let image: Icon = "icons/truck.PNG".parse().unwrap();
+    assert_eq!(image.image(), Some(PathBuf::from("icons/truck.PNG")));
+    assert!((image.height(3.) - 20.).abs() < 0.001);
+    assert!("bus,color=red".parse::<Icon>().is_err());
+  }
+
+  #[test]
+  fn cycled_colors() {
+    assert_eq!(Color::cycled(0), Color::Red);
+    assert_eq!(Color::cycled(1), Color::Blue);
+    assert_eq!(Color::cycled(8), Color::cycled(0));
+  }
+
+  #[test]
+  fn closest_color() {
+    assert_eq!(Color::from_hex("#f00"), Some(Color::Red));
+    assert_eq!(Color::from_hex("#7e7e7e"), Some(Color::Grey));
+    assert_eq!(Color::from_hex("#a05010"), Some(Color::Brown));
+    assert_eq!(Color::from_hex("red"), None);
+  }
+
+  #[test]
+  fn shape_between() {
+    let c = |lon| Coordinate { lat: 0., lon };
+    let shape = Shape::new(vec![c(0.), c(1.), c(2.)]).with_timestamps(vec![10, 20, 30]);
+    assert!(shape.between(0, 5).is_none());
+    assert!(shape.between(35, 40).is_none());
+    let part = shape.between(i64::MIN, 25).unwrap();
+    assert_eq!(part.timestamps, vec![10, 20, 25]);
+    assert_eq!(part.coordinates[2], c(1.5));
+    let part = shape
+      .with_elevations(vec![100., 200., 100.])
+      .between(15, 40)
+      .unwrap();
+    assert_eq!(part.coordinates, vec![c(0.5), c(1.), c(2.)]);
+    assert_eq!(part.elevations, vec![150., 200., 100.]);
+    assert_eq!(
+      Shape::new(vec![c(0.)])
+        .between(0, 0)
+        .unwrap()
+        .coordinates
+        .len(),
+      1
+    );
+  }
+
+  #[test]
+  fn geodesic_parts() {
+    let c = |lat, lon| Coordinate { lat, lon };
+    let track = Shape::new(vec![c(0., 170.), c(0., -170.), c(0., -160.)])
+      .with_timestamps(vec![0, 100, 200])
+      .with_geodesic(true);
+    let parts = track.geodesic_parts();
+    assert_eq!(parts.len(), 2);
+    for part in &parts {
+      assert_eq!(part.timestamps.len(), part.coordinates.len());
+    }
+    let timestamps: Vec<i64> = parts.iter().flat_map(|p| p.timestamps.clone()).collect();
+    assert!(timestamps.windows(2).all(|t| t[0] <= t[1]));
+    assert_eq!(parts[0].timestamps.first(), Some(&0));
+    assert_eq!(parts[0].timestamps.last(), Some(&50));
+    assert_eq!(parts[1].timestamps.last(), Some(&200));
+
+    let polygon = Shape::new(vec![
+      c(10., 170.),
+      c(10., -170.),
+      c(-10., -170.),
+      c(-10., 170.),
+    ])
+    .with_fill(FillStyle::Transparent)
+    .with_geodesic(true);
+    let parts = polygon.geodesic_parts();
+    assert_eq!(parts.len(), 2);
+    for part in parts {
+      let ends = [
+        part.coordinates[0],
+        part.coordinates[part.coordinates.len() - 1],
+      ];
+      assert!(ends.iter().all(|c| (c.lon.abs() - 180.).abs() < 0.001));
+    }
+  }
 }
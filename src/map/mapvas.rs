@@ -1,23 +1,64 @@
+mod commands;
+mod comparison;
+mod drawing;
+mod editing;
+mod input;
+mod layers;
+mod overlays;
+mod selection;
+mod table;
+mod widgets;
+
 use super::{
+  annotation::{Sketch, Tool},
+  attribute_table::AttributeTable,
+  bookmark::{Bookmarks, Marks},
+  command_line::CommandLine,
+  compare::Compare,
+  coordinate_format::{status, CoordinateFormat},
   coordinates::CANVAS_SIZE,
-  coordinates::{
-    tiles_in_box, BoundingBox, Coordinate, PixelPosition, Tile, TileCoordinate, TILE_SIZE,
+  coordinates::{simplify, BoundingBox, Coordinate, PixelPosition, Tile},
+  flight::{flight_duration, Flight},
+  frame_stats::FrameStats,
+  heatmap::{HeatmapInput, HeatmapSettings},
+  layer_order::LayerOrder,
+  map_event::{
+    BlendMode, CacheKind, Camera, ExportFormat, GeoJsonSource, Icon, ImageOverlay, Layer, MapEvent,
+    MapUpdate, RenderMode, Shape, Style, View,
   },
-  map_event::FillStyle,
-  map_event::{Layer, MapEvent, Style},
-  tile_loader::{CachedTileLoader, TileLoader},
+  outline,
+  query::Query,
+  scale::ScaleUnit,
+  selection::{Area, Selection},
+  sprite::{Sprite, SpriteIcon},
+  style_rule::{prepare, Feature, StyleRule},
+  theme::Theme,
+  tile_loader::{decode_tile, tile_layers_from_env, CachedTileLoader, TileLayerConfig, TileLoader},
+  time_zone::TimeZone,
+  timeline::Tracks,
+  track_stats::TrackStats,
+  viewport::Viewport,
 };
 
-use crate::parser::{AutoFileParser, GrepParser, Parser};
+use crate::export::export_to_file;
+
+use table::TABLE_ROWS;
 
-use std::{cmp::max, collections::HashMap, path::PathBuf};
-use std::{num::NonZeroU32, sync::Arc};
+use std::{
+  cmp::max,
+  collections::{HashMap, HashSet},
+  path::PathBuf,
+};
+use std::{
+  num::NonZeroU32,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
 
 use arboard::Clipboard;
-use async_std::task::block_on;
 use chrono::{DateTime, Local};
 use femtovg::{renderer::OpenGl, Canvas, Path};
-use femtovg::{Color, ImageFlags, ImageId, Paint};
+use femtovg::{rgb::RGBA8, ImageFlags, ImageId};
 use glutin::prelude::*;
 use glutin::{
   config::ConfigTemplateBuilder,
@@ -26,13 +67,16 @@ use glutin::{
   surface::{SurfaceAttributesBuilder, WindowSurface},
 };
 use glutin_winit::DisplayBuilder;
-use log::{debug, info, trace};
+use log::{error, info, trace};
 use raw_window_handle::HasRawWindowHandle;
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{
+  broadcast,
+  mpsc::{Receiver, Sender},
+};
 use winit::{
   dpi::PhysicalPosition,
   event::{
-    ElementState, Event, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+    ElementState, Event, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta, WindowEvent,
   },
   event_loop::{ControlFlow, EventLoop, EventLoopBuilder, EventLoopProxy},
   window::{Window, WindowBuilder},
@@ -43,43 +87,311 @@ fn current_time_string() -> String {
   format!("{current}")
 }
 
+fn copy_to_clipboard(text: &str) {
+  if let Err(e) = Clipboard::new().and_then(|mut c| c.set_text(text)) {
+    error!("Cannot copy to the clipboard: {e}");
+  }
+}
+
+/// A short name of a tile url shown when switching tile layers, e.g. the host.
+fn tile_layer_name(url_template: &str) -> String {
+  let without_scheme = url_template
+    .split_once("://")
+    .map_or(url_template, |(_, rest)| rest);
+  if url_template.starts_with("mbtiles://") {
+    return std::path::Path::new(without_scheme)
+      .file_name()
+      .map_or(without_scheme.to_string(), |n| {
+        n.to_string_lossy().to_string()
+      });
+  }
+  without_scheme
+    .split('/')
+    .next()
+    .unwrap_or(without_scheme)
+    .to_string()
+}
+
+/// A label drawn next to its element.
+struct ElementLabel {
+  x: f32,
+  y: f32,
+  text: String,
+  icon: Option<SpriteIcon>,
+}
+
+/// The height of the status bar in screen pixels.
+const STATUS_HEIGHT: f32 = 20.;
+
+/// The first characters of a label drawn on the map.
+fn truncate_label(label: &str) -> String {
+  const MAX_CHARS: usize = 40;
+  if label.chars().count() <= MAX_CHARS {
+    return label.to_string();
+  }
+  let mut truncated: String = label.chars().take(MAX_CHARS - 1).collect();
+  truncated.push('…');
+  truncated
+}
+
+fn build_path(positions: &[PixelPosition], close_path: bool) -> Path {
+  let mut path = Path::new();
+  outline::polyline(&mut path, &to_points(positions), close_path);
+  path
+}
+
+fn to_points(positions: &[PixelPosition]) -> Vec<(f32, f32)> {
+  positions.iter().map(|p| (p.x, p.y)).collect()
+}
+
+/// A path with precomputed simplifications for lower zoom levels.
+#[derive(Debug)]
+struct PathLod {
+  full: Path,
+  /// Simplified paths with their tolerance in canvas pixels, ordered from coarse to fine.
+  simplified: Vec<(f32, Path)>,
+}
+
+impl PathLod {
+  /// Shapes with fewer points are not worth simplifying.
+  const MIN_POINTS: usize = 64;
+
+  fn new(positions: &[PixelPosition], close_path: bool) -> Self {
+    let mut simplified = vec![];
+    if positions.len() >= Self::MIN_POINTS {
+      let mut tolerance = 1.;
+      while tolerance > 1e-6 {
+        let points = simplify(positions, tolerance);
+        if points.len() * 10 > positions.len() * 9 {
+          break;
+        }
+        simplified.push((tolerance, build_path(&points, close_path)));
+        tolerance /= 4.;
+      }
+    }
+    Self {
+      full: build_path(positions, close_path),
+      simplified,
+    }
+  }
+
+  /// The coarsest path that does not deviate more than `tolerance` from the original.
+  fn path(&self, tolerance: f32) -> &Path {
+    self
+      .simplified
+      .iter()
+      .find(|(t, _)| *t <= tolerance)
+      .map_or(&self.full, |(_, p)| p)
+  }
+}
+
+/// The times and elevations of the positions of an element, empty if they are unknown, and what
+/// else it keeps of its shape.
+#[derive(Debug, Default, Clone)]
+struct Track {
+  timestamps: Vec<i64>,
+  elevations: Vec<f32>,
+  /// The center and radius in meters of a circle, whose positions are its outline.
+  circle: Option<(Coordinate, f32)>,
+  /// The number of the multi geometry the element is a part of, unique in its layer.
+  part_of: Option<usize>,
+  properties: Vec<(String, String)>,
+  source: Option<Box<GeoJsonSource>>,
+}
+
+/// The number after the multi geometries of the elements.
+fn next_part(elements: &[(LayerElement, Style)]) -> usize {
+  elements
+    .iter()
+    .filter_map(|(e, _)| e.track().part_of)
+    .max()
+    .map_or(0, |last| last + 1)
+}
+
+/// Moves the numbers of the multi geometries of added elements after the existing ones, so parts
+/// read from different inputs stay apart.
+fn renumber_parts(elements: &mut [(LayerElement, Style)], start: usize) {
+  for (element, _) in elements {
+    if let Some(part_of) = &mut element.track_mut().part_of {
+      *part_of += start;
+    }
+  }
+}
+
 #[derive(Debug)]
 enum LayerElement {
-  Polyline(Path, BoundingBox, Vec<PixelPosition>, Option<String>),
-  Point(PixelPosition, Option<String>),
+  Polyline(
+    PathLod,
+    BoundingBox,
+    Vec<PixelPosition>,
+    Option<String>,
+    Track,
+  ),
+  Point(PixelPosition, Option<String>, Option<Icon>, Track),
 }
 
 impl LayerElement {
+  /// Builds the drawable element for the given positions. A single position becomes a point.
+  pub fn from_positions(positions: Vec<PixelPosition>, close_path: bool) -> Self {
+    if positions.len() == 1 {
+      return Self::Point(positions[0], None, None, Track::default());
+    }
+    Self::Polyline(
+      PathLod::new(&positions, close_path),
+      BoundingBox::from_iterator(positions.iter().copied()),
+      positions,
+      None,
+      Track::default(),
+    )
+  }
+
+  pub fn positions(&self) -> Vec<PixelPosition> {
+    match self {
+      Self::Polyline(_, _, coords, ..) => coords.clone(),
+      Self::Point(p, ..) => vec![*p],
+    }
+  }
+
+  /// If the element may be within the distance of the position.
+  fn is_near(&self, p: PixelPosition, distance: f32) -> bool {
+    match self {
+      Self::Point(point, ..) => point.sq_dist(&p) <= distance * distance,
+      Self::Polyline(_, bb, ..) => bb.is_near(p, distance),
+    }
+  }
+
+  fn add_to_bounding_box(&self, bb: &mut BoundingBox) {
+    match self {
+      Self::Point(p, ..) => bb.add_coordinate(*p),
+      Self::Polyline(_, b, ..) => bb.extend(b),
+    }
+  }
+
+  /// What style rules see of the element.
+  fn feature(&self) -> Feature<'_> {
+    match self {
+      Self::Polyline(_, _, positions, label, track) => Feature {
+        label: label.as_deref(),
+        points: positions.len(),
+        properties: &track.properties,
+      },
+      Self::Point(_, label, .., track) => Feature {
+        label: label.as_deref(),
+        points: 1,
+        properties: &track.properties,
+      },
+    }
+  }
+
   pub fn with_text(self, text: Option<String>) -> Self {
     match self {
-      Self::Point(p, _) => Self::Point(p, text),
-      Self::Polyline(a, b, c, _) => Self::Polyline(a, b, c, text),
+      Self::Point(p, _, icon, track) => Self::Point(p, text, icon, track),
+      Self::Polyline(a, b, c, _, track) => Self::Polyline(a, b, c, text, track),
+    }
+  }
+
+  /// Sets the icon of a point, lines have none.
+  pub fn with_icon(self, icon: Option<Icon>) -> Self {
+    match self {
+      Self::Point(p, text, _, track) => Self::Point(p, text, icon, track),
+      polyline @ Self::Polyline(..) => polyline,
+    }
+  }
+
+  fn with_track(self, track: Track) -> Self {
+    match self {
+      Self::Polyline(a, b, c, text, _) => Self::Polyline(a, b, c, text, track),
+      Self::Point(p, text, icon, _) => Self::Point(p, text, icon, track),
+    }
+  }
+
+  fn track(&self) -> &Track {
+    match self {
+      Self::Polyline(.., track) | Self::Point(.., track) => track,
+    }
+  }
+
+  fn track_mut(&mut self) -> &mut Track {
+    match self {
+      Self::Polyline(.., track) | Self::Point(.., track) => track,
+    }
+  }
+
+  /// If the element has timestamps within the time window. Elements without timestamps are always
+  /// shown.
+  fn visible_in(&self, window: Option<(i64, i64)>) -> bool {
+    let timestamps = &self.track().timestamps;
+    match (window, timestamps.first(), timestamps.last()) {
+      (Some((from, to)), Some(first), Some(last)) => *first <= to && *last >= from,
+      _ => true,
+    }
+  }
+
+  pub fn icon(&self) -> Option<&Icon> {
+    match self {
+      Self::Point(_, _, icon, _) => icon.as_ref(),
+      Self::Polyline(..) => None,
     }
   }
 
   pub fn sq_distance_to_point(&self, p: PixelPosition, point_preference: f32) -> f32 {
     match self {
-      Self::Polyline(_, _, coords, _) => coords
+      Self::Polyline(_, _, coords, ..) => coords
         .windows(2)
         .map(|points| p.sq_distance_line_segment(&points[0], &points[1]))
         .fold(f32::MAX, f32::min),
-      Self::Point(PixelPosition { x, y }, _) => {
+      Self::Point(PixelPosition { x, y }, ..) => {
         (p.x - x) * (p.x - x) + (p.y - y) * (p.y - y) - point_preference
       }
     }
   }
 
+  fn text_mut(&mut self) -> &mut Option<String> {
+    match self {
+      Self::Polyline(_, _, _, t, _) | Self::Point(_, t, ..) => t,
+    }
+  }
+
   pub fn get_text(&self) -> Option<String> {
     match self {
-      Self::Polyline(_, _, _, t) | Self::Point(_, t) => t.clone(),
+      Self::Polyline(_, _, _, t, _) | Self::Point(_, t, ..) => t.clone(),
     }
   }
 
   pub fn has_text(&self) -> bool {
     match self {
-      Self::Polyline(_, _, _, t) | Self::Point(_, t) => t.is_some(),
+      Self::Polyline(_, _, _, t, _) | Self::Point(_, t, ..) => t.is_some(),
     }
   }
+
+  /// Converts the element back into a shape with geographic coordinates.
+  pub fn to_shape(&self, style: Style) -> Shape {
+    let track = self.track().clone();
+    let coordinates = match (self, track.circle) {
+      (_, Some((center, _))) => vec![center],
+      (Self::Polyline(_, _, coords, ..), None) => coords.iter().copied().map(Into::into).collect(),
+      (Self::Point(p, ..), None) => vec![(*p).into()],
+    };
+    let mut shape = Shape::new(coordinates)
+      .with_label(self.get_text())
+      .with_icon(self.icon().cloned())
+      .with_timestamps(track.timestamps)
+      .with_elevations(track.elevations)
+      .with_radius(track.circle.map(|(_, radius)| radius))
+      .with_part_of(track.part_of)
+      .with_properties(track.properties)
+      .with_source(track.source);
+    shape.style = style;
+    shape
+  }
+}
+
+/// The element whose vertices are currently edited.
+#[derive(Debug)]
+struct EditState {
+  layer: String,
+  index: usize,
+  dragged_vertex: Option<usize>,
 }
 
 #[allow(clippy::struct_field_names)]
@@ -87,50 +399,99 @@ struct MapEventHander {
   event_proxy: EventLoopProxy<MapEvent>,
   event_receiver: Option<Receiver<MapEvent>>,
   event_sender: Sender<MapEvent>,
+  /// Tells the subscribers of the remote api about changes.
+  update_sender: broadcast::Sender<MapUpdate>,
 }
 
-struct MapProvider {
+/// The tiles of one tile url. Later tile layers are drawn on top of earlier ones.
+struct TileLayer {
+  /// Identifies the layer of downloaded tiles independent of the drawing order.
+  id: usize,
+  name: String,
+  opacity: f32,
   loaded_images: HashMap<Tile, ImageId>,
-  layers: HashMap<String, Vec<(LayerElement, Style)>>,
+  /// Tiles that are loaded and decoded in the background.
+  pending: Arc<Mutex<HashSet<Tile>>>,
   tile_loader: Arc<CachedTileLoader>,
-  event_sender: Sender<MapEvent>,
 }
 
-impl MapProvider {
-  fn new(tile_loader: CachedTileLoader, event_sender: Sender<MapEvent>) -> Self {
-    Self {
-      tile_loader: Arc::new(tile_loader),
-      event_sender,
-      loaded_images: HashMap::default(),
-      layers: HashMap::default(),
+impl TileLayer {
+  /// The number of tiles loaded at the same time. Further tiles are requested in later frames.
+  const MAX_PENDING: usize = 32;
+
+  /// Loads and decodes the tile off the UI thread and sends the pixels as event.
+  fn request(&self, tile: Tile, event_sender: &Sender<MapEvent>) {
+    {
+      let mut pending = self.pending.lock().expect("not poisoned");
+      if pending.len() >= Self::MAX_PENDING || !pending.insert(tile) {
+        return;
+      }
     }
+    let tile_loader = self.tile_loader.clone();
+    let pending = self.pending.clone();
+    let sender = event_sender.clone();
+    let layer = self.id;
+    tokio::spawn(async move {
+      if let Ok(data) = tile_loader.tile_data(&tile).await {
+        Self::send_tile(layer, tile, data, &sender).await;
+        // Stale tiles are shown until the new version arrives.
+        if tile_loader.is_stale(&tile) {
+          if let Ok(data) = tile_loader.refresh(&tile).await {
+            Self::send_tile(layer, tile, data, &sender).await;
+          }
+        }
+      }
+      pending.lock().expect("not poisoned").remove(&tile);
+    });
   }
 
-  fn layers_bounding_box(&self) -> Option<BoundingBox> {
-    let mut bb = BoundingBox::get_invalid();
-    self
-      .layers
-      .iter()
-      .flat_map(|(_, elements)| elements.iter())
-      .for_each(|e| match &e.0 {
-        LayerElement::Point(p, _) => bb.add_coordinate(*p),
-        LayerElement::Polyline(_, b, _, _) => bb.extend(b),
-      });
-    bb.is_valid().then_some(bb)
+  /// Requests tiles that are likely needed soon while few tiles are loading.
+  fn prefetch(&self, tiles: &[Tile], limit: usize, event_sender: &Sender<MapEvent>) {
+    let missing: Vec<Tile> = {
+      let pending = self.pending.lock().expect("not poisoned");
+      if pending.len() >= Self::MAX_PENDING / 2 {
+        return;
+      }
+      tiles
+        .iter()
+        .filter(|t| !self.loaded_images.contains_key(t) && !pending.contains(t))
+        .take(limit)
+        .copied()
+        .collect()
+    };
+    for tile in missing {
+      self.request(tile, event_sender);
+    }
   }
 
-  fn find_image_or_download(&self, tile: Tile) -> Option<(Tile, &ImageId)> {
+  async fn send_tile(layer: usize, tile: Tile, data: Vec<u8>, sender: &Sender<MapEvent>) {
+    match tokio::task::spawn_blocking(move || decode_tile(&data)).await {
+      Ok(Ok(image)) => {
+        let (width, height) = image.dimensions();
+        let _ = sender
+          .send(MapEvent::TileDataArrived {
+            layer,
+            tile,
+            width,
+            height,
+            data: image.into_raw(),
+          })
+          .await;
+      }
+      _ => info!("Tile {tile:?} image decoding problem"),
+    }
+  }
+
+  fn find_image_or_download(
+    &self,
+    tile: Tile,
+    event_sender: &Sender<MapEvent>,
+  ) -> Option<(Tile, &ImageId)> {
     let image_id = self.loaded_images.get(&tile);
     if let Some(id) = image_id {
       Some((tile, id))
     } else {
-      let tile_loader = self.tile_loader.clone();
-      let sender = self.event_sender.clone();
-      tokio::spawn(async move {
-        if let Ok(data) = tile_loader.tile_data(&tile).await {
-          let _ = sender.send(MapEvent::TileDataArrived { tile, data }).await;
-        }
-      });
+      self.request(tile, event_sender);
       // Load parent tile instead
       let mut parent = tile.parent();
       while let Some(current_tile) = parent {
@@ -143,17 +504,216 @@ impl MapProvider {
       None
     }
   }
+}
 
-  fn add_tile_image(&mut self, tile: Tile, image_id: ImageId) {
-    self.loaded_images.insert(tile, image_id);
+struct MapProvider {
+  tile_layers: Vec<TileLayer>,
+  /// The index of the tile layer changed by the keyboard.
+  selected_tile_layer: usize,
+  /// The number of tiles requested ahead per frame, see `MAPVAS_PREFETCH`.
+  prefetch: usize,
+  /// The center of the last drawn map and the direction of the last panning.
+  last_center: Option<PixelPosition>,
+  pan_direction: (f32, f32),
+  layers: HashMap<String, Vec<(LayerElement, Style)>>,
+  render_modes: HashMap<String, RenderMode>,
+  rules: HashMap<String, Vec<StyleRule>>,
+  opacities: HashMap<String, f32>,
+  blend_modes: HashMap<String, BlendMode>,
+  /// The layers whose points are aggregated into count markers.
+  clustered: HashSet<String>,
+  /// Only the elements matching it are drawn, set by `:filter`.
+  filter: Option<Query>,
+  selection: Selection,
+  /// Elements hidden by `:selection hide`.
+  hidden: Selection,
+  /// Only the elements within the closed elements of the layer are drawn, set by `:within`.
+  within: Option<String>,
+  /// The elements outside of the `within` layer.
+  outside: Selection,
+  /// The draw order of the layers and the overlays.
+  order: LayerOrder,
+  event_sender: Sender<MapEvent>,
+}
+
+impl MapProvider {
+  fn new(tile_layers: Vec<TileLayerConfig>, event_sender: Sender<MapEvent>) -> Self {
+    let tile_layers = tile_layers
+      .into_iter()
+      .enumerate()
+      .map(|(id, config)| TileLayer {
+        id,
+        name: tile_layer_name(&config.url_template),
+        opacity: config.opacity,
+        loaded_images: HashMap::default(),
+        pending: Arc::default(),
+        tile_loader: Arc::new(
+          CachedTileLoader::new(&config.url_template).with_headers(&config.headers),
+        ),
+      })
+      .collect();
+    Self {
+      tile_layers,
+      selected_tile_layer: 0,
+      prefetch: std::env::var("MAPVAS_PREFETCH")
+        .ok()
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(16),
+      last_center: None,
+      pan_direction: (0., 0.),
+      event_sender,
+      layers: HashMap::default(),
+      render_modes: HashMap::default(),
+      rules: HashMap::default(),
+      opacities: HashMap::default(),
+      blend_modes: HashMap::default(),
+      clustered: HashSet::default(),
+      filter: None,
+      selection: Selection::default(),
+      hidden: Selection::default(),
+      within: None,
+      outside: Selection::default(),
+      order: LayerOrder::default(),
+    }
+  }
+
+  fn render_mode(&self, layer: &str) -> RenderMode {
+    self.render_modes.get(layer).copied().unwrap_or_default()
+  }
+
+  /// If the points of the layer are aggregated into count markers.
+  fn clusters(&self, layer: &str) -> bool {
+    self.clustered.contains(layer)
+  }
+
+  /// If the element is neither hidden nor filtered out.
+  fn is_shown(&self, layer: &str, index: usize, element: &LayerElement) -> bool {
+    !self.hidden.contains(layer, index)
+      && !self.outside.contains(layer, index)
+      && self
+        .filter
+        .as_ref()
+        .map_or(true, |f| f.holds(&element.feature()))
+  }
+
+  /// The indices of the parts of the multi geometry the element belongs to, or only its own.
+  fn parts(&self, layer: &str, index: usize) -> Vec<usize> {
+    let elements = self
+      .layers
+      .get(layer)
+      .map(Vec::as_slice)
+      .unwrap_or_default();
+    match elements.get(index).and_then(|(e, _)| e.track().part_of) {
+      Some(part_of) => (0..elements.len())
+        .filter(|i| elements[*i].0.track().part_of == Some(part_of))
+        .collect(),
+      None => vec![index],
+    }
+  }
+
+  fn layers_bounding_box(&self) -> Option<BoundingBox> {
+    let mut bb = BoundingBox::get_invalid();
+    self
+      .layers
+      .iter()
+      .flat_map(|(_, elements)| elements.iter())
+      .for_each(|(e, _)| e.add_to_bounding_box(&mut bb));
+    bb.is_valid().then_some(bb)
+  }
+
+  /// Returns the replaced image of the tile.
+  fn add_tile_image(&mut self, layer: usize, tile: Tile, image_id: ImageId) -> Option<ImageId> {
+    self
+      .tile_layers
+      .iter_mut()
+      .find(|l| l.id == layer)
+      .and_then(|tile_layer| tile_layer.loaded_images.insert(tile, image_id))
+  }
+
+  fn selected_tile_layer(&mut self) -> Option<&mut TileLayer> {
+    self.tile_layers.get_mut(self.selected_tile_layer)
+  }
+
+  fn select_next_tile_layer(&mut self) {
+    if !self.tile_layers.is_empty() {
+      self.selected_tile_layer = (self.selected_tile_layer + 1) % self.tile_layers.len();
+    }
+  }
+
+  /// Moves the selected tile layer one step up in the drawing order.
+  fn raise_selected_tile_layer(&mut self) {
+    let i = self.selected_tile_layer;
+    if i + 1 < self.tile_layers.len() {
+      self.tile_layers.swap(i, i + 1);
+      self.selected_tile_layer = i + 1;
+    }
+  }
+
+  /// Computes the classes of the choropleth rules of the layer from its elements.
+  fn prepare_rules(&mut self, id: &str) {
+    let (Some(rules), Some(elements)) = (self.rules.get_mut(id), self.layers.get(id)) else {
+      return;
+    };
+    let features: Vec<Feature> = elements.iter().map(|(e, _)| e.feature()).collect();
+    prepare(rules, &features);
   }
 
   fn clear_layers(&mut self) {
     self.layers.clear();
+    self.within = None;
+    self.outside.clear();
+    self.selection.clear();
+    self.hidden.clear();
+    self.render_modes.clear();
+    self.rules.clear();
+    self.opacities.clear();
+    self.blend_modes.clear();
+    self.clustered.clear();
+    self.order.clear();
+  }
+
+  fn clear_layer(&mut self, id: &str) {
+    self.layers.remove(id);
+    self.selection.remove_layer(id);
+    self.hidden.remove_layer(id);
+    self.update_within();
+    self.render_modes.remove(id);
+    self.rules.remove(id);
+    self.opacities.remove(id);
+    self.blend_modes.remove(id);
+    self.clustered.remove(id);
+  }
+
+  /// The current layers in draw order as they can be sent to or exported from the map.
+  fn export_layers(&self) -> Vec<Layer> {
+    self
+      .order
+      .ids()
+      .iter()
+      .filter_map(|id| self.layers.get_key_value(id))
+      .map(|(id, elements)| {
+        let mut layer = Layer::new(id.clone())
+          .with_render_mode(self.render_mode(id))
+          .with_rules(self.rules.get(id).cloned().unwrap_or_default())
+          .with_opacity(self.opacities.get(id).copied())
+          .with_blend(self.blend_modes.get(id).copied())
+          .with_cluster(self.clusters(id).then_some(true));
+        layer.shapes = elements.iter().map(|(e, s)| e.to_shape(*s)).collect();
+        layer
+      })
+      .collect()
   }
 }
 
+/// What the next letter typed does with the mark it names.
+#[derive(Debug, Clone, Copy)]
+enum MarkAction {
+  Set,
+  Jump,
+}
+
 /// Keeps data for map and layer drawing.
+#[allow(clippy::struct_excessive_bools)]
 pub struct MapVas {
   event_loop: Option<EventLoop<MapEvent>>,
   canvas: Canvas<OpenGl>,
@@ -167,6 +727,65 @@ pub struct MapVas {
   map_provider: MapProvider,
   closest_text: String,
   screenshot: Option<PathBuf>,
+  editing: Option<EditState>,
+  /// Shows the labels of the elements on the map.
+  show_labels: bool,
+  /// Shows the layers and the colors of their style rules in the bottom right corner.
+  show_legend: bool,
+  /// Snaps dragged vertices to the vertices and segments of other elements.
+  snap_to_geometry: bool,
+  sprite: Option<(Sprite, ImageId)>,
+  /// The images of point icons by file, `None` if the file cannot be loaded.
+  icon_images: HashMap<PathBuf, Option<ImageId>>,
+  theme: Theme,
+  heatmap: HeatmapSettings,
+  /// The heatmap image and what it was computed from.
+  heatmap_image: Option<(ImageId, HeatmapInput)>,
+  modifiers: ModifiersState,
+  show_scale_bar: bool,
+  scale_unit: ScaleUnit,
+  overlays: Vec<(ImageOverlay, ImageId)>,
+  aligning_overlays: bool,
+  /// The index of the overlay and its corner, 0 for north west and 1 for south east.
+  dragged_corner: Option<(usize, usize)>,
+  /// The area dragged with ctrl or clicked with ctrl and the right button to select elements.
+  selecting: Option<Area>,
+  /// The tool of `:draw` left dragging draws annotations with.
+  tool: Option<Tool>,
+  /// The annotation being drawn.
+  sketch: Option<Sketch>,
+  /// The attribute table shown by `:table`.
+  table: Option<AttributeTable>,
+  /// The format of the mouse position shown by P.
+  coordinate_format: CoordinateFormat,
+  /// The camera animation in progress and when it started.
+  flight: Option<(Flight, Instant)>,
+  /// The last view sent to subscribers and when.
+  published_camera: Option<(Camera, Instant)>,
+  marks: Marks,
+  /// Set by `m` and `'` until the letter of the mark is typed.
+  pending_mark: Option<MarkAction>,
+  /// The command line while it is open, it takes all keys.
+  command_line: Option<CommandLine>,
+  /// The time cursor, elements whose first timestamp is later are hidden.
+  time: Option<i64>,
+  /// The timestamps of the layers the time cursor steps between.
+  tracks: Tracks,
+  time_zone: TimeZone,
+  /// Playback in real time: the length of the time window ending now in milliseconds.
+  live: Option<i64>,
+  /// The opacity of elements outside of the time window, 0 hides them.
+  ghost_opacity: f32,
+  /// The two states of the map shown by `:compare`.
+  compare: Option<Compare>,
+  dragging_divider: bool,
+  /// The inset showing the layers with its own camera, set by `:viewport`.
+  viewport: Option<Viewport>,
+  show_status: bool,
+  /// The parts of the status bar with where they start and end on the screen.
+  status: Vec<(f32, f32, String)>,
+  /// Collected while `:set debug_overlay` shows them.
+  frame_stats: Option<FrameStats>,
 }
 
 impl Default for MapVas {
@@ -277,10 +896,47 @@ impl MapVas {
         event_proxy,
         event_receiver: Some(rx),
         event_sender: tx.clone(),
+        update_sender: broadcast::channel(64).0,
       },
-      map_provider: MapProvider::new(CachedTileLoader::default(), tx),
+      map_provider: MapProvider::new(tile_layers_from_env(), tx),
       closest_text: String::default(),
       screenshot: None,
+      editing: None,
+      show_labels: false,
+      show_legend: false,
+      snap_to_geometry: false,
+      sprite: None,
+      icon_images: HashMap::default(),
+      theme: Theme::from_env(),
+      heatmap: HeatmapSettings::default(),
+      heatmap_image: None,
+      modifiers: ModifiersState::empty(),
+      show_scale_bar: true,
+      scale_unit: ScaleUnit::default(),
+      overlays: vec![],
+      aligning_overlays: false,
+      dragged_corner: None,
+      selecting: None,
+      tool: None,
+      sketch: None,
+      table: None,
+      coordinate_format: CoordinateFormat::default(),
+      flight: None,
+      published_camera: None,
+      marks: Marks::default(),
+      pending_mark: None,
+      command_line: None,
+      time: None,
+      tracks: Tracks::default(),
+      time_zone: TimeZone::from_env(),
+      live: None,
+      ghost_opacity: 0.,
+      compare: None,
+      dragging_divider: false,
+      viewport: None,
+      show_status: true,
+      status: vec![],
+      frame_stats: None,
     }
   }
 
@@ -294,7 +950,11 @@ impl MapVas {
     clippy::too_many_lines
   )]
   pub fn run(mut self) {
+    /// How often live playback redraws without new data.
+    const LIVE_REFRESH: Duration = Duration::from_secs(1);
     let _ = self.canvas.add_font_mem(ttf_noto_sans::REGULAR);
+    self.load_emoji_font();
+    self.load_sprite();
 
     self.spawn_event_handler();
     self
@@ -302,7 +962,11 @@ impl MapVas {
       .take()
       .expect("Main event loop started twice.")
       .run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Wait;
+        *control_flow = match self.live {
+          // Redraw every second to drop data leaving the live window.
+          Some(_) => ControlFlow::WaitUntil(Instant::now() + LIVE_REFRESH),
+          None => ControlFlow::Wait,
+        };
 
         match event {
           Event::WindowEvent { ref event, .. } => match event {
@@ -318,9 +982,41 @@ impl MapVas {
               state,
               ..
             } => match state {
-              ElementState::Pressed => self.dragging = true,
-              ElementState::Released => self.dragging = false,
+              ElementState::Pressed if self.compass_clicked() => self.set_rotation(0.),
+              ElementState::Pressed if self.mouse_over_table() => self.focus_table_row(),
+              ElementState::Pressed if self.mouse_over_divider() => self.dragging_divider = true,
+              ElementState::Pressed if self.mouse_over_viewport() => self.center_on_viewport(),
+              ElementState::Pressed if self.mouse_over_status() => self.copy_status(),
+              ElementState::Pressed if self.modifiers.ctrl() => {
+                let mouse = self.mouse_position();
+                self.selecting = Some(Area::Rectangle(mouse, mouse));
+              }
+              ElementState::Pressed if self.tool.is_some() => {
+                let mouse = self.mouse_position();
+                self.sketch = self.tool.map(|tool| Sketch::new(tool, mouse));
+              }
+              ElementState::Pressed => {
+                self.flight = None;
+                self.dragging = !self.start_vertex_drag() && !self.start_corner_drag();
+              }
+              ElementState::Released => {
+                self.dragging = false;
+                self.dragging_divider = false;
+                self.dragged_corner = None;
+                if matches!(self.selecting, Some(Area::Rectangle(..))) {
+                  self.finish_selection();
+                }
+                self.finish_sketch();
+                if let Some(edit) = self.editing.as_mut() {
+                  edit.dragged_vertex = None;
+                }
+              }
             },
+            WindowEvent::MouseInput {
+              button: MouseButton::Right,
+              state: ElementState::Pressed,
+              ..
+            } if self.modifiers.ctrl() => self.add_selection_vertex(),
             WindowEvent::MouseInput {
               button: MouseButton::Right,
               ..
@@ -331,7 +1027,37 @@ impl MapVas {
               position,
               ..
             } => {
-              if self.dragging {
+              if self
+                .editing
+                .as_ref()
+                .is_some_and(|e| e.dragged_vertex.is_some())
+              {
+                self.mousex = position.x as f32;
+                self.mousey = position.y as f32;
+                self.drag_vertex();
+              } else if self.dragged_corner.is_some() {
+                self.mousex = position.x as f32;
+                self.mousey = position.y as f32;
+                self.drag_corner();
+              } else if self.dragging_divider {
+                let width = self.window.inner_size().width as f32;
+                if let Some(compare) = self.compare.as_mut() {
+                  compare.drag(position.x as f32, width);
+                }
+              } else if matches!(self.selecting, Some(Area::Rectangle(..))) {
+                self.mousex = position.x as f32;
+                self.mousey = position.y as f32;
+                self.drag_selection();
+              } else if self.sketch.is_some() {
+                self.mousex = position.x as f32;
+                self.mousey = position.y as f32;
+                let mouse = self.mouse_position();
+                if let Some(sketch) = self.sketch.as_mut() {
+                  sketch.extend(mouse);
+                }
+              } else if self.dragging && self.modifiers.shift() {
+                self.rotate_canvas_center((position.x as f32 - self.mousex) / 200.);
+              } else if self.dragging {
                 self.translate(
                   self.mousex,
                   self.mousey,
@@ -360,7 +1086,16 @@ impl MapVas {
                   max_abs / 10.
                 }
               };
-              self.zoom_canvas(1.0 + (change / 10.0), self.mousex, self.mousey);
+              if self.mouse_over_table() {
+                #[allow(clippy::cast_possible_truncation)]
+                let rows = -change.signum() as isize;
+                if let Some(table) = &mut self.table {
+                  table.scroll(rows, TABLE_ROWS);
+                }
+              } else {
+                self.flight = None;
+                self.zoom_canvas(1.0 + (change / 10.0), self.mousex, self.mousey);
+              }
             }
             WindowEvent::DroppedFile(pathbuf) => {
               self.drop_file(pathbuf.clone());
@@ -370,6 +1105,7 @@ impl MapVas {
               delta,
               ..
             } => {
+              self.flight = None;
               self.zoom_canvas(1.0 + *delta as f32, self.mousex, self.mousey);
             }
             WindowEvent::KeyboardInput {
@@ -381,7 +1117,9 @@ impl MapVas {
                 },
               ..
             } => self.handle_key(*key),
+            WindowEvent::ReceivedCharacter(c) => self.handle_character(*c),
 
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = *modifiers,
             WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
             WindowEvent::ScaleFactorChanged {
               scale_factor: _,
@@ -396,18 +1134,42 @@ impl MapVas {
           },
           Event::RedrawRequested(_) => self.redraw(),
           Event::MainEventsCleared => self.window.request_redraw(),
-          Event::UserEvent(MapEvent::TileDataArrived { tile, data }) => {
-            self.add_tile_image(tile, &data);
+          Event::UserEvent(MapEvent::TileDataArrived {
+            layer,
+            tile,
+            width,
+            height,
+            data,
+          }) => {
+            self.add_tile_image(layer, tile, width, height, &data);
           }
           Event::UserEvent(MapEvent::Layer(layer)) => self.handle_layer_event(layer),
-          Event::UserEvent(MapEvent::Clear) => {
-            self.map_provider.clear_layers();
-          }
+          Event::UserEvent(MapEvent::Clear) => self.clear(),
           Event::LoopDestroyed | Event::UserEvent(MapEvent::Shutdown) => {
             *control_flow = ControlFlow::Exit;
           }
           Event::UserEvent(MapEvent::Focus) => self.handle_focus_event(),
+          Event::UserEvent(MapEvent::Rotation(degrees)) => self.set_rotation(degrees.to_radians()),
           Event::UserEvent(MapEvent::Screenshot(pb)) => self.screenshot = Some(pb),
+          Event::UserEvent(MapEvent::Export { format, path }) => {
+            self.export(format, self.map_provider.export_layers(), path);
+          }
+          Event::UserEvent(MapEvent::ImageOverlay(overlay)) => self.handle_overlay_event(overlay),
+          Event::UserEvent(MapEvent::Theme(theme)) => self.theme = theme,
+          Event::UserEvent(MapEvent::ClearCache(kind)) => self.clear_cache(kind),
+          Event::UserEvent(MapEvent::AddBookmark(name)) => self.add_bookmark(name),
+          Event::UserEvent(MapEvent::GoToBookmark(name)) => self.go_to_bookmark(&name),
+          Event::UserEvent(MapEvent::SetView(view)) => self.set_view(view),
+          Event::UserEvent(MapEvent::ClearLayer(id)) => {
+            if self.editing.as_ref().is_some_and(|e| e.layer == id) {
+              self.editing = None;
+            }
+            self.map_provider.clear_layer(&id);
+            if !self.overlays.iter().any(|(o, _)| o.id == id) {
+              self.map_provider.order.remove(&id);
+            }
+            self.publish(MapUpdate::LayerCleared { id });
+          }
           _ => trace!("Unhandled event: {:?}", event),
         }
       });
@@ -419,23 +1181,43 @@ impl MapVas {
     self.event_handler.event_sender.clone()
   }
 
-  fn draw_text(&mut self) {
-    if self.closest_text.is_empty() {
-      return;
+  /// Allows to subscribe to changes of the map from outside the event loop.
+  #[must_use]
+  pub fn get_update_sender(&self) -> broadcast::Sender<MapUpdate> {
+    self.event_handler.update_sender.clone()
+  }
+
+  fn publish(&self, update: MapUpdate) {
+    // Fails only if nobody is subscribed.
+    let _ = self.event_handler.update_sender.send(update);
+  }
+
+  /// Publishes the view when it changed, at most every 100 ms.
+  fn publish_camera(&mut self) {
+    let camera = self.camera();
+    let changed = match self.published_camera {
+      Some((published, at)) => published != camera && at.elapsed().as_millis() >= 100,
+      None => true,
+    };
+    if changed {
+      self.published_camera = Some((camera, Instant::now()));
+      self.publish(MapUpdate::View { camera });
+    }
+  }
+
+  #[allow(clippy::cast_precision_loss)]
+  fn mouse_over_status(&self) -> bool {
+    self.show_status && self.mousey >= self.window.inner_size().height as f32 - STATUS_HEIGHT
+  }
+
+  /// Copies the part of the status bar under the mouse.
+  fn copy_status(&mut self) {
+    if let Some((.., text)) =
+      (self.status.iter()).find(|(start, end, _)| (*start..=*end).contains(&self.mousex))
+    {
+      copy_to_clipboard(text);
+      self.closest_text = format!("Copied {text}");
     }
-    #[allow(clippy::cast_precision_loss)]
-    let w = self.window.inner_size().width as f32;
-    let h = 25.;
-    let mut path = Path::new();
-    path.rect(0., 0., w, h);
-    self
-      .canvas
-      .fill_path(&path, &Paint::color(Color::rgba(128, 128, 128, 128)));
-    let mut text_paint = Paint::color(Color::rgba(240, 240, 240, 255));
-    text_paint.set_font_size(14.);
-    let _ = self
-      .canvas
-      .fill_text(10., 15., &self.closest_text, &text_paint);
   }
 
   fn spawn_event_handler(&mut self) {
@@ -452,72 +1234,147 @@ impl MapVas {
     });
   }
 
-  fn handle_key(&mut self, key: VirtualKeyCode) {
-    const SCROLL_SPEED: f32 = 20.;
-    const ZOOM_SPEED: f32 = 1.1;
-    match key {
-      VirtualKeyCode::Left => self.translate(0., 0., SCROLL_SPEED, 0.),
-      VirtualKeyCode::Right => self.translate(SCROLL_SPEED, 0., 0., 0.),
-      VirtualKeyCode::Up => self.translate(0., 0., 0., SCROLL_SPEED),
-      VirtualKeyCode::Down => self.translate(0., SCROLL_SPEED, 0., 0.),
-      // Plus and equals to zoom in to avoid holding shift.
-      VirtualKeyCode::Equals | VirtualKeyCode::Plus => self.zoom_canvas_center(ZOOM_SPEED),
-      VirtualKeyCode::Minus => self.zoom_canvas_center(1. / ZOOM_SPEED),
-      VirtualKeyCode::V => self.paste(),
-      VirtualKeyCode::C => self.copy(),
-      VirtualKeyCode::F => self.handle_focus_event(),
-      VirtualKeyCode::L => self.update_closest(),
-      VirtualKeyCode::Delete => self.map_provider.clear_layers(),
-      VirtualKeyCode::S => {
-        self.make_screenshot(format!("mapvas_{}.png", current_time_string()).into());
-      }
-      _ => debug!("{key:?} pressed"),
-    };
-  }
-
-  fn paste(&self) {
-    let sender = self.get_event_sender();
+  fn export(&self, format: ExportFormat, layers: Vec<Layer>, path: PathBuf) {
+    let updates = self.event_handler.update_sender.clone();
     rayon::spawn(move || {
-      if let Ok(text) = Clipboard::new().expect("clipboard").get_text() {
-        if let Some(map_event) = GrepParser::new(false).parse_line(&text) {
-          let _ = block_on(sender.send(map_event));
-        }
+      if let Err(e) = export_to_file(format, &layers, &path) {
+        error!("Could not export to {path:?}: {e}");
       }
+      // Fails only if nobody is subscribed.
+      let _ = updates.send(MapUpdate::Exported { path });
     });
   }
 
-  fn drop_file(&self, path: PathBuf) {
-    let sender = self.get_event_sender();
-    rayon::spawn(move || {
-      let mut parser = AutoFileParser::new(path.clone());
-      parser
-        .parse()
-        .for_each(|e| block_on(sender.send(e)).expect("Can send to self."));
-    });
+  /// The bounding box of the visible area in canvas coordinates and the zoom factor.
+  /// The bounding box is larger than the window if the map is rotated.
+  #[allow(clippy::cast_precision_loss)]
+  fn get_current_canvas_section(&self) -> (PixelPosition, PixelPosition, f32) {
+    let mut trans = self.canvas.transform();
+    let zoom = trans[0].hypot(trans[1]);
+    trans.inverse();
+
+    let size = self.window.inner_size();
+    let (w, h) = (size.width as f32, size.height as f32);
+    let bb = BoundingBox::from_iterator([(0., 0.), (w, 0.), (0., h), (w, h)].map(|(x, y)| {
+      let (x, y) = trans.transform_point(x, y);
+      PixelPosition { x, y }
+    }));
+    (bb.nw(), bb.se(), zoom)
   }
 
-  fn copy(&self) {
-    if self.closest_text.is_empty() {
-      return;
-    }
-    let mut clipboard = Clipboard::new().unwrap();
-    clipboard.set_text(&self.closest_text).unwrap();
+  /// The rotation of the map in radians, clockwise.
+  fn rotation(&self) -> f32 {
+    let trans = self.canvas.transform();
+    trans[1].atan2(trans[0])
+  }
+
+  fn rotate_canvas(&mut self, angle: f32, center_x: f32, center_y: f32) {
+    let pt = self
+      .canvas
+      .transform()
+      .inversed()
+      .transform_point(center_x, center_y);
+
+    self.canvas.translate(pt.0, pt.1);
+    self.canvas.rotate(angle);
+    self.canvas.translate(-pt.0, -pt.1);
   }
 
   #[allow(clippy::cast_precision_loss)]
-  fn get_current_canvas_section(&self) -> (PixelPosition, PixelPosition, f32) {
-    let mut trans = self.canvas.transform();
-    let zoom = trans[0];
-    trans.inverse();
+  fn rotate_canvas_center(&mut self, angle: f32) {
+    let size = self.window.inner_size();
+    self.rotate_canvas(angle, size.width as f32 / 2., size.height as f32 / 2.);
+  }
+
+  fn is_rotated(&self) -> bool {
+    self.rotation().abs() > 1e-4
+  }
 
-    let nw = trans.transform_point(0., 0.);
+  fn set_rotation(&mut self, angle: f32) {
+    self.rotate_canvas_center(angle - self.rotation());
+  }
+
+  /// The canvas position in the center of the window.
+  #[allow(clippy::cast_precision_loss)]
+  fn window_center(&self) -> PixelPosition {
     let size = self.window.inner_size();
-    let se = trans.transform_point(size.width as f32, size.height as f32);
-    (
-      PixelPosition { x: nw.0, y: nw.1 },
-      PixelPosition { x: se.0, y: se.1 },
-      zoom,
-    )
+    let (x, y) = self
+      .canvas
+      .transform()
+      .inversed()
+      .transform_point(size.width as f32 / 2., size.height as f32 / 2.);
+    PixelPosition { x, y }
+  }
+
+  /// The current view of the map.
+  fn camera(&self) -> Camera {
+    Camera {
+      center: self.window_center().into(),
+      zoom: (self.get_zoom_factor() * CANVAS_SIZE / 256.).log2(),
+      rotation: self.rotation().to_degrees(),
+    }
+  }
+
+  fn set_camera(&mut self, camera: Camera) {
+    let zoom_factor = 2f32.powf(camera.zoom) * 256. / CANVAS_SIZE;
+    self.zoom_canvas_center(zoom_factor / self.get_zoom_factor());
+    self.set_rotation(camera.rotation.to_radians());
+    self.set_center(camera.center.into());
+  }
+
+  /// Moves the camera to the view in an animation.
+  fn fly_to(&mut self, to: Camera) {
+    let duration = flight_duration();
+    if duration.is_zero() {
+      self.set_camera(to);
+      return;
+    }
+    self.flight = Some((Flight::new(self.camera(), to, duration), Instant::now()));
+  }
+
+  fn advance_flight(&mut self) {
+    let Some((flight, start)) = &self.flight else {
+      return;
+    };
+    let elapsed = start.elapsed();
+    let camera = flight.camera(elapsed);
+    if flight.is_done(elapsed) {
+      self.flight = None;
+    }
+    self.set_camera(camera);
+  }
+
+  fn add_bookmark(&mut self, name: String) {
+    let mut bookmarks = Bookmarks::load();
+    bookmarks.add(name.clone(), self.camera());
+    match bookmarks.save() {
+      Ok(()) => self.closest_text = format!("Added bookmark {name}"),
+      Err(e) => error!("Cannot save the bookmarks: {e}"),
+    }
+  }
+
+  fn go_to_bookmark(&mut self, name: &str) {
+    match Bookmarks::load().get(name) {
+      Some(bookmark) => self.fly_to(bookmark.camera),
+      None => error!("There is no bookmark {name}."),
+    }
+  }
+
+  fn go_to_numbered_bookmark(&mut self, n: usize) {
+    if let Some(bookmark) = Bookmarks::load().bookmarks.get(n - 1) {
+      self.fly_to(bookmark.camera);
+      self.closest_text.clone_from(&bookmark.name);
+    }
+  }
+
+  /// Lists the bookmarks in the text bar with the number key that shows them.
+  fn show_bookmarks(&mut self) {
+    self.closest_text = Bookmarks::load().numbered().join(" | ");
+  }
+
+  fn compass_clicked(&self) -> bool {
+    let (x, y, r) = self.compass_position();
+    self.is_rotated() && (self.mousex - x).hypot(self.mousey - y) < r
   }
 
   #[allow(unused)]
@@ -532,117 +1389,60 @@ impl MapVas {
     );
   }
 
-  #[allow(
-    clippy::cast_precision_loss,
-    clippy::cast_possible_truncation,
-    clippy::cast_sign_loss
-  )]
-
-  fn get_tiles_to_draw(&mut self) -> impl Iterator<Item = Tile> {
-    let (nw, se, zoom) = self.get_current_canvas_section();
-
-    let size = self.window.inner_size();
-    let vertical_tile_number = (size.height as f32 / TILE_SIZE).round();
-
-    let zoom_level = ((zoom * vertical_tile_number).log2() as i32).clamp(2, 19);
-    let nw_tile = TileCoordinate::from_pixel_position(nw.clamp(), zoom_level as u8);
-    let se_tile = TileCoordinate::from_pixel_position(se.clamp(), zoom_level as u8);
-    tiles_in_box(nw_tile, se_tile)
+  /// The selected layers, or all layers if nothing is selected.
+  fn cluster_targets(&self) -> Vec<String> {
+    let mut ids: Vec<String> = self
+      .map_provider
+      .selected()
+      .map(|(id, _)| id.clone())
+      .collect();
+    ids.dedup();
+    if ids.is_empty() {
+      ids = self.map_provider.layers.keys().cloned().collect();
+    }
+    ids
   }
 
-  fn draw_map(&mut self) {
-    for tile in self.get_tiles_to_draw() {
-      let found_tile_image = self.map_provider.find_image_or_download(tile);
-      if found_tile_image.is_none() {
-        continue;
+  /// Clusters the points of the layers, or stops clustering them if all of them are clustered.
+  fn toggle_clusters(&mut self, ids: Vec<String>) {
+    let clustered = &mut self.map_provider.clustered;
+    if ids.iter().all(|id| clustered.contains(id)) {
+      for id in &ids {
+        clustered.remove(id);
       }
-      let (nw, se) = found_tile_image.unwrap().0.position();
-      let fill_paint = Paint::image(
-        *found_tile_image.unwrap().1,
-        nw.x,
-        nw.y,
-        se.x - nw.x,
-        se.y - nw.y,
-        0.0,
-        1.,
-      );
-      let mut path = Path::new();
-      path.rect(nw.x, nw.y, se.x, se.y);
-      self.canvas.fill_path(&path, &fill_paint);
+    } else {
+      clustered.extend(ids);
     }
   }
 
-  #[allow(clippy::cast_possible_truncation)]
-  fn redraw(&mut self) {
-    self.fit_to_window();
-    let dpi_factor = self.window.scale_factor();
-    let size = self.window.inner_size();
+  /// Scales the radius and the intensity of heatmaps and shows them.
+  fn change_heatmap(&mut self, radius: f32, intensity: f32) {
+    let heatmap = &mut self.heatmap;
+    heatmap.radius = (heatmap.radius * radius).clamp(1., 400.);
+    heatmap.intensity = (heatmap.intensity * intensity).clamp(0.1, 100.);
+    self.closest_text = format!(
+      "Heatmap radius {:.0} px, intensity {:.2}",
+      heatmap.radius, heatmap.intensity
+    );
+  }
 
-    self
-      .canvas
-      .set_size(size.width, size.height, dpi_factor as f32);
-    self
+  /// Creates the image of a tile from its decoded rgba pixels.
+  fn add_tile_image(&mut self, layer: usize, tile: Tile, width: u32, height: u32, data: &[u8]) {
+    let pixels: Vec<RGBA8> = data
+      .chunks_exact(4)
+      .map(|p| RGBA8::new(p[0], p[1], p[2], p[3]))
+      .collect();
+    let raster = femtovg::imgref::Img::new(pixels, width as usize, height as usize);
+    match self
       .canvas
-      .clear_rect(0, 0, size.width, size.height, Color::rgbf(0.3, 0.3, 0.32));
-
-    self.draw_map();
-    self.draw_layers();
-
-    self.canvas.save();
-    self.canvas.reset();
-    self.draw_text();
-    self.canvas.restore();
-
-    self.canvas.flush();
-    self.surface.swap_buffers(&self.context).unwrap();
-    if let Some(screenshot_pb) = &self.screenshot {
-      self.make_screenshot(screenshot_pb.into());
-      self.screenshot = None;
-    }
-  }
-
-  fn draw_layers(&mut self) {
-    let line_width = 3. / self.get_zoom_factor();
-    for layer in &self.map_provider.layers {
-      for (path, style) in layer.1 {
-        let mut stroke = Paint::color(style.color.to_rgb());
-        stroke.set_line_width(line_width);
-        let fill = match style.fill {
-          FillStyle::Transparent => Some(Paint::color(style.color.to_rgba(50))),
-          FillStyle::Solid => Some(Paint::color(style.color.to_rgb())),
-          FillStyle::NoFill => None,
-        };
-
-        match path {
-          LayerElement::Polyline(poly, _, _, _) => {
-            self.canvas.stroke_path(poly, &stroke);
-            if let Some(style) = fill.as_ref() {
-              self.canvas.fill_path(poly, style);
-            };
-          }
-          LayerElement::Point(point, _) => {
-            let mut circle = Path::new();
-            circle.circle(
-              point.x,
-              point.y,
-              (3. / self.get_zoom_factor()).max(0.000_05),
-            );
-            self.canvas.stroke_path(&circle, &stroke);
-            if let Some(style) = fill.as_ref() {
-              self.canvas.fill_path(&circle, style);
-            };
-          }
-        };
+      .create_image(raster.as_ref(), ImageFlags::empty())
+    {
+      Ok(id) => {
+        if let Some(replaced) = self.map_provider.add_tile_image(layer, tile, id) {
+          self.canvas.delete_image(replaced);
+        }
       }
-    }
-  }
-
-  fn add_tile_image(&mut self, tile: Tile, data: &[u8]) {
-    let image_id = self.canvas.load_image_mem(data, ImageFlags::empty());
-    if let Ok(id) = image_id {
-      self.map_provider.add_tile_image(tile, id);
-    } else {
-      info!("Tile {tile:?} image decoding problem");
+      Err(e) => info!("Tile {tile:?} image problem: {e:?}"),
     }
   }
 
@@ -680,6 +1480,10 @@ impl MapVas {
     if ratio > 1. {
       self.zoom_canvas_center(ratio);
     }
+    // Keeping the borders in the window is only done for a north up map.
+    if self.is_rotated() {
+      return;
+    }
     let section = self.get_current_canvas_section();
     self.translate(0., 0., section.0.x.min(0.), section.0.y.min(0.));
     self.translate(
@@ -714,41 +1518,48 @@ impl MapVas {
     self.zoom_canvas(factor, size.width as f32 / 2., size.height as f32 / 2.);
   }
 
-  fn coords_to_element(coords: &[Coordinate], close_path: bool) -> LayerElement {
-    let points = coords
-      .iter()
-      .skip(1)
-      .copied()
-      .map(Into::<PixelPosition>::into);
-    if coords.len() == 1 {
-      LayerElement::Point(coords[0].into(), None)
-    } else {
-      let mut path = Path::new();
-
-      let start: PixelPosition = coords[0].into();
-      path.move_to(start.x, start.y);
-      points.clone().for_each(|to| {
-        path.line_to(to.x, to.y);
-      });
-      if close_path {
-        path.line_to(start.x, start.y);
-      }
-      LayerElement::Polyline(
-        path,
-        BoundingBox::from_iterator(coords.iter().copied().map(Into::into)),
-        coords.iter().copied().map(Into::into).collect(),
-        None,
-      )
-    }
-  }
-
-  #[allow(clippy::cast_precision_loss)]
   fn handle_focus_event(&mut self) {
     let bb = self.map_provider.layers_bounding_box().unwrap_or_default();
     if !bb.is_valid() {
       return;
     }
+    self.focus_bounding_box(&bb);
+    self.publish(MapUpdate::Focus);
+  }
 
+  fn set_view(&mut self, view: View) {
+    match view {
+      View::Center { center, zoom } => {
+        let camera = self.camera();
+        self.fly_to(Camera {
+          center,
+          zoom: zoom.unwrap_or(camera.zoom),
+          ..camera
+        });
+      }
+      View::BoundingBox {
+        bbox: [lat1, lon1, lat2, lon2],
+      } => {
+        let corners = [
+          Coordinate {
+            lat: lat1,
+            lon: lon1,
+          },
+          Coordinate {
+            lat: lat2,
+            lon: lon2,
+          },
+        ];
+        let bb = BoundingBox::from_iterator(corners.map(PixelPosition::from));
+        self.focus_bounding_box(&bb);
+      }
+    }
+  }
+
+  /// Flies to the bounding box in canvas coordinates with a small margin.
+  #[allow(clippy::cast_precision_loss)]
+  fn focus_bounding_box(&mut self, bb: &BoundingBox) {
+    let from = self.camera();
     let window_size = self.window.inner_size();
     let empty_space_width = 30;
     let requested_zoom_x = (window_size.width - empty_space_width) as f32 / (bb.width() + 0.00001);
@@ -757,70 +1568,131 @@ impl MapVas {
     self.zoom_canvas_center(requested_zoom_x.min(requested_zoom_y) / self.get_zoom_factor());
     self.fit_to_window();
     self.set_center(bb.center());
+    let to = self.camera();
+    self.set_camera(from);
+    self.fly_to(to);
+  }
+
+  fn change_tile_layer_opacity(&mut self, change: f32) {
+    if let Some(tile_layer) = self.map_provider.selected_tile_layer() {
+      tile_layer.opacity = (tile_layer.opacity + change).clamp(0., 1.);
+    }
+    self.show_selected_tile_layer();
   }
 
-  fn handle_layer_event(&mut self, layer: Layer) {
-    let mut paths: Vec<(LayerElement, Style)> = layer
-      .shapes
+  /// Shows the size and hit rate of the tile caches of all tile layers in the text bar.
+  fn show_cache_stats(&mut self) {
+    self.closest_text = self
+      .map_provider
+      .tile_layers
       .iter()
-      .map(|shape| {
-        (
-          Self::coords_to_element(&shape.coordinates, shape.style.fill != FillStyle::NoFill)
-            .with_text(shape.label.clone()),
-          shape.style,
+      .map(|l| {
+        format!(
+          "{}: tiles {}, rendered {}",
+          l.name,
+          l.tile_loader.cache_stats(CacheKind::Tiles),
+          l.tile_loader.cache_stats(CacheKind::Rendered)
         )
       })
-      .collect();
+      .collect::<Vec<_>>()
+      .join(" | ");
+  }
 
-    self
-      .map_provider
-      .layers
-      .entry(layer.id)
-      .and_modify(|l| l.append(&mut paths))
-      .or_insert(paths);
+  /// Shows the coordinate under the mouse in the text bar, from where C copies it.
+  fn show_mouse_position(&mut self) {
+    let position = Coordinate::from(self.mouse_position());
+    self.closest_text = self.coordinate_format.format(position);
   }
 
-  fn update_closest(&mut self) {
+  /// Removes the tiles of a cache and forgets the loaded tile images to load them again.
+  fn clear_cache(&mut self, kind: CacheKind) {
+    for tile_layer in &mut self.map_provider.tile_layers {
+      tile_layer.tile_loader.clear_cache(kind);
+      for (_, image) in tile_layer.loaded_images.drain() {
+        self.canvas.delete_image(image);
+      }
+    }
+    info!("Cleared the {kind:?} cache.");
+  }
+
+  /// Shows the selected tile layer with its position in the drawing order in the text bar.
+  fn show_selected_tile_layer(&mut self) {
+    let count = self.map_provider.tile_layers.len();
+    let position = self.map_provider.selected_tile_layer + 1;
+    if let Some(tile_layer) = self.map_provider.selected_tile_layer() {
+      self.closest_text = format!(
+        "Tile layer {position}/{count}: {} ({:.0}%)",
+        tile_layer.name,
+        tile_layer.opacity * 100.
+      );
+    }
+  }
+
+  fn mouse_position(&self) -> PixelPosition {
     let mut trans = self.canvas.transform();
     trans.inverse();
     let pos = trans.transform_point(self.mousex, self.mousey);
-    let mouse = PixelPosition { x: pos.0, y: pos.1 };
+    PixelPosition { x: pos.0, y: pos.1 }
+  }
 
+  /// The distance in canvas pixels that counts as near the mouse.
+  fn distance_threshold(&self) -> f32 {
     let (a, b, _) = self.get_current_canvas_section();
-    let dist_treshold = (b.x - a.x) / 80.;
+    (b.x - a.x) / 80.
+  }
+
+  /// Finds the element closest to the mouse within the threshold.
+  /// Returns the layer id and the index of the element in the layer.
+  fn closest_element(&self, require_text: bool) -> Option<(String, usize)> {
+    let mouse = self.mouse_position();
+    let dist_treshold = self.distance_threshold();
     let point_preference_weight = dist_treshold / 4.;
 
     let (closest, dist) = self
       .map_provider
       .layers
       .iter()
-      .flat_map(|l| l.1.iter().map(|e| &e.0))
-      .fold((None, f32::MAX), |(el, dist), next| {
+      .flat_map(|(id, elements)| elements.iter().enumerate().map(move |(i, e)| (id, i, &e.0)))
+      .fold((None, f32::MAX), |(el, dist), (id, i, next)| {
         let next_dist = next.sq_distance_to_point(mouse, point_preference_weight);
-        if next_dist < dist && next.has_text() {
-          (Some(next), next_dist)
+        if next_dist < dist && (!require_text || next.has_text()) {
+          (Some((id.clone(), i)), next_dist)
         } else {
           (el, dist)
         }
       });
-    self.closest_text =
-      if let (Some(closest), true) = (closest, dist < dist_treshold * dist_treshold) {
-        closest.get_text().unwrap_or(String::new())
-      } else {
-        String::new()
-      };
+    closest.filter(|_| dist < dist_treshold * dist_treshold)
   }
 
-  #[allow(clippy::cast_possible_truncation)]
-  fn make_screenshot(&mut self, pb: std::path::PathBuf) {
-    if let Ok(mut img) = self.canvas.screenshot() {
-      let (buf, w, h) = img.as_contiguous_buf();
-      let img_buf: Vec<_> = buf
-        .iter()
-        .flat_map(|p| [p.r, p.g, p.b].into_iter())
-        .collect();
+  /// Shows the statistics of the line closest to the mouse in the text bar.
+  fn show_track_stats(&mut self) {
+    let stats = self.closest_element(false).and_then(|(id, i)| {
+      let (element, style) = &self.map_provider.layers[&id][i];
+      TrackStats::of(&element.to_shape(*style))
+    });
+    self.closest_text = match stats {
+      Some(stats) => stats.to_string(),
+      None => "There is no line near the mouse.".to_string(),
+    };
+  }
 
-      let _ = image::save_buffer(pb, &img_buf, w as u32, h as u32, image::ColorType::Rgb8);
-    }
+  /// Shows the label of the element closest to the mouse and the time of its first timestamp.
+  fn update_closest(&mut self) {
+    self.closest_text = self
+      .closest_element(true)
+      .and_then(|(id, i)| {
+        let element = &self.map_provider.layers[&id][i].0;
+        let mut text = element.get_text()?;
+        let properties = &element.track().properties;
+        if !properties.is_empty() {
+          let pairs: Vec<String> = properties.iter().map(|(k, v)| format!("{k}={v}")).collect();
+          text = format!("{text} | {}", pairs.join(", "));
+        }
+        Some(match element.track().timestamps.first() {
+          Some(time) => format!("{text} | {}", self.time_zone.format(*time)),
+          None => text,
+        })
+      })
+      .unwrap_or_default();
   }
 }
@@ -0,0 +1,391 @@
+use crate::{
+  map::{
+    bookmark::Bookmarks,
+    frame_stats::FrameStats,
+    layer_order::Move,
+    map_event::{BlendMode, ExportFormat},
+    query::Query,
+    script,
+  },
+  recent::{RecentFile, RecentFiles},
+};
+
+use super::{BoundingBox, MapVas};
+
+/// A command typed after `:` with the argument after its name.
+type Command = fn(&mut MapVas, &str);
+
+/// The commands by their name.
+const COMMANDS: &[(&str, Command)] = &[
+  ("goto", MapVas::goto_command),
+  ("bookmark", MapVas::bookmark_command),
+  ("bookmarks", |map, _| map.show_bookmarks()),
+  ("marks", |map, _| {
+    map.closest_text = map.marks.list().join(" | ")
+  }),
+  ("clear", |map, _| map.clear()),
+  ("stats", |map, _| map.show_track_stats()),
+  ("set", MapVas::set_command),
+  ("search", MapVas::search),
+  ("filter", MapVas::filter_command),
+  ("selection", MapVas::selection_command),
+  ("snap", MapVas::snap_to_road),
+  ("within", MapVas::within_command),
+  ("join", MapVas::join_command),
+  ("draw", MapVas::draw_command),
+  ("table", MapVas::table_command),
+  ("compare", MapVas::compare_command),
+  ("viewport", MapVas::viewport_command),
+  ("note", MapVas::add_note),
+  ("buffer", |map, argument| map.geoprocess("buffer", argument)),
+  ("union", |map, argument| map.geoprocess("union", argument)),
+  ("intersection", |map, argument| {
+    map.geoprocess("intersection", argument)
+  }),
+  ("difference", |map, argument| {
+    map.geoprocess("difference", argument)
+  }),
+  ("time", MapVas::time_command),
+  ("style", MapVas::style_command),
+  ("layers", MapVas::layers_command),
+  ("layer", MapVas::layer_command),
+  ("opacity", MapVas::opacity_command),
+  ("blend", MapVas::blend_command),
+  ("toggle", MapVas::toggle_command),
+  ("script", MapVas::script_command),
+  ("convert", MapVas::convert_command),
+  ("e", MapVas::edit_command),
+  ("export", MapVas::export_command),
+  ("rotate", MapVas::rotate_command),
+];
+
+impl MapVas {
+  /// Runs a command typed after `:`.
+  pub(super) fn execute_command(&mut self, command: &str) {
+    let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+    if name.is_empty() {
+      return;
+    }
+    match COMMANDS.iter().find(|(n, _)| *n == name) {
+      Some((_, run)) => run(self, argument.trim()),
+      None => self.closest_text = format!("Unknown command: {command}"),
+    }
+  }
+
+  /// `goto <view>` shows a position or area, `goto <name>` flies to a bookmark.
+  fn goto_command(&mut self, argument: &str) {
+    match argument.parse() {
+      Ok(view) => self.set_view(view),
+      Err(()) => match Bookmarks::load().get(argument) {
+        Some(bookmark) => self.fly_to(bookmark.camera),
+        None => self.closest_text = format!("Unknown position: {argument}"),
+      },
+    }
+  }
+
+  /// `rotate <degrees>` turns the map clockwise to the angle, `rotate` turns it north up again.
+  fn rotate_command(&mut self, argument: &str) {
+    if argument.is_empty() {
+      self.set_rotation(0.);
+      return;
+    }
+    match argument.parse::<f32>() {
+      Ok(degrees) if degrees.is_finite() => self.set_rotation(degrees.to_radians()),
+      _ => self.closest_text = "Usage: rotate <degrees>".to_string(),
+    }
+  }
+
+  /// `bookmark <name>` saves the current view, `bookmark` lists the bookmarks.
+  fn bookmark_command(&mut self, argument: &str) {
+    if argument.is_empty() {
+      self.show_bookmarks();
+    } else {
+      self.add_bookmark(argument.to_string());
+    }
+  }
+
+  /// `layers` lists the layers from top to bottom.
+  fn layers_command(&mut self, _: &str) {
+    self.closest_text = self
+      .map_provider
+      .order
+      .ids()
+      .iter()
+      .rev()
+      .cloned()
+      .collect::<Vec<_>>()
+      .join(" | ");
+  }
+
+  /// `layer move <layer> up|down|top|bottom` changes the draw order.
+  fn layer_command(&mut self, argument: &str) {
+    const USAGE: &str = "Usage: layer move <layer> up|down|top|bottom";
+    match argument.split_whitespace().collect::<Vec<_>>()[..] {
+      ["move", layer, to] => match to.parse::<Move>() {
+        Ok(to) if self.map_provider.order.move_layer(layer, to) => (),
+        Ok(_) => self.closest_text = format!("Unknown layer: {layer}"),
+        Err(()) => self.closest_text = USAGE.to_string(),
+      },
+      _ => self.closest_text = USAGE.to_string(),
+    }
+  }
+
+  /// `opacity <layer> <0 to 1>` changes the opacity of a layer.
+  fn opacity_command(&mut self, argument: &str) {
+    match argument
+      .split_once(' ')
+      .and_then(|(layer, opacity)| Some((layer, parse_opacity(opacity)?)))
+    {
+      Some((layer, opacity)) => {
+        self
+          .map_provider
+          .opacities
+          .insert(layer.to_string(), opacity);
+      }
+      None => self.closest_text = "Usage: opacity <layer> <0 to 1>".to_string(),
+    }
+  }
+
+  /// `blend <layer> normal|add|multiply` changes how a layer is drawn over the ones below.
+  fn blend_command(&mut self, argument: &str) {
+    match argument
+      .split_once(' ')
+      .and_then(|(layer, mode)| Some((layer, mode.trim().parse::<BlendMode>().ok()?)))
+    {
+      Some((layer, mode)) => {
+        self
+          .map_provider
+          .blend_modes
+          .insert(layer.to_string(), mode);
+      }
+      None => self.closest_text = "Usage: blend <layer> normal|add|multiply".to_string(),
+    }
+  }
+
+  /// `toggle <name>` shows or hides a part of the map or switches a setting. `toggle clusters`
+  /// takes the layers to cluster, by default the selected ones or all.
+  fn toggle_command(&mut self, argument: &str) {
+    match argument {
+      "legend" => self.show_legend = !self.show_legend,
+      "labels" => self.show_labels = !self.show_labels,
+      "scale" => self.show_scale_bar = !self.show_scale_bar,
+      "status" => self.show_status = !self.show_status,
+      "clusters" => self.toggle_clusters(self.cluster_targets()),
+      "snap" => self.snap_to_geometry = !self.snap_to_geometry,
+      _ => match argument.strip_prefix("clusters ") {
+        Some(layers) => {
+          self.toggle_clusters(layers.split_whitespace().map(str::to_string).collect());
+        }
+        None => self.closest_text = format!("Cannot toggle {argument}"),
+      },
+    }
+  }
+
+  /// `script run <file>` runs a script with the current layers.
+  fn script_command(&mut self, argument: &str) {
+    match argument.split_once(' ') {
+      Some(("run", path)) => script::run(
+        path.trim().into(),
+        self.get_event_sender(),
+        self.map_provider.export_layers(),
+      ),
+      _ => self.closest_text = "Usage: script run <file>".to_string(),
+    }
+  }
+
+  /// `convert <file> <command>` draws the output of a command reading the file.
+  fn convert_command(&mut self, argument: &str) {
+    match argument.split_once(' ') {
+      Some((path, command)) => self.convert_file(path.into(), command.trim().to_string()),
+      None => self.closest_text = "Usage: convert <file> <command>".to_string(),
+    }
+  }
+
+  /// `e <file>` draws the file, a recent file with the options it was drawn with before. `e`
+  /// draws the most recent file again.
+  fn edit_command(&mut self, argument: &str) {
+    let recent = RecentFiles::load();
+    let file = if argument.is_empty() {
+      recent.files.into_iter().next()
+    } else {
+      let path = std::path::absolute(argument).unwrap_or_else(|_| argument.into());
+      let file = recent.files.into_iter().find(|f| f.path == path);
+      Some(file.unwrap_or(RecentFile {
+        path,
+        options: vec![],
+      }))
+    };
+    match file {
+      Some(file) => self.open_file(file),
+      None => self.closest_text = "Usage: e <file>".to_string(),
+    }
+  }
+
+  /// `export gpx|kml|geojson <file> [<layers>]` writes the layers, without layers the selected
+  /// shapes, or all layers if nothing is selected.
+  fn export_command(&mut self, argument: &str) {
+    let mut words = argument.split_whitespace();
+    let format = words
+      .next()
+      .and_then(|format| format.parse::<ExportFormat>().ok());
+    let (Some(format), Some(path)) = (format, words.next()) else {
+      self.closest_text = "Usage: export gpx|kml|geojson <file> [<layers>]".to_string();
+      return;
+    };
+    let ids: Vec<&str> = words.collect();
+    let layers = match (ids.is_empty(), self.map_provider.selection.is_empty()) {
+      (false, _) => {
+        let mut layers = self.map_provider.export_layers();
+        layers.retain(|layer| ids.contains(&layer.id.as_str()));
+        layers
+      }
+      (true, true) => self.map_provider.export_layers(),
+      (true, false) => self.map_provider.selected_layers(),
+    };
+    self.export(format, layers, path.into());
+  }
+
+  /// `set <name>=<value>` changes a setting of the map.
+  pub(super) fn set_command(&mut self, argument: &str) {
+    match argument.split_once('=') {
+      Some(("timezone", zone)) => match zone.parse() {
+        Ok(zone) => self.time_zone = zone,
+        Err(e) => self.closest_text = e.to_string(),
+      },
+      Some(("ghost", opacity)) => match parse_opacity(opacity) {
+        Some(opacity) => self.ghost_opacity = opacity,
+        None => self.closest_text = "Usage: set ghost=<0 to 1>".to_string(),
+      },
+      Some(("debug_overlay", "on")) => self.frame_stats = Some(FrameStats::default()),
+      Some(("debug_overlay", "off")) => self.frame_stats = None,
+      None if argument == "debug_overlay" => {
+        self.frame_stats = match self.frame_stats {
+          Some(_) => None,
+          None => Some(FrameStats::default()),
+        };
+      }
+      Some(("coordinates", format)) => match format.trim().parse() {
+        Ok(format) => self.coordinate_format = format,
+        Err(()) => {
+          self.closest_text = "Usage: set coordinates=<decimal|dms|utm|mgrs>".to_string();
+        }
+      },
+      Some(("heatmap_radius", radius)) => match radius.trim().parse::<f32>() {
+        Ok(radius) if (1. ..=400.).contains(&radius) => self.heatmap.radius = radius,
+        _ => self.closest_text = "Usage: set heatmap_radius=<1 to 400 pixels>".to_string(),
+      },
+      Some(("heatmap_intensity", intensity)) => match intensity.trim().parse::<f32>() {
+        Ok(intensity) if (0.1..=100.).contains(&intensity) => self.heatmap.intensity = intensity,
+        _ => self.closest_text = "Usage: set heatmap_intensity=<0.1 to 100>".to_string(),
+      },
+      _ => {
+        self.closest_text = "Usage: set timezone=<utc|local|Europe/Berlin>, ghost=<0 to 1>, \
+          coordinates=<decimal|dms|utm|mgrs>, heatmap_radius=<pixels>, \
+          heatmap_intensity=<factor>, or debug_overlay[=on|off]"
+          .to_string();
+      }
+    }
+  }
+
+  /// `search <query>` focuses the elements of all layers matching the query and lists their
+  /// labels.
+  pub(super) fn search(&mut self, query: &str) {
+    const LISTED: usize = 5;
+    let query: Query = match query.parse() {
+      Ok(query) => query,
+      Err(e) => {
+        self.closest_text = format!("Usage: search <query>, {e}");
+        return;
+      }
+    };
+    let mut bb = BoundingBox::get_invalid();
+    let mut labels = vec![];
+    let mut count = 0;
+    for (element, _) in self.map_provider.layers.values().flatten() {
+      let feature = element.feature();
+      if query.holds(&feature) {
+        count += 1;
+        element.add_to_bounding_box(&mut bb);
+        if labels.len() < LISTED {
+          labels.extend(
+            feature
+              .label
+              .and_then(|l| l.lines().next())
+              .map(String::from),
+          );
+        }
+      }
+    }
+    if count == 0 {
+      self.closest_text = format!("Nothing matches {query}");
+      return;
+    }
+    self.closest_text = format!("{count} matches: {}", labels.join(" | "));
+    self.focus_bounding_box(&bb);
+  }
+
+  /// `filter <query>` only draws the elements matching the query, `filter` draws all again.
+  pub(super) fn filter_command(&mut self, query: &str) {
+    if query.is_empty() {
+      self.map_provider.filter = None;
+      return;
+    }
+    match query.parse() {
+      Ok(query) => self.map_provider.filter = Some(query),
+      Err(e) => self.closest_text = e.to_string(),
+    }
+  }
+
+  /// `style <layer> <rule>` adds a style rule to the layer, `style <layer> clear` removes them, and
+  /// `style <layer>` lists them.
+  pub(super) fn style_command(&mut self, argument: &str) {
+    let (layer, rule) = argument.split_once(' ').unwrap_or((argument, ""));
+    let rule = rule.trim();
+    let rules = &mut self.map_provider.rules;
+    match rule {
+      "" => {
+        let listed: Vec<String> = rules
+          .get(layer)
+          .into_iter()
+          .flatten()
+          .map(ToString::to_string)
+          .collect();
+        self.closest_text = listed.join(" | ");
+      }
+      "clear" => {
+        rules.remove(layer);
+      }
+      rule => match rule.parse() {
+        Ok(rule) => {
+          rules.entry(layer.to_string()).or_default().push(rule);
+          self.map_provider.prepare_rules(layer);
+        }
+        Err(e) => self.closest_text = e.to_string(),
+      },
+    }
+  }
+}
+
+/// An opacity from 0 to 1. Other values and NaN are rejected.
+fn parse_opacity(opacity: &str) -> Option<f32> {
+  opacity
+    .trim()
+    .parse::<f32>()
+    .ok()
+    .filter(|o| (0. ..=1.).contains(o))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn opacities() {
+    assert_eq!(parse_opacity(" 0.5"), Some(0.5));
+    assert_eq!(parse_opacity("1"), Some(1.));
+    assert_eq!(parse_opacity("nan"), None);
+    assert_eq!(parse_opacity("inf"), None);
+    assert_eq!(parse_opacity("1.5"), None);
+    assert_eq!(parse_opacity("-0.1"), None);
+  }
+}
@@ -0,0 +1,293 @@
+use femtovg::{Color, Paint, Path, Transform2D};
+
+use crate::map::{
+  compare::{Compare, Mode, Other},
+  coordinates::PixelPosition,
+  viewport::{Follow, Viewport},
+};
+
+use super::MapVas;
+
+impl MapVas {
+  /// Draws the tiles and layers of the sides of `:compare`, each in its part of the window.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn draw_compared(&mut self, compare: Compare) {
+    let size = self.window.inner_size();
+    let (width, height) = (size.width as f32, size.height as f32);
+    let sides = [false, true].into_iter().zip(compare.sides(width));
+    for (right, side) in sides.clone() {
+      self.clip_side(side, height);
+      let tiles = self.draw_map(Some(right));
+      if !right {
+        self.prefetch(&tiles);
+      }
+      self.canvas.restore();
+    }
+    self.dim_map();
+    for (right, side) in sides {
+      self.clip_side(side, height);
+      let time = match compare.other {
+        Other::Time(time) if right => Some(std::mem::replace(&mut self.time, time)),
+        _ => None,
+      };
+      self.draw_layers();
+      if let Some(time) = time {
+        self.time = time;
+      }
+      self.canvas.restore();
+    }
+  }
+
+  /// Saves the canvas and only draws into the side given as start, width, and how far the map is
+  /// moved horizontally, until the canvas is restored.
+  pub(super) fn clip_side(&mut self, (x, width, shift): (f32, f32, f32), height: f32) {
+    self.canvas.save();
+    let transform = self.canvas.transform();
+    self.canvas.reset_transform();
+    self.canvas.scissor(x, 0., width, height);
+    self.canvas.set_transform(&transform);
+    self.translate(0., 0., shift, 0.);
+  }
+
+  /// Draws the line between the sides of `:compare` and what each side shows.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn draw_compare_divider(&mut self) {
+    let Some(compare) = self.compare else {
+      return;
+    };
+    let size = self.window.inner_size();
+    let (width, height) = (size.width as f32, size.height as f32);
+    let x = compare.divider_x(width);
+    let mut line = Path::new();
+    line.move_to(x, 0.);
+    line.line_to(x, height);
+    let mut paint = Paint::color(self.theme.text());
+    paint.set_line_width(2.);
+    self.canvas.stroke_path(&line, &paint);
+    if compare.mode == Mode::Swipe {
+      let mut handle = Path::new();
+      handle.circle(x, height / 2., 8.);
+      self
+        .canvas
+        .fill_path(&handle, &Paint::color(self.theme.halo()));
+      self.canvas.stroke_path(&handle, &paint);
+    }
+    let mut text_paint = Paint::color(self.theme.text());
+    text_paint.set_font_size(13.);
+    text_paint.set_text_baseline(femtovg::Baseline::Top);
+    let (left, right) = self.compare_names(compare);
+    text_paint.set_text_align(femtovg::Align::Right);
+    let _ = self.canvas.fill_text(x - 10., 10., left, &text_paint);
+    text_paint.set_text_align(femtovg::Align::Left);
+    let _ = self.canvas.fill_text(x + 10., 10., right, &text_paint);
+  }
+
+  /// What the left and the right side of `:compare` show.
+  pub(super) fn compare_names(&self, compare: Compare) -> (String, String) {
+    match compare.other {
+      Other::TileLayer(_) => {
+        let names = |right: bool| {
+          self
+            .map_provider
+            .tile_layers
+            .iter()
+            .filter(|l| compare.shows_tile_layer(right, l.id))
+            .map(|l| l.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+        };
+        (names(false), names(true))
+      }
+      Other::Time(time) => {
+        let name = |time: Option<i64>| time.map_or("all".to_string(), |t| self.time_zone.format(t));
+        (name(self.time), name(time))
+      }
+    }
+  }
+
+  /// `compare tiles [<number>] [split]` shows the tile layer at the position in the drawing
+  /// order, by default the selected one, right of a divider and the other tile layers left of it.
+  /// `compare time [split]` keeps showing the layers at the current time cursor on the right,
+  /// while the time cursor moves the left side. `split` shows the same area in both halves of the
+  /// window instead of dividing one map. `compare off` ends it.
+  pub(super) fn compare_command(&mut self, argument: &str) {
+    const USAGE: &str = "Usage: compare tiles [<number>] [split] | time [split] | off";
+    let mut words: Vec<&str> = argument.split_whitespace().collect();
+    let mode = if words.last() == Some(&"split") {
+      words.pop();
+      Mode::Split
+    } else {
+      Mode::Swipe
+    };
+    let other = match words[..] {
+      ["off"] => {
+        self.compare = None;
+        return;
+      }
+      ["tiles"] => self
+        .map_provider
+        .selected_tile_layer()
+        .map(|l| Other::TileLayer(l.id)),
+      ["tiles", number] => number
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| self.map_provider.tile_layers.get(n.checked_sub(1)?))
+        .map(|l| Other::TileLayer(l.id)),
+      ["time"] => Some(Other::Time(self.time)),
+      _ => None,
+    };
+    match other {
+      Some(other) => self.compare = Some(Compare::new(other, mode)),
+      None => self.closest_text = USAGE.to_string(),
+    }
+  }
+
+  /// If the mouse is on the divider of `:compare`.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn mouse_over_divider(&self) -> bool {
+    let width = self.window.inner_size().width as f32;
+    self
+      .compare
+      .is_some_and(|compare| compare.grabs(self.mousex, width))
+  }
+
+  /// `viewport [overview] [<levels>]` shows the area around the center of the map further out in
+  /// an inset, 4 zoom levels by default. `viewport pin` keeps showing the current view in the
+  /// inset while the map moves on, `viewport off` removes the inset.
+  pub(super) fn viewport_command(&mut self, argument: &str) {
+    let follow = match argument {
+      "off" => {
+        self.viewport = None;
+        return;
+      }
+      "pin" => {
+        let (nw, se, zoom) = self.get_current_canvas_section();
+        let center = PixelPosition {
+          x: (nw.x + se.x) / 2.,
+          y: (nw.y + se.y) / 2.,
+        };
+        Ok(Follow::Pinned(center, zoom))
+      }
+      _ => argument.parse(),
+    };
+    match follow {
+      Ok(follow) => self.viewport = Some(Viewport::new(follow)),
+      Err(()) => {
+        self.closest_text = "Usage: viewport [overview] [<levels>] | pin | off".to_string();
+      }
+    }
+  }
+
+  /// The area of the inset of `:viewport` in the window and the center and zoom factor it shows.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn viewport_camera(&self) -> Option<((f32, f32, f32, f32), PixelPosition, f32)> {
+    let viewport = self.viewport?;
+    let size = self.window.inner_size();
+    let area = Viewport::area(size.width as f32, size.height as f32 - self.status_height());
+    let (nw, se, zoom) = self.get_current_canvas_section();
+    let center = PixelPosition {
+      x: (nw.x + se.x) / 2.,
+      y: (nw.y + se.y) / 2.,
+    };
+    let (center, zoom) = viewport.camera(center, zoom, area.2);
+    Some((area, center, zoom))
+  }
+
+  /// Draws the tiles and layers around the camera of `:viewport` in its inset. An overview
+  /// outlines the area shown by the map.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn draw_viewport(&mut self, transform: Transform2D) {
+    let Some(((left, top, width, height), center, zoom)) =
+      self.with_map_transform(transform, Self::viewport_camera)
+    else {
+      return;
+    };
+    let map = transform.inversed();
+    let size = self.window.inner_size();
+    let corners = [
+      (0., 0.),
+      (size.width as f32, 0.),
+      (size.width as f32, size.height as f32),
+      (0., size.height as f32),
+    ]
+    .map(|(x, y)| map.transform_point(x, y));
+    let mut frame = Path::new();
+    frame.rect(left, top, width, height);
+    self.canvas.save();
+    self.canvas.reset_transform();
+    self
+      .canvas
+      .fill_path(&frame, &Paint::color(self.theme.background()));
+    self.canvas.scissor(left, top, width, height);
+    self.canvas.translate(left + width / 2., top + height / 2.);
+    self.canvas.scale(zoom, zoom);
+    self.canvas.translate(-center.x, -center.y);
+    self.draw_map(None);
+    if let Some(color) = self.theme.tile_dimming() {
+      self.canvas.save();
+      self.canvas.reset_transform();
+      self.canvas.fill_path(&frame, &Paint::color(color));
+      self.canvas.restore();
+    }
+    self.draw_layers();
+    if matches!(
+      self.viewport,
+      Some(Viewport {
+        follow: Follow::Mirror(_)
+      })
+    ) {
+      let mut outline = Path::new();
+      outline.move_to(corners[0].0, corners[0].1);
+      for (x, y) in &corners[1..] {
+        outline.line_to(*x, *y);
+      }
+      outline.close();
+      let mut paint = Paint::color(Color::rgb(220, 30, 30));
+      paint.set_line_width(2. / zoom);
+      self.canvas.stroke_path(&outline, &paint);
+    }
+    self.canvas.restore();
+    let mut border = Paint::color(self.theme.text());
+    border.set_line_width(1.);
+    self.canvas.stroke_path(&frame, &border);
+  }
+
+  /// Calls `f` while the canvas is transformed like the map, from code drawing in screen
+  /// coordinates.
+  pub(super) fn with_map_transform<T>(
+    &mut self,
+    transform: Transform2D,
+    f: impl FnOnce(&Self) -> T,
+  ) -> T {
+    self.canvas.save();
+    self.canvas.reset_transform();
+    self.canvas.set_transform(&transform);
+    let result = f(self);
+    self.canvas.restore();
+    result
+  }
+
+  /// If the mouse is over the inset of `:viewport`.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn mouse_over_viewport(&self) -> bool {
+    let size = self.window.inner_size();
+    self.viewport.is_some()
+      && Viewport::contains(
+        size.width as f32,
+        size.height as f32 - self.status_height(),
+        self.mousex,
+        self.mousey,
+      )
+  }
+
+  /// Centers the map on the position under the mouse in the inset of `:viewport`.
+  pub(super) fn center_on_viewport(&mut self) {
+    let Some(((left, top, width, height), center, zoom)) = self.viewport_camera() else {
+      return;
+    };
+    self.set_center(PixelPosition {
+      x: center.x + (self.mousex - left - width / 2.) / zoom,
+      y: center.y + (self.mousey - top - height / 2.) / zoom,
+    });
+  }
+}
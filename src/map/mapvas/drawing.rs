@@ -0,0 +1,691 @@
+use std::{path::PathBuf, time::Instant};
+
+use femtovg::{BlendFactor, Color, CompositeOperation, ImageFlags, Paint, Path, Transform2D};
+use glutin::prelude::*;
+use log::error;
+
+use crate::map::{
+  cluster::grid_clusters,
+  coordinates::{prefetch_tiles, tiles_in_box, PixelPosition, Tile, TileCoordinate, TILE_SIZE},
+  heatmap::{HeatmapInput, CELL},
+  labels::{CollisionIndex, LabelBox},
+  map_event::{Anchor, BlendMode, Direction, FillStyle, Icon, MapUpdate, RenderMode},
+  outline,
+  sprite::{bundled_icon, Sprite, SpriteIcon},
+  style_rule::apply,
+};
+
+use super::{to_points, truncate_label, ElementLabel, LayerElement, MapVas};
+
+impl MapVas {
+  /// The visible tiles, the ones closest to the center of the window first.
+  #[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+  )]
+  fn get_tiles_to_draw(&mut self) -> Vec<Tile> {
+    let (nw, se, zoom) = self.get_current_canvas_section();
+    let center = PixelPosition {
+      x: (nw.x + se.x) / 2.,
+      y: (nw.y + se.y) / 2.,
+    };
+
+    let size = self.window.inner_size();
+    let vertical_tile_number = (size.height as f32 / TILE_SIZE).round();
+
+    let zoom_level = ((zoom * vertical_tile_number).log2() as i32).clamp(2, 19);
+    let nw_tile = TileCoordinate::from_pixel_position(nw.clamp(), zoom_level as u8);
+    let se_tile = TileCoordinate::from_pixel_position(se.clamp(), zoom_level as u8);
+    let mut tiles: Vec<Tile> = tiles_in_box(nw_tile, se_tile).collect();
+    tiles.sort_by(|a, b| {
+      let distance = |tile: &Tile| {
+        let (nw, se) = tile.position();
+        ((nw.x + se.x) / 2. - center.x).hypot((nw.y + se.y) / 2. - center.y)
+      };
+      distance(a).total_cmp(&distance(b))
+    });
+    tiles
+  }
+
+  /// Draws the tiles of all tile layers, or of those shown by a side of `:compare`, and returns
+  /// the visible tiles.
+  pub(super) fn draw_map(&mut self, right: Option<bool>) -> Vec<Tile> {
+    let tiles = self.get_tiles_to_draw();
+    let compare = self.compare;
+    let tile_layers = self.map_provider.tile_layers.iter().filter(|l| {
+      compare.zip(right).map_or(true, |(compare, right)| {
+        compare.shows_tile_layer(right, l.id)
+      })
+    });
+    for tile_layer in tile_layers {
+      for tile in &tiles {
+        let found_tile_image =
+          tile_layer.find_image_or_download(*tile, &self.map_provider.event_sender);
+        let Some((found_tile, image)) = found_tile_image else {
+          continue;
+        };
+        let (nw, se) = found_tile.position();
+        let fill_paint = Paint::image(
+          *image,
+          nw.x,
+          nw.y,
+          se.x - nw.x,
+          se.y - nw.y,
+          0.0,
+          tile_layer.opacity,
+        );
+        let mut path = Path::new();
+        path.rect(nw.x, nw.y, se.x, se.y);
+        self.canvas.fill_path(&path, &fill_paint);
+      }
+    }
+    tiles
+  }
+
+  /// Loads tiles around the visible ones and in the direction of panning before they are needed.
+  pub(super) fn prefetch(&mut self, visible: &[Tile]) {
+    if self.map_provider.prefetch == 0 {
+      return;
+    }
+    let (nw, se, _) = self.get_current_canvas_section();
+    let center = PixelPosition {
+      x: (nw.x + se.x) / 2.,
+      y: (nw.y + se.y) / 2.,
+    };
+    let provider = &mut self.map_provider;
+    if let Some(last) = provider.last_center {
+      let direction = (center.x - last.x, center.y - last.y);
+      if direction != (0., 0.) {
+        provider.pan_direction = direction;
+      }
+    }
+    provider.last_center = Some(center);
+    let tiles = prefetch_tiles(visible, provider.pan_direction);
+    for tile_layer in &provider.tile_layers {
+      tile_layer.prefetch(&tiles, provider.prefetch, &provider.event_sender);
+    }
+  }
+
+  /// Darkens the tiles if the theme asks for it.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn dim_map(&mut self) {
+    let Some(color) = self.theme.tile_dimming() else {
+      return;
+    };
+    let size = self.window.inner_size();
+    let mut rect = Path::new();
+    rect.rect(0., 0., size.width as f32, size.height as f32);
+    self.canvas.save();
+    self.canvas.reset();
+    self.canvas.fill_path(&rect, &Paint::color(color));
+    self.canvas.restore();
+  }
+
+  #[allow(clippy::cast_possible_truncation)]
+  pub(super) fn redraw(&mut self) {
+    let start = Instant::now();
+    if let Some(stats) = self.frame_stats.as_mut() {
+      stats.start_frame();
+    }
+    self.advance_flight();
+    self.fit_to_window();
+    self.publish_camera();
+    let dpi_factor = self.window.scale_factor();
+    let size = self.window.inner_size();
+
+    self
+      .canvas
+      .set_size(size.width, size.height, dpi_factor as f32);
+    self
+      .canvas
+      .clear_rect(0, 0, size.width, size.height, self.theme.background());
+
+    match self.compare {
+      Some(compare) => self.draw_compared(compare),
+      None => {
+        let tiles = self.draw_map(None);
+        self.prefetch(&tiles);
+        self.dim_map();
+        self.draw_layers();
+      }
+    }
+    let cluster_labels = self.draw_clusters();
+    let element_labels = self.element_labels();
+    self.draw_selection();
+    self.draw_sketch();
+    self.draw_edit_handles();
+    self.draw_overlay_handles();
+
+    let transform = self.canvas.transform();
+    self.canvas.save();
+    self.canvas.reset();
+    self.draw_heatmap(transform);
+    self.draw_cluster_labels(&cluster_labels);
+    self.draw_element_labels(&cluster_labels, &element_labels);
+    self.draw_viewport(transform);
+    self.draw_compare_divider();
+    self.draw_compass();
+    self.draw_scale_bar();
+    self.draw_legend();
+    self.draw_timeline();
+    self.draw_offline_indicator();
+    self.draw_table();
+    self.draw_status_bar(transform);
+    self.draw_debug_overlay();
+    self.draw_text();
+    self.canvas.restore();
+
+    self.canvas.flush();
+    if let Some(stats) = self.frame_stats.as_mut() {
+      stats.record(start.elapsed());
+    }
+    self.surface.swap_buffers(&self.context).unwrap();
+    // A screenshot shows where a flight like the one to a `bbox` of the remote api ends.
+    if self.flight.is_none() {
+      if let Some(path) = self.screenshot.take() {
+        self.make_screenshot(path.clone());
+        self.publish(MapUpdate::Screenshot { path });
+      }
+    }
+  }
+
+  /// Draws the overlays and the shape layers in their order.
+  pub(super) fn draw_layers(&mut self) {
+    for id in self.map_provider.order.ids().to_vec() {
+      self.draw_overlay(&id);
+      self.draw_shape_layer(&id);
+    }
+  }
+
+  fn draw_shape_layer(&mut self, id: &str) {
+    let pixel = 1. / self.get_zoom_factor();
+    // Half a screen pixel in canvas coordinates is not visible.
+    let tolerance = 0.5 * pixel;
+    let Some(elements) = self.map_provider.layers.get(id) else {
+      return;
+    };
+    if self.map_provider.render_mode(id) == RenderMode::Heatmap {
+      return;
+    }
+    let rules = self.map_provider.rules.get(id);
+    let opacity = self.map_provider.opacities.get(id).copied().unwrap_or(1.);
+    let clustered = self.map_provider.clusters(id);
+    self.canvas.save();
+    match self.map_provider.blend_modes.get(id) {
+      Some(BlendMode::Add) => self
+        .canvas
+        .global_composite_operation(CompositeOperation::Lighter),
+      Some(BlendMode::Multiply) => self
+        .canvas
+        .global_composite_blend_func(BlendFactor::DstColor, BlendFactor::OneMinusSrcAlpha),
+      Some(BlendMode::Normal) | None => (),
+    }
+    let window = self.time_window().filter(|_| self.tracks.is_enabled(id));
+    let ghost = self.ghost_opacity;
+    let mut icons = vec![];
+    let mut ghost_icons = vec![];
+    let mut drawn = 0;
+    for (i, (path, style)) in elements.iter().enumerate() {
+      if !self.map_provider.is_shown(id, i, path) {
+        continue;
+      }
+      let shown = path.visible_in(window);
+      if !shown && ghost <= 0. {
+        continue;
+      }
+      self
+        .canvas
+        .set_global_alpha(if shown { opacity } else { opacity * ghost });
+      let style = match rules {
+        Some(rules) => match apply(rules, *style, &path.feature()) {
+          Some(style) => style,
+          None => continue,
+        },
+        None => *style,
+      };
+      drawn += 1;
+      let mut stroke = Paint::color(style.color.to_rgb());
+      stroke.set_line_width(style.line_width() * pixel);
+      let fill = match style.fill {
+        FillStyle::Transparent => Some(Paint::color(style.color.to_rgba(50))),
+        FillStyle::Solid => Some(Paint::color(style.color.to_rgb())),
+        FillStyle::NoFill => None,
+      };
+
+      match path {
+        LayerElement::Polyline(lod, _, positions, ..) => {
+          let poly = lod.path(tolerance);
+          match style.dash {
+            Some(dash) => {
+              let mut points = to_points(positions);
+              if fill.is_some() && points.first() != points.last() {
+                points.push(points[0]);
+              }
+              let mut dashes = Path::new();
+              outline::dashes(&mut dashes, dash, &points, pixel);
+              self.canvas.stroke_path(&dashes, &stroke);
+            }
+            None => self.canvas.stroke_path(poly, &stroke),
+          }
+          if let Some(style) = fill.as_ref() {
+            self.canvas.fill_path(poly, style);
+          };
+          if let Some(direction) = style.direction {
+            let mut marks = Path::new();
+            let points = to_points(positions);
+            outline::direction(&mut marks, direction, &points, style.line_width(), pixel);
+            match direction {
+              Direction::Arrow => self
+                .canvas
+                .fill_path(&marks, &Paint::color(style.color.to_rgb())),
+              Direction::Ticks => self.canvas.stroke_path(&marks, &stroke),
+            }
+          }
+        }
+        LayerElement::Point(..) if clustered => (),
+        LayerElement::Point(point, _, Some(icon), _) if shown => {
+          icons.push((*point, icon.clone()));
+        }
+        LayerElement::Point(point, _, Some(icon), _) => ghost_icons.push((*point, icon.clone())),
+        LayerElement::Point(point, _, None, _) => {
+          let radius = (style.point_radius() * pixel).max(0.000_05);
+          let mut marker = Path::new();
+          outline::marker(&mut marker, style.marker, point.x, point.y, radius);
+          self.canvas.stroke_path(&marker, &stroke);
+          if let Some(style) = fill.as_ref() {
+            self.canvas.fill_path(&marker, style);
+          };
+        }
+      };
+    }
+    self.canvas.set_global_alpha(opacity * ghost);
+    self.draw_point_icons(&ghost_icons);
+    self.canvas.set_global_alpha(opacity);
+    self.draw_point_icons(&icons);
+    self.canvas.restore();
+    if let Some(stats) = self.frame_stats.as_mut() {
+      stats.count(id, drawn);
+    }
+  }
+
+  /// Draws the icons upright in screen coordinates at their points.
+  fn draw_point_icons(&mut self, icons: &[(PixelPosition, Icon)]) {
+    if icons.is_empty() {
+      return;
+    }
+    let zoom = self.camera().zoom;
+    let transform = self.canvas.transform();
+    self.canvas.save();
+    self.canvas.reset_transform();
+    for (point, icon) in icons {
+      let (x, y) = transform.transform_point(point.x, point.y);
+      let height = icon.height(zoom);
+      let y = match icon.anchor {
+        Anchor::Center => y,
+        Anchor::Bottom => y - height / 2.,
+      };
+      if let Some(path) = icon.image() {
+        self.draw_image_icon(&path, x, y, height);
+      } else if let Some(sprite_icon) = self
+        .sprite
+        .as_ref()
+        .and_then(|(sprite, _)| sprite.icon(&icon.name))
+        .copied()
+      {
+        let scale = height / (sprite_icon.height / sprite_icon.pixel_ratio);
+        self.draw_icon(x, y, &sprite_icon, scale);
+      } else {
+        let mut paint = Paint::color(Color::rgb(0, 0, 0));
+        paint.set_font_size(height);
+        paint.set_text_align(femtovg::Align::Center);
+        paint.set_text_baseline(femtovg::Baseline::Middle);
+        let text = bundled_icon(&icon.name).unwrap_or(&icon.name);
+        let _ = self.canvas.fill_text(x, y, text, &paint);
+      }
+    }
+    self.canvas.restore();
+  }
+
+  /// Draws an image file centered at the screen position, loading it on first use.
+  #[allow(clippy::cast_precision_loss)]
+  fn draw_image_icon(&mut self, path: &std::path::Path, x: f32, y: f32, height: f32) {
+    let image = match self.icon_images.get(path) {
+      Some(image) => *image,
+      None => {
+        let image = self
+          .canvas
+          .load_image_file(path, ImageFlags::GENERATE_MIPMAPS)
+          .map_err(|e| error!("Cannot load icon {path:?}: {e:?}"))
+          .ok();
+        self.icon_images.insert(path.to_path_buf(), image);
+        image
+      }
+    };
+    let Some(image) = image else {
+      return;
+    };
+    let Ok((w, h)) = self.canvas.image_size(image) else {
+      return;
+    };
+    let width = height * w as f32 / h.max(1) as f32;
+    let (left, top) = (x - width / 2., y - height / 2.);
+    let mut rect = Path::new();
+    rect.rect(left, top, width, height);
+    let paint = Paint::image(image, left, top, width, height, 0., 1.);
+    self.canvas.fill_path(&rect, &paint);
+  }
+
+  /// Loads a font with emoji for text icons, given by `MAPVAS_EMOJI_FONT` or found in the usual
+  /// system places. Text falls back to it for characters the default font lacks.
+  pub(super) fn load_emoji_font(&mut self) {
+    let candidates = std::env::var("MAPVAS_EMOJI_FONT").map_or_else(
+      |_| {
+        vec![
+          PathBuf::from("/usr/share/fonts/truetype/noto/NotoColorEmoji.ttf"),
+          PathBuf::from("/usr/share/fonts/noto/NotoColorEmoji.ttf"),
+          PathBuf::from("/usr/share/fonts/google-noto-emoji/NotoColorEmoji.ttf"),
+          PathBuf::from("/System/Library/Fonts/Apple Color Emoji.ttc"),
+          PathBuf::from("C:\\Windows\\Fonts\\seguiemj.ttf"),
+        ]
+      },
+      |path| vec![PathBuf::from(path)],
+    );
+    if let Some(path) = candidates.iter().find(|path| path.exists()) {
+      if let Err(e) = self.canvas.add_font(path) {
+        error!("Cannot load emoji font {path:?}: {e:?}");
+      }
+    }
+  }
+
+  /// Draws the points of the clustered layers aggregated on a grid.
+  /// Returns the screen positions and texts of the cluster counts.
+  #[allow(clippy::cast_precision_loss)]
+  fn draw_clusters(&mut self) -> Vec<(f32, f32, String)> {
+    const CELL_SIZE: f32 = 60.;
+    let zoom = self.get_zoom_factor();
+    let transform = self.canvas.transform();
+    let mut labels = vec![];
+    for (id, elements) in &self.map_provider.layers {
+      if !self.map_provider.clusters(id) || self.map_provider.render_mode(id) == RenderMode::Heatmap
+      {
+        continue;
+      }
+      let points = elements.iter().filter_map(|(e, style)| match e {
+        LayerElement::Point(p, ..) => Some((*p, *style)),
+        LayerElement::Polyline(..) => None,
+      });
+      let Some((_, style)) = points.clone().next() else {
+        continue;
+      };
+      let mut stroke = Paint::color(style.color.to_rgb());
+      stroke.set_line_width(1. / zoom);
+      let fill = Paint::color(style.color.to_rgba(160));
+      for cluster in grid_clusters(points.map(|(p, _)| p), CELL_SIZE / zoom) {
+        let radius = if cluster.count == 1 {
+          3.
+        } else {
+          8. + 2. * (cluster.count as f32).log2()
+        };
+        let mut circle = Path::new();
+        circle.circle(cluster.center.x, cluster.center.y, radius / zoom);
+        self.canvas.fill_path(&circle, &fill);
+        self.canvas.stroke_path(&circle, &stroke);
+        if cluster.count > 1 {
+          let (x, y) = transform.transform_point(cluster.center.x, cluster.center.y);
+          labels.push((x, y, cluster.count.to_string()));
+        }
+      }
+    }
+    labels
+  }
+
+  /// Draws the points of heatmap layers as density raster in screen coordinates. The raster is
+  /// only computed again if the points on the screen or the settings changed.
+  /// * `transform` - The canvas transformation of the map.
+  #[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+  )]
+  fn draw_heatmap(&mut self, transform: Transform2D) {
+    let points: Vec<(f32, f32)> = self
+      .map_provider
+      .layers
+      .iter()
+      .filter(|(id, _)| self.map_provider.render_mode(id) == RenderMode::Heatmap)
+      .flat_map(|(_, elements)| elements.iter())
+      .filter_map(|(e, _)| match e {
+        LayerElement::Point(p, ..) => {
+          let (x, y) = transform.transform_point(p.x, p.y);
+          Some((x / CELL, y / CELL))
+        }
+        LayerElement::Polyline(..) => None,
+      })
+      .collect();
+    if points.is_empty() {
+      return;
+    }
+
+    let size = self.window.inner_size();
+    let width = (size.width as f32 / CELL).ceil() as usize;
+    let height = (size.height as f32 / CELL).ceil() as usize;
+    let input = HeatmapInput {
+      points,
+      width,
+      height,
+      settings: self.heatmap,
+    };
+
+    let image_id = match self.heatmap_image.take() {
+      Some((id, drawn)) if drawn == input => id,
+      cached => {
+        let raster = femtovg::imgref::Img::new(input.colorize(), width, height);
+        let updated = cached.map(|(id, _)| id).filter(|id| {
+          let updated = self.canvas.image_size(*id).ok() == Some((width, height))
+            && self.canvas.update_image(*id, raster.as_ref(), 0, 0).is_ok();
+          if !updated {
+            self.canvas.delete_image(*id);
+          }
+          updated
+        });
+        match updated {
+          Some(id) => id,
+          None => match self
+            .canvas
+            .create_image(raster.as_ref(), ImageFlags::empty())
+          {
+            Ok(id) => id,
+            Err(e) => {
+              error!("Cannot create heatmap image: {e:?}");
+              return;
+            }
+          },
+        }
+      }
+    };
+    self.heatmap_image = Some((image_id, input));
+
+    let (w, h) = (width as f32 * CELL, height as f32 * CELL);
+    let mut rect = Path::new();
+    rect.rect(0., 0., w, h);
+    self
+      .canvas
+      .fill_path(&rect, &Paint::image(image_id, 0., 0., w, h, 0., 1.));
+  }
+
+  /// The labels of visible elements in screen coordinates, points first.
+  fn element_labels(&self) -> Vec<ElementLabel> {
+    if !self.show_labels {
+      return vec![];
+    }
+    let transform = self.canvas.transform();
+    let size = self.window.inner_size();
+    #[allow(clippy::cast_precision_loss)]
+    let (w, h) = (size.width as f32, size.height as f32);
+    let mut points = vec![];
+    let mut lines = vec![];
+    for (id, elements) in &self.map_provider.layers {
+      if self.map_provider.render_mode(id) == RenderMode::Heatmap {
+        continue;
+      }
+      let window = self.time_window().filter(|_| self.tracks.is_enabled(id));
+      for (element, _) in elements
+        .iter()
+        .enumerate()
+        .filter(|(i, (e, _))| self.map_provider.is_shown(id, *i, e))
+        .map(|(_, e)| e)
+        .filter(|(e, _)| e.visible_in(window))
+      {
+        let (anchor, label, labels) = match element {
+          LayerElement::Point(..) if self.map_provider.clusters(id) => continue,
+          LayerElement::Point(p, label, ..) => (*p, label, &mut points),
+          LayerElement::Polyline(_, _, positions, label, _) => {
+            let Some(middle) = positions.get(positions.len() / 2) else {
+              continue;
+            };
+            (*middle, label, &mut lines)
+          }
+        };
+        let Some(label) = label else {
+          continue;
+        };
+        let Some(text) = label.lines().next() else {
+          continue;
+        };
+        let (x, y) = transform.transform_point(anchor.x, anchor.y);
+        if (0. ..w).contains(&x) && (0. ..h).contains(&y) {
+          let icon = self
+            .sprite
+            .as_ref()
+            .and_then(|(sprite, _)| sprite.icon_for_label(label))
+            .copied();
+          labels.push(ElementLabel {
+            x,
+            y,
+            text: truncate_label(text),
+            icon,
+          });
+        }
+      }
+    }
+    points.extend(lines);
+    points
+  }
+
+  /// Draws the labels that do not overlap cluster counts or labels drawn before them.
+  fn draw_element_labels(
+    &mut self,
+    cluster_labels: &[(f32, f32, String)],
+    labels: &[ElementLabel],
+  ) {
+    const FONT_SIZE: f32 = 12.;
+    let mut index = CollisionIndex::new(64.);
+    for (x, y, _) in cluster_labels {
+      index.try_insert(LabelBox {
+        x: x - FONT_SIZE,
+        y: y - FONT_SIZE,
+        width: 2. * FONT_SIZE,
+        height: 2. * FONT_SIZE,
+      });
+    }
+    let mut outline = Paint::color(self.theme.halo());
+    outline.set_font_size(FONT_SIZE);
+    outline.set_line_width(3.);
+    outline.set_text_baseline(femtovg::Baseline::Middle);
+    let mut text_paint = Paint::color(self.theme.text());
+    text_paint.set_font_size(FONT_SIZE);
+    text_paint.set_text_baseline(femtovg::Baseline::Middle);
+    for label in labels {
+      let (icon_width, icon_height) = label.icon.map_or((0., 0.), |i| {
+        (i.width / i.pixel_ratio, i.height / i.pixel_ratio)
+      });
+      let x = label.x + icon_width / 2. + 6.;
+      let Ok(metrics) = self
+        .canvas
+        .measure_text(x, label.y, &label.text, &text_paint)
+      else {
+        continue;
+      };
+      let top = metrics.y.min(label.y - icon_height / 2.);
+      let bottom = (metrics.y + metrics.height()).max(label.y + icon_height / 2.);
+      let label_box = LabelBox {
+        x: label.x - icon_width / 2. - 2.,
+        y: top - 2.,
+        width: metrics.x + metrics.width() - label.x + icon_width / 2. + 4.,
+        height: bottom - top + 4.,
+      };
+      if index.try_insert(label_box) {
+        if let Some(icon) = label.icon {
+          self.draw_icon(label.x, label.y, &icon, 1.);
+        }
+        let _ = self.canvas.stroke_text(x, label.y, &label.text, &outline);
+        let _ = self.canvas.fill_text(x, label.y, &label.text, &text_paint);
+      }
+    }
+  }
+
+  /// Draws an icon of the sprite centered at the screen position.
+  #[allow(clippy::cast_precision_loss)]
+  fn draw_icon(&mut self, x: f32, y: f32, icon: &SpriteIcon, scale: f32) {
+    let Some(image) = self.sprite.as_ref().map(|(_, image)| *image) else {
+      return;
+    };
+    let Ok((sheet_width, sheet_height)) = self.canvas.image_size(image) else {
+      return;
+    };
+    let scale = scale / icon.pixel_ratio;
+    let (w, h) = (icon.width * scale, icon.height * scale);
+    let (left, top) = (x - w / 2., y - h / 2.);
+    let paint = Paint::image(
+      image,
+      left - icon.x * scale,
+      top - icon.y * scale,
+      sheet_width as f32 * scale,
+      sheet_height as f32 * scale,
+      0.,
+      1.,
+    );
+    let mut rect = Path::new();
+    rect.rect(left, top, w, h);
+    self.canvas.fill_path(&rect, &paint);
+  }
+
+  /// Loads the spritesheet given by `MAPVAS_SPRITE` for the icons of labels.
+  pub(super) fn load_sprite(&mut self) {
+    match Sprite::from_env() {
+      None => (),
+      Some(Err(e)) => error!("Cannot read sprite: {e}"),
+      Some(Ok(sprite)) => match self
+        .canvas
+        .load_image_file(&sprite.image, ImageFlags::empty())
+      {
+        Ok(image) => self.sprite = Some((sprite, image)),
+        Err(e) => error!("Cannot load sprite image {:?}: {e:?}", sprite.image),
+      },
+    }
+  }
+
+  fn draw_cluster_labels(&mut self, labels: &[(f32, f32, String)]) {
+    let mut text_paint = Paint::color(Color::rgba(255, 255, 255, 255));
+    text_paint.set_font_size(12.);
+    text_paint.set_text_align(femtovg::Align::Center);
+    text_paint.set_text_baseline(femtovg::Baseline::Middle);
+    for (x, y, text) in labels {
+      let _ = self.canvas.fill_text(*x, *y, text, &text_paint);
+    }
+  }
+
+  #[allow(clippy::cast_possible_truncation)]
+  pub(super) fn make_screenshot(&mut self, pb: std::path::PathBuf) {
+    if let Ok(mut img) = self.canvas.screenshot() {
+      let (buf, w, h) = img.as_contiguous_buf();
+      let img_buf: Vec<_> = buf
+        .iter()
+        .flat_map(|p| [p.r, p.g, p.b].into_iter())
+        .collect();
+
+      let _ = image::save_buffer(pb, &img_buf, w as u32, h as u32, image::ColorType::Rgb8);
+    }
+  }
+}
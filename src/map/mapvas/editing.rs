@@ -0,0 +1,276 @@
+use async_std::task::block_on;
+use femtovg::{Color, Paint, Path};
+
+use crate::{
+  export::GeoJsonExporter,
+  map::{
+    annotation::{self, Sketch, Tool},
+    coordinates::{Coordinate, PixelPosition},
+    map_event::{FillStyle, Layer, MapEvent, Shape, Style},
+    spatial::{closest_vertex, insert_vertex, remove_vertex, snap},
+  },
+  parser::{CommandParser, FileParser},
+};
+
+use super::{build_path, EditState, LayerElement, MapVas, Track};
+
+impl MapVas {
+  /// `draw pen|circle|rectangle` lets left dragging draw annotations, `draw off` stops it.
+  pub(super) fn draw_command(&mut self, argument: &str) {
+    match argument.parse::<Tool>() {
+      Ok(tool) => self.tool = Some(tool),
+      Err(()) if argument == "off" => self.tool = None,
+      Err(()) => self.closest_text = "Usage: draw pen|circle|rectangle|off".to_string(),
+    }
+    self.sketch = None;
+  }
+
+  /// Adds the drawn annotation to the annotation layer, so it is exported and drawn in
+  /// screenshots like other elements.
+  pub(super) fn finish_sketch(&mut self) {
+    let Some(sketch) = self.sketch.take() else {
+      return;
+    };
+    let Some((outline, closed)) = sketch.finish(1. / self.get_zoom_factor()) else {
+      return;
+    };
+    let fill = if closed {
+      FillStyle::Transparent
+    } else {
+      FillStyle::NoFill
+    };
+    let mut layer = Layer::new(annotation::LAYER.to_string());
+    layer.shapes.push(
+      Shape::new(outline.into_iter().map(Coordinate::from).collect())
+        .with_color(crate::map::map_event::Color::Red)
+        .with_fill(fill),
+    );
+    self.handle_layer_event(layer);
+  }
+
+  /// `note <text>` places a text note at the mouse in the annotation layer.
+  pub(super) fn add_note(&mut self, text: &str) {
+    if text.is_empty() {
+      self.closest_text = "Usage: note <text>".to_string();
+      return;
+    }
+    let mut layer = Layer::new(annotation::LAYER.to_string());
+    layer.shapes.push(
+      Shape::new(vec![Coordinate::from(self.mouse_position())])
+        .with_color(crate::map::map_event::Color::Red)
+        .with_label(Some(text.to_string())),
+    );
+    self.handle_layer_event(layer);
+    self.show_labels = true;
+  }
+
+  pub(super) fn toggle_editing(&mut self) {
+    self.editing = match self.editing {
+      Some(_) => None,
+      None => self.closest_element(false).map(|(layer, index)| EditState {
+        layer,
+        index,
+        dragged_vertex: None,
+      }),
+    };
+  }
+
+  fn edited_element(&self) -> Option<&(LayerElement, Style)> {
+    self
+      .editing
+      .as_ref()
+      .and_then(|e| self.map_provider.layers.get(&e.layer)?.get(e.index))
+  }
+
+  fn replace_edited_positions(&mut self, positions: Vec<PixelPosition>) {
+    let Some(edit) = &self.editing else {
+      return;
+    };
+    if let Some((element, style)) = self
+      .map_provider
+      .layers
+      .get_mut(&edit.layer)
+      .and_then(|l| l.get_mut(edit.index))
+    {
+      let (text, icon) = (element.get_text(), element.icon().cloned());
+      // Times and elevations only fit while no vertex is added or removed. An edited circle is
+      // an outline.
+      let track = if positions.len() == element.positions().len() {
+        Track {
+          circle: None,
+          ..element.track().clone()
+        }
+      } else {
+        Track {
+          part_of: element.track().part_of,
+          properties: element.track().properties.clone(),
+          source: element.track().source.clone(),
+          ..Track::default()
+        }
+      };
+      *element = LayerElement::from_positions(positions, style.fill != FillStyle::NoFill)
+        .with_text(text)
+        .with_icon(icon)
+        .with_track(track);
+    }
+  }
+
+  /// The index of the vertex of the edited element under the mouse.
+  fn vertex_under_mouse(&self) -> Option<usize> {
+    let positions = self.edited_element()?.0.positions();
+    closest_vertex(
+      &positions,
+      self.mouse_position(),
+      self.distance_threshold() / 2.,
+    )
+  }
+
+  /// Starts dragging a vertex of the edited element if the mouse is close to one. Clicking on a
+  /// segment inserts a new vertex there. Returns whether a vertex is dragged.
+  pub(super) fn start_vertex_drag(&mut self) -> bool {
+    if self.editing.is_none() {
+      return false;
+    }
+    let vertex = self.vertex_under_mouse().or_else(|| {
+      let mut positions = self.edited_element()?.0.positions();
+      let vertex = insert_vertex(
+        &mut positions,
+        self.mouse_position(),
+        self.distance_threshold() / 2.,
+      )?;
+      self.replace_edited_positions(positions);
+      Some(vertex)
+    });
+    if let Some(edit) = self.editing.as_mut() {
+      edit.dragged_vertex = vertex;
+    }
+    vertex.is_some()
+  }
+
+  pub(super) fn drag_vertex(&mut self) {
+    let Some(vertex) = self.editing.as_ref().and_then(|e| e.dragged_vertex) else {
+      return;
+    };
+    let Some((element, _)) = self.edited_element() else {
+      return;
+    };
+    let mut positions = element.positions();
+    if let Some(p) = positions.get_mut(vertex) {
+      *p = self.snapped(self.mouse_position());
+    }
+    self.replace_edited_positions(positions);
+  }
+
+  /// The position snapped to the vertices and segments of the other shown elements if snapping is
+  /// on.
+  fn snapped(&self, position: PixelPosition) -> PixelPosition {
+    if !self.snap_to_geometry {
+      return position;
+    }
+    let distance = self.distance_threshold() / 2.;
+    let edited = self.editing.as_ref().map(|e| (e.layer.as_str(), e.index));
+    let lines: Vec<Vec<PixelPosition>> = self
+      .map_provider
+      .layers
+      .iter()
+      .flat_map(|(id, elements)| {
+        elements
+          .iter()
+          .enumerate()
+          .map(move |(i, (e, _))| (id, i, e))
+      })
+      .filter(|(id, i, e)| {
+        edited != Some((id.as_str(), *i))
+          && e.is_near(position, distance)
+          && self.map_provider.is_shown(id, *i, e)
+      })
+      .map(|(.., e)| e.positions())
+      .collect();
+    snap(position, &lines, distance).unwrap_or(position)
+  }
+
+  /// `snap [command]` pipes the edited element, or else the one closest to the mouse, as GeoJSON
+  /// through a map matching command, `MAPVAS_SNAP_COMMAND` by default. Its output is drawn in the
+  /// layer `snapped <layer>`.
+  pub(super) fn snap_to_road(&mut self, command: &str) {
+    let command = match command {
+      "" => std::env::var("MAPVAS_SNAP_COMMAND").ok(),
+      command => Some(command.to_string()),
+    };
+    let Some(command) = command else {
+      self.closest_text = "Usage: snap <command>, or set MAPVAS_SNAP_COMMAND".to_string();
+      return;
+    };
+    let target = self
+      .editing
+      .as_ref()
+      .map(|e| (e.layer.clone(), e.index))
+      .or_else(|| self.closest_element(false));
+    let Some((id, i)) = target else {
+      self.closest_text = "There is no element to snap.".to_string();
+      return;
+    };
+    let (element, style) = &self.map_provider.layers[&id][i];
+    let feature = GeoJsonExporter::feature(&id, &element.to_shape(*style)).to_string();
+    let color = style.color;
+    let sender = self.get_event_sender();
+    rayon::spawn(move || {
+      let events: Vec<MapEvent> = CommandParser::new(command)
+        .with_color(color)
+        .parse(Box::new(std::io::Cursor::new(feature.into_bytes())))
+        .collect();
+      for event in events {
+        let event = match event {
+          MapEvent::Layer(mut layer) => {
+            layer.id = format!("snapped {id}");
+            MapEvent::Layer(layer.with_replace(true))
+          }
+          event => event,
+        };
+        block_on(sender.send(event)).expect("Can send to self.");
+      }
+    });
+  }
+
+  pub(super) fn delete_vertex(&mut self) {
+    let Some(vertex) = self.vertex_under_mouse() else {
+      return;
+    };
+    let Some((element, _)) = self.edited_element() else {
+      return;
+    };
+    let mut positions = element.positions();
+    if remove_vertex(&mut positions, vertex) {
+      self.replace_edited_positions(positions);
+    }
+  }
+
+  /// Previews the annotation being drawn.
+  pub(super) fn draw_sketch(&mut self) {
+    let Some((outline, closed)) = self.sketch.as_ref().and_then(Sketch::outline) else {
+      return;
+    };
+    let mut stroke = Paint::color(Color::rgb(220, 40, 40));
+    stroke.set_line_width(2. / self.get_zoom_factor());
+    self
+      .canvas
+      .stroke_path(&build_path(&outline, closed), &stroke);
+  }
+
+  pub(super) fn draw_edit_handles(&mut self) {
+    let Some((element, style)) = self.edited_element() else {
+      return;
+    };
+    let size = 5. / self.get_zoom_factor();
+    let mut handles = Path::new();
+    for p in element.positions() {
+      handles.rect(p.x - size / 2., p.y - size / 2., size, size);
+    }
+    let mut stroke = Paint::color(style.color.to_rgb());
+    stroke.set_line_width(1. / self.get_zoom_factor());
+    self
+      .canvas
+      .fill_path(&handles, &Paint::color(Color::rgb(255, 255, 255)));
+    self.canvas.stroke_path(&handles, &stroke);
+  }
+}
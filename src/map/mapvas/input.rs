@@ -0,0 +1,294 @@
+use std::path::PathBuf;
+
+use arboard::Clipboard;
+use async_std::task::block_on;
+use log::{debug, error, info};
+use winit::event::VirtualKeyCode;
+
+use crate::{
+  export::{shape_to_wkt, GeoJsonExporter},
+  map::{
+    bookmark::Bookmarks,
+    command_line::CommandLine,
+    coordinates::Coordinate,
+    map_event::{ExportFormat, MapEvent},
+    overlay::{georeferenced_overlay, is_image},
+  },
+  parser::{AutoFileParser, CommandParser, FileParser, GrepParser, Parser},
+  recent::{RecentFile, RecentFiles},
+};
+
+use super::{copy_to_clipboard, current_time_string, MapVas, MarkAction};
+
+impl MapVas {
+  pub(super) fn handle_key(&mut self, key: VirtualKeyCode) {
+    const SCROLL_SPEED: f32 = 20.;
+    const ZOOM_SPEED: f32 = 1.1;
+    const ROTATION_SPEED: f32 = std::f32::consts::PI / 12.;
+    // The letter of a mark is handled as character.
+    if self.pending_mark.is_some() {
+      return;
+    }
+    if self.command_line.is_some() {
+      self.handle_command_key(key);
+      return;
+    }
+    match key {
+      VirtualKeyCode::Left => self.translate(0., 0., SCROLL_SPEED, 0.),
+      VirtualKeyCode::Right => self.translate(SCROLL_SPEED, 0., 0., 0.),
+      VirtualKeyCode::Up => self.translate(0., 0., 0., SCROLL_SPEED),
+      VirtualKeyCode::Down => self.translate(0., SCROLL_SPEED, 0., 0.),
+      // Plus and equals to zoom in to avoid holding shift.
+      VirtualKeyCode::Equals | VirtualKeyCode::Plus => self.zoom_canvas_center(ZOOM_SPEED),
+      VirtualKeyCode::Minus => self.zoom_canvas_center(1. / ZOOM_SPEED),
+      VirtualKeyCode::V => self.paste(),
+      VirtualKeyCode::C if self.modifiers.shift() => self.copy_bounding_box(),
+      VirtualKeyCode::C => self.copy(),
+      VirtualKeyCode::X if self.modifiers.shift() => self.copy_closest_element(true),
+      VirtualKeyCode::X => self.copy_closest_element(false),
+      VirtualKeyCode::F => self.handle_focus_event(),
+      VirtualKeyCode::L if self.modifiers.shift() => self.show_labels = !self.show_labels,
+      VirtualKeyCode::L => self.update_closest(),
+      VirtualKeyCode::LBracket if self.modifiers.shift() => self.step_time(false),
+      VirtualKeyCode::RBracket if self.modifiers.shift() => self.step_time(true),
+      VirtualKeyCode::LBracket => self.rotate_canvas_center(-ROTATION_SPEED),
+      VirtualKeyCode::RBracket => self.rotate_canvas_center(ROTATION_SPEED),
+      VirtualKeyCode::N => self.set_rotation(0.),
+      VirtualKeyCode::D => self.theme = self.theme.toggle(),
+      VirtualKeyCode::B => self.show_scale_bar = !self.show_scale_bar,
+      VirtualKeyCode::U => self.scale_unit = self.scale_unit.toggle(),
+      VirtualKeyCode::E => self.toggle_editing(),
+      VirtualKeyCode::K => self.toggle_clusters(self.cluster_targets()),
+      VirtualKeyCode::A => self.toggle_overlay_alignment(),
+      VirtualKeyCode::O if self.modifiers.shift() => self.change_overlay_opacity(0.1),
+      VirtualKeyCode::O => self.change_overlay_opacity(-0.1),
+      VirtualKeyCode::T if self.modifiers.shift() => {
+        self.map_provider.raise_selected_tile_layer();
+        self.show_selected_tile_layer();
+      }
+      VirtualKeyCode::T => {
+        self.map_provider.select_next_tile_layer();
+        self.show_selected_tile_layer();
+      }
+      VirtualKeyCode::I => self.show_cache_stats(),
+      VirtualKeyCode::H if self.modifiers.shift() => {
+        let name = format!("bookmark {}", Bookmarks::load().bookmarks.len() + 1);
+        self.add_bookmark(name);
+      }
+      VirtualKeyCode::H => self.show_bookmarks(),
+      VirtualKeyCode::Key1 => self.go_to_numbered_bookmark(1),
+      VirtualKeyCode::Key2 => self.go_to_numbered_bookmark(2),
+      VirtualKeyCode::Key3 => self.go_to_numbered_bookmark(3),
+      VirtualKeyCode::Key4 => self.go_to_numbered_bookmark(4),
+      VirtualKeyCode::Key5 => self.go_to_numbered_bookmark(5),
+      VirtualKeyCode::Key6 => self.go_to_numbered_bookmark(6),
+      VirtualKeyCode::Key7 => self.go_to_numbered_bookmark(7),
+      VirtualKeyCode::Key8 => self.go_to_numbered_bookmark(8),
+      VirtualKeyCode::Key9 => self.go_to_numbered_bookmark(9),
+      VirtualKeyCode::R if self.modifiers.shift() => self.reopen_recent_file(),
+      VirtualKeyCode::R => self.show_recent_files(),
+      VirtualKeyCode::P if self.modifiers.shift() => {
+        self.coordinate_format = self.coordinate_format.next();
+        self.show_mouse_position();
+      }
+      VirtualKeyCode::P => self.show_mouse_position(),
+      VirtualKeyCode::Comma if self.modifiers.shift() => self.change_heatmap(0.8, 1.),
+      VirtualKeyCode::Period if self.modifiers.shift() => self.change_heatmap(1.25, 1.),
+      VirtualKeyCode::Comma if self.modifiers.ctrl() => self.change_heatmap(1., 0.8),
+      VirtualKeyCode::Period if self.modifiers.ctrl() => self.change_heatmap(1., 1.25),
+      VirtualKeyCode::Comma => self.change_tile_layer_opacity(-0.1),
+      VirtualKeyCode::Period => self.change_tile_layer_opacity(0.1),
+      VirtualKeyCode::Escape => {
+        self.editing = None;
+        self.selecting = None;
+        self.tool = None;
+        self.sketch = None;
+        self.map_provider.selection.clear();
+      }
+      VirtualKeyCode::Return => self.finish_selection(),
+      VirtualKeyCode::Delete | VirtualKeyCode::Back if self.editing.is_some() => {
+        self.delete_vertex();
+      }
+      VirtualKeyCode::Delete => self.clear(),
+      VirtualKeyCode::S => {
+        self.make_screenshot(format!("mapvas_{}.png", current_time_string()).into());
+      }
+      VirtualKeyCode::W => self.export(
+        ExportFormat::GeoJson,
+        self.map_provider.export_layers(),
+        format!("mapvas_{}.geojson", current_time_string()).into(),
+      ),
+      _ => debug!("{key:?} pressed"),
+    };
+  }
+
+  fn paste(&self) {
+    let sender = self.get_event_sender();
+    rayon::spawn(move || {
+      if let Ok(text) = Clipboard::new().expect("clipboard").get_text() {
+        if let Some(map_event) = GrepParser::new(false).parse_line(&text) {
+          let _ = block_on(sender.send(map_event));
+        }
+      }
+    });
+  }
+
+  pub(super) fn drop_file(&self, path: PathBuf) {
+    self.open_file(RecentFile {
+      path,
+      options: vec![],
+    });
+  }
+
+  /// Draws the file with its parser and style options and remembers it as the most recent one.
+  pub(super) fn open_file(&self, file: RecentFile) {
+    let sender = self.get_event_sender();
+    rayon::spawn(move || {
+      let path = &file.path;
+      if is_image(path) {
+        match georeferenced_overlay(path) {
+          Ok(overlay) => {
+            block_on(sender.send(MapEvent::ImageOverlay(overlay))).expect("Can send to self.");
+          }
+          Err(e) => error!("Cannot show image: {e}"),
+        }
+        return;
+      }
+      let mut recent = RecentFiles::load();
+      recent.add(path.clone(), file.options.clone());
+      if let Err(e) = recent.save() {
+        error!("Cannot save the recent files: {e}");
+      }
+      let mut parser = AutoFileParser::reopened(&file);
+      parser
+        .parse()
+        .for_each(|e| block_on(sender.send(e)).expect("Can send to self."));
+    });
+  }
+
+  /// Draws the GeoJSON the command prints for the file, like `mapcat --parser-cmd`.
+  pub(super) fn convert_file(&self, path: PathBuf, command: String) {
+    let sender = self.get_event_sender();
+    rayon::spawn(move || {
+      let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+          error!("Cannot read {path:?}: {e}");
+          return;
+        }
+      };
+      CommandParser::new(command)
+        .parse(Box::new(std::io::BufReader::new(file)))
+        .for_each(|e| block_on(sender.send(e)).expect("Can send to self."));
+    });
+  }
+
+  /// Shows the recently drawn files in the text bar, `mapcat --reopen <n>` draws them again.
+  fn show_recent_files(&mut self) {
+    self.closest_text = RecentFiles::load().numbered().join(" | ");
+  }
+
+  /// Draws the most recent file again with the options it was drawn with.
+  fn reopen_recent_file(&self) {
+    if let Some(file) = RecentFiles::load().files.into_iter().next() {
+      self.open_file(file);
+    }
+  }
+
+  fn copy(&self) {
+    if self.closest_text.is_empty() {
+      return;
+    }
+    copy_to_clipboard(&self.closest_text);
+  }
+
+  /// Copies the visible area as `lat1,lon1,lat2,lon2` like the `--bbox` of mapcat.
+  fn copy_bounding_box(&self) {
+    let (nw, se, _) = self.get_current_canvas_section();
+    let (nw, se) = (Coordinate::from(nw.clamp()), Coordinate::from(se.clamp()));
+    copy_to_clipboard(&format!(
+      "{:.6},{:.6},{:.6},{:.6}",
+      nw.lat, nw.lon, se.lat, se.lon
+    ));
+  }
+
+  /// Copies the element closest to the mouse as GeoJSON feature or as WKT.
+  fn copy_closest_element(&self, wkt: bool) {
+    let Some((id, i)) = self.closest_element(false) else {
+      return;
+    };
+    let (element, style) = &self.map_provider.layers[&id][i];
+    let shape = element.to_shape(*style);
+    let text = if wkt {
+      shape_to_wkt(&shape)
+    } else {
+      GeoJsonExporter::feature(&id, &shape).to_string()
+    };
+    copy_to_clipboard(&text);
+    info!("Copied an element of layer {id}.");
+  }
+
+  /// Marks like in vim: `m` and a letter stores the view, `'` and the letter goes back to it, and
+  /// `M` lists the marks. The keys arrive before their characters, so `handle_key` ignores them
+  /// while a mark is pending. `:` opens the command line, which takes the characters until it is
+  /// closed.
+  pub(super) fn handle_character(&mut self, c: char) {
+    if let Some(command_line) = &mut self.command_line {
+      if !c.is_control() {
+        command_line.push(c);
+        self.closest_text = command_line.prompt();
+      }
+      return;
+    }
+    match (self.pending_mark.take(), c) {
+      (Some(MarkAction::Set), letter) => {
+        if self.marks.set(letter, self.camera()) {
+          self.closest_text = format!("Mark {letter}");
+        }
+      }
+      (Some(MarkAction::Jump), letter) => match self.marks.jump(letter, self.camera()) {
+        Some(camera) => self.fly_to(camera),
+        None => self.closest_text = format!("There is no mark {letter}."),
+      },
+      (None, 'm') => self.pending_mark = Some(MarkAction::Set),
+      (None, '\'') => self.pending_mark = Some(MarkAction::Jump),
+      (None, 'M') => self.closest_text = self.marks.list().join(" | "),
+      (None, ':') => {
+        let command_line = CommandLine::load();
+        self.closest_text = command_line.prompt();
+        self.command_line = Some(command_line);
+      }
+      _ => (),
+    }
+  }
+
+  fn handle_command_key(&mut self, key: VirtualKeyCode) {
+    let Some(command_line) = &mut self.command_line else {
+      return;
+    };
+    match key {
+      VirtualKeyCode::Escape => {
+        self.command_line = None;
+        self.closest_text.clear();
+        return;
+      }
+      VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => {
+        let command = command_line.submit();
+        if let Err(e) = command_line.save() {
+          error!("Cannot save the command history: {e}");
+        }
+        self.command_line = None;
+        self.closest_text.clear();
+        self.execute_command(&command);
+        return;
+      }
+      VirtualKeyCode::Back => command_line.backspace(),
+      VirtualKeyCode::Tab => command_line.complete(),
+      VirtualKeyCode::Up => command_line.previous(),
+      VirtualKeyCode::Down => command_line.next(),
+      VirtualKeyCode::R if self.modifiers.ctrl() => command_line.search_backwards(),
+      _ => return,
+    }
+    self.closest_text = command_line.prompt();
+  }
+}
@@ -0,0 +1,202 @@
+use chrono::Utc;
+
+use crate::map::{
+  coordinates::Coordinate,
+  map_event::{FillStyle, Layer, MapUpdate, Style},
+  timeline::{Timeline, Tracks},
+};
+
+use super::{next_part, renumber_parts, LayerElement, MapVas, Track};
+
+impl MapVas {
+  fn coords_to_element(coords: &[Coordinate], close_path: bool) -> LayerElement {
+    LayerElement::from_positions(coords.iter().copied().map(Into::into).collect(), close_path)
+  }
+
+  pub(super) fn handle_layer_event(&mut self, layer: Layer) {
+    let mut paths: Vec<(LayerElement, Style)> = layer
+      .shapes
+      .iter()
+      .flat_map(|shape| {
+        let circle = shape.circle();
+        shape
+          .geodesic_parts()
+          .into_iter()
+          .map(move |part| (part, circle))
+      })
+      .map(|(shape, circle)| {
+        (
+          Self::coords_to_element(&shape.coordinates, shape.style.fill != FillStyle::NoFill)
+            .with_text(shape.label)
+            .with_icon(shape.icon)
+            .with_track(Track {
+              timestamps: shape.timestamps,
+              elevations: shape.elevations,
+              circle,
+              part_of: shape.part_of,
+              properties: shape.properties,
+              source: shape.source,
+            }),
+          shape.style,
+        )
+      })
+      .collect();
+
+    self.publish(MapUpdate::LayerAdded {
+      id: layer.id.clone(),
+      shapes: layer.shapes.len(),
+    });
+    self
+      .map_provider
+      .render_modes
+      .insert(layer.id.clone(), layer.render_mode);
+    if !layer.rules.is_empty() {
+      self
+        .map_provider
+        .rules
+        .insert(layer.id.clone(), layer.rules.clone());
+    }
+    if let Some(opacity) = layer.opacity {
+      self
+        .map_provider
+        .opacities
+        .insert(layer.id.clone(), opacity);
+    }
+    if let Some(blend) = layer.blend {
+      self
+        .map_provider
+        .blend_modes
+        .insert(layer.id.clone(), blend);
+    }
+    match layer.cluster {
+      Some(true) => {
+        self.map_provider.clustered.insert(layer.id.clone());
+      }
+      Some(false) => {
+        self.map_provider.clustered.remove(&layer.id);
+      }
+      None => (),
+    }
+    if layer.replace {
+      if self.editing.as_ref().is_some_and(|e| e.layer == layer.id) {
+        self.editing = None;
+      }
+      self.map_provider.selection.remove_layer(&layer.id);
+      self.map_provider.hidden.remove_layer(&layer.id);
+      self.map_provider.layers.insert(layer.id.clone(), paths);
+    } else {
+      let elements = self
+        .map_provider
+        .layers
+        .entry(layer.id.clone())
+        .or_default();
+      renumber_parts(&mut paths, next_part(elements));
+      elements.append(&mut paths);
+    }
+    self.map_provider.prepare_rules(&layer.id);
+    self.map_provider.update_within();
+    self.map_provider.order.add(&layer.id);
+    if self.time_window().is_some() {
+      self.update_tracks();
+    }
+  }
+
+  /// Removes all layers and overlays.
+  pub(super) fn clear(&mut self) {
+    self.editing = None;
+    self.time = None;
+    self.live = None;
+    self.tracks = Tracks::default();
+    self.dragged_corner = None;
+    self.map_provider.clear_layers();
+    for (_, image) in self.overlays.drain(..) {
+      self.canvas.delete_image(image);
+    }
+    self.publish(MapUpdate::Cleared);
+  }
+
+  /// The times elements with timestamps are shown for: the last minutes during live playback or
+  /// everything up to the time cursor.
+  pub(super) fn time_window(&self) -> Option<(i64, i64)> {
+    match (self.live, self.time) {
+      (Some(duration), _) => {
+        let now = Utc::now().timestamp_millis();
+        Some((now - duration, now))
+      }
+      (None, Some(time)) => Some((i64::MIN, time)),
+      (None, None) => None,
+    }
+  }
+
+  /// Collects the timestamps of the layers in their drawing order.
+  fn update_tracks(&mut self) {
+    let tracks = self
+      .map_provider
+      .order
+      .ids()
+      .iter()
+      .filter_map(|id| {
+        let elements = self.map_provider.layers.get(id)?;
+        let timeline = Timeline::new(
+          elements
+            .iter()
+            .flat_map(|(e, _)| e.track().timestamps.iter().copied()),
+        );
+        timeline.range().is_some().then(|| (id.clone(), timeline))
+      })
+      .collect();
+    self.tracks.set(tracks);
+  }
+
+  /// `time next` and `time prev` step the time cursor, `time live <minutes>` follows the current
+  /// time, `time toggle <layer>` leaves a layer out, and `time off` shows everything again.
+  #[allow(clippy::cast_possible_truncation)]
+  pub(super) fn time_command(&mut self, argument: &str) {
+    match argument.split_whitespace().collect::<Vec<_>>()[..] {
+      ["next"] => self.step_time(true),
+      ["prev" | "previous"] => self.step_time(false),
+      ["off"] => {
+        self.time = None;
+        self.live = None;
+      }
+      ["toggle", layer] => {
+        self.update_tracks();
+        if !self.tracks.toggle(layer) {
+          self.closest_text = format!("Layer {layer} has no timestamps.");
+        }
+      }
+      ["live", minutes] => match minutes.parse::<f32>() {
+        Ok(minutes) if minutes > 0. => {
+          self.update_tracks();
+          self.live = Some((minutes * 60_000.) as i64);
+        }
+        _ => self.closest_text = "Usage: time live <minutes>".to_string(),
+      },
+      _ => {
+        self.closest_text = "Usage: time next|prev|off|toggle <layer>|live <minutes>".to_string();
+      }
+    }
+  }
+
+  /// Moves the time cursor to the next or previous timestamp of all elements. Without a time cursor
+  /// it starts at the first or last timestamp.
+  pub(super) fn step_time(&mut self, forward: bool) {
+    self.update_tracks();
+    let timeline = self.tracks.enabled();
+    let time = match (self.time, forward) {
+      (Some(time), true) => timeline.next(time),
+      (Some(time), false) => timeline.previous(time),
+      (None, true) => timeline.range().map(|(first, _)| first),
+      (None, false) => timeline.range().map(|(_, last)| last),
+    };
+    match time {
+      Some(time) => {
+        self.time = Some(time);
+        self.live = None;
+        self.closest_text = self.time_zone.format(time);
+      }
+      None if self.time.is_some() => (),
+      None => self.closest_text = "There are no timestamps.".to_string(),
+    }
+  }
+}
@@ -0,0 +1,120 @@
+use femtovg::{Color, ImageFlags, Paint, Path};
+use log::{error, info};
+
+use crate::map::{
+  coordinates::{BoundingBox, Coordinate, PixelPosition},
+  map_event::ImageOverlay,
+};
+
+use super::MapVas;
+
+impl MapVas {
+  pub(super) fn handle_overlay_event(&mut self, overlay: ImageOverlay) {
+    let image = match self
+      .canvas
+      .load_image_file(&overlay.path, ImageFlags::empty())
+    {
+      Ok(image) => image,
+      Err(e) => {
+        error!("Cannot load overlay {:?}: {e:?}", overlay.path);
+        return;
+      }
+    };
+    if let Some(i) = self.overlays.iter().position(|(o, _)| o.id == overlay.id) {
+      let (_, old) = self.overlays.remove(i);
+      self.canvas.delete_image(old);
+      self.dragged_corner = None;
+    }
+    self.map_provider.order.add(&overlay.id);
+    self.overlays.push((overlay, image));
+  }
+
+  pub(super) fn draw_overlay(&mut self, id: &str) {
+    for (overlay, image) in self.overlays.iter().filter(|(o, _)| o.id == id) {
+      let bb = BoundingBox::from_iterator([overlay.nw, overlay.se].map(PixelPosition::from));
+      let (nw, width, height) = (bb.nw(), bb.width(), bb.height());
+      let mut rect = Path::new();
+      rect.rect(nw.x, nw.y, width, height);
+      let paint = Paint::image(*image, nw.x, nw.y, width, height, 0., overlay.opacity);
+      self.canvas.fill_path(&rect, &paint);
+    }
+  }
+
+  /// Changes the opacity of the overlay added last.
+  pub(super) fn change_overlay_opacity(&mut self, change: f32) {
+    if let Some((overlay, _)) = self.overlays.last_mut() {
+      overlay.opacity = (overlay.opacity + change).clamp(0.1, 1.);
+    }
+  }
+
+  /// Toggles dragging the corners of overlays. The final corners are logged to reuse them.
+  pub(super) fn toggle_overlay_alignment(&mut self) {
+    self.aligning_overlays = !self.aligning_overlays;
+    if !self.aligning_overlays {
+      for (overlay, _) in &self.overlays {
+        info!(
+          "Overlay {:?}: --bbox {},{},{},{}",
+          overlay.path, overlay.nw.lat, overlay.nw.lon, overlay.se.lat, overlay.se.lon
+        );
+      }
+    }
+  }
+
+  pub(super) fn overlay_corners(
+    &self,
+  ) -> impl Iterator<Item = ((usize, usize), PixelPosition)> + '_ {
+    self.overlays.iter().enumerate().flat_map(|(i, (o, _))| {
+      [o.nw, o.se]
+        .into_iter()
+        .enumerate()
+        .map(move |(corner, c)| ((i, corner), PixelPosition::from(c)))
+    })
+  }
+
+  /// Starts dragging the overlay corner under the mouse while aligning overlays.
+  pub(super) fn start_corner_drag(&mut self) -> bool {
+    if !self.aligning_overlays {
+      return false;
+    }
+    let mouse = self.mouse_position();
+    let threshold = self.distance_threshold() / 2.;
+    self.dragged_corner = self
+      .overlay_corners()
+      .map(|(corner, p)| (corner, p.sq_dist(&mouse)))
+      .filter(|(_, d)| *d < threshold * threshold)
+      .min_by(|a, b| a.1.total_cmp(&b.1))
+      .map(|(corner, _)| corner);
+    self.dragged_corner.is_some()
+  }
+
+  pub(super) fn drag_corner(&mut self) {
+    let Some((i, corner)) = self.dragged_corner else {
+      return;
+    };
+    let position = Coordinate::from(self.mouse_position());
+    if let Some((overlay, _)) = self.overlays.get_mut(i) {
+      if corner == 0 {
+        overlay.nw = position;
+      } else {
+        overlay.se = position;
+      }
+    }
+  }
+
+  pub(super) fn draw_overlay_handles(&mut self) {
+    if !self.aligning_overlays {
+      return;
+    }
+    let size = 8. / self.get_zoom_factor();
+    let mut handles = Path::new();
+    for (_, p) in self.overlay_corners() {
+      handles.rect(p.x - size / 2., p.y - size / 2., size, size);
+    }
+    let mut stroke = Paint::color(Color::rgb(30, 30, 30));
+    stroke.set_line_width(1. / self.get_zoom_factor());
+    self
+      .canvas
+      .fill_path(&handles, &Paint::color(Color::rgb(255, 255, 255)));
+    self.canvas.stroke_path(&handles, &stroke);
+  }
+}
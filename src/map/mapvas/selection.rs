@@ -0,0 +1,440 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use async_std::task::block_on;
+use femtovg::{Color, Paint, Path};
+use log::error;
+
+use crate::map::{
+  coordinates::PixelPosition,
+  geoprocessing::{self, Operation},
+  map_event::{self, ExportFormat, FillStyle, Layer, MapEvent, Shape, Style},
+  selection::{intersects, Area},
+  spatial::{count_points, with_field, within},
+};
+
+use super::{build_path, LayerElement, MapProvider, MapVas};
+
+impl MapProvider {
+  /// The number of selected features, the parts of a multi geometry count once.
+  pub(super) fn selected_features(&self) -> usize {
+    self
+      .selection
+      .layers()
+      .map(|(id, indices)| {
+        let elements = self.layers.get(id).map(Vec::as_slice).unwrap_or_default();
+        indices
+          .iter()
+          .map(
+            |i| match elements.get(*i).and_then(|(e, _)| e.track().part_of) {
+              Some(part_of) => (true, part_of),
+              None => (false, *i),
+            },
+          )
+          .collect::<HashSet<_>>()
+          .len()
+      })
+      .sum()
+  }
+
+  /// The selected elements with their layer.
+  pub(super) fn selected(&self) -> impl Iterator<Item = (&String, &(LayerElement, Style))> {
+    self.selection.layers().flat_map(move |(id, indices)| {
+      let elements = self.layers.get(id);
+      indices
+        .iter()
+        .filter_map(move |i| Some((id, elements?.get(*i)?)))
+    })
+  }
+
+  /// The selected shapes in one layer per layer they belong to.
+  pub(super) fn selected_layers(&self) -> Vec<Layer> {
+    let mut layers: Vec<Layer> = vec![];
+    for (id, (element, style)) in self.selected() {
+      let shape = element.to_shape(*style);
+      match layers.last_mut() {
+        Some(layer) if layer.id == *id => layer.shapes.push(shape),
+        _ => {
+          let mut layer = Layer::new(id.clone());
+          layer.shapes.push(shape);
+          layers.push(layer);
+        }
+      }
+    }
+    layers
+  }
+
+  pub(super) fn hide_selected(&mut self) {
+    for (id, indices) in self.selection.layers() {
+      for i in indices {
+        self.hidden.add(id, *i);
+      }
+    }
+    self.selection.clear();
+  }
+
+  pub(super) fn recolor_selected(&mut self, color: map_event::Color) {
+    for (id, indices) in self.selection.layers() {
+      let Some(elements) = self.layers.get_mut(id) else {
+        continue;
+      };
+      for i in indices {
+        if let Some((_, style)) = elements.get_mut(*i) {
+          style.color = color;
+        }
+      }
+    }
+  }
+
+  /// The positions of the closed elements of a layer.
+  pub(super) fn polygons(&self, id: &str) -> Vec<(usize, Vec<PixelPosition>)> {
+    self
+      .layers
+      .get(id)
+      .into_iter()
+      .flatten()
+      .enumerate()
+      .filter(|(_, (e, style))| {
+        style.fill != FillStyle::NoFill && matches!(e, LayerElement::Polyline(..))
+      })
+      .map(|(i, (e, _))| (i, e.positions()))
+      .filter(|(_, positions)| positions.len() > 2)
+      .collect()
+  }
+
+  /// Finds the elements outside of the closed elements of the `within` layer.
+  pub(super) fn update_within(&mut self) {
+    self.outside.clear();
+    let Some(id) = &self.within else {
+      return;
+    };
+    let polygons: Vec<Vec<PixelPosition>> = self.polygons(id).into_iter().map(|(_, p)| p).collect();
+    for (layer, elements) in self.layers.iter().filter(|(layer, _)| *layer != id) {
+      for (i, (element, _)) in elements.iter().enumerate() {
+        let positions = element.positions();
+        if !polygons.iter().any(|polygon| within(&positions, polygon)) {
+          self.outside.add(layer, i);
+        }
+      }
+    }
+  }
+
+  /// Writes the number of points of a layer inside of each closed element of another layer into
+  /// a field of their labels. Returns the number of closed elements.
+  pub(super) fn count_points_in(&mut self, points: &str, polygons: &str, field: &str) -> usize {
+    let positions: Vec<PixelPosition> = self
+      .layers
+      .get(points)
+      .into_iter()
+      .flatten()
+      .filter_map(|(e, _)| match e {
+        LayerElement::Point(p, ..) => Some(*p),
+        LayerElement::Polyline(..) => None,
+      })
+      .collect();
+    let (indices, areas): (Vec<usize>, Vec<Vec<PixelPosition>>) =
+      self.polygons(polygons).into_iter().unzip();
+    let counts = count_points(&positions, &areas);
+    if let Some(elements) = self.layers.get_mut(polygons) {
+      for (i, count) in indices.iter().zip(counts) {
+        let text = elements[*i].0.text_mut();
+        *text = Some(with_field(text.as_deref(), field, &count.to_string()));
+      }
+    }
+    self.prepare_rules(polygons);
+    indices.len()
+  }
+
+  /// Selects the elements of the layer that are not hidden.
+  pub(super) fn select_layer(&mut self, id: &str) {
+    let Some(elements) = self.layers.get(id) else {
+      return;
+    };
+    for i in (0..elements.len()).filter(|i| !self.hidden.contains(id, *i)) {
+      self.selection.add(id, i);
+    }
+  }
+
+  /// Removes the selected elements from their layers and returns them.
+  pub(super) fn take_selected(&mut self) -> Vec<(LayerElement, Style)> {
+    let selection = std::mem::take(&mut self.selection);
+    let mut taken = vec![];
+    for (id, indices) in selection.layers() {
+      if let Some(elements) = self.layers.remove(id) {
+        let (selected, kept): (Vec<_>, Vec<_>) = elements
+          .into_iter()
+          .enumerate()
+          .partition(|(i, _)| indices.contains(i));
+        taken.extend(selected.into_iter().map(|(_, e)| e));
+        self
+          .layers
+          .insert(id.clone(), kept.into_iter().map(|(_, e)| e).collect());
+      }
+      self.hidden.remove_elements(id, indices);
+      self.prepare_rules(id);
+    }
+    self.update_within();
+    taken
+  }
+
+  /// Moves the selected elements to the end of a layer, which is created if needed. They stay
+  /// selected.
+  pub(super) fn move_selected(&mut self, to: &str) {
+    let mut moved = self.take_selected();
+    let elements = self.layers.entry(to.to_string()).or_default();
+    let start = elements.len();
+    renumber_parts(&mut moved, next_part(elements));
+    elements.extend(moved);
+    for i in start..elements.len() {
+      self.selection.add(to, i);
+    }
+    self.order.add(to);
+    self.prepare_rules(to);
+    self.update_within();
+  }
+}
+
+impl MapVas {
+  /// `selection` lists the selected elements and `selection layer <layers>` adds all elements of
+  /// layers. `selection hide`, `delete`, `color <color>`, `move <layer>`, and `export <file>`
+  /// change them, `selection clear` unselects them, and `selection unhide` shows the hidden
+  /// elements again.
+  pub(super) fn selection_command(&mut self, argument: &str) {
+    let (action, value) = argument.split_once(' ').unwrap_or((argument, ""));
+    let value = value.trim();
+    match action {
+      "" => self.show_selection(),
+      "clear" => self.map_provider.selection.clear(),
+      "hide" => self.map_provider.hide_selected(),
+      "unhide" => self.map_provider.hidden.clear(),
+      "layer" if !value.is_empty() => {
+        for layer in value.split_whitespace() {
+          self.map_provider.select_layer(layer);
+        }
+        self.show_selection();
+      }
+      "delete" => {
+        self.stop_editing_selection();
+        self.map_provider.take_selected();
+      }
+      "move" if !value.is_empty() => {
+        self.stop_editing_selection();
+        self.map_provider.move_selected(value);
+      }
+      "color" => match value.parse() {
+        Ok(color) => self.map_provider.recolor_selected(color),
+        Err(()) => self.closest_text = format!("Unknown color: {value}"),
+      },
+      "export" if !value.is_empty() => self.export_selection(value.into()),
+      _ => {
+        self.closest_text = concat!(
+          "Usage: selection [clear|layer <layers>|hide|unhide|delete|color <color>|",
+          "move <layer>|export <file>]"
+        )
+        .to_string();
+      }
+    }
+  }
+
+  /// `within <layer>` only draws the elements inside of the closed elements of the layer,
+  /// `within` draws all again.
+  pub(super) fn within_command(&mut self, layer: &str) {
+    let provider = &mut self.map_provider;
+    if layer.is_empty() {
+      provider.within = None;
+    } else if provider.polygons(layer).is_empty() {
+      self.closest_text = format!("{layer} has no closed elements.");
+      return;
+    } else {
+      provider.within = Some(layer.to_string());
+    }
+    provider.update_within();
+  }
+
+  /// `join <points> <polygons> [field]` writes the number of points of the first layer inside of
+  /// each closed element of the second one into a field of its label, `count` by default.
+  pub(super) fn join_command(&mut self, argument: &str) {
+    let (points, polygons, field) = match argument.split_whitespace().collect::<Vec<_>>()[..] {
+      [points, polygons] => (points, polygons, "count"),
+      [points, polygons, field] => (points, polygons, field),
+      _ => {
+        self.closest_text = "Usage: join <points layer> <polygon layer> [field]".to_string();
+        return;
+      }
+    };
+    let areas = self.map_provider.count_points_in(points, polygons, field);
+    self.closest_text = format!("Wrote {field} of {areas} areas of {polygons}.");
+  }
+
+  /// Draws the result of an operation like `buffer 500m` on the selected elements as a layer named
+  /// after the command.
+  pub(super) fn geoprocess(&mut self, name: &str, argument: &str) {
+    let operation = match Operation::parse(name, argument) {
+      Ok(operation) => operation,
+      Err(e) => {
+        self.closest_text = e.to_string();
+        return;
+      }
+    };
+    if self.map_provider.selection.is_empty() {
+      self.closest_text = format!("Select the elements to {name} first.");
+      return;
+    }
+    let shapes: Vec<Shape> = self
+      .map_provider
+      .selected()
+      .map(|(_, (element, style))| element.to_shape(*style))
+      .collect();
+    let id = format!("{name} {argument}").trim().to_string();
+    let sender = self.get_event_sender();
+    rayon::spawn(move || match geoprocessing::apply(operation, &shapes) {
+      Ok(shapes) => {
+        let mut layer = Layer::new(id).with_replace(true);
+        layer.shapes = shapes;
+        block_on(sender.send(MapEvent::Layer(layer))).expect("Can send to self.");
+      }
+      Err(e) => error!("Cannot compute {id}: {e}"),
+    });
+  }
+
+  /// Ends editing if the edited element is in a selected layer, since changing the layer moves the
+  /// indices of its elements.
+  pub(super) fn stop_editing_selection(&mut self) {
+    let selection = &self.map_provider.selection;
+    if self
+      .editing
+      .as_ref()
+      .is_some_and(|e| selection.layers().any(|(id, _)| *id == e.layer))
+    {
+      self.editing = None;
+    }
+  }
+
+  /// Shows the number of selected elements and their first labels in the text bar.
+  pub(super) fn show_selection(&mut self) {
+    const LISTED: usize = 5;
+    let count = self.map_provider.selected_features();
+    if count == 0 {
+      self.closest_text = "Nothing is selected.".to_string();
+      return;
+    }
+    let labels: Vec<String> = self
+      .map_provider
+      .selected()
+      .filter_map(|(_, (element, _))| element.get_text())
+      .filter_map(|text| text.lines().next().map(String::from))
+      .take(LISTED)
+      .collect();
+    self.closest_text = format!("{count} selected: {}", labels.join(" | "));
+  }
+
+  /// Writes the selected elements to a file in the format of its extension.
+  pub(super) fn export_selection(&mut self, path: PathBuf) {
+    let Some(format) = ExportFormat::from_path(&path) else {
+      self.closest_text = format!("Unknown export format of {}", path.display());
+      return;
+    };
+    self.export(format, self.map_provider.selected_layers(), path);
+  }
+
+  /// Selects the shown elements touching the drawn area, with shift in addition to the selected
+  /// ones.
+  pub(super) fn finish_selection(&mut self) {
+    let Some(area) = self.selecting.take() else {
+      return;
+    };
+    if let Area::Rectangle(a, b) = &area {
+      // A click toggles the element under the mouse.
+      let threshold = self.distance_threshold() / 4.;
+      if a.sq_dist(b) < threshold * threshold {
+        if let Some((id, i)) = self.closest_element(false) {
+          // The parts of a multi geometry are selected together.
+          let parts = self.map_provider.parts(&id, i);
+          let selection = &mut self.map_provider.selection;
+          let selected = selection.contains(&id, i);
+          for part in parts {
+            if selection.contains(&id, part) == selected {
+              selection.toggle(&id, part);
+            }
+          }
+        }
+        self.show_selection();
+        return;
+      }
+    }
+    let area = area.polygon();
+    let provider = &mut self.map_provider;
+    if !self.modifiers.shift() {
+      provider.selection.clear();
+    }
+    let mut touched = vec![];
+    for (id, elements) in &provider.layers {
+      for (i, (element, style)) in elements.iter().enumerate() {
+        if provider.is_shown(id, i, element)
+          && intersects(&element.positions(), style.fill != FillStyle::NoFill, &area)
+        {
+          touched.push((id.clone(), i));
+        }
+      }
+    }
+    for (id, i) in touched {
+      for part in provider.parts(&id, i) {
+        provider.selection.add(&id, part);
+      }
+    }
+    self.show_selection();
+  }
+
+  /// Moves the dragged corner of the selection rectangle to the mouse.
+  pub(super) fn drag_selection(&mut self) {
+    let mouse = self.mouse_position();
+    if let Some(Area::Rectangle(_, corner)) = &mut self.selecting {
+      *corner = mouse;
+    }
+  }
+
+  /// Adds the mouse position to the polygon elements are selected with, Return selects them.
+  pub(super) fn add_selection_vertex(&mut self) {
+    let mouse = self.mouse_position();
+    match &mut self.selecting {
+      Some(Area::Polygon(vertices)) => vertices.push(mouse),
+      _ => self.selecting = Some(Area::Polygon(vec![mouse])),
+    }
+  }
+
+  /// Outlines the selected elements and the area elements are being selected with.
+  pub(super) fn draw_selection(&mut self) {
+    let pixel = 1. / self.get_zoom_factor();
+    let mut outline = Paint::color(Color::rgba(255, 200, 0, 180));
+    outline.set_line_width(5. * pixel);
+    for (_, (element, style)) in self.map_provider.selected() {
+      let path = match element {
+        LayerElement::Point(p, ..) => {
+          let mut circle = Path::new();
+          circle.circle(p.x, p.y, 8. * pixel);
+          circle
+        }
+        LayerElement::Polyline(_, _, positions, ..) => {
+          build_path(positions, style.fill != FillStyle::NoFill)
+        }
+      };
+      self.canvas.stroke_path(&path, &outline);
+    }
+    let Some(area) = &self.selecting else {
+      return;
+    };
+    let polygon = area.polygon();
+    let mut path = build_path(&polygon, matches!(area, Area::Rectangle(..)));
+    let mut stroke = Paint::color(Color::rgb(255, 200, 0));
+    stroke.set_line_width(1.5 * pixel);
+    if let Area::Rectangle(..) = area {
+      self
+        .canvas
+        .fill_path(&path, &Paint::color(Color::rgba(255, 200, 0, 40)));
+    } else {
+      for p in &polygon {
+        path.circle(p.x, p.y, 3. * pixel);
+      }
+    }
+    self.canvas.stroke_path(&path, &stroke);
+  }
+}
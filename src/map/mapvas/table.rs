@@ -0,0 +1,179 @@
+use femtovg::{Paint, Path};
+
+use crate::map::{attribute_table::AttributeTable, coordinates::BoundingBox};
+
+use super::MapVas;
+
+/// The rows of the attribute table shown at once.
+pub(super) const TABLE_ROWS: usize = 15;
+
+impl MapVas {
+  /// `table <layer> [sort <column> [desc]]` shows the properties of the elements of the layer as
+  /// table, `table` hides it.
+  pub(super) fn table_command(&mut self, argument: &str) {
+    let words: Vec<&str> = argument.split_whitespace().collect();
+    let (layer, sort) = match words[..] {
+      [] | ["off"] => {
+        self.table = None;
+        return;
+      }
+      [layer] => (layer, None),
+      [layer, "sort", column] => (layer, Some((column, false))),
+      [layer, "sort", column, "desc"] => (layer, Some((column, true))),
+      _ => {
+        self.closest_text = "Usage: table <layer> [sort <column> [desc]]".to_string();
+        return;
+      }
+    };
+    let Some(elements) = self.map_provider.layers.get(layer) else {
+      self.closest_text = format!("Unknown layer: {layer}");
+      return;
+    };
+    let mut table = AttributeTable::new(
+      layer.to_string(),
+      elements.iter().enumerate().map(|(i, (element, _))| {
+        let feature = element.feature();
+        (i, feature.label, feature.properties)
+      }),
+    );
+    if let Some((column, descending)) = sort {
+      if let Err(e) = table.sort(column, descending) {
+        self.closest_text = e.to_string();
+        return;
+      }
+    }
+    self.closest_text = format!(
+      "{layer}: {} rows, click a row to zoom to it, scroll for more",
+      table.len()
+    );
+    self.table = Some(table);
+  }
+
+  pub(super) fn table_paint(&self) -> Paint {
+    let mut paint = Paint::color(self.theme.text());
+    paint.set_font_size(12.);
+    paint.set_text_baseline(femtovg::Baseline::Middle);
+    paint
+  }
+
+  /// The text of a cell of the attribute table.
+  pub(super) fn table_cell(text: &str) -> String {
+    const MAX_CHARS: usize = 24;
+    if text.chars().count() <= MAX_CHARS {
+      return text.to_string();
+    }
+    let mut cell: String = text.chars().take(MAX_CHARS - 1).collect();
+    cell.push('…');
+    cell
+  }
+
+  /// Where the attribute table is drawn below the text bar: the left and top edge, the height of
+  /// a row, and the width of each column.
+  pub(super) fn table_layout(&self) -> Option<(f32, f32, f32, Vec<f32>)> {
+    const LEFT: f32 = 12.;
+    const TOP: f32 = 35.;
+    const ROW: f32 = 18.;
+    let table = self.table.as_ref()?;
+    let paint = self.table_paint();
+    let width = |text: &str| {
+      self
+        .canvas
+        .measure_text(0., 0., Self::table_cell(text), &paint)
+        .map_or(0., |m| m.width())
+    };
+    let widths = table
+      .columns
+      .iter()
+      .enumerate()
+      .map(|(j, column)| {
+        let values = table.shown(TABLE_ROWS).iter().map(|row| &row.values[j]);
+        std::iter::once(column)
+          .chain(values)
+          .map(|text| width(text))
+          .fold(0., f32::max)
+          + 16.
+      })
+      .collect();
+    Some((LEFT, TOP, ROW, widths))
+  }
+
+  pub(super) fn mouse_over_table(&self) -> bool {
+    let Some((x, y, row, widths)) = self.table_layout() else {
+      return false;
+    };
+    let shown = self.table.as_ref().map_or(0, |t| t.shown(TABLE_ROWS).len());
+    #[allow(clippy::cast_precision_loss)]
+    let height = row * (shown + 1) as f32;
+    let width: f32 = widths.iter().sum();
+    (x..x + width).contains(&self.mousex) && (y..y + height).contains(&self.mousey)
+  }
+
+  /// Zooms to and selects the element of the row of the attribute table under the mouse.
+  pub(super) fn focus_table_row(&mut self) {
+    let (Some((_, y, row, _)), Some(table)) = (self.table_layout(), &self.table) else {
+      return;
+    };
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let i = ((self.mousey - y) / row) as usize;
+    // The first row is the header.
+    let Some(index) = i
+      .checked_sub(1)
+      .and_then(|i| table.shown(TABLE_ROWS).get(i))
+      .map(|r| r.index)
+    else {
+      return;
+    };
+    let layer = table.layer.clone();
+    let Some((element, _)) = self
+      .map_provider
+      .layers
+      .get(&layer)
+      .and_then(|l| l.get(index))
+    else {
+      return;
+    };
+    let mut bb = BoundingBox::get_invalid();
+    element.add_to_bounding_box(&mut bb);
+    self.map_provider.selection.clear();
+    self.map_provider.selection.add(&layer, index);
+    self.focus_bounding_box(&bb);
+  }
+
+  /// Draws the attribute table below the text bar with a header row of the columns.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn draw_table(&mut self) {
+    let Some((x, y, row, widths)) = self.table_layout() else {
+      return;
+    };
+    let paint = self.table_paint();
+    let Some(table) = &self.table else {
+      return;
+    };
+    let rows = table.shown(TABLE_ROWS);
+    let mut background = Path::new();
+    background.rounded_rect(x, y, widths.iter().sum(), row * (rows.len() + 1) as f32, 4.);
+    self
+      .canvas
+      .fill_path(&background, &Paint::color(self.theme.halo()));
+    let header = table.columns.iter().map(String::as_str);
+    let lines = std::iter::once(header.collect::<Vec<_>>()).chain(
+      rows
+        .iter()
+        .map(|r| r.values.iter().map(String::as_str).collect()),
+    );
+    for (i, cells) in lines.enumerate() {
+      let row_y = y + row * (i as f32 + 0.5);
+      let mut cell_x = x + 8.;
+      for (cell, width) in cells.iter().zip(&widths) {
+        let _ = self
+          .canvas
+          .fill_text(cell_x, row_y, Self::table_cell(cell), &paint);
+        cell_x += width;
+      }
+    }
+    let mut line = Path::new();
+    line.move_to(x, y + row);
+    line.line_to(x + widths.iter().sum::<f32>(), y + row);
+    self.canvas.stroke_path(&line, &paint);
+  }
+}
@@ -0,0 +1,405 @@
+use femtovg::{Color, Paint, Path, Transform2D};
+
+use crate::map::{
+  coordinate_format::status,
+  coordinates::{Coordinate, PixelPosition, CANVAS_SIZE},
+  map_event::CacheKind,
+  scale::{meters_per_pixel, scale_bar},
+  style_rule::StyleRule,
+};
+
+use super::{MapVas, STATUS_HEIGHT};
+
+impl MapVas {
+  pub(super) fn draw_text(&mut self) {
+    if self.closest_text.is_empty() {
+      return;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let w = self.window.inner_size().width as f32;
+    let h = 25.;
+    let mut path = Path::new();
+    path.rect(0., 0., w, h);
+    self
+      .canvas
+      .fill_path(&path, &Paint::color(self.theme.panel()));
+    let mut text_paint = Paint::color(Color::rgba(240, 240, 240, 255));
+    text_paint.set_font_size(14.);
+    let _ = self
+      .canvas
+      .fill_text(10., 15., &self.closest_text, &text_paint);
+  }
+
+  /// Draws the frame times, the drawn elements of each layer, the tiles loading and the caches
+  /// of each tile layer, and the events waiting to be handled, while `:set debug_overlay` is on.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn draw_debug_overlay(&mut self) {
+    const ROW: f32 = 16.;
+    let Some(stats) = &self.frame_stats else {
+      return;
+    };
+    let mut lines = stats.lines();
+    for l in &self.map_provider.tile_layers {
+      lines.push(format!(
+        "{}: {} loading, {} images, tiles {}, rendered {}",
+        l.name,
+        l.pending.lock().expect("not poisoned").len(),
+        l.loaded_images.len(),
+        l.tile_loader.cache_stats(CacheKind::Tiles),
+        l.tile_loader.cache_stats(CacheKind::Rendered)
+      ));
+    }
+    let sender = &self.map_provider.event_sender;
+    lines.push(format!(
+      "events waiting: {}",
+      sender.max_capacity() - sender.capacity()
+    ));
+    let mut text_paint = Paint::color(self.theme.text());
+    text_paint.set_font_size(12.);
+    text_paint.set_text_align(femtovg::Align::Right);
+    text_paint.set_text_baseline(femtovg::Baseline::Top);
+    let width = lines
+      .iter()
+      .filter_map(|line| self.canvas.measure_text(0., 0., line, &text_paint).ok())
+      .map(|m| m.width())
+      .fold(0., f32::max);
+    let right = self.window.inner_size().width as f32 - 12.;
+    let top = 60.;
+    let mut background = Path::new();
+    background.rounded_rect(
+      right - width - 8.,
+      top - 4.,
+      width + 16.,
+      lines.len() as f32 * ROW + 8.,
+      4.,
+    );
+    self
+      .canvas
+      .fill_path(&background, &Paint::color(self.theme.halo()));
+    for (i, line) in lines.iter().enumerate() {
+      let _ = self
+        .canvas
+        .fill_text(right, top + i as f32 * ROW, line, &text_paint);
+    }
+  }
+
+  /// The height of the status bar at the bottom of the window, 0 while it is hidden.
+  pub(super) fn status_height(&self) -> f32 {
+    if self.show_status {
+      STATUS_HEIGHT
+    } else {
+      0.
+    }
+  }
+
+  /// Draws the status bar with the position under the mouse, the zoom level, and the visible
+  /// area. The position stays the last one on the map while the mouse is over the bar.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn draw_status_bar(&mut self, transform: Transform2D) {
+    const SEPARATOR: f32 = 24.;
+    if !self.show_status {
+      return;
+    }
+    let size = self.window.inner_size();
+    let (w, top) = (size.width as f32, size.height as f32 - STATUS_HEIGHT);
+    let mut texts: Vec<String> = self.status.iter().map(|(.., t)| t.clone()).collect();
+    if self.mousey < top || texts.is_empty() {
+      texts = self
+        .with_map_transform(transform, |map| {
+          let (nw, se, _) = map.get_current_canvas_section();
+          status(
+            map.coordinate_format,
+            map.mouse_position().into(),
+            map.camera().zoom,
+            nw.clamp().into(),
+            se.clamp().into(),
+          )
+        })
+        .to_vec();
+    }
+    let mut path = Path::new();
+    path.rect(0., top, w, STATUS_HEIGHT);
+    self
+      .canvas
+      .fill_path(&path, &Paint::color(self.theme.panel()));
+    let mut text_paint = Paint::color(Color::rgba(240, 240, 240, 255));
+    text_paint.set_font_size(12.);
+    text_paint.set_text_baseline(femtovg::Baseline::Middle);
+    let mut x = 10.;
+    self.status.clear();
+    for text in texts {
+      let width = self
+        .canvas
+        .fill_text(x, top + STATUS_HEIGHT / 2., &text, &text_paint)
+        .map_or(0., |m| m.width());
+      self.status.push((x, x + width, text));
+      x += width + SEPARATOR;
+    }
+  }
+
+  /// Draws a scale bar for the latitude of the window center in the lower left corner.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn draw_scale_bar(&mut self) {
+    const MAX_WIDTH: f32 = 120.;
+    const MARGIN: f32 = 12.;
+    if !self.show_scale_bar {
+      return;
+    }
+    let (nw, se, zoom) = self.get_current_canvas_section();
+    let center = Coordinate::from(PixelPosition {
+      x: (nw.x + se.x) / 2.,
+      y: (nw.y + se.y) / 2.,
+    });
+    let resolution = meters_per_pixel(center.lat, CANVAS_SIZE * zoom);
+    let (width, label) = scale_bar(resolution, MAX_WIDTH, self.scale_unit);
+
+    let y = self.window.inner_size().height as f32 - MARGIN - self.status_height();
+    let mut bar = Path::new();
+    bar.move_to(MARGIN, y - 6.);
+    bar.line_to(MARGIN, y);
+    bar.line_to(MARGIN + width, y);
+    bar.line_to(MARGIN + width, y - 6.);
+    let mut outline = Paint::color(self.theme.halo());
+    outline.set_line_width(4.);
+    self.canvas.stroke_path(&bar, &outline);
+    let mut stroke = Paint::color(self.theme.text());
+    stroke.set_line_width(2.);
+    self.canvas.stroke_path(&bar, &stroke);
+
+    let mut text_paint = Paint::color(self.theme.text());
+    text_paint.set_font_size(12.);
+    let _ = self
+      .canvas
+      .fill_text(MARGIN + 4., y - 5., &label, &text_paint);
+  }
+
+  /// The center and radius of the compass in screen coordinates.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn compass_position(&self) -> (f32, f32, f32) {
+    const RADIUS: f32 = 18.;
+    let width = self.window.inner_size().width as f32;
+    (width - RADIUS - 12., RADIUS + 37., RADIUS)
+  }
+
+  /// Draws a compass needle pointing north while the map is rotated.
+  pub(super) fn draw_compass(&mut self) {
+    if !self.is_rotated() {
+      return;
+    }
+    let rotation = self.rotation();
+    let (x, y, r) = self.compass_position();
+    let mut background = Path::new();
+    background.circle(x, y, r);
+    self
+      .canvas
+      .fill_path(&background, &Paint::color(self.theme.panel()));
+
+    let (sin, cos) = rotation.sin_cos();
+    // North is (0, -1) on the unrotated map.
+    let (nx, ny) = (sin * (r - 4.), -cos * (r - 4.));
+    let (px, py) = (cos * 5., sin * 5.);
+    let mut north = Path::new();
+    north.move_to(x + nx, y + ny);
+    north.line_to(x + px, y + py);
+    north.line_to(x - px, y - py);
+    north.close();
+    self
+      .canvas
+      .fill_path(&north, &Paint::color(Color::rgb(220, 30, 30)));
+    let mut south = Path::new();
+    south.move_to(x - nx, y - ny);
+    south.line_to(x + px, y + py);
+    south.line_to(x - px, y - py);
+    south.close();
+    self
+      .canvas
+      .fill_path(&south, &Paint::color(Color::rgb(240, 240, 240)));
+  }
+
+  /// Draws the legend in the bottom right corner: each layer with its most used color followed by
+  /// the colors of its style rules.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn draw_legend(&mut self) {
+    const MARGIN: f32 = 12.;
+    const ROW: f32 = 18.;
+    if !self.show_legend {
+      return;
+    }
+    // The legend lists the layers drawn on top first.
+    let layers: Vec<_> = self
+      .map_provider
+      .order
+      .ids()
+      .iter()
+      .rev()
+      .filter_map(|id| self.map_provider.layers.get_key_value(id))
+      .filter(|(_, elements)| !elements.is_empty())
+      .collect();
+    let mut rows: Vec<(Option<Color>, String)> = vec![];
+    for (id, elements) in layers {
+      let mut counts = vec![];
+      for (_, style) in elements {
+        match counts.iter_mut().find(|(color, _)| *color == style.color) {
+          Some((_, count)) => *count += 1,
+          None => counts.push((style.color, 1)),
+        }
+      }
+      let color = counts
+        .iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(c, _)| c.to_rgb());
+      rows.push((color, id.clone()));
+      let rules = self.map_provider.rules.get(id).into_iter().flatten();
+      rows.extend(
+        rules
+          .flat_map(StyleRule::legend)
+          .map(|(c, text)| (Some(c.to_rgb()), format!("  {text}"))),
+      );
+    }
+    if rows.is_empty() {
+      return;
+    }
+
+    let mut text_paint = Paint::color(self.theme.text());
+    text_paint.set_font_size(12.);
+    text_paint.set_text_baseline(femtovg::Baseline::Middle);
+    let width = rows
+      .iter()
+      .filter_map(|(_, text)| self.canvas.measure_text(0., 0., text, &text_paint).ok())
+      .map(|m| m.width())
+      .fold(0., f32::max)
+      + 20.;
+    let size = self.window.inner_size();
+    let height = rows.len() as f32 * ROW;
+    let x = size.width as f32 - MARGIN - width - 12.;
+    let y = size.height as f32 - MARGIN - height - 8. - self.status_height();
+    let mut background = Path::new();
+    background.rounded_rect(x, y, width + 12., height + 8., 4.);
+    self
+      .canvas
+      .fill_path(&background, &Paint::color(self.theme.halo()));
+    for (i, (color, text)) in rows.iter().enumerate() {
+      let row_y = y + 4. + ROW * (i as f32 + 0.5);
+      let text_x = match color {
+        Some(color) => {
+          let mut square = Path::new();
+          square.rect(x + 6., row_y - 5., 10., 10.);
+          self.canvas.fill_path(&square, &Paint::color(*color));
+          x + 26.
+        }
+        None => x + 6.,
+      };
+      let _ = self.canvas.fill_text(text_x, row_y, text, &text_paint);
+    }
+  }
+
+  /// Draws a row for each layer with timestamps at the bottom of the window while the time cursor
+  /// is set: the layer, the number of timestamps over time as histogram, and the time cursor.
+  /// Disabled layers are greyed out.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn draw_timeline(&mut self) {
+    const LABEL: f32 = 90.;
+    const WIDTH: f32 = 300.;
+    const ROW: f32 = 20.;
+    const BINS: usize = 60;
+    let Some((from, time)) = self.time_window() else {
+      return;
+    };
+    // Live playback shows the window up to now, the time cursor all timestamps.
+    let range = match self.live {
+      Some(_) => Some((from, time)),
+      None => self.tracks.range(),
+    };
+    let Some(range) = range else {
+      return;
+    };
+    let rows = self.tracks.rows().count() as f32;
+    let size = self.window.inner_size();
+    let x = (size.width as f32 - LABEL - WIDTH) / 2.;
+    let y = size.height as f32 - rows * ROW - 16. - self.status_height();
+    let mut panel = Path::new();
+    panel.rounded_rect(x - 4., y - 4., LABEL + WIDTH + 8., rows * ROW + 8., 4.);
+    self
+      .canvas
+      .fill_path(&panel, &Paint::color(self.theme.panel()));
+
+    let bin_width = WIDTH / BINS as f32;
+    let mut text_paint = Paint::color(self.theme.text());
+    text_paint.set_font_size(12.);
+    let mut rows = vec![];
+    for (i, (id, timeline, enabled)) in self.tracks.rows().enumerate() {
+      let row_y = y + i as f32 * ROW;
+      let histogram = timeline.histogram(range, BINS);
+      let max = histogram.iter().copied().max().unwrap_or(1).max(1) as f32;
+      let mut bars = Path::new();
+      for (bin, count) in histogram.iter().enumerate().filter(|(_, c)| **c > 0) {
+        // Single timestamps stay visible next to dense bins.
+        let height = (ROW - 4.) * (*count as f32 / max).max(0.1);
+        bars.rect(
+          x + LABEL + bin as f32 * bin_width,
+          row_y + ROW - 2. - height,
+          bin_width - 1.,
+          height,
+        );
+      }
+      let color = if enabled {
+        self.theme.text()
+      } else {
+        let mut color = self.theme.text();
+        color.set_alphaf(0.3);
+        color
+      };
+      rows.push((id.to_string(), row_y, bars, color));
+    }
+    for (id, row_y, bars, color) in rows {
+      text_paint.set_color(color);
+      let _ = self.canvas.fill_text(x, row_y + ROW - 6., &id, &text_paint);
+      self.canvas.fill_path(&bars, &Paint::color(color));
+    }
+
+    let (first, last) = range;
+    let position =
+      x + LABEL + WIDTH * ((time - first) as f32 / (last - first).max(1) as f32).clamp(0., 1.);
+    let mut cursor = Path::new();
+    cursor.move_to(position, y - 2.);
+    cursor.line_to(position, y + rows * ROW + 2.);
+    let mut paint = Paint::color(Color::rgb(220, 30, 30));
+    paint.set_line_width(2.);
+    self.canvas.stroke_path(&cursor, &paint);
+  }
+
+  /// Shows that tiles cannot be downloaded and the map may be incomplete or outdated.
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn draw_offline_indicator(&mut self) {
+    const TEXT: &str = "offline - showing cached tiles";
+    if !self
+      .map_provider
+      .tile_layers
+      .iter()
+      .any(|l| l.tile_loader.is_offline())
+    {
+      return;
+    }
+    let w = self.window.inner_size().width as f32;
+    let mut text_paint = Paint::color(self.theme.text());
+    text_paint.set_font_size(12.);
+    text_paint.set_text_align(femtovg::Align::Right);
+    text_paint.set_text_baseline(femtovg::Baseline::Middle);
+    let (x, y) = (w - 10., 40.);
+    let width = self
+      .canvas
+      .measure_text(x, y, TEXT, &text_paint)
+      .map_or(180., |m| m.width());
+    let mut background = Path::new();
+    background.rounded_rect(x - width - 22., y - 10., width + 30., 20., 4.);
+    self
+      .canvas
+      .fill_path(&background, &Paint::color(self.theme.halo()));
+    let mut dot = Path::new();
+    dot.circle(x - width - 11., y, 4.);
+    self
+      .canvas
+      .fill_path(&dot, &Paint::color(Color::rgb(220, 60, 30)));
+    let _ = self.canvas.fill_text(x, y, TEXT, &text_paint);
+  }
+}
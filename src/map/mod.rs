@@ -1,4 +1,35 @@
+pub mod annotation;
+pub mod attribute_table;
+pub mod bookmark;
+pub mod cluster;
+pub mod command_line;
+pub mod compare;
+pub mod coordinate_format;
 pub mod coordinates;
+pub mod flight;
+pub mod frame_stats;
+pub mod geodesic;
+pub mod geoprocessing;
+pub mod heatmap;
+pub mod hillshade;
+pub mod labels;
+pub mod layer_order;
 pub mod map_event;
 pub mod mapvas;
+pub mod outline;
+pub mod overlay;
+pub mod query;
+pub mod render;
+pub mod scale;
+pub mod script;
+pub mod selection;
+pub mod spatial;
+pub mod sprite;
+pub mod style_rule;
+pub mod theme;
+pub mod time_zone;
+pub mod timeline;
 mod tile_loader;
+pub mod track_stats;
+pub mod vector_tile;
+pub mod viewport;
@@ -0,0 +1,158 @@
+use femtovg::Path;
+
+use super::map_event::{Dash, Direction, Marker};
+
+/// Takes the lines of a path. Shapes are turned into paths by the functions of this module for
+/// the map window, which draws femtovg paths, and for the headless renderer, which rasterizes
+/// [`Lines`].
+pub trait PathBuilder {
+  fn move_to(&mut self, x: f32, y: f32);
+  fn line_to(&mut self, x: f32, y: f32);
+  /// Ends the current line with a segment back to its start.
+  fn close(&mut self);
+  fn circle(&mut self, x: f32, y: f32, radius: f32);
+}
+
+impl PathBuilder for Path {
+  fn move_to(&mut self, x: f32, y: f32) {
+    Path::move_to(self, x, y);
+  }
+
+  fn line_to(&mut self, x: f32, y: f32) {
+    Path::line_to(self, x, y);
+  }
+
+  fn close(&mut self) {
+    Path::close(self);
+  }
+
+  fn circle(&mut self, x: f32, y: f32, radius: f32) {
+    Path::circle(self, x, y, radius);
+  }
+}
+
+/// A path as lists of points for drawing without femtovg.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Lines {
+  /// The points of each line and if it is closed.
+  pub lines: Vec<(Vec<(f32, f32)>, bool)>,
+  /// The centers and radii of circles.
+  pub circles: Vec<((f32, f32), f32)>,
+}
+
+impl PathBuilder for Lines {
+  fn move_to(&mut self, x: f32, y: f32) {
+    self.lines.push((vec![(x, y)], false));
+  }
+
+  fn line_to(&mut self, x: f32, y: f32) {
+    match self.lines.last_mut() {
+      Some((line, _)) => line.push((x, y)),
+      None => self.move_to(x, y),
+    }
+  }
+
+  fn close(&mut self) {
+    if let Some((_, closed)) = self.lines.last_mut() {
+      *closed = true;
+    }
+  }
+
+  fn circle(&mut self, x: f32, y: f32, radius: f32) {
+    self.circles.push(((x, y), radius));
+  }
+}
+
+/// Connects the points, back to the first one if `close`.
+pub fn polyline(path: &mut impl PathBuilder, points: &[(f32, f32)], close: bool) {
+  let Some(&(x, y)) = points.first() else {
+    return;
+  };
+  path.move_to(x, y);
+  for &(x, y) in &points[1..] {
+    path.line_to(x, y);
+  }
+  if close {
+    path.line_to(x, y);
+  }
+}
+
+/// The dashes of the line. `scale` converts pixels into the units of the points.
+pub fn dashes(path: &mut impl PathBuilder, dash: Dash, points: &[(f32, f32)], scale: f32) {
+  for part in dash.split(points, scale) {
+    polyline(path, &part, false);
+  }
+}
+
+/// The outline of a point marker.
+pub fn marker(path: &mut impl PathBuilder, marker: Marker, x: f32, y: f32, radius: f32) {
+  match marker {
+    Marker::Circle => path.circle(x, y, radius),
+    Marker::Square => {
+      let corners = [
+        (x - radius, y - radius),
+        (x + radius, y - radius),
+        (x + radius, y + radius),
+        (x - radius, y + radius),
+      ];
+      polyline(path, &corners, false);
+      path.close();
+    }
+    Marker::Triangle => {
+      let corners = [
+        (x, y - radius),
+        (x + radius * 0.866, y + radius * 0.5),
+        (x - radius * 0.866, y + radius * 0.5),
+      ];
+      polyline(path, &corners, false);
+      path.close();
+    }
+    Marker::Cross => {
+      let (left, right) = (x - radius, x + radius);
+      polyline(path, &[(left, y - radius), (right, y + radius)], false);
+      polyline(path, &[(left, y + radius), (right, y - radius)], false);
+    }
+  }
+}
+
+/// The arrowheads or ticks showing the direction of a line. Arrowheads are closed to be filled.
+pub fn direction(
+  path: &mut impl PathBuilder,
+  direction: Direction,
+  points: &[(f32, f32)],
+  width: f32,
+  scale: f32,
+) {
+  for mark in direction.marks(points, width, scale) {
+    polyline(path, &mark, false);
+    if direction == Direction::Arrow {
+      path.close();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn lines() {
+    let mut lines = Lines::default();
+    marker(&mut lines, Marker::Triangle, 0., 0., 2.);
+    marker(&mut lines, Marker::Cross, 0., 0., 1.);
+    marker(&mut lines, Marker::Circle, 1., 2., 3.);
+    assert_eq!(lines.lines.len(), 3);
+    assert_eq!(lines.lines[0].0[0], (0., -2.));
+    assert!(lines.lines[0].1);
+    assert!(!lines.lines[1].1);
+    assert_eq!(lines.circles, vec![((1., 2.), 3.)]);
+
+    let mut dashed = Lines::default();
+    let dash = Dash { on: 2., off: 1. };
+    dashes(&mut dashed, dash, &[(0., 0.), (6., 0.)], 1.);
+    assert_eq!(dashed.lines.len(), 2);
+    let mut closed = Lines::default();
+    polyline(&mut closed, &[(0., 0.), (1., 0.), (1., 1.)], true);
+    assert_eq!(closed.lines[0].0.last(), Some(&(0., 0.)));
+  }
+}
@@ -0,0 +1,161 @@
+use std::{
+  fs::File,
+  path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use tiff::{decoder::Decoder, tags::Tag};
+
+use super::{coordinates::Coordinate, map_event::ImageOverlay};
+
+/// Candidates for the world file of an image: `map.pgw`, `map.pngw`, and `map.wld` for `map.png`.
+fn world_file_paths(path: &Path) -> Vec<PathBuf> {
+  let extension = path
+    .extension()
+    .and_then(|e| e.to_str())
+    .unwrap_or_default()
+    .to_lowercase();
+  let mut paths = vec![];
+  if let (Some(first), Some(last)) = (extension.chars().next(), extension.chars().last()) {
+    paths.push(path.with_extension(format!("{first}{last}w")));
+    paths.push(path.with_extension(format!("{extension}w")));
+  }
+  paths.push(path.with_extension("wld"));
+  paths
+}
+
+/// Computes the corners from the six lines of a world file. Rotation terms are ignored.
+/// The coordinates have to be in WGS84.
+fn corners_from_world_file(
+  content: &str,
+  width: u32,
+  height: u32,
+) -> Result<(Coordinate, Coordinate)> {
+  let values = content
+    .lines()
+    .filter(|l| !l.trim().is_empty())
+    .map(|l| l.trim().parse::<f64>())
+    .collect::<Result<Vec<_>, _>>()?;
+  let [a, _d, _b, e, c, f] = values[..] else {
+    return Err(anyhow!("A world file has six lines."));
+  };
+  // c and f are the center of the upper left pixel.
+  let west = c - a / 2.;
+  let north = f - e / 2.;
+  Ok(corners(
+    north,
+    west,
+    north + e * f64::from(height),
+    west + a * f64::from(width),
+  ))
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn corners(north: f64, west: f64, south: f64, east: f64) -> (Coordinate, Coordinate) {
+  (
+    Coordinate {
+      lat: north as f32,
+      lon: west as f32,
+    },
+    Coordinate {
+      lat: south as f32,
+      lon: east as f32,
+    },
+  )
+}
+
+/// Reads the corners from the GeoTIFF tags of a tiff in WGS84.
+fn corners_from_geotiff(path: &Path) -> Result<(Coordinate, Coordinate)> {
+  let mut decoder = Decoder::new(File::open(path)?)?;
+  let (width, height) = decoder.dimensions()?;
+  // The size of a pixel in map units and a raster position with its map position.
+  let scale = decoder.get_tag_f64_vec(Tag::ModelPixelScaleTag)?;
+  let tiepoint = decoder.get_tag_f64_vec(Tag::ModelTiepointTag)?;
+  let (&[sx, sy, ..], &[i, j, _, x, y, ..]) = (&scale[..], &tiepoint[..]) else {
+    return Err(anyhow!("Invalid GeoTIFF tags in {path:?}."));
+  };
+  let west = x - i * sx;
+  let north = y + j * sy;
+  Ok(corners(
+    north,
+    west,
+    north - f64::from(height) * sy,
+    west + f64::from(width) * sx,
+  ))
+}
+
+/// Creates an overlay of an image georeferenced by a world file next to it or by GeoTIFF tags.
+///
+/// # Errors
+/// If the image cannot be read or has no georeference.
+pub fn georeferenced_overlay(path: &Path) -> Result<ImageOverlay> {
+  if let Some(world_file) = world_file_paths(path).into_iter().find(|p| p.exists()) {
+    let (width, height) = image::image_dimensions(path)?;
+    let (nw, se) = corners_from_world_file(&std::fs::read_to_string(world_file)?, width, height)?;
+    return Ok(ImageOverlay::new(path.to_path_buf(), nw, se));
+  }
+  let (nw, se) = corners_from_geotiff(path)
+    .map_err(|e| anyhow!("{path:?} has neither a world file nor GeoTIFF tags: {e}"))?;
+  Ok(ImageOverlay::new(path.to_path_buf(), nw, se))
+}
+
+/// Whether the file is an image that can be shown as overlay.
+#[must_use]
+pub fn is_image(path: &Path) -> bool {
+  path.extension().and_then(|e| e.to_str()).is_some_and(|e| {
+    matches!(
+      e.to_lowercase().as_str(),
+      "png" | "jpg" | "jpeg" | "tif" | "tiff"
+    )
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn world_file() {
+    let content = "0.001\n0.0\n0.0\n-0.001\n13.0005\n52.4995\n";
+    let (nw, se) = corners_from_world_file(content, 1000, 500).unwrap();
+    assert!((nw.lat - 52.5).abs() < 1e-5 && (nw.lon - 13.).abs() < 1e-5);
+    assert!((se.lat - 52.).abs() < 1e-5 && (se.lon - 14.).abs() < 1e-5);
+    assert!(corners_from_world_file("1\n2\n", 10, 10).is_err());
+
+    let paths = world_file_paths(Path::new("plan.png"));
+    assert_eq!(paths[0], Path::new("plan.pgw"));
+    assert_eq!(paths[1], Path::new("plan.pngw"));
+  }
+
+  #[test]
+  fn geotiff() {
+    use tiff::encoder::{colortype::Gray8, TiffEncoder};
+
+    let path = std::env::temp_dir().join(format!("mapvas_{}.tif", std::process::id()));
+    {
+      let mut tiff = TiffEncoder::new(File::create(&path).unwrap()).unwrap();
+      let mut image = tiff.new_image::<Gray8>(4, 2).unwrap();
+      image
+        .encoder()
+        .write_tag(Tag::ModelPixelScaleTag, &[0.5f64, 0.25, 0.][..])
+        .unwrap();
+      image
+        .encoder()
+        .write_tag(Tag::ModelTiepointTag, &[0f64, 0., 0., 13., 52.5, 0.][..])
+        .unwrap();
+      image.write_data(&[0; 8]).unwrap();
+    }
+    let overlay = georeferenced_overlay(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(
+      (overlay.nw, overlay.se),
+      (
+        Coordinate {
+          lat: 52.5,
+          lon: 13.
+        },
+        Coordinate { lat: 52., lon: 15. }
+      )
+    );
+  }
+}
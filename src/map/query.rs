@@ -0,0 +1,215 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, Error, Result};
+
+use super::style_rule::{Comparison, Feature};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+  /// A word found in any attribute.
+  Word(String),
+  /// `field:text`, the field contains the text.
+  Contains(String, String),
+  /// `field=value` or `field!=value`, numbers are compared as numbers.
+  Equals(String, bool, String),
+  Number(String, Comparison, f32),
+}
+
+/// The text of a field. `name` is the label unless the label has a `name=` field, since parsers
+/// read the label from attributes like the `name` of GeoJSON.
+fn text<'a>(feature: &'a Feature, field: &str) -> Option<&'a str> {
+  feature
+    .text(field)
+    .or_else(|| feature.label.filter(|_| field == "name"))
+}
+
+impl Term {
+  fn holds(&self, feature: &Feature) -> bool {
+    match self {
+      Term::Word(word) => feature
+        .label
+        .into_iter()
+        .chain(feature.properties.iter().map(|(_, value)| value.as_str()))
+        .any(|text| text.to_lowercase().contains(word)),
+      Term::Contains(field, part) => {
+        text(feature, field).is_some_and(|t| t.to_lowercase().contains(part))
+      }
+      Term::Equals(field, equal, value) => text(feature, field).is_some_and(|t| {
+        let same = match (t.parse::<f32>(), value.parse::<f32>()) {
+          (Ok(a), Ok(b)) => Comparison::Equal.holds(a, b),
+          _ => t.to_lowercase() == *value,
+        };
+        same == *equal
+      }),
+      Term::Number(field, comparison, value) => feature
+        .number(field)
+        .is_some_and(|n| comparison.holds(n, *value)),
+    }
+  }
+}
+
+impl FromStr for Term {
+  type Err = Error;
+  fn from_str(term: &str) -> Result<Self> {
+    let unquote = |s: &str| s.trim_matches('"').to_lowercase();
+    let Some(operator_start) = term
+      .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+      .filter(|&start| start > 0)
+    else {
+      return Ok(Term::Word(unquote(term)));
+    };
+    let field = term[..operator_start].to_lowercase();
+    let rest = &term[operator_start..];
+    let operators = [
+      (":", None),
+      ("!=", None),
+      ("==", None),
+      ("=", None),
+      ("<=", Some(Comparison::LessOrEqual)),
+      (">=", Some(Comparison::GreaterOrEqual)),
+      ("<", Some(Comparison::Less)),
+      (">", Some(Comparison::Greater)),
+    ];
+    let Some((operator, comparison)) = operators
+      .into_iter()
+      .find(|(operator, _)| rest.starts_with(operator))
+    else {
+      return Ok(Term::Word(unquote(term)));
+    };
+    let value = &rest[operator.len()..];
+    if value.is_empty() {
+      return Err(anyhow!("Missing value in {term}"));
+    }
+    Ok(match (operator, comparison) {
+      (":", _) => Term::Contains(field, unquote(value)),
+      ("!=", _) => Term::Equals(field, false, unquote(value)),
+      (_, None) => Term::Equals(field, true, unquote(value)),
+      (_, Some(comparison)) => Term::Number(
+        field,
+        comparison,
+        value
+          .parse()
+          .map_err(|_| anyhow!("Not a number in {term}"))?,
+      ),
+    })
+  }
+}
+
+/// A query like `name:berlin speed>50` for `:search` and `:filter`. Words separated by spaces must
+/// all match: a bare word is searched in all attributes, `field:text` in one, and `field=value`,
+/// `field!=value`, `field<50`, `field<=50`, `field>50`, and `field>=50` compare it. Attributes are
+/// read like in style rules, `name` is also the label. Case is ignored and quotes keep spaces, like
+/// in `name:"new york"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+  source: String,
+  terms: Vec<Term>,
+}
+
+impl Query {
+  #[must_use]
+  pub fn holds(&self, feature: &Feature) -> bool {
+    self.terms.iter().all(|term| term.holds(feature))
+  }
+}
+
+/// The words of a query, spaces inside quotes do not separate them.
+fn words(query: &str) -> Vec<&str> {
+  let mut words = vec![];
+  let mut start = None;
+  let mut quoted = false;
+  for (i, c) in query.char_indices() {
+    match c {
+      '"' => quoted = !quoted,
+      c if c.is_whitespace() && !quoted => {
+        if let Some(start) = start.take() {
+          words.push(&query[start..i]);
+        }
+        continue;
+      }
+      _ => (),
+    }
+    start.get_or_insert(i);
+  }
+  words.extend(start.map(|start| &query[start..]));
+  words
+}
+
+impl FromStr for Query {
+  type Err = Error;
+  fn from_str(query: &str) -> Result<Self> {
+    let terms = words(query)
+      .into_iter()
+      .map(str::parse)
+      .collect::<Result<Vec<Term>>>()?;
+    if terms.is_empty() {
+      return Err(anyhow!("The query is empty."));
+    }
+    Ok(Query {
+      source: query.trim().to_string(),
+      terms,
+    })
+  }
+}
+
+impl Display for Query {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.source)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn matches(query: &str, label: &str) -> bool {
+    let query: Query = query.parse().unwrap();
+    query.holds(&Feature {
+      label: Some(label),
+      points: 1,
+      properties: &[],
+    })
+  }
+
+  #[test]
+  fn fields() {
+    assert!(matches("berlin", "Berlin"));
+    assert!(matches("name:berl", "Berlin"));
+    assert!(matches("name:berlin speed>50", "name=Berlin speed=80"));
+    assert!(!matches("name:berlin speed>50", "name=Berlin speed=30"));
+    assert!(!matches("name:berlin", "name=Hamburg via Berlin"));
+    assert!(matches("vehicle=7 speed!=0", "vehicle: 7.0, speed: 12"));
+    assert!(matches("kind=highway", "kind=Highway"));
+    assert!(matches(r#"name:"new york""#, "New York"));
+    assert!(matches("points<=1", "anything"));
+    assert!(!matches("missing=1", "anything"));
+  }
+
+  #[test]
+  fn properties() {
+    let properties = [
+      ("highway".to_string(), "primary".to_string()),
+      ("maxspeed".to_string(), "50".to_string()),
+    ];
+    let feature = Feature {
+      label: Some("Unter den Linden maxspeed=30"),
+      points: 2,
+      properties: &properties,
+    };
+    let holds = |query: &str| query.parse::<Query>().unwrap().holds(&feature);
+    assert!(holds("primary"));
+    assert!(holds("highway=primary maxspeed<=50 linden"));
+    assert!(!holds("maxspeed=30"));
+  }
+
+  #[test]
+  fn errors() {
+    assert!("".parse::<Query>().is_err());
+    assert!("speed>fast".parse::<Query>().is_err());
+    assert!("speed:".parse::<Query>().is_err());
+    assert_eq!(
+      words(r#"a  name:"new york" b"#),
+      vec!["a", r#"name:"new york""#, "b"]
+    );
+  }
+}
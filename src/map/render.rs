@@ -0,0 +1,607 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use futures::future::join_all;
+use image::{imageops::FilterType, Rgba, RgbaImage};
+use imageproc::{
+  drawing::{
+    draw_filled_circle_mut, draw_hollow_circle_mut, draw_line_segment_mut, draw_polygon_mut, Blend,
+  },
+  point::Point,
+};
+use log::{debug, error};
+
+use super::{
+  coordinates::{
+    tiles_in_box, BoundingBox, Coordinate, PixelPosition, Tile, TileCoordinate, CANVAS_SIZE,
+  },
+  heatmap::{HeatmapInput, HeatmapSettings, CELL},
+  map_event::{Anchor, Color, Direction, FillStyle, Icon, Layer, RenderMode, Shape, Style},
+  outline::{self, Lines},
+  style_rule::{apply, prepare, Feature},
+  tile_loader::{CachedTileLoader, TileLoader},
+};
+
+const BACKGROUND: Rgba<u8> = Rgba([77, 77, 82, 255]);
+
+/// The size of a tile image in screen pixels the zoom level is chosen for.
+const TARGET_TILE_SIZE: f32 = 256.;
+
+/// The whole world in canvas coordinates.
+const WORLD: [PixelPosition; 2] = [
+  PixelPosition { x: 0., y: 0. },
+  PixelPosition {
+    x: CANVAS_SIZE,
+    y: CANVAS_SIZE,
+  },
+];
+
+/// How timed shapes are shown in the frames of a replay.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Playback {
+  /// Draws a point at the current position of each timed line.
+  pub marker: bool,
+  /// Only draws the last milliseconds of timed lines, fading out.
+  pub trail: Option<i64>,
+}
+
+impl Playback {
+  /// The shapes as shown at the time.
+  #[must_use]
+  pub fn shapes(&self, shapes: &[Shape], time: i64) -> Vec<Shape> {
+    let from = self.trail.map_or(i64::MIN, |trail| time - trail);
+    let mut visible: Vec<Shape> = shapes
+      .iter()
+      .filter_map(|s| s.between(from, time))
+      .collect();
+    if self.marker {
+      let markers: Vec<Shape> = visible
+        .iter()
+        .filter(|s| s.coordinates.len() > 1 && !s.timestamps.is_empty())
+        .filter_map(|s| {
+          let mut marker = Shape::new(vec![*s.coordinates.last()?])
+            .with_label(s.label.clone())
+            .with_icon(s.icon.clone());
+          marker.style = s.style;
+          Some(marker)
+        })
+        .collect();
+      visible.extend(markers);
+    }
+    visible
+  }
+}
+
+/// The first and last timestamp of the shapes.
+#[must_use]
+pub fn time_range(layers: &[Layer]) -> Option<(i64, i64)> {
+  let mut times = layers
+    .iter()
+    .flat_map(|l| l.shapes.iter())
+    .flat_map(|s| s.timestamps.iter().copied());
+  let first = times.next()?;
+  Some(times.fold((first, first), |(a, b), t| (a.min(t), b.max(t))))
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn rgba(color: Color, alpha: u8) -> Rgba<u8> {
+  let c = color.to_rgb();
+  let channel = |v: f32| (v * 255.).round() as u8;
+  Rgba([channel(c.r), channel(c.g), channel(c.b), alpha])
+}
+
+/// Renders layers on top of map tiles into an image without a window.
+pub struct HeadlessRenderer {
+  width: u32,
+  height: u32,
+  tile_loader: CachedTileLoader,
+}
+
+/// The visible section: the north west corner in canvas coordinates and the number of screen pixels
+/// per canvas pixel.
+#[derive(Debug, Clone, Copy)]
+struct View {
+  nw: PixelPosition,
+  scale: f32,
+}
+
+impl View {
+  fn to_screen(self, p: PixelPosition) -> (f32, f32) {
+    (
+      (p.x - self.nw.x) * self.scale,
+      (p.y - self.nw.y) * self.scale,
+    )
+  }
+}
+
+impl HeadlessRenderer {
+  #[must_use]
+  pub fn new(width: u32, height: u32) -> Self {
+    Self {
+      width,
+      height,
+      tile_loader: CachedTileLoader::default(),
+    }
+  }
+
+  /// Renders the layers. The image shows `bbox` or all shapes if it is not given.
+  ///
+  /// # Errors
+  /// If the image has no area.
+  pub async fn render(
+    &self,
+    layers: &[Layer],
+    bbox: Option<(Coordinate, Coordinate)>,
+  ) -> Result<RgbaImage> {
+    let view = self.view(layers, bbox)?;
+    let mut image = Blend(RgbaImage::from_pixel(self.width, self.height, BACKGROUND));
+    self.draw_tiles(&mut image.0, view).await;
+    self.draw_layers(&mut image, view, layers, None);
+    Ok(image.0)
+  }
+
+  /// Renders the growth of timed shapes as frames evenly spread from the first to the last
+  /// timestamp. Each frame shows the shapes up to its time, interpolated between timestamps, shapes
+  /// without timestamps are in all frames. All frames show the same area.
+  ///
+  /// # Errors
+  /// If the image has no area or no shape has timestamps.
+  #[allow(clippy::cast_possible_wrap)]
+  pub async fn render_frames(
+    &self,
+    layers: &[Layer],
+    bbox: Option<(Coordinate, Coordinate)>,
+    frames: usize,
+    playback: Playback,
+  ) -> Result<Vec<RgbaImage>> {
+    let (start, end) = time_range(layers).ok_or(anyhow!("The input has no timestamps."))?;
+    let view = self.view(layers, bbox)?;
+    let mut tiles = RgbaImage::from_pixel(self.width, self.height, BACKGROUND);
+    self.draw_tiles(&mut tiles, view).await;
+    let steps = frames.max(2) as i64 - 1;
+    Ok(
+      (0..=steps)
+        .map(|i| {
+          let time = start + (end - start) * i / steps;
+          let visible: Vec<Layer> = layers
+            .iter()
+            .map(|layer| Layer {
+              shapes: playback.shapes(&layer.shapes, time),
+              ..layer.clone()
+            })
+            .collect();
+          let mut image = Blend(tiles.clone());
+          let fade = playback.trail.map(|trail| (time, trail));
+          self.draw_layers(&mut image, view, &visible, fade);
+          image.0
+        })
+        .collect(),
+    )
+  }
+
+  /// The view showing `bbox` or all shapes.
+  #[allow(clippy::cast_precision_loss)]
+  fn view(&self, layers: &[Layer], bbox: Option<(Coordinate, Coordinate)>) -> Result<View> {
+    if self.width == 0 || self.height == 0 {
+      return Err(anyhow!("Cannot render an empty image."));
+    }
+    let bb = match bbox {
+      Some((a, b)) => BoundingBox::from_iterator([a.into(), b.into()]),
+      None => BoundingBox::from_iterator(
+        layers
+          .iter()
+          .flat_map(|l| l.shapes.iter())
+          .flat_map(|s| s.coordinates.iter())
+          .map(|c| PixelPosition::from(*c)),
+      ),
+    };
+    let bb = if bb.is_valid() {
+      bb
+    } else {
+      BoundingBox::from_iterator(WORLD)
+    };
+
+    let (width, height) = (self.width as f32, self.height as f32);
+    // Leave a margin around the shapes as the map window does when focusing.
+    let scale = ((width - 30.).max(1.) / (bb.width() + 0.000_01))
+      .min((height - 30.).max(1.) / (bb.height() + 0.000_01));
+    let center = bb.center();
+    Ok(View {
+      nw: PixelPosition {
+        x: center.x - width / 2. / scale,
+        y: center.y - height / 2. / scale,
+      },
+      scale,
+    })
+  }
+
+  /// Draws the heatmaps and shapes of the layers. Timed lines fade out over the trail given as
+  /// time of the frame and length.
+  fn draw_layers(
+    &self,
+    image: &mut Blend<RgbaImage>,
+    view: View,
+    layers: &[Layer],
+    fade: Option<(i64, i64)>,
+  ) {
+    let mut icons = HashMap::new();
+    self.draw_heatmap(&mut image.0, view, layers);
+    for layer in layers
+      .iter()
+      .filter(|l| l.render_mode == RenderMode::Shapes)
+    {
+      let features: Vec<Feature> = layer.shapes.iter().map(Feature::of).collect();
+      let mut rules = layer.rules.clone();
+      prepare(&mut rules, &features);
+      for mut shape in layer.shapes.iter().flat_map(Shape::geodesic_parts) {
+        let Some(style) = apply(&rules, shape.style, &Feature::of(&shape)) else {
+          continue;
+        };
+        shape.style = style;
+        if let Some(icon) = shape.icon.as_ref().filter(|_| shape.coordinates.len() == 1) {
+          if Self::draw_image_icon(image, view, &shape, icon, &mut icons) {
+            continue;
+          }
+        }
+        let opacity = layer.opacity.unwrap_or(1.);
+        match fade {
+          Some((time, trail))
+            if shape.coordinates.len() > 1 && shape.timestamps.len() == shape.coordinates.len() =>
+          {
+            Self::draw_trail(image, view, &shape, opacity, time, trail);
+          }
+          _ => Self::draw_shape(image, view, &shape, opacity),
+        }
+      }
+    }
+  }
+
+  /// Draws a timed line segment by segment, the older the more transparent.
+  #[allow(clippy::cast_precision_loss)]
+  fn draw_trail(
+    image: &mut Blend<RgbaImage>,
+    view: View,
+    shape: &Shape,
+    opacity: f32,
+    time: i64,
+    trail: i64,
+  ) {
+    for (c, t) in shape
+      .coordinates
+      .windows(2)
+      .zip(shape.timestamps.windows(2))
+    {
+      let age = (time - t[1]) as f32 / trail.max(1) as f32;
+      let mut segment = Shape::new(c.to_vec());
+      segment.style = Style {
+        fill: FillStyle::NoFill,
+        direction: None,
+        ..shape.style
+      };
+      Self::draw_shape(image, view, &segment, opacity * (1. - age).clamp(0.1, 1.));
+    }
+  }
+
+  #[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+  )]
+  async fn draw_tiles(&self, image: &mut RgbaImage, view: View) {
+    // A tile of zoom level 0 covers the whole canvas.
+    let zoom = (CANVAS_SIZE * view.scale / TARGET_TILE_SIZE)
+      .log2()
+      .round()
+      .clamp(0., 19.) as u8;
+    let se = PixelPosition {
+      x: view.nw.x + self.width as f32 / view.scale,
+      y: view.nw.y + self.height as f32 / view.scale,
+    };
+    let tiles: Vec<Tile> = tiles_in_box(
+      TileCoordinate::from_pixel_position(view.nw.clamp(), zoom),
+      TileCoordinate::from_pixel_position(se.clamp(), zoom),
+    )
+    .collect();
+
+    let data = join_all(tiles.iter().map(|t| self.tile_loader.tile_data(t))).await;
+    for (tile, data) in tiles.iter().zip(data) {
+      let Some(tile_image) = data.ok().and_then(|d| image::load_from_memory(&d).ok()) else {
+        debug!("Tile {tile:?} not available for rendering.");
+        continue;
+      };
+      let (nw, se) = tile.position();
+      let (x0, y0) = view.to_screen(nw);
+      let (x1, y1) = view.to_screen(se);
+      let tile_image = tile_image.resize_exact(
+        (x1.ceil() - x0.floor()) as u32,
+        (y1.ceil() - y0.floor()) as u32,
+        FilterType::Triangle,
+      );
+      image::imageops::overlay(
+        image,
+        &tile_image.to_rgba8(),
+        x0.floor() as i64,
+        y0.floor() as i64,
+      );
+    }
+  }
+
+  /// Draws the points of all heatmap layers as one density raster.
+  #[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+  )]
+  fn draw_heatmap(&self, image: &mut RgbaImage, view: View, layers: &[Layer]) {
+    let points: Vec<(f32, f32)> = layers
+      .iter()
+      .filter(|l| l.render_mode == RenderMode::Heatmap)
+      .flat_map(|l| l.shapes.iter())
+      .filter(|s| s.visible && s.coordinates.len() == 1)
+      .map(|s| {
+        let (x, y) = view.to_screen(s.coordinates[0].into());
+        (x / CELL, y / CELL)
+      })
+      .collect();
+    if points.is_empty() {
+      return;
+    }
+    let width = (self.width as f32 / CELL).ceil() as usize;
+    let height = (self.height as f32 / CELL).ceil() as usize;
+    let input = HeatmapInput {
+      points,
+      width,
+      height,
+      settings: HeatmapSettings::default(),
+    };
+    let raster: Vec<u8> = input
+      .colorize()
+      .into_iter()
+      .flat_map(|c| [c.r, c.g, c.b, c.a])
+      .collect();
+    let Some(raster) = RgbaImage::from_raw(width as u32, height as u32, raster) else {
+      return;
+    };
+    let raster = image::imageops::resize(
+      &raster,
+      width as u32 * CELL as u32,
+      height as u32 * CELL as u32,
+      FilterType::Triangle,
+    );
+    image::imageops::overlay(image, &raster, 0, 0);
+  }
+
+  /// Draws the icon of a point if it is an image file and returns if it did. Other icons need the
+  /// fonts and the sprite of the map window, the points get their marker instead.
+  #[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+  )]
+  fn draw_image_icon(
+    image: &mut Blend<RgbaImage>,
+    view: View,
+    shape: &Shape,
+    icon: &Icon,
+    icons: &mut HashMap<PathBuf, Option<RgbaImage>>,
+  ) -> bool {
+    let Some(path) = icon.image() else {
+      return false;
+    };
+    let loaded = icons.entry(path.clone()).or_insert_with(|| {
+      image::open(&path)
+        .map_err(|e| error!("Cannot load icon {path:?}: {e}"))
+        .ok()
+        .map(|i| i.to_rgba8())
+    });
+    let Some(loaded) = loaded else {
+      return false;
+    };
+    let (x, y) = view.to_screen(shape.coordinates[0].into());
+    let zoom = (view.scale * CANVAS_SIZE / 256.).log2();
+    let height = icon.height(zoom).max(1.);
+    let width = height * loaded.width() as f32 / loaded.height().max(1) as f32;
+    let scaled = image::imageops::resize(
+      loaded,
+      width.round().max(1.) as u32,
+      height.round() as u32,
+      FilterType::Triangle,
+    );
+    let y = match icon.anchor {
+      Anchor::Center => y - height / 2.,
+      Anchor::Bottom => y - height,
+    };
+    image::imageops::overlay(
+      &mut image.0,
+      &scaled,
+      (x - width / 2.).round() as i64,
+      y.round() as i64,
+    );
+    true
+  }
+
+  /// Draws the shape with the opacity of its layer. Blend modes only apply in the map window.
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn draw_shape(image: &mut Blend<RgbaImage>, view: View, shape: &Shape, opacity: f32) {
+    if !shape.visible || shape.coordinates.is_empty() {
+      return;
+    }
+    let style = shape.style;
+    let width = style.line_width();
+    let alpha = |a: f32| (a * opacity.clamp(0., 1.)).round() as u8;
+    let stroke = rgba(style.color, alpha(255.));
+    let fill = match style.fill {
+      FillStyle::NoFill => None,
+      FillStyle::Transparent => Some(rgba(style.color, alpha(50.))),
+      FillStyle::Solid => Some(stroke),
+    };
+    let mut points: Vec<(f32, f32)> = shape
+      .coordinates
+      .iter()
+      .map(|c| view.to_screen((*c).into()))
+      .collect();
+
+    if let [(x, y)] = points[..] {
+      let mut marker = Lines::default();
+      outline::marker(&mut marker, style.marker, x, y, style.point_radius());
+      if let Some(fill) = fill {
+        fill_lines(image, &marker, fill);
+      }
+      stroke_lines(image, &marker, width, stroke);
+      return;
+    }
+
+    if let Some(fill) = fill {
+      let mut area = Lines::default();
+      outline::polyline(&mut area, &points, false);
+      fill_lines(image, &area, fill);
+      if points.first() != points.last() {
+        points.push(points[0]);
+      }
+    }
+    let mut lines = Lines::default();
+    match style.dash {
+      Some(dash) => outline::dashes(&mut lines, dash, &points, 1.),
+      None => outline::polyline(&mut lines, &points, false),
+    }
+    stroke_lines(image, &lines, width, stroke);
+    if let Some(direction) = style.direction {
+      let mut marks = Lines::default();
+      outline::direction(&mut marks, direction, &points, width, 1.);
+      match direction {
+        Direction::Arrow => fill_lines(image, &marks, stroke),
+        Direction::Ticks => stroke_lines(image, &marks, width, stroke),
+      }
+    }
+  }
+}
+
+/// Fills the lines and circles of the path.
+#[allow(clippy::cast_possible_truncation)]
+fn fill_lines(image: &mut Blend<RgbaImage>, path: &Lines, color: Rgba<u8>) {
+  for (line, _) in &path.lines {
+    let mut polygon: Vec<Point<i32>> = line
+      .iter()
+      .map(|(x, y)| Point::new(x.round() as i32, y.round() as i32))
+      .collect();
+    polygon.dedup();
+    while polygon.len() > 1 && polygon.first() == polygon.last() {
+      polygon.pop();
+    }
+    if polygon.len() > 2 {
+      draw_polygon_mut(image, &polygon, color);
+    }
+  }
+  for &((x, y), radius) in &path.circles {
+    let center = (x.round() as i32, y.round() as i32);
+    draw_filled_circle_mut(image, center, radius.round() as i32, color);
+  }
+}
+
+/// Draws the lines and circles of the path with the width in pixels.
+#[allow(clippy::cast_possible_truncation)]
+fn stroke_lines(image: &mut Blend<RgbaImage>, path: &Lines, width: f32, color: Rgba<u8>) {
+  for (line, closed) in &path.lines {
+    for w in line.windows(2) {
+      draw_thick_line(image, w[0], w[1], width, color);
+    }
+    if let (true, Some(first), Some(last)) = (*closed, line.first(), line.last()) {
+      draw_thick_line(image, *last, *first, width, color);
+    }
+  }
+  for &((x, y), radius) in &path.circles {
+    let center = (x.round() as i32, y.round() as i32);
+    draw_hollow_circle_mut(image, center, radius.round() as i32, color);
+  }
+}
+
+/// Draws a line of the width in pixels with round joins.
+#[allow(clippy::cast_possible_truncation)]
+fn draw_thick_line(
+  image: &mut Blend<RgbaImage>,
+  start: (f32, f32),
+  end: (f32, f32),
+  width: f32,
+  color: Rgba<u8>,
+) {
+  let half = width / 2.;
+  let length = (end.0 - start.0).hypot(end.1 - start.1);
+  if half <= 0.75 || length < f32::EPSILON {
+    draw_line_segment_mut(image, start, end, color);
+    return;
+  }
+  let (nx, ny) = (
+    -(end.1 - start.1) / length * half,
+    (end.0 - start.0) / length * half,
+  );
+  let corners = [
+    (start.0 + nx, start.1 + ny),
+    (end.0 + nx, end.1 + ny),
+    (end.0 - nx, end.1 - ny),
+    (start.0 - nx, start.1 - ny),
+  ];
+  let polygon: Vec<Point<i32>> = corners
+    .iter()
+    .map(|(x, y)| Point::new(x.round() as i32, y.round() as i32))
+    .collect();
+  if polygon.first() == polygon.last() || polygon[0] == polygon[1] {
+    draw_line_segment_mut(image, start, end, color);
+  } else {
+    draw_polygon_mut(image, &polygon, color);
+  }
+  let radius = (half.round() as i32 - 1).max(0);
+  if radius > 0 {
+    draw_filled_circle_mut(
+      image,
+      (end.0.round() as i32, end.1.round() as i32),
+      radius,
+      color,
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn color_conversion() {
+    assert_eq!(rgba(Color::Red, 255), Rgba([255, 0, 0, 255]));
+    assert_eq!(rgba(Color::Brown, 50), Rgba([153, 76, 0, 50]));
+  }
+
+  #[test]
+  fn timestamps_of_layers() {
+    let c = Coordinate {
+      lat: 52.5,
+      lon: 13.4,
+    };
+    let mut layer = Layer::new("trace".to_string());
+    assert_eq!(time_range(&[layer.clone()]), None);
+    layer.shapes = vec![
+      Shape::new(vec![c, c]).with_timestamps(vec![20, 40]),
+      Shape::new(vec![c]),
+      Shape::new(vec![c]).with_timestamps(vec![10]),
+    ];
+    assert_eq!(time_range(&[layer]), Some((10, 40)));
+  }
+
+  #[test]
+  fn playback() {
+    let c = |lon| Coordinate { lat: 0., lon };
+    let shapes = vec![
+      Shape::new(vec![c(0.), c(1.), c(2.)]).with_timestamps(vec![0, 60_000, 120_000]),
+      Shape::new(vec![c(5.)]),
+    ];
+    let playback = Playback {
+      marker: true,
+      trail: Some(30_000),
+    };
+    let visible = playback.shapes(&shapes, 90_000);
+    assert_eq!(visible.len(), 3);
+    assert_eq!(visible[0].coordinates, vec![c(1.), c(1.5)]);
+    assert_eq!(visible[2].coordinates, vec![c(1.5)]);
+    assert_eq!(Playback::default().shapes(&shapes, -1).len(), 1);
+  }
+}
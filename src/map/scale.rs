@@ -0,0 +1,106 @@
+/// The circumference of the earth at the equator in meters.
+const EARTH_CIRCUMFERENCE: f32 = 40_075_016.7;
+const METERS_PER_FOOT: f32 = 0.3048;
+const FEET_PER_MILE: f32 = 5280.;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ScaleUnit {
+  #[default]
+  Metric,
+  Imperial,
+}
+
+impl ScaleUnit {
+  #[must_use]
+  pub fn toggle(self) -> Self {
+    match self {
+      Self::Metric => Self::Imperial,
+      Self::Imperial => Self::Metric,
+    }
+  }
+}
+
+/// Meters per pixel of a web mercator map at the given latitude.
+/// * `world_size` - The size of the whole world in pixels.
+#[must_use]
+pub fn meters_per_pixel(latitude: f32, world_size: f32) -> f32 {
+  EARTH_CIRCUMFERENCE * latitude.to_radians().cos() / world_size
+}
+
+/// The largest number of the form 1, 2, or 5 times a power of ten not exceeding `value`.
+#[allow(clippy::cast_possible_truncation)]
+fn round_down_nice(value: f32) -> f32 {
+  let magnitude = 10f32.powi(value.log10().floor() as i32);
+  let leading = value / magnitude;
+  let nice = if leading >= 5. {
+    5.
+  } else if leading >= 2. {
+    2.
+  } else {
+    1.
+  };
+  nice * magnitude
+}
+
+/// Finds a scale bar with a round length that is at most `max_width` pixels wide.
+/// Returns the width in pixels and the label.
+#[must_use]
+pub fn scale_bar(meters_per_pixel: f32, max_width: f32, unit: ScaleUnit) -> (f32, String) {
+  let max_meters = meters_per_pixel * max_width;
+  match unit {
+    ScaleUnit::Metric => {
+      let meters = round_down_nice(max_meters);
+      let label = if meters >= 1000. {
+        format!("{} km", meters / 1000.)
+      } else {
+        format!("{meters} m")
+      };
+      (meters / meters_per_pixel, label)
+    }
+    ScaleUnit::Imperial => {
+      let max_feet = max_meters / METERS_PER_FOOT;
+      let (feet, label) = if max_feet >= FEET_PER_MILE {
+        let miles = round_down_nice(max_feet / FEET_PER_MILE);
+        (miles * FEET_PER_MILE, format!("{miles} mi"))
+      } else {
+        let feet = round_down_nice(max_feet);
+        (feet, format!("{feet} ft"))
+      };
+      (feet * METERS_PER_FOOT / meters_per_pixel, label)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn nice_numbers() {
+    assert!((round_down_nice(7.3) - 5.).abs() < 1e-4);
+    assert!((round_down_nice(230.) - 200.).abs() < 1e-2);
+    assert!((round_down_nice(0.19) - 0.1).abs() < 1e-6);
+  }
+
+  #[test]
+  fn scale_bars() {
+    let (width, label) = scale_bar(10., 100., ScaleUnit::Metric);
+    assert!((width - 100.).abs() < 1e-3);
+    assert_eq!(label, "1 km");
+
+    let (width, label) = scale_bar(3., 100., ScaleUnit::Metric);
+    assert!((width - 200. / 3.).abs() < 1e-3);
+    assert_eq!(label, "200 m");
+
+    let (_, label) = scale_bar(100., 100., ScaleUnit::Imperial);
+    assert_eq!(label, "5 mi");
+    let (_, label) = scale_bar(1., 100., ScaleUnit::Imperial);
+    assert_eq!(label, "200 ft");
+  }
+
+  #[test]
+  fn equator_resolution() {
+    assert!((meters_per_pixel(0., 1000.) - 40_075.016).abs() < 0.1);
+    assert!((meters_per_pixel(60., 1000.) - 20_037.508).abs() < 0.1);
+  }
+}
@@ -0,0 +1,223 @@
+use std::{cell::RefCell, path::PathBuf, rc::Rc, time::Duration};
+
+use async_std::task::block_on;
+use log::{error, info};
+use rhai::{
+  serde::{from_dynamic, to_dynamic},
+  Array, Dynamic, Engine, EvalAltResult, Map,
+};
+use tokio::sync::mpsc::Sender;
+
+use super::{
+  coordinates::Coordinate,
+  geodesic::distance,
+  map_event::{ExportFormat, Layer, MapEvent, Shape},
+};
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+fn send(sender: &Sender<MapEvent>, event: MapEvent) -> ScriptResult<()> {
+  block_on(sender.send(event)).map_err(|_| "The map is closed.".into())
+}
+
+fn to_shapes(shapes: Array) -> ScriptResult<Vec<Shape>> {
+  shapes
+    .into_iter()
+    .map(|shape| from_dynamic(&shape))
+    .collect()
+}
+
+/// Sends the shapes to the map and keeps the layers seen by the script up to date.
+fn draw(
+  sender: &Sender<MapEvent>,
+  layers: &RefCell<Vec<Layer>>,
+  id: &str,
+  shapes: Vec<Shape>,
+  replace: bool,
+) -> ScriptResult<()> {
+  {
+    let mut layers = layers.borrow_mut();
+    match layers.iter_mut().find(|l| l.id == id) {
+      Some(layer) if replace => layer.shapes.clone_from(&shapes),
+      Some(layer) => layer.shapes.extend(shapes.iter().cloned()),
+      None => {
+        let mut layer = Layer::new(id.to_string());
+        layer.shapes.clone_from(&shapes);
+        layers.push(layer);
+      }
+    }
+  }
+  let mut layer = Layer::new(id.to_string()).with_replace(replace);
+  layer.shapes = shapes;
+  send(sender, MapEvent::Layer(layer))
+}
+
+/// Registers `shape(coordinates)`, `point(lat, lon)`, and `distance(a, b)`, which scripts and
+/// plugins share.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn register_shapes(engine: &mut Engine) {
+  engine.register_fn("shape", |coordinates: Array| -> ScriptResult<Dynamic> {
+    let coordinates = coordinates
+      .iter()
+      .map(from_dynamic)
+      .collect::<ScriptResult<Vec<Coordinate>>>()?;
+    to_dynamic(Shape::new(coordinates))
+  });
+  engine.register_fn("point", |lat: f64, lon: f64| -> ScriptResult<Dynamic> {
+    to_dynamic(Coordinate {
+      lat: lat as f32,
+      lon: lon as f32,
+    })
+  });
+  engine.register_fn("distance", |a: Dynamic, b: Dynamic| -> ScriptResult<f64> {
+    Ok(f64::from(distance(from_dynamic(&a)?, from_dynamic(&b)?)))
+  });
+}
+
+/// A rhai engine with functions working on the map.
+///
+/// - `layers()` returns a map from layer id to its shapes, `shapes(id)` the shapes of one layer.
+///   Shapes are maps like `#{coordinates: [#{lat: 52.5, lon: 13.4}], style: #{color: "Red",
+///   fill: "NoFill"}, label: "Berlin", visible: true, timestamps: []}`.
+/// - `shape(coordinates)` creates a shape, `point(lat, lon)` a coordinate.
+/// - `draw(id, shapes)` replaces the shapes of a layer, `add(id, shapes)` adds to them.
+/// - `clear()`, `clear_layer(id)`, `focus()`, `goto(target)`, and `rotate(degrees)` change the
+///   map, `screenshot(path)` and `export(path)` write it to files.
+/// - `distance(a, b)` is the great circle distance in kilometers, `sleep(seconds)` waits.
+///
+/// The layers are the ones when the script started and the changes made by the script.
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub fn engine(sender: Sender<MapEvent>, layers: Vec<Layer>) -> Engine {
+  let layers = Rc::new(RefCell::new(layers));
+  let mut engine = Engine::new();
+  engine.on_print(|text| info!("{text}"));
+
+  let l = layers.clone();
+  engine.register_fn("layers", move || -> ScriptResult<Map> {
+    l.borrow()
+      .iter()
+      .map(|layer| Ok((layer.id.as_str().into(), to_dynamic(&layer.shapes)?)))
+      .collect()
+  });
+  let l = layers.clone();
+  engine.register_fn("shapes", move |id: &str| -> ScriptResult<Dynamic> {
+    match l.borrow().iter().find(|layer| layer.id == id) {
+      Some(layer) => to_dynamic(&layer.shapes),
+      None => Ok(Dynamic::from_array(vec![])),
+    }
+  });
+  register_shapes(&mut engine);
+
+  let (s, l) = (sender.clone(), layers.clone());
+  engine.register_fn("draw", move |id: &str, shapes: Array| {
+    draw(&s, &l, id, to_shapes(shapes)?, true)
+  });
+  let (s, l) = (sender.clone(), layers.clone());
+  engine.register_fn("add", move |id: &str, shapes: Array| {
+    draw(&s, &l, id, to_shapes(shapes)?, false)
+  });
+  let (s, l) = (sender.clone(), layers.clone());
+  engine.register_fn("clear", move || {
+    l.borrow_mut().clear();
+    send(&s, MapEvent::Clear)
+  });
+  let (s, l) = (sender.clone(), layers);
+  engine.register_fn("clear_layer", move |id: &str| {
+    l.borrow_mut().retain(|layer| layer.id != id);
+    send(&s, MapEvent::ClearLayer(id.to_string()))
+  });
+  let s = sender.clone();
+  engine.register_fn("focus", move || send(&s, MapEvent::Focus));
+  let s = sender.clone();
+  engine.register_fn("goto", move |target: &str| {
+    let view = target
+      .parse()
+      .map_err(|()| format!("Unknown position: {target}"))?;
+    send(&s, MapEvent::SetView(view))
+  });
+  let s = sender.clone();
+  engine.register_fn("rotate", move |degrees: f64| {
+    send(&s, MapEvent::Rotation(degrees as f32))
+  });
+  let s = sender.clone();
+  engine.register_fn("screenshot", move |path: &str| {
+    send(&s, MapEvent::Screenshot(path.into()))
+  });
+  let s = sender;
+  engine.register_fn("export", move |path: &str| {
+    let path = PathBuf::from(path);
+    let format = ExportFormat::from_path(&path).unwrap_or_default();
+    send(&s, MapEvent::Export { format, path })
+  });
+  engine.register_fn("sleep", |seconds: f64| {
+    std::thread::sleep(Duration::from_secs_f64(seconds.max(0.)));
+  });
+  engine.register_fn("sleep", |seconds: i64| {
+    std::thread::sleep(Duration::from_secs(seconds.max(0).unsigned_abs()));
+  });
+  engine
+}
+
+/// Runs the script file in its own thread, so it may wait without blocking the map.
+pub fn run(path: PathBuf, sender: Sender<MapEvent>, layers: Vec<Layer>) {
+  std::thread::spawn(move || {
+    if let Err(e) = engine(sender, layers).run_file(path.clone()) {
+      error!("Script {path:?} failed: {e}");
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::map::map_event::Color;
+
+  #[test]
+  fn recolor_layer() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+    let mut layer = Layer::new("trace".to_string());
+    layer.shapes = vec![
+      Shape::new(vec![
+        Coordinate {
+          lat: 52.5,
+          lon: 13.4,
+        },
+        Coordinate {
+          lat: 52.6,
+          lon: 13.4,
+        },
+      ]),
+      Shape::new(vec![Coordinate {
+        lat: 52.5,
+        lon: 13.4,
+      }]),
+    ];
+    let script = r#"
+      let shapes = layers()["trace"];
+      for i in 0..shapes.len() {
+        let c = shapes[i].coordinates;
+        if c.len() > 1 && distance(c[0], c[1]) > 10.0 {
+          shapes[i].style.color = "Red";
+        }
+      }
+      draw("trace", shapes);
+      add("new", [shape([point(48.1, 11.6)])]);
+      goto("48.1,11.6,z12");
+    "#;
+    engine(tx, vec![layer]).run(script).unwrap();
+
+    let Ok(MapEvent::Layer(layer)) = rx.try_recv() else {
+      panic!("no layer");
+    };
+    assert!(layer.replace);
+    assert_eq!(layer.shapes[0].style.color, Color::Red);
+    assert_eq!(layer.shapes[1].style.color, Color::default());
+    let Ok(MapEvent::Layer(added)) = rx.try_recv() else {
+      panic!("no added layer");
+    };
+    assert!(!added.replace);
+    assert_eq!(added.shapes[0].coordinates.len(), 1);
+    assert!(matches!(rx.try_recv(), Ok(MapEvent::SetView(_))));
+  }
+}
@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::coordinates::PixelPosition;
+
+/// Elements picked on the map, by layer and index in the layer, as target of commands like
+/// `:selection delete`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Selection {
+  elements: BTreeMap<String, BTreeSet<usize>>,
+}
+
+impl Selection {
+  pub fn add(&mut self, layer: &str, index: usize) {
+    self
+      .elements
+      .entry(layer.to_string())
+      .or_default()
+      .insert(index);
+  }
+
+  /// Adds the element or removes it if it is selected.
+  pub fn toggle(&mut self, layer: &str, index: usize) {
+    let indices = self.elements.entry(layer.to_string()).or_default();
+    if !indices.remove(&index) {
+      indices.insert(index);
+    }
+    if indices.is_empty() {
+      self.elements.remove(layer);
+    }
+  }
+
+  #[must_use]
+  pub fn contains(&self, layer: &str, index: usize) -> bool {
+    self
+      .elements
+      .get(layer)
+      .is_some_and(|indices| indices.contains(&index))
+  }
+
+  #[must_use]
+  pub fn len(&self) -> usize {
+    self.elements.values().map(BTreeSet::len).sum()
+  }
+
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.elements.is_empty()
+  }
+
+  pub fn clear(&mut self) {
+    self.elements.clear();
+  }
+
+  /// The selected layers with the indices of their selected elements.
+  pub fn layers(&self) -> impl Iterator<Item = (&String, &BTreeSet<usize>)> {
+    self.elements.iter()
+  }
+
+  /// Forgets the elements of a layer that was removed or replaced.
+  pub fn remove_layer(&mut self, layer: &str) {
+    self.elements.remove(layer);
+  }
+
+  /// Forgets removed elements of a layer and moves the indices of the later ones.
+  pub fn remove_elements(&mut self, layer: &str, removed: &BTreeSet<usize>) {
+    let Some(indices) = self.elements.remove(layer) else {
+      return;
+    };
+    let moved: BTreeSet<usize> = indices
+      .into_iter()
+      .filter(|i| !removed.contains(i))
+      .map(|i| i - removed.range(..i).count())
+      .collect();
+    if !moved.is_empty() {
+      self.elements.insert(layer.to_string(), moved);
+    }
+  }
+}
+
+/// The area elements are selected with while it is drawn, in canvas coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Area {
+  /// Dragged from the first to the second corner.
+  Rectangle(PixelPosition, PixelPosition),
+  /// Clicked vertex by vertex.
+  Polygon(Vec<PixelPosition>),
+}
+
+impl Area {
+  /// The outline of the area.
+  #[must_use]
+  pub fn polygon(&self) -> Vec<PixelPosition> {
+    match self {
+      Area::Rectangle(a, b) => vec![
+        *a,
+        PixelPosition { x: b.x, y: a.y },
+        *b,
+        PixelPosition { x: a.x, y: b.y },
+      ],
+      Area::Polygon(vertices) => vertices.clone(),
+    }
+  }
+}
+
+/// If the point lies inside of the polygon by the even-odd rule.
+#[must_use]
+pub fn contains(polygon: &[PixelPosition], p: PixelPosition) -> bool {
+  let mut inside = false;
+  let mut previous = polygon.last().copied();
+  for &vertex in polygon {
+    let Some(last) = previous.replace(vertex) else {
+      break;
+    };
+    if (vertex.y > p.y) != (last.y > p.y)
+      && p.x < (last.x - vertex.x) * (p.y - vertex.y) / (last.y - vertex.y) + vertex.x
+    {
+      inside = !inside;
+    }
+  }
+  inside
+}
+
+/// If the segments a-b and c-d cross.
+fn crosses(a: PixelPosition, b: PixelPosition, c: PixelPosition, d: PixelPosition) -> bool {
+  let side = |p: PixelPosition, q: PixelPosition, r: PixelPosition| {
+    (q.x - p.x) * (r.y - p.y) - (q.y - p.y) * (r.x - p.x)
+  };
+  let (d1, d2) = (side(c, d, a), side(c, d, b));
+  let (d3, d4) = (side(a, b, c), side(a, b, d));
+  (d1 > 0.) != (d2 > 0.) && (d3 > 0.) != (d4 > 0.)
+}
+
+fn edges(
+  positions: &[PixelPosition],
+  closed: bool,
+) -> impl Iterator<Item = (PixelPosition, PixelPosition)> + '_ {
+  let closing = positions
+    .last()
+    .zip(positions.first())
+    .filter(|_| closed && positions.len() > 2);
+  positions
+    .windows(2)
+    .map(|w| (w[0], w[1]))
+    .chain(closing.map(|(a, b)| (*a, *b)))
+}
+
+/// If an element with the positions touches the area: a vertex lies inside of it, an edge crosses
+/// its border, or the area lies inside of the closed element.
+#[must_use]
+pub fn intersects(positions: &[PixelPosition], closed: bool, area: &[PixelPosition]) -> bool {
+  if area.len() < 3 {
+    return false;
+  }
+  positions.iter().any(|p| contains(area, *p))
+    || edges(positions, closed).any(|(a, b)| edges(area, true).any(|(c, d)| crosses(a, b, c, d)))
+    || (closed && area.first().is_some_and(|p| contains(positions, *p)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn positions(coordinates: &[(f32, f32)]) -> Vec<PixelPosition> {
+    coordinates
+      .iter()
+      .map(|&(x, y)| PixelPosition { x, y })
+      .collect()
+  }
+
+  #[test]
+  fn intersection() {
+    let area = Area::Rectangle(
+      PixelPosition { x: 0., y: 0. },
+      PixelPosition { x: 10., y: 10. },
+    )
+    .polygon();
+    assert!(intersects(&positions(&[(5., 5.)]), false, &area));
+    assert!(!intersects(&positions(&[(15., 5.)]), false, &area));
+    // Crossing without a vertex inside.
+    assert!(intersects(
+      &positions(&[(-5., 5.), (15., 5.)]),
+      false,
+      &area
+    ));
+    assert!(!intersects(
+      &positions(&[(-5., 15.), (15., 15.)]),
+      false,
+      &area
+    ));
+    // A polygon around the area, but not a line around it.
+    let around = positions(&[(-5., -5.), (15., -5.), (15., 15.), (-5., 15.)]);
+    assert!(intersects(&around, true, &area));
+    assert!(!intersects(&around, false, &area));
+    let triangle = positions(&[(0., 0.), (10., 0.), (0., 10.)]);
+    assert!(intersects(&positions(&[(2., 2.)]), false, &triangle));
+    assert!(!intersects(&positions(&[(8., 8.)]), false, &triangle));
+  }
+
+  #[test]
+  fn remove_elements() {
+    let mut selection = Selection::default();
+    for i in [1, 3, 5] {
+      selection.add("a", i);
+    }
+    selection.add("b", 0);
+    selection.remove_elements("a", &BTreeSet::from([0, 3]));
+    assert!(selection.contains("a", 0) && selection.contains("a", 3));
+    assert_eq!(selection.len(), 3);
+    selection.toggle("b", 1);
+    selection.toggle("b", 1);
+    selection.remove_elements("b", &BTreeSet::from([0]));
+    selection.remove_layer("a");
+    assert!(selection.is_empty());
+  }
+}
@@ -0,0 +1,166 @@
+use regex::Regex;
+
+use super::{coordinates::PixelPosition, selection::contains};
+
+/// If all positions lie inside of the polygon.
+#[must_use]
+pub fn within(positions: &[PixelPosition], polygon: &[PixelPosition]) -> bool {
+  !positions.is_empty() && positions.iter().all(|p| contains(polygon, *p))
+}
+
+/// The number of points inside of each polygon.
+#[must_use]
+pub fn count_points(points: &[PixelPosition], polygons: &[Vec<PixelPosition>]) -> Vec<usize> {
+  polygons
+    .iter()
+    .map(|polygon| points.iter().filter(|p| contains(polygon, **p)).count())
+    .collect()
+}
+
+/// The vertex of the lines closest to the position within the distance, or else the closest
+/// point on one of their segments. Vertices win, so snapped shapes share their corners.
+#[must_use]
+pub fn snap(
+  p: PixelPosition,
+  lines: &[Vec<PixelPosition>],
+  distance: f32,
+) -> Option<PixelPosition> {
+  let max = distance * distance;
+  let closest = |candidates: &mut dyn Iterator<Item = PixelPosition>| {
+    candidates
+      .map(|c| (c, c.sq_dist(&p)))
+      .filter(|(_, d)| *d <= max)
+      .min_by(|a, b| a.1.total_cmp(&b.1))
+      .map(|(c, _)| c)
+  };
+  closest(&mut lines.iter().flatten().copied()).or_else(|| {
+    closest(
+      &mut lines
+        .iter()
+        .flat_map(|line| line.windows(2))
+        .map(|s| p.closest_on_line_segment(&s[0], &s[1])),
+    )
+  })
+}
+
+/// The index of the vertex closest to the position within the distance.
+#[must_use]
+pub fn closest_vertex(
+  positions: &[PixelPosition],
+  p: PixelPosition,
+  distance: f32,
+) -> Option<usize> {
+  positions
+    .iter()
+    .enumerate()
+    .map(|(i, v)| (i, v.sq_dist(&p)))
+    .filter(|(_, d)| *d < distance * distance)
+    .min_by(|a, b| a.1.total_cmp(&b.1))
+    .map(|(i, _)| i)
+}
+
+/// Inserts the position as a vertex into the closest segment within the distance. Returns the
+/// index of the new vertex.
+pub fn insert_vertex(
+  positions: &mut Vec<PixelPosition>,
+  p: PixelPosition,
+  distance: f32,
+) -> Option<usize> {
+  let (segment, d) = positions
+    .windows(2)
+    .enumerate()
+    .map(|(i, s)| (i, p.sq_distance_line_segment(&s[0], &s[1])))
+    .min_by(|a, b| a.1.total_cmp(&b.1))?;
+  (d < distance * distance).then(|| {
+    positions.insert(segment + 1, p);
+    segment + 1
+  })
+}
+
+/// Removes the vertex unless it is the last one. Returns whether it was removed.
+pub fn remove_vertex(positions: &mut Vec<PixelPosition>, index: usize) -> bool {
+  let removed = positions.len() > 1 && index < positions.len();
+  if removed {
+    positions.remove(index);
+  }
+  removed
+}
+
+/// The label with the field set to the value. A `field=value` or `field: value` already in the
+/// label is replaced, otherwise `field=value` is appended, so style rules read it.
+#[must_use]
+pub fn with_field(label: Option<&str>, field: &str, value: &str) -> String {
+  let Some(label) = label.filter(|l| !l.is_empty()) else {
+    return format!("{field}={value}");
+  };
+  let existing = Regex::new(&format!(
+    r"(?i)(^|[^\w])({})\s*[=:]\s*[^\s,;]*",
+    regex::escape(field)
+  ))
+  .expect("The field is escaped.");
+  if existing.is_match(label) {
+    let replacement = format!("${{1}}{field}={value}");
+    existing.replace(label, replacement.as_str()).into_owned()
+  } else {
+    format!("{label} {field}={value}")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn points_in_polygons() {
+    let p = |x, y| PixelPosition { x, y };
+    let square = vec![p(0., 0.), p(10., 0.), p(10., 10.), p(0., 10.)];
+    let other = vec![p(20., 0.), p(30., 0.), p(30., 10.)];
+    let points = [p(1., 1.), p(5., 5.), p(15., 5.), p(29., 5.)];
+    assert_eq!(count_points(&points, &[square.clone(), other]), vec![2, 1]);
+    assert!(within(&points[..2], &square));
+    assert!(!within(&points[..3], &square));
+    assert!(!within(&[], &square));
+  }
+
+  #[test]
+  fn snapping() {
+    let p = |x, y| PixelPosition { x, y };
+    let lines = vec![vec![p(0., 0.), p(10., 0.)], vec![p(20., 0.)]];
+    assert_eq!(snap(p(1., 1.), &lines, 2.), Some(p(0., 0.)));
+    assert_eq!(snap(p(5., 1.), &lines, 2.), Some(p(5., 0.)));
+    assert_eq!(snap(p(19., 3.), &lines, 2.), None);
+    assert_eq!(snap(p(19., 1.), &lines, 2.), Some(p(20., 0.)));
+  }
+
+  #[test]
+  fn vertex_editing() {
+    let p = |x, y| PixelPosition { x, y };
+    let mut line = vec![p(0., 0.), p(10., 0.), p(10., 10.)];
+    assert_eq!(closest_vertex(&line, p(9., 1.), 2.), Some(1));
+    assert_eq!(closest_vertex(&line, p(5., 1.), 2.), None);
+    assert_eq!(insert_vertex(&mut line, p(9., 5.), 2.), Some(2));
+    assert_eq!(line[2], p(9., 5.));
+    assert_eq!(insert_vertex(&mut line, p(5., 5.), 2.), None);
+    assert_eq!(line.len(), 4);
+    assert!(remove_vertex(&mut line, 2));
+    assert_eq!(line, vec![p(0., 0.), p(10., 0.), p(10., 10.)]);
+    assert!(!remove_vertex(&mut line, 3));
+    let mut point = vec![p(1., 1.)];
+    assert!(!remove_vertex(&mut point, 0));
+    assert_eq!(insert_vertex(&mut point, p(1., 1.), 2.), None);
+  }
+
+  #[test]
+  fn fields() {
+    assert_eq!(with_field(None, "count", "3"), "count=3");
+    assert_eq!(with_field(Some("Mitte"), "count", "3"), "Mitte count=3");
+    assert_eq!(
+      with_field(Some("Mitte count: 2, area=4"), "count", "3"),
+      "Mitte count=3, area=4"
+    );
+    assert_eq!(
+      with_field(Some("discount=1"), "count", "3"),
+      "discount=1 count=3"
+    );
+  }
+}
@@ -0,0 +1,158 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+/// Label keys whose values select an icon, in order of preference.
+const ICON_KEYS: [&str; 9] = [
+  "kind",
+  "class",
+  "aeroway",
+  "amenity",
+  "railway",
+  "public_transport",
+  "shop",
+  "tourism",
+  "leisure",
+];
+
+/// The icons that are always available by name, drawn as emoji.
+const BUNDLED_ICONS: [(&str, &str); 16] = [
+  ("car", "🚗"),
+  ("bus", "🚌"),
+  ("truck", "🚚"),
+  ("tram", "🚊"),
+  ("train", "🚆"),
+  ("bike", "🚲"),
+  ("pedestrian", "🚶"),
+  ("plane", "✈️"),
+  ("ship", "🚢"),
+  ("pin", "📍"),
+  ("flag", "🚩"),
+  ("star", "⭐"),
+  ("home", "🏠"),
+  ("warning", "⚠️"),
+  ("parking", "🅿️"),
+  ("fuel", "⛽"),
+];
+
+/// The emoji of a bundled icon, e.g. 🚌 for `bus`.
+#[must_use]
+pub fn bundled_icon(name: &str) -> Option<&'static str> {
+  BUNDLED_ICONS
+    .iter()
+    .find(|(n, _)| *n == name)
+    .map(|(_, emoji)| *emoji)
+}
+
+/// The position of an icon in the sprite image.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SpriteIcon {
+  pub x: f32,
+  pub y: f32,
+  pub width: f32,
+  pub height: f32,
+  #[serde(default = "SpriteIcon::default_pixel_ratio", rename = "pixelRatio")]
+  pub pixel_ratio: f32,
+}
+
+impl SpriteIcon {
+  fn default_pixel_ratio() -> f32 {
+    1.
+  }
+}
+
+/// A spritesheet in the format of Mapbox styles, an image with all icons and a json index
+/// with the position of each icon by name.
+#[derive(Debug)]
+pub struct Sprite {
+  pub image: PathBuf,
+  icons: HashMap<String, SpriteIcon>,
+}
+
+impl Sprite {
+  /// Reads the sprite given by `MAPVAS_SPRITE`, e.g. `~/maps/sprite` for `~/maps/sprite.json`
+  /// and `~/maps/sprite.png`.
+  #[must_use]
+  pub fn from_env() -> Option<Result<Self>> {
+    let path = std::env::var("MAPVAS_SPRITE").ok()?;
+    Some(Self::from_path(&PathBuf::from(path)))
+  }
+
+  /// Reads the index `<path>.json` of the image `<path>.png`.
+  ///
+  /// # Errors
+  /// If the index cannot be read.
+  pub fn from_path(path: &std::path::Path) -> Result<Self> {
+    let index = std::fs::read_to_string(path.with_extension("json"))?;
+    Ok(Self {
+      image: path.with_extension("png"),
+      icons: Self::parse_index(&index)?,
+    })
+  }
+
+  fn parse_index(index: &str) -> Result<HashMap<String, SpriteIcon>> {
+    Ok(serde_json::from_str(index)?)
+  }
+
+  /// The icon for a label of `key: value` pairs, e.g. `aerodrome` for
+  /// `aeroway: aerodrome, name: Berlin`. Icons may also be named `<value>-15` or
+  /// `<value>_11` as in the Maki icon set.
+  #[must_use]
+  pub fn icon_for_label(&self, label: &str) -> Option<&SpriteIcon> {
+    let tags: HashMap<&str, &str> = label
+      .split(", ")
+      .filter_map(|pair| pair.split_once(": "))
+      .map(|(k, v)| (k.trim(), v.trim()))
+      .collect();
+    ICON_KEYS
+      .iter()
+      .filter_map(|key| tags.get(key))
+      .find_map(|value| self.icon(value))
+  }
+
+  /// The icon with the name, also with the suffixes of the Maki icon set.
+  #[must_use]
+  pub fn icon(&self, name: &str) -> Option<&SpriteIcon> {
+    self.icons.get(name).or_else(|| {
+      ["-15", "-11", "_15", "_11"]
+        .iter()
+        .find_map(|suffix| self.icons.get(&format!("{name}{suffix}")))
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn icon_for_label() {
+    let sprite = Sprite {
+      image: PathBuf::from("sprite.png"),
+      icons: Sprite::parse_index(
+        r#"{
+          "airport-15": {"x": 0, "y": 0, "width": 21, "height": 21, "pixelRatio": 1},
+          "hospital": {"x": 21, "y": 0, "width": 42, "height": 42, "pixelRatio": 2}
+        }"#,
+      )
+      .unwrap(),
+    };
+    assert_eq!(
+      sprite
+        .icon_for_label("amenity: hospital, name: Charité")
+        .unwrap()
+        .x,
+      21.
+    );
+    assert_eq!(
+      sprite
+        .icon_for_label("name: BER, aeroway: airport")
+        .unwrap()
+        .width,
+      21.
+    );
+    assert!(sprite.icon_for_label("amenity: bench").is_none());
+    assert!(sprite.icon_for_label("hospital").is_none());
+  }
+}
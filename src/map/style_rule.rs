@@ -0,0 +1,551 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, Error, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::map_event::{Color, Dash, Direction, FillStyle, Marker, Shape, Style};
+
+/// What a rule sees of a drawn element.
+/// Besides `label` and `points` a field is read from the properties or a `name=value` or
+/// `name:value` in the label.
+#[derive(Debug, Clone, Copy)]
+pub struct Feature<'a> {
+  pub label: Option<&'a str>,
+  pub points: usize,
+  pub properties: &'a [(String, String)],
+}
+
+impl<'a> Feature<'a> {
+  #[must_use]
+  pub fn of(shape: &'a Shape) -> Self {
+    Feature {
+      label: shape.label.as_deref(),
+      points: shape.coordinates.len(),
+      properties: &shape.properties,
+    }
+  }
+
+  pub(super) fn text(&self, field: &str) -> Option<&str> {
+    if let Some((_, value)) = self
+      .properties
+      .iter()
+      .find(|(key, _)| key.eq_ignore_ascii_case(field))
+    {
+      return Some(value);
+    }
+    let label = self.label?;
+    if field == "label" {
+      return Some(label);
+    }
+    let lowercase = label.to_ascii_lowercase();
+    let field = field.to_ascii_lowercase();
+    lowercase.match_indices(&field).find_map(|(start, _)| {
+      let before = lowercase[..start].chars().next_back();
+      if before.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+      }
+      let rest = label[start + field.len()..].trim_start();
+      let value = rest.strip_prefix(['=', ':'])?.trim_start();
+      let end = value
+        .find(|c: char| c.is_whitespace() || c == ',' || c == ';')
+        .unwrap_or(value.len());
+      Some(&value[..end])
+    })
+  }
+
+  #[allow(clippy::cast_precision_loss)]
+  pub(super) fn number(&self, field: &str) -> Option<f32> {
+    if field == "points" {
+      return Some(self.points as f32);
+    }
+    self.text(field)?.parse().ok()
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Comparison {
+  Equal,
+  NotEqual,
+  Less,
+  LessOrEqual,
+  Greater,
+  GreaterOrEqual,
+}
+
+impl Comparison {
+  pub(super) fn holds(self, a: f32, b: f32) -> bool {
+    match self {
+      Comparison::Equal => (a - b).abs() < f32::EPSILON,
+      Comparison::NotEqual => (a - b).abs() >= f32::EPSILON,
+      Comparison::Less => a < b,
+      Comparison::LessOrEqual => a <= b,
+      Comparison::Greater => a > b,
+      Comparison::GreaterOrEqual => a >= b,
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+  All,
+  Matches(String, Regex),
+  Text(String, bool, String),
+  Number(String, Comparison, f32),
+}
+
+impl Condition {
+  fn field(&self) -> Option<&str> {
+    match self {
+      Condition::All => None,
+      Condition::Matches(field, _) | Condition::Text(field, ..) | Condition::Number(field, ..) => {
+        Some(field)
+      }
+    }
+  }
+
+  fn holds(&self, feature: &Feature) -> bool {
+    match self {
+      Condition::All => true,
+      Condition::Matches(field, regex) => feature.text(field).is_some_and(|t| regex.is_match(t)),
+      Condition::Text(field, equal, text) => feature
+        .text(field)
+        .is_some_and(|t| (t == text.as_str()) == *equal),
+      Condition::Number(field, comparison, value) => feature
+        .number(field)
+        .is_some_and(|n| comparison.holds(n, *value)),
+    }
+  }
+}
+
+impl FromStr for Condition {
+  type Err = Error;
+  fn from_str(condition: &str) -> Result<Self> {
+    let condition = condition.trim();
+    if condition.is_empty() || condition == "*" {
+      return Ok(Condition::All);
+    }
+    let operator_start = condition
+      .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+      .ok_or(anyhow!("Missing comparison in {condition}"))?;
+    let field = condition[..operator_start].to_string();
+    let rest = condition[operator_start..].trim_start();
+    let operators = [
+      ("~", None),
+      ("==", Some(Comparison::Equal)),
+      ("!=", Some(Comparison::NotEqual)),
+      ("<=", Some(Comparison::LessOrEqual)),
+      (">=", Some(Comparison::GreaterOrEqual)),
+      ("<", Some(Comparison::Less)),
+      (">", Some(Comparison::Greater)),
+    ];
+    let (operator, comparison) = operators
+      .into_iter()
+      .find(|(operator, _)| rest.starts_with(operator))
+      .ok_or(anyhow!("Unknown comparison in {condition}"))?;
+    let value = rest[operator.len()..].trim();
+    let text = value.strip_prefix('"').and_then(|v| v.strip_suffix('"'));
+    Ok(match (comparison, text) {
+      (None, Some(pattern)) => Condition::Matches(field, Regex::new(pattern)?),
+      (None, None) => return Err(anyhow!("The pattern after ~ needs quotes: {condition}")),
+      (Some(Comparison::Equal), Some(text)) => Condition::Text(field, true, text.to_string()),
+      (Some(Comparison::NotEqual), Some(text)) => Condition::Text(field, false, text.to_string()),
+      (Some(comparison), _) => Condition::Number(field, comparison, value.parse()?),
+    })
+  }
+}
+
+/// How the values of a layer are divided into the classes of a choropleth.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Classification {
+  /// Classes of the same width between the smallest and the largest value.
+  EqualInterval,
+  /// Classes with the same number of values.
+  Quantile,
+}
+
+impl FromStr for Classification {
+  type Err = Error;
+  fn from_str(input: &str) -> Result<Self> {
+    match input {
+      "equal" | "equal-interval" => Ok(Classification::EqualInterval),
+      "quantile" => Ok(Classification::Quantile),
+      _ => Err(anyhow!("Unknown classification: {input}")),
+    }
+  }
+}
+
+impl Classification {
+  /// The bounds of the classes from the smallest to the largest value, one more than classes.
+  #[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+  )]
+  fn bounds(self, mut values: Vec<f32>, classes: usize) -> Vec<f32> {
+    values.sort_by(f32::total_cmp);
+    let (Some(&min), Some(&max)) = (values.first(), values.last()) else {
+      return vec![];
+    };
+    let inner = (1..classes).map(|i| match self {
+      Classification::EqualInterval => min + (max - min) * i as f32 / classes as f32,
+      Classification::Quantile => values[values.len() * i / classes],
+    });
+    std::iter::once(min)
+      .chain(inner)
+      .chain(std::iter::once(max))
+      .collect()
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+  Color(Color),
+  Fill(FillStyle),
+  Hide,
+  /// Picks the color for the value of the condition's field between min and max.
+  Ramp(f32, f32, Vec<Color>),
+  /// A choropleth with one color per class. The bounds of the classes are computed from the
+  /// values of the layer by [`prepare`].
+  Classes(Classification, Vec<Color>, Vec<f32>),
+  Width(f32),
+  Dash(Dash),
+  Radius(f32),
+  Marker(Marker),
+  Direction(Direction),
+}
+
+/// A number for the legend without needless decimals.
+fn format_value(value: f32) -> String {
+  if value.fract().abs() < f32::EPSILON || value.abs() >= 100. {
+    format!("{value:.0}")
+  } else {
+    format!("{value:.2}")
+  }
+}
+
+fn color(name: &str) -> Result<Color> {
+  Color::from_str(name).map_err(|()| anyhow!("Unknown color: {name}"))
+}
+
+/// A width or radius in pixels.
+fn size(value: &str) -> Result<f32> {
+  match value.parse::<f32>() {
+    Ok(size) if size > 0. => Ok(size),
+    _ => Err(anyhow!("Not a size in pixels: {value}")),
+  }
+}
+
+impl FromStr for Action {
+  type Err = Error;
+  fn from_str(action: &str) -> Result<Self> {
+    let mut words = action.split_whitespace();
+    let action = match (words.next(), words.next()) {
+      (Some("hide"), None) => Action::Hide,
+      (Some("color"), Some(name)) => Action::Color(color(name)?),
+      (Some("fill"), Some(fill)) => {
+        Action::Fill(FillStyle::from_str(fill).map_err(|()| anyhow!("Unknown fill: {fill}"))?)
+      }
+      (Some("ramp"), Some(range)) => {
+        let (min, max) = range
+          .split_once("..")
+          .ok_or(anyhow!("A ramp needs a range like 0..100: {range}"))?;
+        let colors = words.by_ref().map(color).collect::<Result<Vec<_>>>()?;
+        if colors.len() < 2 {
+          return Err(anyhow!("A ramp needs at least two colors."));
+        }
+        Action::Ramp(min.parse()?, max.parse()?, colors)
+      }
+      (Some("width"), Some(width)) => Action::Width(size(width)?),
+      (Some("dash"), Some(dash)) => {
+        Action::Dash(Dash::from_str(dash).map_err(|()| anyhow!("Unknown dash: {dash}"))?)
+      }
+      (Some("radius"), Some(radius)) => Action::Radius(size(radius)?),
+      (Some("marker"), Some(marker)) => {
+        Action::Marker(Marker::from_str(marker).map_err(|()| anyhow!("Unknown marker: {marker}"))?)
+      }
+      (Some("direction"), Some(direction)) => Action::Direction(
+        Direction::from_str(direction).map_err(|()| anyhow!("Unknown direction: {direction}"))?,
+      ),
+      (Some("classes"), Some(classification)) => {
+        let colors = words.by_ref().map(color).collect::<Result<Vec<_>>>()?;
+        if colors.len() < 2 {
+          return Err(anyhow!("Classes need at least two colors."));
+        }
+        Action::Classes(classification.parse()?, colors, vec![])
+      }
+      _ => return Err(anyhow!("Unknown action: {action}")),
+    };
+    if words.next().is_some() {
+      return Err(anyhow!("Too many words in {action:?}"));
+    }
+    Ok(action)
+  }
+}
+
+/// A rule like `label ~ "error" => color red` or `speed > 0 => ramp 0..120 green yellow red`
+/// changing the style of the elements of a layer it matches. The condition is `*` for all
+/// elements, a regex match with `~`, or a comparison with a text in quotes or a number. The
+/// actions separated by commas are `color <color>`, `fill <fill>`, `hide`, `width <pixels>`,
+/// `dash <dash>`, `radius <pixels>`, `marker <marker>`, `direction <arrow|ticks>`, and `ramp`,
+/// which picks the color for the value of the condition's field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct StyleRule {
+  source: String,
+  condition: Condition,
+  actions: Vec<Action>,
+}
+
+impl PartialEq for StyleRule {
+  fn eq(&self, other: &Self) -> bool {
+    self.source == other.source
+  }
+}
+
+impl FromStr for StyleRule {
+  type Err = Error;
+  fn from_str(rule: &str) -> Result<Self> {
+    let (condition, actions) = rule.split_once("=>").ok_or(anyhow!(
+      "A rule needs => between condition and style: {rule}"
+    ))?;
+    let condition: Condition = condition.parse()?;
+    let actions = actions
+      .split(',')
+      .map(str::parse)
+      .collect::<Result<Vec<Action>>>()?;
+    if condition.field().is_none()
+      && actions
+        .iter()
+        .any(|a| matches!(a, Action::Ramp(..) | Action::Classes(..)))
+    {
+      return Err(anyhow!("A ramp needs a field in the condition: {rule}"));
+    }
+    Ok(Self {
+      source: rule.trim().to_string(),
+      condition,
+      actions,
+    })
+  }
+}
+
+impl TryFrom<String> for StyleRule {
+  type Error = Error;
+  fn try_from(rule: String) -> Result<Self> {
+    rule.parse()
+  }
+}
+
+impl From<StyleRule> for String {
+  fn from(rule: StyleRule) -> Self {
+    rule.source
+  }
+}
+
+impl StyleRule {
+  /// The colors set by the rule with the condition or the values they stand for.
+  #[must_use]
+  #[allow(clippy::cast_precision_loss)]
+  pub fn legend(&self) -> Vec<(Color, String)> {
+    let field = self.condition.field().unwrap_or_default();
+    let mut entries = vec![];
+    for action in &self.actions {
+      match action {
+        Action::Ramp(min, max, colors) => {
+          let step = (max - min) / (colors.len() - 1) as f32;
+          entries.extend(colors.iter().enumerate().map(|(i, color)| {
+            (
+              *color,
+              format!("{field} {}", format_value(min + step * i as f32)),
+            )
+          }));
+        }
+        Action::Classes(_, colors, bounds) => {
+          entries.extend(colors.iter().zip(bounds.windows(2)).map(|(color, bounds)| {
+            (
+              *color,
+              format!(
+                "{field} {} – {}",
+                format_value(bounds[0]),
+                format_value(bounds[1])
+              ),
+            )
+          }));
+        }
+        Action::Color(color) => {
+          let condition = self.source.split_once("=>").map_or("", |(c, _)| c.trim());
+          entries.push((*color, condition.to_string()));
+        }
+        Action::Fill(_)
+        | Action::Hide
+        | Action::Width(_)
+        | Action::Dash(_)
+        | Action::Radius(_)
+        | Action::Marker(_)
+        | Action::Direction(_) => (),
+      }
+    }
+    entries
+  }
+}
+
+impl Display for StyleRule {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.source)
+  }
+}
+
+/// The style of a feature after applying the matching rules in order, `None` if one hides it.
+#[must_use]
+#[allow(
+  clippy::cast_possible_truncation,
+  clippy::cast_precision_loss,
+  clippy::cast_sign_loss
+)]
+pub fn apply(rules: &[StyleRule], mut style: Style, feature: &Feature) -> Option<Style> {
+  for rule in rules.iter().filter(|r| r.condition.holds(feature)) {
+    for action in &rule.actions {
+      match action {
+        Action::Color(color) => style.color = *color,
+        Action::Fill(fill) => style.fill = *fill,
+        Action::Hide => return None,
+        Action::Width(width) => style.width = Some(*width),
+        Action::Dash(dash) => style.dash = Some(*dash),
+        Action::Radius(radius) => style.radius = Some(*radius),
+        Action::Marker(marker) => style.marker = *marker,
+        Action::Direction(direction) => style.direction = Some(*direction),
+        Action::Ramp(min, max, colors) => {
+          let Some(value) = rule.condition.field().and_then(|f| feature.number(f)) else {
+            continue;
+          };
+          let t = ((value - min) / (max - min)).clamp(0., 1.);
+          style.color = colors[(t * (colors.len() - 1) as f32).round() as usize];
+        }
+        Action::Classes(_, colors, bounds) => {
+          let Some(value) = rule.condition.field().and_then(|f| feature.number(f)) else {
+            continue;
+          };
+          let inner = bounds
+            .get(1..bounds.len().saturating_sub(1))
+            .unwrap_or_default();
+          let class = inner.iter().filter(|&&bound| value >= bound).count();
+          if let Some(color) = colors.get(class) {
+            style.color = *color;
+          }
+        }
+      }
+    }
+  }
+  Some(style)
+}
+
+/// Computes the classes of choropleth rules from the features of their layer matching them.
+pub fn prepare(rules: &mut [StyleRule], features: &[Feature]) {
+  for rule in rules {
+    let Some(field) = rule.condition.field() else {
+      continue;
+    };
+    let values: Vec<f32> = features
+      .iter()
+      .filter(|f| rule.condition.holds(f))
+      .filter_map(|f| f.number(field))
+      .collect();
+    for action in &mut rule.actions {
+      if let Action::Classes(classification, colors, bounds) = action {
+        *bounds = classification.bounds(values.clone(), colors.len());
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn feature(label: &str) -> Feature {
+    Feature {
+      label: Some(label),
+      points: 2,
+      properties: &[],
+    }
+  }
+
+  #[test]
+  fn rules() {
+    let rules: Vec<StyleRule> = [
+      r#"label ~ "error" => color red, fill solid"#,
+      "speed >= 0 => ramp 0..100 green yellow red",
+      r#"kind == "debug" => hide"#,
+    ]
+    .iter()
+    .map(|r| r.parse().unwrap())
+    .collect();
+    let style = Style::default();
+
+    let error = apply(&rules, style, &feature("error at 52.5,13.4")).unwrap();
+    assert_eq!(error.color, Color::Red);
+    assert_eq!(error.fill, FillStyle::Solid);
+    let slow = apply(&rules, style, &feature("vehicle 7 speed=20")).unwrap();
+    assert_eq!(slow.color, Color::Green);
+    let medium = apply(&rules, style, &feature("vehicle 7, speed: 55")).unwrap();
+    assert_eq!(medium.color, Color::Yellow);
+    assert!(apply(&rules, style, &feature("kind=debug")).is_none());
+    assert_eq!(apply(&rules, style, &feature("other")), Some(style));
+    let highway: StyleRule = r#"kind == "highway" => width 6, dash dashed, marker square"#
+      .parse()
+      .unwrap();
+    let wide = apply(&[highway], style, &feature("kind=highway")).unwrap();
+    assert_eq!(wide.width, Some(6.));
+    assert_eq!(wide.dash, Some(Dash { on: 8., off: 6. }));
+    assert_eq!(wide.marker, Marker::Square);
+    assert_eq!(
+      rules[0].legend(),
+      vec![(Color::Red, r#"label ~ "error""#.to_string())]
+    );
+  }
+
+  #[test]
+  fn choropleth() {
+    let labels: Vec<String> = [1, 2, 3, 4, 5, 6, 7, 8, 100]
+      .iter()
+      .map(|p| format!("population={p}"))
+      .collect();
+    let features: Vec<Feature> = labels.iter().map(|l| feature(l)).collect();
+    let mut quantile: Vec<StyleRule> = vec!["population > 0 => classes quantile green yellow red"
+      .parse()
+      .unwrap()];
+    let mut equal: Vec<StyleRule> = vec!["population > 0 => classes equal green yellow red"
+      .parse()
+      .unwrap()];
+    prepare(&mut quantile, &features);
+    prepare(&mut equal, &features);
+    let color =
+      |rules: &[StyleRule], i: usize| apply(rules, Style::default(), &features[i]).unwrap().color;
+    assert_eq!(color(&quantile, 0), Color::Green);
+    assert_eq!(color(&quantile, 4), Color::Yellow);
+    assert_eq!(color(&quantile, 7), Color::Red);
+    assert_eq!(color(&equal, 7), Color::Green);
+    assert_eq!(color(&equal, 8), Color::Red);
+    assert_eq!(
+      quantile[0].legend(),
+      vec![
+        (Color::Green, "population 1 – 4".to_string()),
+        (Color::Yellow, "population 4 – 7".to_string()),
+        (Color::Red, "population 7 – 100".to_string()),
+      ]
+    );
+  }
+
+  #[test]
+  fn invalid_rules() {
+    assert!("label ~ error => color red".parse::<StyleRule>().is_err());
+    assert!("* => ramp 0..10 red green".parse::<StyleRule>().is_err());
+    assert!("points > 2 => color pink".parse::<StyleRule>().is_err());
+    assert!("points > 2 color red".parse::<StyleRule>().is_err());
+    let rule: StyleRule = "points > 2 => hide".parse().unwrap();
+    assert_eq!(
+      serde_json::to_string(&rule).unwrap(),
+      r#""points > 2 => hide""#
+    );
+  }
+}
@@ -0,0 +1,88 @@
+use std::str::FromStr;
+
+use femtovg::Color;
+use serde::{Deserialize, Serialize};
+
+/// The colors of the map window. The dark theme dims the tiles for night-time use.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum Theme {
+  #[default]
+  Light,
+  Dark,
+}
+
+impl Theme {
+  /// The theme given by `MAPVAS_THEME`.
+  #[must_use]
+  pub fn from_env() -> Self {
+    std::env::var("MAPVAS_THEME")
+      .ok()
+      .and_then(|t| Self::from_str(&t).ok())
+      .unwrap_or_default()
+  }
+
+  #[must_use]
+  pub fn toggle(self) -> Self {
+    match self {
+      Self::Light => Self::Dark,
+      Self::Dark => Self::Light,
+    }
+  }
+
+  /// The color of areas without tiles.
+  #[must_use]
+  pub fn background(self) -> Color {
+    match self {
+      Self::Light => Color::rgbf(0.3, 0.3, 0.32),
+      Self::Dark => Color::rgbf(0.08, 0.08, 0.1),
+    }
+  }
+
+  /// Drawn over the tiles to darken them.
+  #[must_use]
+  pub fn tile_dimming(self) -> Option<Color> {
+    match self {
+      Self::Light => None,
+      Self::Dark => Some(Color::rgba(10, 10, 20, 150)),
+    }
+  }
+
+  /// The background of the text bar and the compass.
+  #[must_use]
+  pub fn panel(self) -> Color {
+    match self {
+      Self::Light => Color::rgba(128, 128, 128, 128),
+      Self::Dark => Color::rgba(30, 30, 35, 200),
+    }
+  }
+
+  /// The color of text and the scale bar on the map.
+  #[must_use]
+  pub fn text(self) -> Color {
+    match self {
+      Self::Light => Color::rgb(30, 30, 30),
+      Self::Dark => Color::rgb(230, 230, 230),
+    }
+  }
+
+  /// The outline around text on the map to keep it readable.
+  #[must_use]
+  pub fn halo(self) -> Color {
+    match self {
+      Self::Light => Color::rgba(255, 255, 255, 220),
+      Self::Dark => Color::rgba(20, 20, 25, 220),
+    }
+  }
+}
+
+impl FromStr for Theme {
+  type Err = ();
+  fn from_str(input: &str) -> Result<Theme, Self::Err> {
+    let lowercase = input.to_lowercase();
+    match lowercase.as_str() {
+      "light" => Ok(Theme::Light),
+      "dark" => Ok(Theme::Dark),
+      _ => Err(()),
+    }
+  }
+}
@@ -1,18 +1,25 @@
 use crate::map::coordinates::Tile;
+use crate::map::hillshade::{hillshade, style_key, DemEncoding};
+use crate::map::map_event::CacheKind;
+use crate::map::vector_tile;
 use anyhow::Result;
 use async_std::task::block_on;
 use log::{debug, error, trace};
 use regex::Regex;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
 use std::collections::HashSet;
-use std::fs::File;
+use std::fs::{File, FileTimes};
 use std::hash::{DefaultHasher, Hash, Hasher};
 use std::io::Write;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use surf::http::Method;
 use surf::{Request, Url};
 use surf_governor::GovernorMiddleware;
 use thiserror::Error;
+use tokio::sync::Semaphore;
 
 #[derive(Error, Debug)]
 pub enum TileLoaderError {
@@ -20,11 +27,25 @@ pub enum TileLoaderError {
   TileNotAvailableError { tile: Tile },
   #[error("Download already in progress.")]
   TileDownloadInProgressError { tile: Tile },
+  #[error("Tile server not reachable.")]
+  ServerUnreachableError { tile: Tile },
+  #[error("Unsupported tile format {format} in {path:?}.")]
+  UnsupportedTileFormatError { format: String, path: PathBuf },
+  #[error("Cannot read tiles from {source_url}: {reason}")]
+  SourceUnavailableError { source_url: String, reason: String },
 }
 
 /// The png data of a tile.
 pub type TileData = Vec<u8>;
 
+/// Decodes the png or jpeg data of a tile.
+///
+/// # Errors
+/// If the data is no supported image.
+pub fn decode_tile(data: &[u8]) -> Result<image::RgbaImage> {
+  Ok(image::load_from_memory(data)?.to_rgba8())
+}
+
 /// The interface the cached and non-cached tile loader.
 pub trait TileLoader {
   /// Tries to fetch the tile data asyncroneously.
@@ -36,12 +57,69 @@ pub trait TileLoader {
   }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 struct TileCache {
   base_path: Option<PathBuf>,
+  /// The least recently used tiles are removed when the cache grows beyond this size.
+  max_bytes: Option<u64>,
+  writes: AtomicUsize,
+  hits: AtomicUsize,
+  misses: AtomicUsize,
+}
+
+/// The size and usage of a tile cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+  pub bytes: u64,
+  pub hits: usize,
+  pub misses: usize,
+}
+
+impl CacheStats {
+  /// The share of tiles found in the cache.
+  #[must_use]
+  #[allow(clippy::cast_precision_loss)]
+  pub fn hit_rate(&self) -> f32 {
+    match self.hits + self.misses {
+      0 => 0.,
+      total => self.hits as f32 / total as f32,
+    }
+  }
+}
+
+impl std::fmt::Display for CacheStats {
+  #[allow(clippy::cast_precision_loss)]
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{:.1} MB, {:.0}% hits",
+      self.bytes as f32 / 1024. / 1024.,
+      self.hit_rate() * 100.
+    )
+  }
 }
 
 impl TileCache {
+  /// The number of written tiles after which the size of the cache is checked.
+  const EVICTION_INTERVAL: usize = 100;
+
+  fn new(base_path: Option<PathBuf>, max_bytes: Option<u64>) -> Self {
+    if let Some(ref path) = base_path {
+      if !path.exists() {
+        let _ = std::fs::create_dir_all(path);
+      }
+    }
+    let cache = Self {
+      base_path,
+      max_bytes,
+      writes: AtomicUsize::new(0),
+      hits: AtomicUsize::new(0),
+      misses: AtomicUsize::new(0),
+    };
+    cache.evict();
+    cache
+  }
+
   fn path(&self, tile: &Tile) -> Option<PathBuf> {
     self
       .base_path
@@ -57,6 +135,69 @@ impl TileCache {
     if succ.is_err() {
       debug!("Error when writing file: {}", succ.unwrap_err());
     }
+    if self.writes.fetch_add(1, Ordering::Relaxed) % Self::EVICTION_INTERVAL == 0 {
+      self.evict();
+    }
+  }
+
+  fn files(&self) -> Vec<(SystemTime, u64, PathBuf)> {
+    let Some(Ok(entries)) = self.base_path.as_ref().map(std::fs::read_dir) else {
+      return vec![];
+    };
+    entries
+      .filter_map(Result::ok)
+      .filter_map(|e| {
+        let metadata = e.metadata().ok()?;
+        metadata
+          .is_file()
+          .then_some((metadata.accessed().ok()?, metadata.len(), e.path()))
+      })
+      .collect()
+  }
+
+  /// The time since the tile was downloaded.
+  fn age(&self, tile: &Tile) -> Option<Duration> {
+    let modified = std::fs::metadata(self.path(tile)?).ok()?.modified().ok()?;
+    modified.elapsed().ok()
+  }
+
+  fn stats(&self) -> CacheStats {
+    CacheStats {
+      bytes: self.files().iter().map(|(_, len, _)| len).sum(),
+      hits: self.hits.load(Ordering::Relaxed),
+      misses: self.misses.load(Ordering::Relaxed),
+    }
+  }
+
+  /// Removes all cached tiles.
+  fn clear(&self) {
+    for (_, _, path) in self.files() {
+      let _ = std::fs::remove_file(path);
+    }
+    self.hits.store(0, Ordering::Relaxed);
+    self.misses.store(0, Ordering::Relaxed);
+  }
+
+  /// Removes the least recently used tiles until the cache is below 90% of its size limit.
+  fn evict(&self) {
+    let (Some(base_path), Some(max_bytes)) = (&self.base_path, self.max_bytes) else {
+      return;
+    };
+    let mut files = self.files();
+    let mut size: u64 = files.iter().map(|(_, len, _)| len).sum();
+    if size <= max_bytes {
+      return;
+    }
+    files.sort_by_key(|(accessed, _, _)| *accessed);
+    for (_, len, path) in files {
+      if size <= max_bytes / 10 * 9 {
+        break;
+      }
+      if std::fs::remove_file(&path).is_ok() {
+        size -= len;
+      }
+    }
+    debug!("Evicted tiles from {base_path:?}, {size} bytes left.");
   }
 }
 
@@ -65,8 +206,18 @@ impl TileLoader for TileCache {
     match self.path(tile) {
       Some(p) => {
         if p.exists() {
+          self.hits.fetch_add(1, Ordering::Relaxed);
+          if self.max_bytes.is_some() {
+            // The access time marks recently used tiles for the eviction.
+            // The modification time is kept as time of the download.
+            let _ = File::options()
+              .write(true)
+              .open(&p)
+              .and_then(|f| f.set_times(FileTimes::new().set_accessed(SystemTime::now())));
+          }
           Ok(std::fs::read(p)?)
         } else {
+          self.misses.fetch_add(1, Ordering::Relaxed);
           Err(TileLoaderError::TileNotAvailableError { tile: *tile }.into())
         }
       }
@@ -76,31 +227,229 @@ impl TileLoader for TileCache {
   }
 }
 
+/// Half the circumference of the earth in the spherical mercator projection EPSG:3857.
+const MERCATOR_EXTENT: f64 = 20_037_508.342_789_244;
+
+/// The bounding box of a tile in EPSG:3857 as `minx,miny,maxx,maxy` as used by WMS.
+fn mercator_bbox(tile: &Tile) -> String {
+  let size = 2. * MERCATOR_EXTENT / f64::from(1u32 << tile.zoom);
+  let min_x = -MERCATOR_EXTENT + f64::from(tile.x) * size;
+  let max_y = MERCATOR_EXTENT - f64::from(tile.y) * size;
+  format!("{},{},{},{}", min_x, max_y - size, min_x + size, max_y)
+}
+
+/// Replaces the placeholders of a tile url or path by the values of the tile.
+fn fill_template(template: &str, tile: &Tile) -> String {
+  template
+    .replace("{x}", &tile.x.to_string())
+    .replace("{y}", &tile.y.to_string())
+    .replace("{-y}", &tms_y(tile).to_string())
+    .replace("{zoom}", &tile.zoom.to_string())
+    .replace("{z}", &tile.zoom.to_string())
+    .replace("{quadkey}", &quadkey(tile))
+    .replace("{bbox}", &mercator_bbox(tile))
+}
+
+/// The row of a tile counted from the south as used by TMS servers.
+fn tms_y(tile: &Tile) -> u32 {
+  (1u32 << tile.zoom) - 1 - tile.y
+}
+
+/// The quadkey of a tile as used by Bing maps, one digit per zoom level.
+fn quadkey(tile: &Tile) -> String {
+  (1..=tile.zoom)
+    .rev()
+    .map(|level| {
+      let mask = 1 << (level - 1);
+      let digit = u8::from(tile.x & mask != 0) + 2 * u8::from(tile.y & mask != 0);
+      char::from(b'0' + digit)
+    })
+    .collect()
+}
+
+/// Turns the url of a WMS service into a `GetMap` url template for tiles.
+/// The url needs `SERVICE=WMS` and `LAYERS`. Missing parameters like `FORMAT` or `STYLES` get
+/// defaults. Returns `None` for urls that are no WMS or already contain `{bbox}`.
+fn wms_template(url: &str) -> Option<String> {
+  let lowercase = url.to_lowercase();
+  if !lowercase.contains("service=wms") || url.contains("{bbox}") {
+    return None;
+  }
+  let has = |parameter: &str| {
+    lowercase.contains(&format!("?{parameter}=")) || lowercase.contains(&format!("&{parameter}="))
+  };
+  // WMS 1.1 calls the coordinate reference system SRS.
+  let crs = if lowercase.contains("version=1.1") {
+    "srs"
+  } else {
+    "crs"
+  };
+  let mut template = url.to_string();
+  for (parameter, value) in [
+    ("request", "GetMap"),
+    ("version", "1.3.0"),
+    (crs, "EPSG:3857"),
+    ("styles", ""),
+    ("format", "image/png"),
+    ("transparent", "true"),
+    ("width", "512"),
+    ("height", "512"),
+  ] {
+    if !has(parameter) {
+      template.push_str(&format!("&{}={value}", parameter.to_uppercase()));
+    }
+  }
+  template.push_str("&BBOX={bbox}");
+  Some(template)
+}
+
 #[derive(Debug)]
 struct TileDownloader {
   url_template: String,
+  /// The url template with resolved keys.
+  url: String,
   tiles_in_download: Arc<Mutex<HashSet<Tile>>>,
   client: surf::Client,
+  /// Limits the parallel requests to the tile server, see `MAPVAS_TILE_CONCURRENCY`.
+  permits: Semaphore,
+  /// Sent with each request, e.g. `Authorization`.
+  headers: Vec<(String, String)>,
 }
 
+/// A tile url with the settings of its layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileLayerConfig {
+  pub url_template: String,
+  pub opacity: f32,
+  pub headers: Vec<(String, String)>,
+}
+
+/// The url templates of `MAPVAS_TILE_URL` separated by `|` with their opacity from
+/// `MAPVAS_TILE_OPACITY`, e.g. `1,0.5`, and headers from `MAPVAS_TILE_HEADERS`, e.g.
+/// `Authorization: Bearer {key}|`. The first one is the base map, the others are drawn on top.
+#[must_use]
+pub fn tile_layers_from_env() -> Vec<TileLayerConfig> {
+  let templates = std::env::var("MAPVAS_TILE_URL").unwrap_or(String::from(DEFAULT_TILE_URL));
+  let opacities = std::env::var("MAPVAS_TILE_OPACITY").unwrap_or_default();
+  let mut opacities = opacities.split(',').map(|o| o.trim().parse::<f32>().ok());
+  let headers = std::env::var("MAPVAS_TILE_HEADERS").unwrap_or_default();
+  let mut headers = headers.split('|').map(parse_headers);
+  templates
+    .split('|')
+    .map(str::trim)
+    .filter(|t| !t.is_empty())
+    .map(|t| TileLayerConfig {
+      url_template: t.to_string(),
+      opacity: opacities.next().flatten().unwrap_or(1.).clamp(0., 1.),
+      headers: headers.next().unwrap_or_default(),
+    })
+    .collect()
+}
+
+/// Parses headers of the form `Name: value; Other-Name: value`.
+fn parse_headers(headers: &str) -> Vec<(String, String)> {
+  headers
+    .split(';')
+    .filter_map(|h| h.split_once(':'))
+    .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+    .filter(|(name, _)| !name.is_empty())
+    .collect()
+}
+
+/// Replaces `{key}` by the value of `MAPVAS_TILE_KEY` and `{key:NAME}` by the value of the
+/// environment variable `NAME`, so api keys do not have to be part of the tile url.
+fn resolve_keys(template: &str) -> String {
+  resolve_keys_with(template, |variable| std::env::var(variable).ok())
+}
+
+/// Replaces the keys like [`resolve_keys`] with the values `var` gives for the variables.
+fn resolve_keys_with(template: &str, var: impl Fn(&str) -> Option<String>) -> String {
+  let key_re = Regex::new(r"\{key(?::([A-Za-z0-9_]+))?\}").expect("re did not compile");
+  key_re
+    .replace_all(template, |captures: &regex::Captures| {
+      let variable = captures
+        .get(1)
+        .map_or("MAPVAS_TILE_KEY", |name| name.as_str());
+      var(variable).unwrap_or_else(|| {
+        error!("The environment variable {variable} for the tile key is not set.");
+        String::new()
+      })
+    })
+    .to_string()
+}
+
+const DEFAULT_TILE_URL: &str = "https://tile.openstreetmap.org/{zoom}/{x}/{y}.png";
+
 impl TileDownloader {
-  pub fn from_env() -> Self {
-    let url_template = std::env::var("MAPVAS_TILE_URL").unwrap_or(String::from(
-      "https://tile.openstreetmap.org/{zoom}/{x}/{y}.png",
-    ));
+  /// Retries of requests the server answered with 429 or a server error.
+  const MAX_RETRIES: u32 = 3;
+
+  fn new(url_template: &str) -> Self {
+    let url_template = wms_template(url_template).unwrap_or(url_template.to_string());
+    let concurrency = std::env::var("MAPVAS_TILE_CONCURRENCY")
+      .ok()
+      .and_then(|c| c.parse::<usize>().ok())
+      .unwrap_or(6)
+      .max(1);
     Self {
+      url: resolve_keys(&url_template),
       url_template,
       tiles_in_download: Arc::default(),
       client: surf::Client::new().with(GovernorMiddleware::per_second(50).unwrap()),
+      permits: Semaphore::new(concurrency),
+      headers: vec![],
     }
   }
 
+  /// Requests the url and retries with exponential backoff while the server is overloaded.
+  async fn fetch(&self, url: &str, tile: &Tile) -> Result<Vec<u8>, TileLoaderError> {
+    let not_available = || TileLoaderError::TileNotAvailableError { tile: *tile };
+    let _permit = self.permits.acquire().await.map_err(|_| not_available())?;
+    let url = Url::parse(url).map_err(|_| not_available())?;
+    let mut delay = Duration::from_millis(500);
+    for attempt in 0..=Self::MAX_RETRIES {
+      let mut request = Request::new(Method::Get, url.clone());
+      for (name, value) in &self.headers {
+        request.insert_header(name.as_str(), value.as_str());
+      }
+      match self.client.send(request).await {
+        Ok(mut response) if response.status() == 200 => {
+          return response.body_bytes().await.map_err(|_| not_available());
+        }
+        Ok(response)
+          if attempt < Self::MAX_RETRIES
+            && (response.status() == 429 || response.status().is_server_error()) =>
+        {
+          let retry_after = response
+            .header("Retry-After")
+            .and_then(|v| v.as_str().parse::<u64>().ok())
+            .map(|s| Duration::from_secs(s.min(30)));
+          let wait = retry_after.unwrap_or(delay);
+          debug!(
+            "{url} returned {}, retrying in {wait:?}.",
+            response.status()
+          );
+          async_std::task::sleep(wait).await;
+          delay *= 2;
+        }
+        Ok(response) => {
+          debug!("{url} returned {}.", response.status());
+          if response.status() == 429 || response.status().is_server_error() {
+            return Err(TileLoaderError::ServerUnreachableError { tile: *tile });
+          }
+          break;
+        }
+        Err(e) => {
+          debug!("{url} failed: {e}");
+          return Err(TileLoaderError::ServerUnreachableError { tile: *tile });
+        }
+      }
+    }
+    Err(not_available())
+  }
+
   fn get_path_for_tile(&self, tile: &Tile) -> String {
-    self
-      .url_template
-      .replace("{x}", &tile.x.to_string())
-      .replace("{y}", &tile.y.to_string())
-      .replace("{zoom}", &tile.zoom.to_string())
+    fill_template(&self.url, tile)
   }
 }
 
@@ -117,25 +466,7 @@ impl TileLoader for TileDownloader {
 
     let url = self.get_path_for_tile(tile);
     debug!("Downloading {}.", url);
-    let request = Request::new(Method::Get, Url::parse(&url).unwrap());
-    let result = self
-      .client
-      .send(request)
-      .await
-      .map_err(|_| TileLoaderError::TileNotAvailableError { tile: *tile });
-    let result = if let Ok(mut result) = result {
-      if result.status() == 200 {
-        result
-          .body_bytes()
-          .await
-          .map_err(|_| TileLoaderError::TileNotAvailableError { tile: *tile })
-      } else {
-        Err(TileLoaderError::TileNotAvailableError { tile: *tile })
-      }
-    } else {
-      debug!("{result:?}");
-      Err(TileLoaderError::TileNotAvailableError { tile: *tile })
-    };
+    let result = self.fetch(&url, tile).await;
 
     let mut tiles_in_download = self.tiles_in_download.lock().unwrap();
     tiles_in_download.remove(tile);
@@ -144,10 +475,199 @@ impl TileLoader for TileDownloader {
   }
 }
 
+/// Serves tiles from a local mbtiles (sqlite) file, e.g. for offline usage.
+/// The file stores the rows in TMS order, so the y coordinate is flipped.
+#[derive(Debug)]
+struct MBTilesLoader {
+  path: PathBuf,
+  /// Shared with the blocking tasks that run the queries.
+  connection: Arc<Mutex<Connection>>,
+  /// If the file contains vector tiles, which are rendered before they are shown.
+  vector: bool,
+}
+
+impl MBTilesLoader {
+  const URL_PREFIX: &'static str = "mbtiles://";
+
+  fn open(path: PathBuf) -> Result<Self> {
+    let connection = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let format: Option<String> = connection
+      .query_row(
+        "SELECT value FROM metadata WHERE name = 'format'",
+        [],
+        |row| row.get(0),
+      )
+      .optional()?;
+    match format.as_deref() {
+      None | Some("png" | "jpg" | "jpeg" | "webp" | "pbf" | "mvt") => Ok(Self {
+        path,
+        connection: Arc::new(Mutex::new(connection)),
+        vector: matches!(format.as_deref(), Some("pbf" | "mvt")),
+      }),
+      Some(format) => Err(
+        TileLoaderError::UnsupportedTileFormatError {
+          format: format.to_string(),
+          path,
+        }
+        .into(),
+      ),
+    }
+  }
+
+  fn tms_row(tile: &Tile) -> u32 {
+    (1 << tile.zoom) - 1 - tile.y
+  }
+}
+
+impl TileLoader for MBTilesLoader {
+  async fn tile_data(&self, tile: &Tile) -> Result<TileData> {
+    let connection = self.connection.clone();
+    let row = (tile.zoom, tile.x, Self::tms_row(tile));
+    let data: Option<TileData> = tokio::task::spawn_blocking(move || {
+      connection
+        .lock()
+        .unwrap()
+        .query_row(
+          "SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 \
+           AND tile_row = ?3",
+          row,
+          |row| row.get(0),
+        )
+        .optional()
+    })
+    .await??;
+    trace!("Read tile {tile:?} from {:?}", self.path);
+    data.ok_or(TileLoaderError::TileNotAvailableError { tile: *tile }.into())
+  }
+}
+
+/// Serves tiles from a directory tree of tile images, e.g. downloaded before for offline usage.
+#[derive(Debug)]
+struct DirectoryLoader {
+  /// A path with the placeholders of tile urls, e.g. `/data/tiles/{zoom}/{x}/{y}.png`.
+  path_template: String,
+}
+
+impl DirectoryLoader {
+  const URL_PREFIX: &'static str = "file://";
+
+  fn open(path_template: &str) -> Result<Self> {
+    let extension = std::path::Path::new(path_template)
+      .extension()
+      .map(|e| e.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+      None | Some("png" | "jpg" | "jpeg" | "webp") => Ok(Self {
+        path_template: path_template.to_string(),
+      }),
+      Some(format) => Err(
+        TileLoaderError::UnsupportedTileFormatError {
+          format: format.to_string(),
+          path: PathBuf::from(path_template),
+        }
+        .into(),
+      ),
+    }
+  }
+}
+
+impl TileLoader for DirectoryLoader {
+  async fn tile_data(&self, tile: &Tile) -> Result<TileData> {
+    let path = fill_template(&self.path_template, tile);
+    trace!("Read tile {tile:?} from {path}");
+    std::fs::read(&path).map_err(|_| TileLoaderError::TileNotAvailableError { tile: *tile }.into())
+  }
+}
+
+/// The origin of tiles that are not in the cache.
+#[derive(Debug)]
+enum TileSource {
+  Download(TileDownloader),
+  MBTiles(MBTilesLoader),
+  Directory(DirectoryLoader),
+  /// A local source that cannot be read. Its url is not downloaded instead.
+  Unavailable {
+    url_template: String,
+    reason: String,
+  },
+}
+
+impl TileSource {
+  fn new(url_template: &str) -> Self {
+    let downloader = TileDownloader::new(url_template);
+    let local = if let Some(path) = downloader
+      .url_template
+      .strip_prefix(MBTilesLoader::URL_PREFIX)
+    {
+      MBTilesLoader::open(PathBuf::from(path)).map(Self::MBTiles)
+    } else if let Some(path) = downloader
+      .url_template
+      .strip_prefix(DirectoryLoader::URL_PREFIX)
+    {
+      DirectoryLoader::open(path).map(Self::Directory)
+    } else {
+      return Self::Download(downloader);
+    };
+    local.unwrap_or_else(|e| {
+      error!("Cannot read tiles from {}: {e}", downloader.url_template);
+      Self::Unavailable {
+        url_template: downloader.url_template,
+        reason: e.to_string(),
+      }
+    })
+  }
+
+  fn is_local(&self) -> bool {
+    !matches!(self, Self::Download(_))
+  }
+
+  /// If the tiles are vector tiles, which are rendered before they are shown.
+  fn is_vector(&self) -> bool {
+    matches!(self, Self::MBTiles(m) if m.vector)
+  }
+
+  fn url_template(&self) -> String {
+    match self {
+      Self::Download(d) => d.url_template.clone(),
+      Self::MBTiles(m) => format!("{}{}", MBTilesLoader::URL_PREFIX, m.path.display()),
+      Self::Directory(d) => format!("{}{}", DirectoryLoader::URL_PREFIX, d.path_template),
+      Self::Unavailable { url_template, .. } => url_template.clone(),
+    }
+  }
+}
+
+impl TileLoader for TileSource {
+  async fn tile_data(&self, tile: &Tile) -> Result<TileData> {
+    match self {
+      Self::Download(d) => d.tile_data(tile).await,
+      Self::MBTiles(m) => m.tile_data(tile).await,
+      Self::Directory(d) => d.tile_data(tile).await,
+      Self::Unavailable {
+        url_template,
+        reason,
+      } => Err(
+        TileLoaderError::SourceUnavailableError {
+          source_url: url_template.clone(),
+          reason: reason.clone(),
+        }
+        .into(),
+      ),
+    }
+  }
+}
+
 #[derive(Debug)]
 pub struct CachedTileLoader {
   tile_cache: TileCache,
-  tile_loader: TileDownloader,
+  tile_loader: TileSource,
+  /// Terrain tiles are turned into hillshading.
+  dem: Option<DemEncoding>,
+  /// The hillshading computed from terrain tiles and the images drawn from vector tiles.
+  rendered_cache: TileCache,
+  /// Cached tiles older than this are downloaded again, see `TILECACHE_MAX_AGE_DAYS`.
+  max_age: Option<Duration>,
+  /// If the server could not be reached by the last download, e.g. because there is no network.
+  /// Tiles the server does not have do not count.
+  download_failed: AtomicBool,
 }
 
 impl CachedTileLoader {
@@ -158,47 +678,164 @@ impl CachedTileLoader {
   async fn download(&self, tile: &Tile) -> Result<TileData> {
     match self.tile_loader.tile_data(tile).await {
       Ok(data) => {
+        self.download_failed.store(false, Ordering::Relaxed);
         self.tile_cache.cache_tile(tile, &data);
+        // Small images are placeholders, but vector tiles of e.g. the open sea are small.
         match data.len() {
-          0..=100 => Err(TileLoaderError::TileNotAvailableError { tile: *tile }.into()),
+          0..=100 if !self.tile_loader.is_vector() => {
+            Err(TileLoaderError::TileNotAvailableError { tile: *tile }.into())
+          }
           _ => Ok(data),
         }
       }
-      Err(e) => Err(e),
+      Err(e) => {
+        if matches!(
+          e.downcast_ref::<TileLoaderError>(),
+          Some(TileLoaderError::ServerUnreachableError { .. })
+        ) {
+          self.download_failed.store(true, Ordering::Relaxed);
+        }
+        Err(e)
+      }
     }
   }
+
+  /// Sends the headers with each download. `{key}` in the values is replaced like in the url.
+  #[must_use]
+  pub fn with_headers(mut self, headers: &[(String, String)]) -> Self {
+    if let TileSource::Download(downloader) = &mut self.tile_loader {
+      downloader.headers = headers
+        .iter()
+        .map(|(name, value)| (name.clone(), resolve_keys(value)))
+        .collect();
+    }
+    self
+  }
+
+  /// If the loaded tiles are turned into other images before they are shown.
+  fn renders(&self) -> bool {
+    self.dem.is_some() || self.tile_loader.is_vector()
+  }
+
+  /// Turns terrain tiles into hillshading and draws vector tiles.
+  fn render(&self, tile: &Tile, data: TileData) -> Result<TileData> {
+    let rendered = match self.dem {
+      Some(encoding) => hillshade(&data, encoding, tile)?,
+      None if self.tile_loader.is_vector() => vector_tile::render(&data)?,
+      None => return Ok(data),
+    };
+    self.rendered_cache.cache_tile(tile, &rendered);
+    Ok(rendered)
+  }
+
+  /// If the cached tile is older than `TILECACHE_MAX_AGE_DAYS`.
+  #[must_use]
+  pub fn is_stale(&self, tile: &Tile) -> bool {
+    self
+      .max_age
+      .is_some_and(|max_age| self.tile_cache.age(tile).is_some_and(|age| age > max_age))
+  }
+
+  /// Downloads the tile again, e.g. if it is stale.
+  ///
+  /// # Errors
+  /// If the download fails.
+  pub async fn refresh(&self, tile: &Tile) -> Result<TileData> {
+    let data = self.download(tile).await?;
+    self.render(tile, data)
+  }
+
+  /// If tiles cannot be downloaded, so only cached tiles are shown.
+  #[must_use]
+  pub fn is_offline(&self) -> bool {
+    self.download_failed.load(Ordering::Relaxed)
+  }
 }
 
 impl Default for CachedTileLoader {
+  /// The loader of the base map.
   fn default() -> CachedTileLoader {
+    match tile_layers_from_env().into_iter().next() {
+      Some(config) => CachedTileLoader::new(&config.url_template).with_headers(&config.headers),
+      None => CachedTileLoader::new(DEFAULT_TILE_URL),
+    }
+  }
+}
+
+impl CachedTileLoader {
+  /// Creates a loader for the url template. Downloaded tiles are cached in `TILECACHE`.
+  /// Templates prefixed with `terrarium+` or `terrainrgb+` load terrain tiles as hillshading.
+  #[must_use]
+  pub fn new(url_template: &str) -> CachedTileLoader {
+    let (dem, url_template) = DemEncoding::split_prefix(url_template);
     let base_path = match std::env::var("TILECACHE") {
       Ok(path) => Some(PathBuf::from(path)),
       Err(_) => None,
     };
 
-    let tile_loader = TileDownloader::from_env();
-    let cache_path = base_path.map(|mut p| {
+    let tile_loader = TileSource::new(url_template);
+    let source_key = {
       let key_re = Regex::new("[Kk]ey=([A-Za-z0-9-_]*)").expect("re did not compile");
-      let res = key_re.replace(&tile_loader.url_template, "*");
+      let url_template = tile_loader.url_template();
+      let res = key_re.replace(&url_template, "*");
       let mut hasher = DefaultHasher::new();
       res.hash(&mut hasher);
-      p.push(hasher.finish().to_string());
-      p
-    });
-
-    if let Some(ref cache_path) = cache_path {
-      if !cache_path.exists() {
-        let _ = std::fs::create_dir_all(cache_path);
-      }
-    }
-
-    let tile_cache = TileCache {
-      base_path: cache_path,
+      hasher.finish().to_string()
+    };
+    let style = match dem {
+      Some(encoding) => Some(style_key(encoding)),
+      None => tile_loader
+        .is_vector()
+        .then(|| vector_tile::STYLE_KEY.to_string()),
     };
+    let rendered_path = style
+      .zip(base_path.clone())
+      .map(|(style, p)| p.join(&source_key).join(style));
+    // Local sources do not need to be copied into the cache.
+    let cache_path = base_path
+      .filter(|_| !tile_loader.is_local())
+      .map(|p| p.join(&source_key));
 
     CachedTileLoader {
-      tile_cache,
+      tile_cache: TileCache::new(cache_path, cache_size("TILECACHE_MAX_MB")),
       tile_loader,
+      dem,
+      rendered_cache: TileCache::new(
+        rendered_path,
+        cache_size("MAPVAS_RENDERED_CACHE_MB").or(Some(256 * 1024 * 1024)),
+      ),
+      max_age: std::env::var("TILECACHE_MAX_AGE_DAYS")
+        .ok()
+        .and_then(|d| d.parse::<u64>().ok())
+        .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+      download_failed: AtomicBool::new(false),
+    }
+  }
+}
+
+/// A cache size limit in bytes from an environment variable in megabytes.
+fn cache_size(variable: &str) -> Option<u64> {
+  std::env::var(variable)
+    .ok()
+    .and_then(|mb| mb.parse::<u64>().ok())
+    .map(|mb| mb * 1024 * 1024)
+}
+
+impl CachedTileLoader {
+  /// The size and hit rate of the cache of downloaded and of rendered tiles.
+  #[must_use]
+  pub fn cache_stats(&self, kind: CacheKind) -> CacheStats {
+    match kind {
+      CacheKind::Tiles => self.tile_cache.stats(),
+      CacheKind::Rendered => self.rendered_cache.stats(),
+    }
+  }
+
+  /// Removes the tiles of the cache from disk.
+  pub fn clear_cache(&self, kind: CacheKind) {
+    match kind {
+      CacheKind::Tiles => self.tile_cache.clear(),
+      CacheKind::Rendered => self.rendered_cache.clear(),
     }
   }
 }
@@ -206,10 +843,16 @@ impl Default for CachedTileLoader {
 impl TileLoader for CachedTileLoader {
   async fn tile_data(&self, tile: &Tile) -> Result<TileData> {
     trace!("Loading tile from file {:?}", &tile);
-    match self.get_from_cache(tile).await {
-      Ok(data) => Ok(data),
-      Err(_) => self.download(tile).await,
+    if self.renders() {
+      if let Ok(data) = self.rendered_cache.tile_data(tile).await {
+        return Ok(data);
+      }
     }
+    let data = match self.get_from_cache(tile).await {
+      Ok(data) => data,
+      Err(_) => self.download(tile).await?,
+    };
+    self.render(tile, data)
   }
 }
 
@@ -217,6 +860,214 @@ impl TileLoader for CachedTileLoader {
 mod tests {
   use super::*;
 
+  #[test]
+  fn tms_row_is_flipped() {
+    assert_eq!(
+      MBTilesLoader::tms_row(&Tile {
+        x: 0,
+        y: 0,
+        zoom: 0
+      }),
+      0
+    );
+    assert_eq!(
+      MBTilesLoader::tms_row(&Tile {
+        x: 3,
+        y: 1,
+        zoom: 2
+      }),
+      2
+    );
+    assert_eq!(
+      MBTilesLoader::tms_row(&Tile {
+        x: 70419,
+        y: 42984,
+        zoom: 17
+      }),
+      88087
+    );
+  }
+
+  #[test]
+  fn wms() {
+    assert_eq!(
+      mercator_bbox(&Tile {
+        x: 1,
+        y: 0,
+        zoom: 1
+      }),
+      "0,0,20037508.342789244,20037508.342789244"
+    );
+    assert_eq!(
+      wms_template("https://example.com/wms?SERVICE=WMS&LAYERS=roads&format=image/jpeg").unwrap(),
+      "https://example.com/wms?SERVICE=WMS&LAYERS=roads&format=image/jpeg&REQUEST=GetMap\
+       &VERSION=1.3.0&CRS=EPSG:3857&STYLES=&TRANSPARENT=true&WIDTH=512&HEIGHT=512&BBOX={bbox}"
+    );
+    assert!(wms_template("https://tile.openstreetmap.org/{zoom}/{x}/{y}.png").is_none());
+  }
+
+  #[test]
+  fn url_schemes() {
+    let tile = Tile {
+      x: 3,
+      y: 5,
+      zoom: 3,
+    };
+    assert_eq!(quadkey(&tile), "213");
+    assert_eq!(
+      quadkey(&Tile {
+        x: 0,
+        y: 0,
+        zoom: 0
+      }),
+      ""
+    );
+    assert_eq!(tms_y(&tile), 2);
+    let downloader = TileDownloader::new("https://example.com/{quadkey}.png?z={z}&row={-y}");
+    assert_eq!(
+      downloader.get_path_for_tile(&tile),
+      "https://example.com/213.png?z=3&row=2"
+    );
+  }
+
+  #[test]
+  fn directory_tiles() {
+    let dir = std::env::temp_dir().join(format!("mapvas_tiles_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("3/1")).unwrap();
+    std::fs::write(dir.join("3/1/2.png"), [1, 2, 3]).unwrap();
+    let source = TileSource::new(&format!(
+      "file://{}/{{zoom}}/{{x}}/{{y}}.png",
+      dir.display()
+    ));
+    assert!(source.is_local());
+    let tile = |y| Tile { x: 1, y, zoom: 3 };
+    assert_eq!(source.tile_data_blocking(&tile(2)).unwrap(), vec![1, 2, 3]);
+    assert!(source.tile_data_blocking(&tile(3)).is_err());
+    assert!(DirectoryLoader::open("/tiles/{zoom}/{x}/{y}.mvt").is_err());
+    let _ = std::fs::remove_dir_all(dir);
+  }
+
+  #[tokio::test]
+  async fn mbtiles() {
+    let path = std::env::temp_dir().join(format!("mapvas_{}.mbtiles", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let connection = Connection::open(&path).unwrap();
+    connection
+      .execute_batch(
+        "CREATE TABLE metadata (name TEXT, value TEXT);
+         INSERT INTO metadata VALUES ('format', 'pbf');
+         CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER,
+           tile_data BLOB);
+         INSERT INTO tiles VALUES (2, 3, 2, x'010203');",
+      )
+      .unwrap();
+    drop(connection);
+
+    let source = TileSource::new(&format!("mbtiles://{}", path.display()));
+    assert!(source.is_local());
+    assert!(source.is_vector());
+    let tile = |y| Tile { x: 3, y, zoom: 2 };
+    assert_eq!(source.tile_data(&tile(1)).await.unwrap(), vec![1, 2, 3]);
+    assert!(source.tile_data(&tile(2)).await.is_err());
+    let _ = std::fs::remove_file(&path);
+
+    // A missing file is not downloaded from its url instead.
+    let missing = TileSource::new("mbtiles:///nonexistent/dir/tiles.mbtiles");
+    assert!(missing.is_local());
+    assert!(matches!(
+      missing
+        .tile_data(&tile(1))
+        .await
+        .unwrap_err()
+        .downcast_ref::<TileLoaderError>(),
+      Some(TileLoaderError::SourceUnavailableError { .. })
+    ));
+  }
+
+  /// A tile server that drops the first connection, then answers tiles of zoom level 1 with 404.
+  fn tile_server() -> u16 {
+    use std::io::Read;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+      for (i, stream) in listener.incoming().enumerate() {
+        let Ok(mut stream) = stream else {
+          continue;
+        };
+        if i == 0 {
+          continue;
+        }
+        let mut request = [0; 1024];
+        let length = stream.read(&mut request).unwrap_or(0);
+        let response = if request[..length].starts_with(b"GET /1/") {
+          "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        } else {
+          format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: 200\r\nConnection: close\r\n\r\n{}",
+            "x".repeat(200)
+          )
+        };
+        let _ = stream.write_all(response.as_bytes());
+      }
+    });
+    port
+  }
+
+  #[test]
+  fn offline() {
+    let port = tile_server();
+    let loader = CachedTileLoader::new(&format!("http://127.0.0.1:{port}/{{zoom}}/{{x}}/{{y}}"));
+    let tile = |zoom| Tile { x: 0, y: 0, zoom };
+    assert!(loader.tile_data_blocking(&tile(2)).is_err());
+    assert!(loader.is_offline());
+    // Missing tiles do not tell whether the server is reachable.
+    assert!(loader.tile_data_blocking(&tile(1)).is_err());
+    assert!(loader.is_offline());
+    assert!(loader.tile_data_blocking(&tile(3)).is_ok());
+    assert!(!loader.is_offline());
+    assert!(loader.tile_data_blocking(&tile(1)).is_err());
+    assert!(!loader.is_offline());
+  }
+
+  #[test]
+  fn headers_and_keys() {
+    assert_eq!(
+      parse_headers("Authorization: Bearer abc; Referer: https://example.com"),
+      vec![
+        ("Authorization".to_string(), "Bearer abc".to_string()),
+        ("Referer".to_string(), "https://example.com".to_string())
+      ]
+    );
+    assert!(parse_headers("").is_empty());
+    let var = |name: &str| (name == "THUNDERFOREST_KEY").then(|| "secret".to_string());
+    assert_eq!(
+      resolve_keys_with(
+        "https://example.com/{zoom}/{x}/{y}.png?apikey={key:THUNDERFOREST_KEY}",
+        var
+      ),
+      "https://example.com/{zoom}/{x}/{y}.png?apikey=secret"
+    );
+    assert_eq!(resolve_keys_with("?key={key}", var), "?key=");
+  }
+
+  #[test]
+  fn cache_evicts_least_recently_used() {
+    let dir = std::env::temp_dir().join(format!("mapvas_cache_{}", std::process::id()));
+    let cache = TileCache::new(Some(dir.clone()), Some(2500));
+    let tile = |x| Tile { x, y: 0, zoom: 5 };
+    for x in 0..3 {
+      cache.cache_tile(&tile(x), &[0; 1000]);
+      std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert!(block_on(cache.tile_data(&tile(0))).is_ok());
+    cache.evict();
+    assert!(block_on(cache.tile_data(&tile(0))).is_ok());
+    assert!(block_on(cache.tile_data(&tile(1))).is_err());
+    assert!(block_on(cache.tile_data(&tile(2))).is_ok());
+    let _ = std::fs::remove_dir_all(dir);
+  }
+
   #[test]
   fn downloader_test() {
     let downloader = CachedTileLoader::default();
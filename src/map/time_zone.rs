@@ -0,0 +1,75 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, Utc};
+use chrono_tz::Tz;
+
+const FORMAT: &str = "%Y-%m-%d %H:%M:%S %Z";
+
+/// The time zone timestamps are shown in, given by `MAPVAS_TIMEZONE` and changed with
+/// `:set timezone=`. Named zones like `Europe/Berlin` switch to daylight saving time on their own.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum TimeZone {
+  #[default]
+  Utc,
+  /// The time zone of the system.
+  Local,
+  Named(Tz),
+}
+
+impl FromStr for TimeZone {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s.trim().to_lowercase().as_str() {
+      "utc" | "z" => Ok(Self::Utc),
+      "local" => Ok(Self::Local),
+      _ => s
+        .trim()
+        .parse::<Tz>()
+        .map(Self::Named)
+        .map_err(|_| anyhow!("Unknown time zone: {s}")),
+    }
+  }
+}
+
+impl TimeZone {
+  /// The zone from `MAPVAS_TIMEZONE`, UTC if it is not set or unknown.
+  #[must_use]
+  pub fn from_env() -> Self {
+    std::env::var("MAPVAS_TIMEZONE")
+      .ok()
+      .and_then(|zone| zone.parse().ok())
+      .unwrap_or_default()
+  }
+
+  /// Formats milliseconds since the epoch in the zone.
+  #[must_use]
+  pub fn format(self, time: i64) -> String {
+    let Some(utc) = DateTime::<Utc>::from_timestamp_millis(time) else {
+      return String::new();
+    };
+    match self {
+      Self::Utc => utc.format(FORMAT).to_string(),
+      Self::Local => utc.with_timezone(&Local).format(FORMAT).to_string(),
+      Self::Named(zone) => utc.with_timezone(&zone).format(FORMAT).to_string(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn zones() {
+    // 2024-01-15 12:00 and 2024-07-15 12:00 UTC.
+    let (winter, summer) = (1_705_320_000_000, 1_721_044_800_000);
+    assert_eq!(TimeZone::Utc.format(winter), "2024-01-15 12:00:00 UTC");
+    let berlin: TimeZone = "Europe/Berlin".parse().unwrap();
+    assert_eq!(berlin.format(winter), "2024-01-15 13:00:00 CET");
+    assert_eq!(berlin.format(summer), "2024-07-15 14:00:00 CEST");
+    assert_eq!("LOCAL".parse::<TimeZone>().unwrap(), TimeZone::Local);
+    assert!("Mars/Olympus".parse::<TimeZone>().is_err());
+  }
+}
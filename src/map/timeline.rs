@@ -0,0 +1,172 @@
+use std::collections::HashSet;
+
+/// The distinct timestamps of shapes. The time cursor steps from one to the next, so sparse
+/// data does not need many steps.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Timeline {
+  /// Sorted without duplicates.
+  times: Vec<i64>,
+}
+
+impl Timeline {
+  #[must_use]
+  pub fn new(times: impl IntoIterator<Item = i64>) -> Self {
+    let mut times: Vec<i64> = times.into_iter().collect();
+    times.sort_unstable();
+    times.dedup();
+    Self { times }
+  }
+
+  /// The first and last timestamp.
+  #[must_use]
+  pub fn range(&self) -> Option<(i64, i64)> {
+    Some((*self.times.first()?, *self.times.last()?))
+  }
+
+  #[must_use]
+  pub fn times(&self) -> &[i64] {
+    &self.times
+  }
+
+  /// The first timestamp after the time.
+  #[must_use]
+  pub fn next(&self, time: i64) -> Option<i64> {
+    let i = self.times.partition_point(|t| *t <= time);
+    self.times.get(i).copied()
+  }
+
+  /// The last timestamp before the time.
+  #[must_use]
+  pub fn previous(&self, time: i64) -> Option<i64> {
+    let i = self.times.partition_point(|t| *t < time);
+    i.checked_sub(1).map(|i| self.times[i])
+  }
+
+  /// The number of timestamps in each of the bins of equal length dividing the range.
+  #[must_use]
+  #[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss
+  )]
+  pub fn histogram(&self, (first, last): (i64, i64), bins: usize) -> Vec<usize> {
+    let mut histogram = vec![0; bins];
+    let length = (last - first).max(1);
+    for time in self.times.iter().filter(|t| (first..=last).contains(*t)) {
+      let bin = ((time - first) * bins as i64 / length) as usize;
+      if let Some(count) = histogram.get_mut(bin.min(bins.saturating_sub(1))) {
+        *count += 1;
+      }
+    }
+    histogram
+  }
+}
+
+/// The timelines of the layers with timestamps. The time cursor steps through the enabled ones and
+/// only hides shapes of enabled layers.
+#[derive(Debug, Default)]
+pub struct Tracks {
+  tracks: Vec<(String, Timeline)>,
+  disabled: HashSet<String>,
+}
+
+impl Tracks {
+  /// Replaces the timelines, layers stay disabled.
+  pub fn set(&mut self, tracks: Vec<(String, Timeline)>) {
+    self.tracks = tracks;
+  }
+
+  /// Enables or disables the layer and returns if it has a timeline.
+  pub fn toggle(&mut self, layer: &str) -> bool {
+    if !self.tracks.iter().any(|(id, _)| id == layer) {
+      return false;
+    }
+    if !self.disabled.remove(layer) {
+      self.disabled.insert(layer.to_string());
+    }
+    true
+  }
+
+  #[must_use]
+  pub fn is_enabled(&self, layer: &str) -> bool {
+    !self.disabled.contains(layer)
+  }
+
+  /// The timestamps of all enabled layers.
+  #[must_use]
+  pub fn enabled(&self) -> Timeline {
+    Timeline::new(
+      self
+        .tracks
+        .iter()
+        .filter(|(id, _)| self.is_enabled(id))
+        .flat_map(|(_, timeline)| timeline.times().iter().copied()),
+    )
+  }
+
+  /// The first and last timestamp of all layers.
+  #[must_use]
+  pub fn range(&self) -> Option<(i64, i64)> {
+    self
+      .tracks
+      .iter()
+      .filter_map(|(_, timeline)| timeline.range())
+      .reduce(|(a, b), (c, d)| (a.min(c), b.max(d)))
+  }
+
+  /// The layers with their timeline and if they are enabled.
+  pub fn rows(&self) -> impl Iterator<Item = (&str, &Timeline, bool)> {
+    self
+      .tracks
+      .iter()
+      .map(|(id, timeline)| (id.as_str(), timeline, self.is_enabled(id)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn steps() {
+    let timeline = Timeline::new([30, 10, 20, 10]);
+    assert_eq!(timeline.range(), Some((10, 30)));
+    assert_eq!(timeline.next(i64::MIN), Some(10));
+    assert_eq!(timeline.next(10), Some(20));
+    assert_eq!(timeline.next(30), None);
+    assert_eq!(timeline.previous(25), Some(20));
+    assert_eq!(timeline.previous(10), None);
+    assert_eq!(Timeline::default().next(0), None);
+  }
+
+  #[test]
+  fn histogram() {
+    let timeline = Timeline::new([0, 1, 2, 50, 99, 100]);
+    assert_eq!(timeline.histogram((0, 100), 4), vec![3, 0, 1, 2]);
+    assert_eq!(timeline.histogram((0, 200), 2), vec![5, 1]);
+    assert_eq!(Timeline::new([5]).histogram((5, 5), 2), vec![1, 0]);
+    assert_eq!(Timeline::default().histogram((0, 1), 3), vec![0, 0, 0]);
+  }
+
+  #[test]
+  fn layer_tracks() {
+    let mut tracks = Tracks::default();
+    tracks.set(vec![
+      ("gps".to_string(), Timeline::new([10, 30])),
+      ("log".to_string(), Timeline::new([20, 40])),
+    ]);
+    assert_eq!(tracks.range(), Some((10, 40)));
+    assert!(tracks.toggle("log"));
+    assert!(!tracks.toggle("other"));
+    assert!(!tracks.is_enabled("log"));
+    assert_eq!(tracks.enabled().times(), [10, 30]);
+    assert_eq!(tracks.range(), Some((10, 40)));
+    tracks.set(vec![("log".to_string(), Timeline::new([20]))]);
+    assert!(tracks.enabled().range().is_none());
+    assert!(tracks.toggle("log"));
+    assert_eq!(
+      tracks.rows().next(),
+      Some(("log", &Timeline::new([20]), true))
+    );
+  }
+}
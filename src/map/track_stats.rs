@@ -0,0 +1,177 @@
+use std::fmt;
+
+use super::{coordinates::Coordinate, geodesic::distance, map_event::Shape};
+
+/// The summary of a track shown by `:stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackStats {
+  /// The length in kilometers.
+  pub length: f32,
+  /// The time from the first to the last coordinate in milliseconds, if the track has timestamps.
+  pub duration: Option<i64>,
+  /// The fastest speed between two coordinates in km/h.
+  pub max_speed: Option<f32>,
+  /// The summed up climbs and descents in meters, if the track has elevations.
+  pub gain: Option<f32>,
+  pub loss: Option<f32>,
+  /// The south west and north east corner.
+  pub bounds: (Coordinate, Coordinate),
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn hours(milliseconds: i64) -> f32 {
+  milliseconds as f32 / 3_600_000.
+}
+
+impl TrackStats {
+  /// The statistics of the shape, `None` for shapes without coordinates.
+  #[must_use]
+  pub fn of(shape: &Shape) -> Option<Self> {
+    let first = *shape.coordinates.first()?;
+    let bounds = shape
+      .coordinates
+      .iter()
+      .fold((first, first), |(sw, ne), c| {
+        (
+          Coordinate {
+            lat: sw.lat.min(c.lat),
+            lon: sw.lon.min(c.lon),
+          },
+          Coordinate {
+            lat: ne.lat.max(c.lat),
+            lon: ne.lon.max(c.lon),
+          },
+        )
+      });
+    let lengths: Vec<f32> = shape
+      .coordinates
+      .windows(2)
+      .map(|c| distance(c[0], c[1]))
+      .collect();
+
+    let times = (shape.timestamps.len() == shape.coordinates.len()).then_some(&shape.timestamps);
+    let duration = times.and_then(|t| Some(t.last()? - t.first()?));
+    let max_speed = times.and_then(|t| {
+      t.windows(2)
+        .zip(&lengths)
+        .filter(|(t, _)| t[1] > t[0])
+        .map(|(t, length)| length / hours(t[1] - t[0]))
+        .reduce(f32::max)
+    });
+
+    let elevations =
+      (shape.elevations.len() == shape.coordinates.len()).then_some(&shape.elevations);
+    let climbs = |up: bool| {
+      elevations.map(|e| {
+        e.windows(2)
+          .map(|e| if up { e[1] - e[0] } else { e[0] - e[1] })
+          .filter(|d| *d > 0.)
+          .sum()
+      })
+    };
+
+    Some(Self {
+      length: lengths.iter().sum(),
+      duration,
+      max_speed,
+      gain: climbs(true),
+      loss: climbs(false),
+      bounds,
+    })
+  }
+
+  /// The average speed in km/h.
+  #[must_use]
+  pub fn average_speed(&self) -> Option<f32> {
+    self
+      .duration
+      .filter(|d| *d > 0)
+      .map(|d| self.length / hours(d))
+  }
+}
+
+impl fmt::Display for TrackStats {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{:.2} km", self.length)?;
+    if let Some(duration) = self.duration {
+      let seconds = duration / 1000;
+      write!(
+        f,
+        " | {}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+      )?;
+    }
+    if let Some(speed) = self.average_speed() {
+      write!(f, " | avg {speed:.1} km/h")?;
+    }
+    if let Some(speed) = self.max_speed {
+      write!(f, " | max {speed:.1} km/h")?;
+    }
+    if let (Some(gain), Some(loss)) = (self.gain, self.loss) {
+      write!(f, " | +{gain:.0} m -{loss:.0} m")?;
+    }
+    let (sw, ne) = self.bounds;
+    write!(
+      f,
+      " | {:.5},{:.5} - {:.5},{:.5}",
+      sw.lat, sw.lon, ne.lat, ne.lon
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn track() {
+    let shape = Shape::new(vec![
+      Coordinate { lat: 0., lon: 0. },
+      Coordinate { lat: 0., lon: 0.1 },
+      Coordinate { lat: 0.1, lon: 0.1 },
+    ])
+    .with_timestamps(vec![0, 1_800_000, 2_700_000])
+    .with_elevations(vec![100., 150., 120.]);
+    let stats = TrackStats::of(&shape).unwrap();
+    assert!((stats.length - 22.24).abs() < 0.01);
+    assert_eq!(stats.duration, Some(2_700_000));
+    assert!((stats.average_speed().unwrap() - 29.65).abs() < 0.01);
+    assert!((stats.max_speed.unwrap() - 44.48).abs() < 0.01);
+    assert_eq!((stats.gain, stats.loss), (Some(50.), Some(30.)));
+    assert_eq!(
+      stats.to_string(),
+      "22.24 km | 0:45:00 | avg 29.7 km/h | max 44.5 km/h | +50 m -30 m \
+       | 0.00000,0.00000 - 0.10000,0.10000"
+    );
+  }
+
+  #[test]
+  fn plain_line() {
+    let shape = Shape::new(vec![
+      Coordinate {
+        lat: 52.5,
+        lon: 13.4,
+      },
+      Coordinate {
+        lat: 52.4,
+        lon: 13.5,
+      },
+    ]);
+    let stats = TrackStats::of(&shape).unwrap();
+    assert_eq!(
+      (stats.duration, stats.max_speed, stats.gain),
+      (None, None, None)
+    );
+    assert!(stats.average_speed().is_none());
+    assert_eq!(
+      stats.bounds.0,
+      Coordinate {
+        lat: 52.4,
+        lon: 13.4
+      }
+    );
+    assert!(TrackStats::of(&Shape::new(vec![])).is_none());
+  }
+}
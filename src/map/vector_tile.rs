@@ -0,0 +1,423 @@
+use std::{
+  borrow::Cow,
+  io::{Cursor, Read},
+};
+
+use anyhow::{anyhow, bail, Result};
+use flate2::read::GzDecoder;
+use image::{ImageOutputFormat, Rgba, RgbaImage};
+use imageproc::drawing::draw_line_segment_mut;
+
+/// The width and height of a rendered tile in pixels.
+const SIZE: u32 = 512;
+/// The color of land without other features.
+const BACKGROUND: Rgba<u8> = Rgba([242, 239, 233, 255]);
+
+/// Identifies tiles rendered with this style in the cache of rendered tiles.
+pub const STYLE_KEY: &str = "vector_v1";
+
+/// The geometry types of features in Mapbox vector tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GeometryType {
+  Point,
+  Line,
+  Polygon,
+}
+
+/// A feature of a vector tile with its lines or rings in tile coordinates.
+#[derive(Debug, Clone, PartialEq)]
+struct Feature {
+  kind: GeometryType,
+  lines: Vec<Vec<(f32, f32)>>,
+}
+
+/// A layer of a vector tile, e.g. `water` or `transportation`.
+#[derive(Debug, Clone, PartialEq)]
+struct Layer {
+  name: String,
+  /// The tile coordinates of the width of the tile.
+  extent: u32,
+  features: Vec<Feature>,
+}
+
+/// How the features of a layer are drawn.
+struct Style {
+  fill: Option<Rgba<u8>>,
+  /// The color and the width in pixels.
+  line: Option<(Rgba<u8>, u8)>,
+}
+
+/// The style of the layers of the common schemas like OpenMapTiles, `None` for layers that are
+/// not drawn, e.g. labels.
+fn style(layer: &str) -> Option<Style> {
+  let (fill, line) = match layer {
+    "water" | "ocean" => (Some(Rgba([170, 211, 223, 255])), None),
+    "waterway" => (None, Some((Rgba([170, 211, 223, 255]), 2))),
+    "landcover" | "landuse" | "park" => (Some(Rgba([205, 235, 176, 255])), None),
+    "building" => (
+      Some(Rgba([217, 208, 201, 255])),
+      Some((Rgba([196, 182, 171, 255]), 1)),
+    ),
+    "transportation" | "road" | "roads" => (None, Some((Rgba([160, 160, 160, 255]), 2))),
+    "aeroway" => (None, Some((Rgba([190, 190, 210, 255]), 1))),
+    "boundary" | "admin" => (None, Some((Rgba([150, 120, 170, 255]), 1))),
+    _ => return None,
+  };
+  Some(Style { fill, line })
+}
+
+/// A field value of a protobuf message.
+enum Value<'a> {
+  Varint(u64),
+  Bytes(&'a [u8]),
+  Fixed,
+}
+
+/// Reads the fields of a protobuf message as field number and value.
+struct Fields<'a> {
+  data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+  fn new(data: &'a [u8]) -> Self {
+    Self { data }
+  }
+
+  fn varint(&mut self) -> Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+      let (&byte, rest) = self
+        .data
+        .split_first()
+        .ok_or_else(|| anyhow!("Truncated varint."))?;
+      self.data = rest;
+      value |= u64::from(byte & 0x7f) << shift;
+      if byte & 0x80 == 0 {
+        return Ok(value);
+      }
+    }
+    bail!("Varint too long.")
+  }
+
+  fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+    if len > self.data.len() {
+      bail!("Truncated field.");
+    }
+    let (bytes, rest) = self.data.split_at(len);
+    self.data = rest;
+    Ok(bytes)
+  }
+
+  fn field(&mut self) -> Result<(u64, Value<'a>)> {
+    let key = self.varint()?;
+    let value = match key & 7 {
+      0 => Value::Varint(self.varint()?),
+      1 => {
+        self.take(8)?;
+        Value::Fixed
+      }
+      2 => {
+        let len = usize::try_from(self.varint()?)?;
+        Value::Bytes(self.take(len)?)
+      }
+      5 => {
+        self.take(4)?;
+        Value::Fixed
+      }
+      wire => bail!("Unsupported wire type {wire}."),
+    };
+    Ok((key >> 3, value))
+  }
+}
+
+impl<'a> Iterator for Fields<'a> {
+  type Item = Result<(u64, Value<'a>)>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.data.is_empty() {
+      return None;
+    }
+    let field = self.field();
+    if field.is_err() {
+      self.data = &[];
+    }
+    Some(field)
+  }
+}
+
+/// The values of a packed repeated field.
+fn packed(data: &[u8]) -> Result<Vec<u32>> {
+  let mut fields = Fields::new(data);
+  let mut values = vec![];
+  while !fields.data.is_empty() {
+    values.push(u32::try_from(fields.varint()?)?);
+  }
+  Ok(values)
+}
+
+fn decode(data: &[u8]) -> Result<Vec<Layer>> {
+  let mut layers = vec![];
+  for field in Fields::new(data) {
+    if let (3, Value::Bytes(layer)) = field? {
+      layers.push(decode_layer(layer)?);
+    }
+  }
+  Ok(layers)
+}
+
+fn decode_layer(data: &[u8]) -> Result<Layer> {
+  let mut layer = Layer {
+    name: String::new(),
+    extent: 4096,
+    features: vec![],
+  };
+  for field in Fields::new(data) {
+    match field? {
+      (1, Value::Bytes(name)) => layer.name = String::from_utf8_lossy(name).into_owned(),
+      (2, Value::Bytes(feature)) => layer.features.extend(decode_feature(feature)?),
+      (5, Value::Varint(extent)) => layer.extent = u32::try_from(extent)?.max(1),
+      _ => {}
+    }
+  }
+  Ok(layer)
+}
+
+fn decode_feature(data: &[u8]) -> Result<Option<Feature>> {
+  let (mut kind, mut commands) = (None, vec![]);
+  for field in Fields::new(data) {
+    match field? {
+      (3, Value::Varint(1)) => kind = Some(GeometryType::Point),
+      (3, Value::Varint(2)) => kind = Some(GeometryType::Line),
+      (3, Value::Varint(3)) => kind = Some(GeometryType::Polygon),
+      (4, Value::Bytes(geometry)) => commands = packed(geometry)?,
+      _ => {}
+    }
+  }
+  Ok(kind.map(|kind| Feature {
+    kind,
+    lines: lines(&commands),
+  }))
+}
+
+fn zigzag(value: u32) -> i64 {
+  i64::from(value >> 1) ^ -i64::from(value & 1)
+}
+
+/// Decodes the commands of a geometry into lines. Closed rings repeat their first point.
+#[allow(clippy::cast_precision_loss)]
+fn lines(commands: &[u32]) -> Vec<Vec<(f32, f32)>> {
+  let (mut lines, mut line) = (vec![], vec![]);
+  let (mut x, mut y) = (0i64, 0i64);
+  let mut commands = commands.iter().copied();
+  while let Some(command) = commands.next() {
+    match command & 7 {
+      id @ (1 | 2) => {
+        for _ in 0..command >> 3 {
+          let (Some(dx), Some(dy)) = (commands.next(), commands.next()) else {
+            break;
+          };
+          x += zigzag(dx);
+          y += zigzag(dy);
+          if id == 1 && !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+          }
+          line.push((x as f32, y as f32));
+        }
+      }
+      7 => {
+        if let Some(&first) = line.first() {
+          line.push(first);
+        }
+      }
+      _ => break,
+    }
+  }
+  if !line.is_empty() {
+    lines.push(line);
+  }
+  lines
+}
+
+/// Fills the rings with the even-odd rule, so inner rings are holes.
+#[allow(
+  clippy::cast_possible_truncation,
+  clippy::cast_precision_loss,
+  clippy::cast_sign_loss
+)]
+fn fill(image: &mut RgbaImage, rings: &[Vec<(f32, f32)>], color: Rgba<u8>) {
+  let edges: Vec<((f32, f32), (f32, f32))> = rings
+    .iter()
+    .flat_map(|ring| {
+      ring
+        .iter()
+        .copied()
+        .zip(ring.iter().copied().cycle().skip(1))
+    })
+    .collect();
+  let (width, height) = image.dimensions();
+  let mut crossings = vec![];
+  for row in 0..height {
+    let y = row as f32 + 0.5;
+    crossings.clear();
+    for &((x0, y0), (x1, y1)) in &edges {
+      if (y0 <= y) != (y1 <= y) {
+        crossings.push(x0 + (y - y0) / (y1 - y0) * (x1 - x0));
+      }
+    }
+    crossings.sort_by(f32::total_cmp);
+    for pair in crossings.chunks_exact(2) {
+      // The pixels whose centers lie between the crossings.
+      let start = (pair[0] - 0.5).ceil().max(0.) as u32;
+      let end = (pair[1] - 0.5).ceil().clamp(0., width as f32) as u32;
+      for x in start..end {
+        image.put_pixel(x, row, color);
+      }
+    }
+  }
+}
+
+fn stroke(image: &mut RgbaImage, line: &[(f32, f32)], color: Rgba<u8>, width: u8) {
+  let offsets: Vec<f32> = (0..width)
+    .map(|o| f32::from(o) - f32::from(width - 1) / 2.)
+    .collect();
+  for segment in line.windows(2) {
+    let ((x0, y0), (x1, y1)) = (segment[0], segment[1]);
+    for dx in &offsets {
+      for dy in &offsets {
+        draw_line_segment_mut(image, (x0 + dx, y0 + dy), (x1 + dx, y1 + dy), color);
+      }
+    }
+  }
+}
+
+/// Draws a Mapbox vector tile, which may be gzip compressed like in mbtiles files, as png.
+///
+/// # Errors
+/// If the data is no vector tile.
+#[allow(clippy::cast_precision_loss)]
+pub fn render(data: &[u8]) -> Result<Vec<u8>> {
+  let data = if data.starts_with(&[0x1f, 0x8b]) {
+    let mut unzipped = vec![];
+    GzDecoder::new(data).read_to_end(&mut unzipped)?;
+    Cow::Owned(unzipped)
+  } else {
+    Cow::Borrowed(data)
+  };
+  let mut image = RgbaImage::from_pixel(SIZE, SIZE, BACKGROUND);
+  for layer in decode(&data)? {
+    let Some(style) = style(&layer.name) else {
+      continue;
+    };
+    let scale = SIZE as f32 / layer.extent as f32;
+    for feature in &layer.features {
+      let lines: Vec<Vec<(f32, f32)>> = feature
+        .lines
+        .iter()
+        .map(|line| line.iter().map(|(x, y)| (x * scale, y * scale)).collect())
+        .collect();
+      if let (GeometryType::Polygon, Some(color)) = (feature.kind, style.fill) {
+        fill(&mut image, &lines, color);
+      }
+      if let (GeometryType::Line | GeometryType::Polygon, Some((color, width))) =
+        (feature.kind, style.line)
+      {
+        for line in &lines {
+          stroke(&mut image, line, color, width);
+        }
+      }
+    }
+  }
+
+  let mut png = Cursor::new(vec![]);
+  image.write_to(&mut png, ImageOutputFormat::Png)?;
+  Ok(png.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[allow(clippy::cast_possible_truncation)]
+  fn varint(mut value: u64) -> Vec<u8> {
+    let mut bytes = vec![];
+    loop {
+      let byte = (value & 0x7f) as u8;
+      value >>= 7;
+      if value == 0 {
+        bytes.push(byte);
+        return bytes;
+      }
+      bytes.push(byte | 0x80);
+    }
+  }
+
+  fn bytes(number: u64, data: &[u8]) -> Vec<u8> {
+    let mut field = varint(number << 3 | 2);
+    field.extend(varint(data.len() as u64));
+    field.extend(data);
+    field
+  }
+
+  /// A command of a geometry with the moves as zigzag encoded parameters.
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn command(id: u32, moves: &[(i32, i32)]) -> Vec<u8> {
+    let mut values = vec![id | (moves.len() as u32) << 3];
+    for &(dx, dy) in moves {
+      values.push(((dx << 1) ^ (dx >> 31)) as u32);
+      values.push(((dy << 1) ^ (dy >> 31)) as u32);
+    }
+    values.into_iter().flat_map(|v| varint(v.into())).collect()
+  }
+
+  /// A square ring with the corner and the side length.
+  fn square(from: (i32, i32), to: (i32, i32), side: i32) -> Vec<u8> {
+    let mut geometry = command(1, &[(to.0 - from.0, to.1 - from.1)]);
+    geometry.extend(command(2, &[(side, 0), (0, side), (-side, 0)]));
+    geometry.extend(command(7, &[]));
+    geometry
+  }
+
+  #[test]
+  fn polygon_with_hole() {
+    // A square of the whole tile with a hole in its center.
+    let mut geometry = square((0, 0), (0, 0), 4096);
+    geometry.extend(square((0, 4096), (1024, 1024), 2048));
+    let mut feature = varint(3 << 3);
+    feature.extend(varint(3));
+    feature.extend(bytes(4, &geometry));
+    let mut layer = bytes(1, b"water");
+    layer.extend(bytes(2, &feature));
+    layer.extend(varint(5 << 3));
+    layer.extend(varint(4096));
+    let tile = bytes(3, &layer);
+
+    let layers = decode(&tile).unwrap();
+    assert_eq!(layers.len(), 1);
+    assert_eq!(layers[0].name, "water");
+    assert_eq!(
+      layers[0].features[0].lines[1],
+      vec![
+        (1024., 1024.),
+        (3072., 1024.),
+        (3072., 3072.),
+        (1024., 3072.),
+        (1024., 1024.)
+      ]
+    );
+
+    let image = image::load_from_memory(&render(&tile).unwrap())
+      .unwrap()
+      .to_rgba8();
+    assert_eq!(image.dimensions(), (SIZE, SIZE));
+    let water = style("water").unwrap().fill.unwrap();
+    assert_eq!(*image.get_pixel(10, 10), water);
+    assert_eq!(*image.get_pixel(256, 256), BACKGROUND);
+    assert_eq!(*image.get_pixel(500, 256), water);
+  }
+
+  #[test]
+  fn invalid_tiles() {
+    assert!(render(&[0x1a, 0x10, 0x0a]).is_err());
+    assert!(decode(&[]).unwrap().is_empty());
+  }
+}
@@ -0,0 +1,111 @@
+use std::str::FromStr;
+
+use super::coordinates::{PixelPosition, CANVAS_SIZE};
+
+/// The part of the window the inset covers along each side.
+const SIZE: f32 = 0.3;
+/// Screen pixels between the inset and the left edge of the window.
+const MARGIN: f32 = 12.;
+/// Screen pixels below the inset, which leave room for the scale bar.
+const BOTTOM: f32 = 50.;
+
+/// How the camera of the inset follows the map.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Follow {
+  /// Shows the center of the map this many zoom levels further out.
+  Mirror(f32),
+  /// Keeps showing the center at the zoom factor, independent of the map.
+  Pinned(PixelPosition, f32),
+}
+
+impl FromStr for Follow {
+  type Err = ();
+  /// Reads `overview`, `overview <levels>`, or a plain number of levels.
+  fn from_str(input: &str) -> Result<Self, Self::Err> {
+    match input
+      .trim()
+      .strip_prefix("overview")
+      .unwrap_or(input)
+      .trim()
+    {
+      "" => Ok(Follow::Mirror(Viewport::DEFAULT_LEVELS)),
+      levels => levels
+        .parse::<f32>()
+        .ok()
+        .filter(|levels| levels.is_finite())
+        .map(Follow::Mirror)
+        .ok_or(()),
+    }
+  }
+}
+
+/// A second view of the layers in a corner of the window, set by `:viewport`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+  pub follow: Follow,
+}
+
+impl Viewport {
+  /// The zoom levels an overview shows further out by default.
+  pub const DEFAULT_LEVELS: f32 = 4.;
+
+  #[must_use]
+  pub fn new(follow: Follow) -> Self {
+    Self { follow }
+  }
+
+  /// The inset in a window of the size as left, top, width, and height in screen pixels.
+  #[must_use]
+  pub fn area(width: f32, height: f32) -> (f32, f32, f32, f32) {
+    let (w, h) = (width * SIZE, height * SIZE);
+    (MARGIN, (height - BOTTOM - h).max(0.), w, h)
+  }
+
+  /// The center and zoom factor of the inset of the width while the map shows the center at the
+  /// zoom factor. The inset never shows more than the whole canvas.
+  #[must_use]
+  pub fn camera(&self, center: PixelPosition, zoom: f32, width: f32) -> (PixelPosition, f32) {
+    let (center, zoom) = match self.follow {
+      Follow::Mirror(levels) => (center, zoom / 2f32.powf(levels)),
+      Follow::Pinned(center, zoom) => (center, zoom),
+    };
+    (center, zoom.max(width / CANVAS_SIZE))
+  }
+
+  /// If the screen position lies in the inset of a window of the size.
+  #[must_use]
+  pub fn contains(width: f32, height: f32, x: f32, y: f32) -> bool {
+    let (left, top, w, h) = Self::area(width, height);
+    (left..=left + w).contains(&x) && (top..=top + h).contains(&y)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn cameras() {
+    let center = PixelPosition { x: 100., y: 200. };
+    let overview = Viewport::new("overview 2".parse().unwrap());
+    assert_eq!(overview.camera(center, 64., 300.), (center, 16.));
+    assert_eq!(
+      overview.camera(center, 0.001, 300.),
+      (center, 300. / CANVAS_SIZE)
+    );
+    let pinned = Viewport::new(Follow::Pinned(PixelPosition { x: 1., y: 2. }, 8.));
+    assert_eq!(
+      pinned.camera(center, 64., 300.),
+      (PixelPosition { x: 1., y: 2. }, 8.)
+    );
+    assert_eq!(
+      "overview".parse(),
+      Ok(Follow::Mirror(Viewport::DEFAULT_LEVELS))
+    );
+    assert_eq!("3".parse(), Ok(Follow::Mirror(3.)));
+    assert_eq!("closer".parse::<Follow>(), Err(()));
+    assert_eq!(Viewport::area(1000., 1000.), (12., 650., 300., 300.));
+    assert!(Viewport::contains(1000., 1000., 100., 700.));
+    assert!(!Viewport::contains(1000., 1000., 100., 600.));
+  }
+}
@@ -0,0 +1,401 @@
+use std::{
+  collections::HashMap,
+  io::{BufRead, Read},
+  path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use log::error;
+
+use crate::map::{
+  coordinates::Coordinate,
+  map_event::{Color, Layer, MapEvent, Shape},
+};
+
+use super::FileParser;
+
+/// Seconds between the unix epoch and the FIT epoch 1989-12-31T00:00:00Z.
+const FIT_EPOCH: i64 = 631_065_600;
+/// The global message number of FIT record messages, i.e. trackpoints.
+const FIT_RECORD: u16 = 20;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Trackpoint {
+  coordinate: Option<Coordinate>,
+  /// Milliseconds since the unix epoch.
+  time: Option<i64>,
+  altitude: Option<f32>,
+  heart_rate: Option<u8>,
+}
+
+/// A summary of elevation and heart rate as label of a track.
+#[allow(clippy::cast_precision_loss)]
+fn label(name: &str, points: &[Trackpoint]) -> String {
+  let mut label = format!("{name}: {} points", points.len());
+  let altitudes: Vec<f32> = points.iter().filter_map(|p| p.altitude).collect();
+  if !altitudes.is_empty() {
+    let min = altitudes.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = altitudes.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    label.push_str(&format!(", elevation {min:.0}-{max:.0} m"));
+  }
+  let heart_rates: Vec<u32> = points
+    .iter()
+    .filter_map(|p| p.heart_rate.map(u32::from))
+    .collect();
+  if !heart_rates.is_empty() {
+    let average = heart_rates.iter().sum::<u32>() as f32 / heart_rates.len() as f32;
+    let max = heart_rates.iter().max().unwrap_or(&0);
+    label.push_str(&format!(", heart rate avg {average:.0} max {max} bpm"));
+  }
+  label
+}
+
+fn child_text(node: roxmltree::Node, name: &str) -> Option<String> {
+  node
+    .descendants()
+    .find(|n| n.has_tag_name(name))
+    .and_then(|n| n.text())
+    .map(|t| t.trim().to_string())
+}
+
+fn parse_tcx(data: &str) -> Result<Vec<(String, Vec<Trackpoint>)>> {
+  let document = roxmltree::Document::parse(data)?;
+  let mut tracks = vec![];
+  for activity in document
+    .descendants()
+    .filter(|n| n.has_tag_name("Activity"))
+  {
+    let name = activity
+      .attribute("Sport")
+      .unwrap_or("Activity")
+      .to_string();
+    for track in activity.descendants().filter(|n| n.has_tag_name("Track")) {
+      let points = track
+        .children()
+        .filter(|n| n.has_tag_name("Trackpoint"))
+        .map(|p| {
+          let lat = child_text(p, "LatitudeDegrees").and_then(|v| v.parse().ok());
+          let lon = child_text(p, "LongitudeDegrees").and_then(|v| v.parse().ok());
+          Trackpoint {
+            coordinate: lat
+              .zip(lon)
+              .map(|(lat, lon)| Coordinate { lat, lon })
+              .filter(Coordinate::is_valid),
+            time: child_text(p, "Time")
+              .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+              .map(|t| t.timestamp_millis()),
+            altitude: child_text(p, "AltitudeMeters").and_then(|v| v.parse().ok()),
+            heart_rate: p
+              .descendants()
+              .find(|n| n.has_tag_name("HeartRateBpm"))
+              .and_then(|n| child_text(n, "Value"))
+              .and_then(|v| v.parse().ok()),
+          }
+        })
+        .collect();
+      tracks.push((name.clone(), points));
+    }
+  }
+  Ok(tracks)
+}
+
+/// The layout of the messages of one local message type.
+#[derive(Debug, Clone)]
+struct FitDefinition {
+  big_endian: bool,
+  global: u16,
+  /// Field number and size.
+  fields: Vec<(u8, usize)>,
+  developer_size: usize,
+}
+
+fn read_uint(bytes: &[u8], big_endian: bool) -> u64 {
+  let fold = |acc: u64, b: &u8| (acc << 8) | u64::from(*b);
+  if big_endian {
+    bytes.iter().fold(0, fold)
+  } else {
+    bytes.iter().rev().fold(0, fold)
+  }
+}
+
+/// Decodes the record messages of a FIT file. Other messages are skipped.
+#[allow(
+  clippy::cast_possible_truncation,
+  clippy::cast_possible_wrap,
+  clippy::cast_precision_loss
+)]
+fn parse_fit(data: &[u8]) -> Result<Vec<Trackpoint>> {
+  let header_size = usize::from(*data.first().ok_or(anyhow!("Empty file."))?);
+  if data.get(8..12) != Some(b".FIT") {
+    return Err(anyhow!("Not a FIT file."));
+  }
+  let data_size = read_uint(&data[4..8], false) as usize;
+  let end = (header_size + data_size).min(data.len());
+
+  let mut definitions: HashMap<u8, FitDefinition> = HashMap::new();
+  let mut points = vec![];
+  let mut last_timestamp: u32 = 0;
+  let mut offset = header_size;
+  let truncated = || anyhow!("Truncated FIT file.");
+  while offset < end {
+    let header = data[offset];
+    offset += 1;
+    let (local, compressed_time) = if header & 0x80 != 0 {
+      ((header >> 5) & 0x03, Some(u32::from(header & 0x1f)))
+    } else {
+      (header & 0x0f, None)
+    };
+
+    if compressed_time.is_none() && header & 0x40 != 0 {
+      let content = data.get(offset..offset + 5).ok_or_else(truncated)?;
+      let big_endian = content[1] == 1;
+      let global = read_uint(&content[2..4], big_endian) as u16;
+      let field_count = usize::from(content[4]);
+      offset += 5;
+      let fields = data
+        .get(offset..offset + 3 * field_count)
+        .ok_or_else(truncated)?
+        .chunks(3)
+        .map(|f| (f[0], usize::from(f[1])))
+        .collect();
+      offset += 3 * field_count;
+      let mut developer_size = 0;
+      if header & 0x20 != 0 {
+        let count = usize::from(*data.get(offset).ok_or_else(truncated)?);
+        developer_size = data
+          .get(offset + 1..offset + 1 + 3 * count)
+          .ok_or_else(truncated)?
+          .chunks(3)
+          .map(|f| usize::from(f[1]))
+          .sum();
+        offset += 1 + 3 * count;
+      }
+      definitions.insert(
+        local,
+        FitDefinition {
+          big_endian,
+          global,
+          fields,
+          developer_size,
+        },
+      );
+      continue;
+    }
+
+    let definition = definitions
+      .get(&local)
+      .ok_or(anyhow!("Data message without definition at {offset}."))?;
+    let mut point = Trackpoint::default();
+    let (mut lat, mut lon) = (None, None);
+    let mut timestamp = None;
+    for &(number, size) in &definition.fields {
+      let bytes = data.get(offset..offset + size).ok_or_else(truncated)?;
+      offset += size;
+      let value = read_uint(bytes, definition.big_endian);
+      let invalid = value == (1 << (8 * size.min(4))) - 1 || (size == 4 && value == 0x7FFF_FFFF);
+      if definition.global != FIT_RECORD || invalid {
+        continue;
+      }
+      let semicircles = |v: u64| (v as u32 as i32) as f32 * 180. / 2_147_483_648.;
+      match number {
+        253 => timestamp = Some(value as u32),
+        0 => lat = Some(semicircles(value)),
+        1 => lon = Some(semicircles(value)),
+        2 | 78 => point.altitude = Some(value as f32 / 5. - 500.),
+        3 => point.heart_rate = Some(value as u8),
+        _ => (),
+      }
+    }
+    offset += definition.developer_size;
+
+    if let Some(t) = timestamp {
+      last_timestamp = t;
+    } else if let Some(t) = compressed_time {
+      let rollover = if t >= last_timestamp & 0x1f { 0 } else { 0x20 };
+      last_timestamp = (last_timestamp & !0x1f) + t + rollover;
+      timestamp = Some(last_timestamp);
+    }
+    if definition.global == FIT_RECORD {
+      point.time = timestamp.map(|t| (i64::from(t) + FIT_EPOCH) * 1000);
+      point.coordinate = lat
+        .zip(lon)
+        .map(|(lat, lon)| Coordinate { lat, lon })
+        .filter(Coordinate::is_valid);
+      points.push(point);
+    }
+  }
+  Ok(points)
+}
+
+/// Parses activity files of sports devices: Garmin FIT and TCX.
+/// Each track becomes a line with the times of the trackpoints. Elevation and heart rate are
+/// summarized in the label.
+#[derive(Debug, Default)]
+pub struct ActivityParser {
+  fit: bool,
+  color: Color,
+}
+
+impl ActivityParser {
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Returns a parser for `.fit` and `.tcx` files.
+  #[must_use]
+  pub fn for_path(path: &Path) -> Option<Self> {
+    match path.extension()?.to_str()?.to_lowercase().as_str() {
+      "fit" => Some(Self::new().with_fit(true)),
+      "tcx" => Some(Self::new()),
+      _ => None,
+    }
+  }
+
+  /// Reads the binary FIT format instead of TCX.
+  #[must_use]
+  pub fn with_fit(mut self, fit: bool) -> Self {
+    self.fit = fit;
+    self
+  }
+
+  #[must_use]
+  pub fn with_color(mut self, color: Color) -> Self {
+    self.color = color;
+    self
+  }
+
+  fn read(&self, data: &[u8]) -> Result<Vec<(String, Vec<Trackpoint>)>> {
+    if self.fit {
+      Ok(vec![("Activity".to_string(), parse_fit(data)?)])
+    } else {
+      parse_tcx(std::str::from_utf8(data)?)
+    }
+  }
+
+  fn to_layer(&self, tracks: Vec<(String, Vec<Trackpoint>)>) -> Layer {
+    let mut layer = Layer::new("Activity".to_string());
+    for (name, points) in tracks {
+      let label = label(&name, &points);
+      let located: Vec<&Trackpoint> = points.iter().filter(|p| p.coordinate.is_some()).collect();
+      if located.is_empty() {
+        continue;
+      }
+      let coordinates = located.iter().filter_map(|p| p.coordinate).collect();
+      // Timestamps and elevations are only kept if every coordinate has one.
+      let timestamps = located.iter().map(|p| p.time).collect::<Option<Vec<_>>>();
+      let elevations = located
+        .iter()
+        .map(|p| p.altitude)
+        .collect::<Option<Vec<_>>>();
+      layer.shapes.push(
+        Shape::new(coordinates)
+          .with_color(self.color)
+          .with_label(Some(label))
+          .with_timestamps(timestamps.unwrap_or_default())
+          .with_elevations(elevations.unwrap_or_default()),
+      );
+    }
+    layer
+  }
+}
+
+impl FileParser for ActivityParser {
+  fn parse<'a>(
+    &'a mut self,
+    mut file: Box<dyn BufRead>,
+  ) -> Box<dyn Iterator<Item = MapEvent> + '_> {
+    let mut data = vec![];
+    if let Err(e) = file.read_to_end(&mut data) {
+      error!("Cannot read activity: {e}");
+      return Box::new(std::iter::empty());
+    }
+    match self.read(&data) {
+      Ok(tracks) => Box::new(std::iter::once(MapEvent::Layer(self.to_layer(tracks)))),
+      Err(e) => {
+        error!("Cannot parse activity: {e}");
+        Box::new(std::iter::empty())
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_tcx_file() {
+    let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+  <Activities>
+    <Activity Sport="Running">
+      <Lap StartTime="2024-05-01T10:00:00Z">
+        <Track>
+          <Trackpoint>
+            <Time>2024-05-01T10:00:00Z</Time>
+            <Position><LatitudeDegrees>50.9</LatitudeDegrees><LongitudeDegrees>6.9</LongitudeDegrees></Position>
+            <AltitudeMeters>50.0</AltitudeMeters>
+            <HeartRateBpm><Value>120</Value></HeartRateBpm>
+          </Trackpoint>
+          <Trackpoint>
+            <Time>2024-05-01T10:00:05Z</Time>
+            <Position><LatitudeDegrees>50.91</LatitudeDegrees><LongitudeDegrees>6.91</LongitudeDegrees></Position>
+            <AltitudeMeters>55.0</AltitudeMeters>
+            <HeartRateBpm><Value>130</Value></HeartRateBpm>
+          </Trackpoint>
+        </Track>
+      </Lap>
+    </Activity>
+  </Activities>
+</TrainingCenterDatabase>"#;
+    let layer = ActivityParser::new().to_layer(parse_tcx(data).unwrap());
+    assert_eq!(layer.shapes.len(), 1);
+    let shape = &layer.shapes[0];
+    assert_eq!(shape.coordinates.len(), 2);
+    assert_eq!(shape.timestamps, vec![1_714_557_600_000, 1_714_557_605_000]);
+    assert_eq!(shape.elevations, vec![50., 55.]);
+    assert_eq!(
+      shape.label.as_deref(),
+      Some("Running: 2 points, elevation 50-55 m, heart rate avg 125 max 130 bpm")
+    );
+  }
+
+  #[test]
+  fn parse_fit_file() {
+    let mut records = vec![];
+    // Definition of local message 0 as record with timestamp, lat, lon, heart rate.
+    records.extend([0x40, 0, 0, 20, 0, 4]);
+    records.extend([253, 4, 0x86, 0, 4, 0x85, 1, 4, 0x85, 3, 1, 0x02]);
+    // Data message with all fields.
+    records.push(0x00);
+    records.extend(1_000_000_000u32.to_le_bytes());
+    records.extend(607_452_000i32.to_le_bytes());
+    records.extend(82_319_000i32.to_le_bytes());
+    records.push(140);
+    // Definition of local message 1 as record without timestamp.
+    records.extend([0x41, 0, 0, 20, 0, 3]);
+    records.extend([0, 4, 0x85, 1, 4, 0x85, 3, 1, 0x02]);
+    // Compressed timestamp header for local message 1, 5 seconds later.
+    let offset = u8::try_from((1_000_000_000u32 + 5) & 0x1f).unwrap();
+    records.push(0x80 | (1 << 5) | offset);
+    records.extend(607_452_000i32.to_le_bytes());
+    records.extend(82_319_000i32.to_le_bytes());
+    records.push(0xFF);
+
+    let mut data = vec![12, 0x10, 0, 0];
+    data.extend(u32::try_from(records.len()).unwrap().to_le_bytes());
+    data.extend(b".FIT");
+    data.extend(records);
+
+    let points = parse_fit(&data).unwrap();
+    assert_eq!(points.len(), 2);
+    let c = points[0].coordinate.unwrap();
+    assert!((c.lat - 50.916).abs() < 1e-3 && (c.lon - 6.8999).abs() < 1e-3);
+    assert_eq!(points[0].time, Some((1_000_000_000 + FIT_EPOCH) * 1000));
+    assert_eq!(points[0].heart_rate, Some(140));
+    assert_eq!(points[1].time, Some((1_000_000_005 + FIT_EPOCH) * 1000));
+    assert_eq!(points[1].heart_rate, None);
+  }
+}
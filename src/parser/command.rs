@@ -0,0 +1,212 @@
+use std::{
+  io::{BufRead, BufReader, Lines, Read, Write},
+  process::{Child, ChildStdout, Command, Stdio},
+  thread::JoinHandle,
+};
+
+use anyhow::{anyhow, Result};
+use log::error;
+use serde_json::Value;
+
+use crate::map::map_event::{Color, Layer, MapEvent};
+
+use super::{FileParser, GeoJsonParser};
+
+/// Pipes the input through an external command that converts it to GeoJSON, so formats without a
+/// parser can be drawn with a small script. The command is run by the shell, `sh -c` or `cmd /C`
+/// on Windows, so it may have arguments and pipes. The shapes are drawn in a layer named after the
+/// command. Lines of the output that are a GeoJSON document of their own, like GeoJSON text
+/// sequences, are drawn while the command runs.
+#[derive(Debug)]
+pub struct CommandParser {
+  command: String,
+  color: Color,
+}
+
+/// The shell running the command.
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+  let mut shell = Command::new("sh");
+  shell.arg("-c").arg(command);
+  shell
+}
+
+/// The shell running the command. `cmd` does not unquote its arguments like other programs, so
+/// the command is passed as it is.
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+  use std::os::windows::process::CommandExt;
+  let mut shell = Command::new("cmd");
+  shell.arg("/C").raw_arg(command);
+  shell
+}
+
+impl CommandParser {
+  #[must_use]
+  pub fn new(command: String) -> Self {
+    Self {
+      command,
+      color: Color::default(),
+    }
+  }
+
+  #[must_use]
+  pub fn with_color(mut self, color: Color) -> Self {
+    self.color = color;
+    self
+  }
+
+  /// Starts the command with the input.
+  fn convert(&self, input: Vec<u8>) -> Result<Conversion> {
+    let mut child = shell(&self.command)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .spawn()?;
+    let mut stdin = child.stdin.take().ok_or(anyhow!("No stdin."))?;
+    let stdout = child.stdout.take().ok_or(anyhow!("No stdout."))?;
+    // Writing in its own thread, so a command that writes before it has read everything does not
+    // block on a full pipe.
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    Ok(Conversion {
+      child: Some(child),
+      writer: Some(writer),
+      lines: BufReader::new(stdout).lines(),
+      document: String::new(),
+    })
+  }
+}
+
+/// A running command and the output read so far.
+struct Conversion {
+  child: Option<Child>,
+  writer: Option<JoinHandle<std::io::Result<()>>>,
+  lines: Lines<BufReader<ChildStdout>>,
+  /// The lines of a document spanning several lines.
+  document: String,
+}
+
+impl Conversion {
+  /// The next GeoJSON document of the output: a line holding a whole document, or the rest of
+  /// the output once the command has ended.
+  fn next_document(&mut self) -> Option<Value> {
+    for line in self.lines.by_ref() {
+      let line = match line {
+        Ok(line) => line,
+        Err(e) => {
+          error!("Cannot read the output: {e}");
+          break;
+        }
+      };
+      // GeoJSON text sequences start each document with a record separator.
+      let text = line.trim().trim_start_matches('\u{1e}');
+      if self.document.trim().is_empty() && !text.is_empty() {
+        if let Ok(document) = serde_json::from_str(text) {
+          return Some(document);
+        }
+      }
+      self.document.push_str(&line);
+      self.document.push('\n');
+    }
+    let document = std::mem::take(&mut self.document);
+    if document.trim().is_empty() {
+      return None;
+    }
+    serde_json::from_str(&document)
+      .map_err(|e| error!("Cannot read GeoJSON: {e}"))
+      .ok()
+  }
+
+  /// Waits for the command to end.
+  fn finish(&mut self) -> Result<()> {
+    let Some(mut child) = self.child.take() else {
+      return Ok(());
+    };
+    let status = child.wait()?;
+    if let Some(Ok(Err(e))) = self.writer.take().map(JoinHandle::join) {
+      // Commands may stop reading once they have what they need.
+      if e.kind() != std::io::ErrorKind::BrokenPipe {
+        return Err(e.into());
+      }
+    }
+    if !status.success() {
+      return Err(anyhow!("exited with {status}"));
+    }
+    Ok(())
+  }
+}
+
+impl FileParser for CommandParser {
+  fn parse<'a>(
+    &'a mut self,
+    mut file: Box<dyn BufRead>,
+  ) -> Box<dyn Iterator<Item = MapEvent> + '_> {
+    let mut input = vec![];
+    if let Err(e) = file.read_to_end(&mut input) {
+      error!("Cannot read the input of {}: {e}", self.command);
+      return Box::new(std::iter::empty());
+    }
+    let mut conversion = match self.convert(input) {
+      Ok(conversion) => conversion,
+      Err(e) => {
+        error!("Cannot convert the input with {}: {e}", self.command);
+        return Box::new(std::iter::empty());
+      }
+    };
+    let geojson = GeoJsonParser::new().with_color(self.color);
+    Box::new(std::iter::from_fn(move || loop {
+      let Some(document) = conversion.next_document() else {
+        if let Err(e) = conversion.finish() {
+          error!("Cannot convert the input with {}: {e}", self.command);
+        }
+        return None;
+      };
+      let shapes = geojson.shapes(&document);
+      if !shapes.is_empty() {
+        let mut layer = Layer::new(self.command.clone());
+        layer.shapes = shapes;
+        return Some(MapEvent::Layer(layer));
+      }
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+
+  use super::*;
+
+  fn parse(command: &str, input: &str) -> Vec<MapEvent> {
+    let mut parser = CommandParser::new(command.to_string());
+    parser
+      .parse(Box::new(Cursor::new(input.as_bytes().to_vec())))
+      .collect()
+  }
+
+  #[test]
+  fn converts() {
+    let events = parse(
+      r#"read lat lon; echo "{\"type\": \"Point\", \"coordinates\": [$lon, $lat]}""#,
+      "52.5 13.4\n",
+    );
+    let [MapEvent::Layer(layer)] = &events[..] else {
+      panic!("Expected a layer: {events:?}");
+    };
+    assert!(layer.id.starts_with("read lat lon"));
+    assert!((layer.shapes[0].coordinates[0].lat - 52.5).abs() < 1e-5);
+    assert!(parse("exit 1", "").is_empty());
+  }
+
+  #[test]
+  fn streams_lines() {
+    let events = parse(
+      r#"while read lat lon; do echo "{\"type\": \"Point\", \"coordinates\": [$lon, $lat]}"; done"#,
+      "52.5 13.4\n48.1 11.6\n",
+    );
+    assert_eq!(events.len(), 2);
+    let MapEvent::Layer(layer) = &events[1] else {
+      panic!("Expected a layer: {events:?}");
+    };
+    assert!((layer.shapes[0].coordinates[0].lat - 48.1).abs() < 1e-5);
+  }
+}
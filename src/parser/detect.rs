@@ -0,0 +1,263 @@
+use std::{fmt, str::FromStr};
+
+use log::{info, warn};
+use serde_json::Value;
+
+use crate::map::map_event::Color;
+
+use super::{
+  polyline, FileParser, GeoJsonParser, GrepParser, KmlParser, NmeaParser, Parser, PolylineParser,
+  TTJsonParser,
+};
+
+/// Formats whose scores are closer than this are reported as ambiguous.
+const AMBIGUITY: f32 = 0.2;
+/// Grep finds coordinates in almost any text, so formats that explain the input better win.
+const GREP_WEIGHT: f32 = 0.5;
+
+/// The formats that are recognized by the start of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  GeoJson,
+  Kml,
+  Nmea,
+  Polyline,
+  TtJson,
+  Grep,
+}
+
+const FORMATS: [Format; 6] = [
+  Format::GeoJson,
+  Format::Kml,
+  Format::Nmea,
+  Format::Polyline,
+  Format::TtJson,
+  Format::Grep,
+];
+
+impl Format {
+  /// The name used by `--parser`.
+  #[must_use]
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::GeoJson => "geojson",
+      Self::Kml => "kml",
+      Self::Nmea => "nmea",
+      Self::Polyline => "polyline",
+      Self::TtJson => "ttjson",
+      Self::Grep => "grep",
+    }
+  }
+
+  /// A parser for the format with default settings.
+  #[must_use]
+  pub fn parser(self, color: Color) -> Box<dyn FileParser> {
+    match self {
+      Self::GeoJson => Box::new(GeoJsonParser::new().with_color(color)),
+      Self::Kml => Box::new(KmlParser::new().with_color(color)),
+      Self::Nmea => Box::new(NmeaParser::new().with_color(color)),
+      Self::Polyline => Box::new(PolylineParser::new().with_color(color)),
+      Self::TtJson => Box::new(TTJsonParser::new().with_color(color)),
+      Self::Grep => Box::new(GrepParser::new(false).with_color(color)),
+    }
+  }
+
+  /// How well the format explains the sample, between 0 and 1.
+  fn score(self, text: &str, lines: &[&str]) -> f32 {
+    let start = text.trim_start();
+    match self {
+      Self::GeoJson if start.starts_with(['{', '[']) => {
+        match serde_json::from_str::<Value>(text) {
+          Ok(document) if !GeoJsonParser::new().shapes(&document).is_empty() => 1.,
+          // The sample may end in the middle of the document.
+          _ if start.contains("\"type\"")
+            && (start.contains("\"Feature") || start.contains("\"coordinates\"")) =>
+          {
+            0.8
+          }
+          _ if start.contains("\"type\"") => 0.4,
+          _ => 0.,
+        }
+      }
+      Self::Kml if start.starts_with('<') => {
+        if start.contains("<kml") {
+          1.
+        } else if start.contains("<Placemark") {
+          0.8
+        } else {
+          0.
+        }
+      }
+      Self::TtJson if start.starts_with('{') => {
+        if start.contains("\"routes\"") || start.contains("\"reachableRange\"") {
+          0.9
+        } else {
+          0.
+        }
+      }
+      // NMEA sentences start with `$`, a talker, and a type like `$GPGGA,`.
+      Self::Nmea => share(lines, |line| {
+        line.starts_with('$')
+          && line
+            .get(1..6)
+            .is_some_and(|t| t.bytes().all(|b| b.is_ascii_uppercase()))
+          && line.get(6..7) == Some(",")
+      }),
+      Self::Polyline => share(lines, |line| {
+        !line.contains(char::is_whitespace)
+          && line.chars().any(|c| !c.is_ascii_alphabetic())
+          && polyline::decode(line, 5).is_some_and(|c| c.len() > 1)
+      }),
+      Self::Grep => {
+        let mut grep = GrepParser::new(false);
+        GREP_WEIGHT * share(lines, |line| grep.parse_line(line).is_some())
+      }
+      _ => 0.,
+    }
+  }
+}
+
+/// The share of the lines that match.
+#[allow(clippy::cast_precision_loss)]
+fn share(lines: &[&str], mut matches: impl FnMut(&str) -> bool) -> f32 {
+  lines.iter().filter(|l| matches(l)).count() as f32 / lines.len().max(1) as f32
+}
+
+impl fmt::Display for Format {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+impl FromStr for Format {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    FORMATS
+      .into_iter()
+      .find(|f| f.name().eq_ignore_ascii_case(s.trim()))
+      .ok_or(())
+  }
+}
+
+/// The formats that can read the sample, the best first. A format scores by how much of the sample
+/// it understands: line based formats by the share of lines they read, documents by their
+/// structure.
+#[must_use]
+pub fn scores(sample: &[u8]) -> Vec<(Format, f32)> {
+  let text = String::from_utf8_lossy(sample);
+  let mut lines: Vec<&str> = text
+    .lines()
+    .map(str::trim)
+    .filter(|l| !l.is_empty())
+    .collect();
+  // The last line of a sample may be cut off.
+  if !text.ends_with('\n') && lines.len() > 1 {
+    lines.pop();
+  }
+  let mut scores: Vec<(Format, f32)> = FORMATS
+    .into_iter()
+    .map(|format| (format, format.score(&text, &lines)))
+    .filter(|(_, score)| *score > 0.)
+    .collect();
+  scores.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+  scores
+}
+
+/// The format of the input by its start, e.g. the first buffered chunk of stdin. `prefer` wins
+/// if it reads the sample about as well as the best format. Ambiguous samples and samples no
+/// format reads are logged, so a wrong guess does not silently draw nothing.
+#[must_use]
+pub fn detect(sample: &[u8], prefer: Option<Format>) -> Option<Format> {
+  let scores = scores(sample);
+  let Some(&(best, score)) = scores.first() else {
+    warn!("No format recognized in the input, use --parser to choose one.");
+    return None;
+  };
+  let close: Vec<Format> = scores
+    .iter()
+    .filter(|(_, s)| score - s < AMBIGUITY)
+    .map(|(f, _)| *f)
+    .collect();
+  if let Some(prefer) = prefer.filter(|p| close.contains(p)) {
+    return Some(prefer);
+  }
+  if close.len() > 1 {
+    let names: Vec<String> = scores
+      .iter()
+      .filter(|(f, _)| close.contains(f))
+      .map(|(f, s)| format!("{f} ({s:.2})"))
+      .collect();
+    warn!(
+      "The input could be {}, reading it as {best}. Use --prefer or --parser to choose.",
+      names.join(" or ")
+    );
+  } else {
+    info!("Reading the input as {best} ({score:.2}).");
+  }
+  Some(best)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Samples of each format with the expected detection.
+  const CORPUS: [(&str, Option<Format>); 12] = [
+    (
+      r#"{"type": "FeatureCollection", "features": [{"type": "Feature", "properties": {}, "geometry": {"type": "Point", "coordinates": [13.4, 52.5]}}]}"#,
+      Some(Format::GeoJson),
+    ),
+    // Cut off after the buffer of stdin.
+    (
+      r#"{"type": "FeatureCollection", "features": [{"type": "Feature", "geometry": {"type": "LineString", "coordinates": [[13.4, 52.5], [13.5"#,
+      Some(Format::GeoJson),
+    ),
+    (
+      "  {\n  \"type\": \"Point\",\n  \"coordinates\": [13.4, 52.5]\n}\n",
+      Some(Format::GeoJson),
+    ),
+    (
+      r#"<?xml version="1.0"?><kml xmlns="http://www.opengis.net/kml/2.2"><Document>"#,
+      Some(Format::Kml),
+    ),
+    (
+      "$GPGSV,3,1,11\n$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\n",
+      Some(Format::Nmea),
+    ),
+    ("_p~iF~ps|U_ulLnnqC_mqNvxq`@\n", Some(Format::Polyline)),
+    (
+      r#"{"formatVersion": "0.0.12", "routes": [{"legs": [{"points": [{"latitude": 52.5, "longitude": 13.4}]}]}]}"#,
+      Some(Format::TtJson),
+    ),
+    ("52.5, 13.4\n52.6, 13.5 red\n", Some(Format::Grep)),
+    (
+      "2024-01-15 12:00:00 INFO vehicle 7 at 52.5, 13.4\n2024-01-15 12:00:01 INFO idle\n",
+      Some(Format::Grep),
+    ),
+    ("no coordinates here\nnor here\n", None),
+    ("", None),
+    (r#"{"name": "not geographic"}"#, None),
+  ];
+
+  #[test]
+  fn corpus() {
+    for (sample, expected) in CORPUS {
+      assert_eq!(detect(sample.as_bytes(), None), expected, "{sample}");
+    }
+  }
+
+  #[test]
+  fn prefer() {
+    // Grep only reads polylines with `--grep-polylines`.
+    let polyline = "_p~iF~ps|U_ulLnnqC_mqNvxq`@\n".as_bytes();
+    assert_eq!(detect(polyline, Some(Format::Grep)), Some(Format::Polyline));
+    // Both read a part of the lines.
+    let mixed = "$GPGSV,3,1,11\n48.1, 11.5\n48.2, 11.6\n".as_bytes();
+    assert_eq!(scores(mixed).len(), 2);
+    assert_eq!(detect(mixed, Some(Format::Grep)), Some(Format::Grep));
+    assert_eq!(detect(mixed, Some(Format::Nmea)), Some(Format::Nmea));
+    assert_eq!("GeoJSON".parse(), Ok(Format::GeoJson));
+    assert!("shapefile".parse::<Format>().is_err());
+  }
+}
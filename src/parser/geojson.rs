@@ -0,0 +1,367 @@
+use std::str::FromStr;
+
+use log::error;
+use serde_json::{json, Value};
+
+use crate::export::GeoJsonExporter;
+use crate::map::{
+  coordinates::Coordinate,
+  map_event::{Color, FillStyle, GeoJsonSource, Icon, Layer, MapEvent, Shape, Style},
+};
+
+use super::Parser;
+
+/// Reads a GeoJSON `FeatureCollection`, `Feature`, or geometry into one layer.
+/// Multi geometries and geometry collections are split into single shapes, polygons lose their
+/// holes. The parts of a multi geometry stay one feature by their `part_of`. The simplestyle properties `stroke`, `marker-color`, `fill`, `fill-opacity`,
+/// `stroke-width`, `marker-size`, `marker-symbol`, and `description` are kept, the label is read from `label`,
+/// `name`, or `title`. Colors become the closest color of the map, `stroke-opacity` is ignored.
+/// The other properties are kept as properties of the shapes.
+/// Points with a `radius` in meters, as written by Leaflet and other drawing tools, are circles.
+/// The shapes keep the feature they were read from, so the exporter writes its id, properties, and
+/// geometry again.
+#[derive(Debug)]
+pub struct GeoJsonParser {
+  data: String,
+  color: Color,
+  layer: String,
+}
+
+impl Default for GeoJsonParser {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl GeoJsonParser {
+  #[must_use]
+  pub fn new() -> Self {
+    Self {
+      data: String::new(),
+      color: Color::default(),
+      layer: "GeoJSON".to_string(),
+    }
+  }
+
+  #[must_use]
+  pub fn with_color(mut self, color: Color) -> Self {
+    self.color = color;
+    self
+  }
+
+  #[must_use]
+  pub fn with_layer(mut self, layer: String) -> Self {
+    self.layer = layer;
+    self
+  }
+
+  /// The shapes of a GeoJSON document.
+  #[must_use]
+  pub fn shapes(&self, document: &Value) -> Vec<Shape> {
+    let mut shapes = vec![];
+    match document["type"].as_str() {
+      Some("FeatureCollection") => {
+        for feature in document["features"].as_array().into_iter().flatten() {
+          self.feature(feature, &mut shapes);
+        }
+      }
+      Some("Feature") => self.feature(document, &mut shapes),
+      _ => self.feature(&json!({"geometry": document}), &mut shapes),
+    }
+    shapes
+  }
+
+  #[allow(clippy::cast_possible_truncation)]
+  fn feature(&self, feature: &Value, shapes: &mut Vec<Shape>) {
+    let (geometry, properties) = (&feature["geometry"], &feature["properties"]);
+    let text = |keys: &[&str]| {
+      keys
+        .iter()
+        .find_map(|key| properties.get(key).and_then(Value::as_str))
+    };
+    let number = |key: &str| properties.get(key).and_then(Value::as_f64);
+    let stroke = text(&["stroke", "marker-color"]).and_then(parse_color);
+    let fill_color = text(&["fill"]).and_then(parse_color);
+    let label = text(&["label", "name", "title"]).map(String::from);
+    let description = text(&["description"]).map(String::from);
+    let attributes = attributes(properties);
+    let icon = text(&["marker-symbol"]).map(|symbol| Icon::new(symbol.to_string()));
+    let fill_opacity = number("fill-opacity");
+    let width = number("stroke-width")
+      .filter(|width| *width > 0.)
+      .map(|width| width as f32);
+    let radius = match text(&["marker-size"]) {
+      Some("small") => Some(2.),
+      Some("large") => Some(5.),
+      Some("medium") => Some(Style::DEFAULT_RADIUS),
+      _ => None,
+    };
+    let meters = number("radius")
+      .filter(|meters| *meters > 0.)
+      .map(|meters| meters as f32);
+
+    let start = shapes.len();
+    self::geometry(geometry, shapes);
+    let multi = geometry["type"]
+      .as_str()
+      .is_some_and(|kind| kind.starts_with("Multi"));
+    // The index of the first part is unique in the document.
+    let part_of = (multi && shapes.len() - start > 1).then_some(start);
+    for shape in &mut shapes[start..] {
+      if shape.coordinates.len() == 1 && meters.is_some() {
+        shape.radius = meters;
+        if fill_color.is_some() {
+          shape.style.fill = FillStyle::Transparent;
+        }
+      }
+      let polygon = shape.style.fill != FillStyle::NoFill;
+      shape.style.color = match (stroke, fill_color) {
+        (Some(color), _) => color,
+        (None, Some(color)) if polygon => color,
+        _ => self.color,
+      };
+      shape.part_of = part_of;
+      shape.label.clone_from(&label);
+      shape.description.clone_from(&description);
+      shape.properties.clone_from(&attributes);
+      if shape.coordinates.len() == 1 {
+        shape.icon.clone_from(&icon);
+      }
+      shape.style.width = width;
+      shape.style.radius = radius;
+      if polygon && fill_opacity.is_some_and(|opacity| opacity >= 1.) {
+        shape.style.fill = FillStyle::Solid;
+      }
+      shape.source = Some(Box::new(GeoJsonSource {
+        id: feature.get("id").filter(|id| !id.is_null()).cloned(),
+        geometry: geometry.clone(),
+        properties: properties.as_object().cloned().unwrap_or_default(),
+        read_as: GeoJsonExporter::style_properties(shape),
+      }));
+    }
+  }
+}
+
+/// The properties read as style, label, or by the exporter instead of kept as properties.
+const STYLE_PROPERTIES: [&str; 13] = [
+  "stroke",
+  "stroke-width",
+  "stroke-opacity",
+  "fill",
+  "fill-opacity",
+  "marker-color",
+  "marker-size",
+  "marker-symbol",
+  "label",
+  "title",
+  "description",
+  "radius",
+  "layer",
+];
+
+/// The properties besides the style as text, nested values as JSON.
+fn attributes(properties: &Value) -> Vec<(String, String)> {
+  properties
+    .as_object()
+    .into_iter()
+    .flatten()
+    .filter(|(key, _)| !STYLE_PROPERTIES.contains(&key.as_str()))
+    .filter_map(|(key, value)| {
+      let text = match value {
+        Value::Null => return None,
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+      };
+      Some((key.clone(), text))
+    })
+    .collect()
+}
+
+/// Reads a color name or the color closest to a hex value.
+fn parse_color(color: &str) -> Option<Color> {
+  Color::from_str(color)
+    .ok()
+    .or_else(|| Color::from_hex(color))
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn position(value: &Value) -> Option<Coordinate> {
+  let position = value.as_array()?;
+  Some(Coordinate {
+    lat: position.get(1)?.as_f64()? as f32,
+    lon: position.first()?.as_f64()? as f32,
+  })
+}
+
+fn positions(value: &Value) -> Option<Vec<Coordinate>> {
+  value.as_array()?.iter().map(position).collect()
+}
+
+fn polygon(value: &Value) -> Option<Shape> {
+  let outer = value.as_array()?.first()?;
+  Some(Shape::new(positions(outer)?).with_fill(FillStyle::Transparent))
+}
+
+fn geometry(geometry: &Value, shapes: &mut Vec<Shape>) {
+  let coordinates = &geometry["coordinates"];
+  let parts = || coordinates.as_array().into_iter().flatten();
+  match geometry["type"].as_str() {
+    Some("Point") => shapes.extend(position(coordinates).map(|c| Shape::new(vec![c]))),
+    Some("MultiPoint") => shapes.extend(parts().filter_map(position).map(|c| Shape::new(vec![c]))),
+    Some("LineString") => shapes.extend(positions(coordinates).map(Shape::new)),
+    Some("MultiLineString") => shapes.extend(parts().filter_map(positions).map(Shape::new)),
+    Some("Polygon") => shapes.extend(polygon(coordinates)),
+    Some("MultiPolygon") => shapes.extend(parts().filter_map(polygon)),
+    Some("GeometryCollection") => {
+      for part in geometry["geometries"].as_array().into_iter().flatten() {
+        self::geometry(part, shapes);
+      }
+    }
+    Some(other) => error!("Unknown GeoJSON geometry: {other}"),
+    None => (),
+  }
+}
+
+impl Parser for GeoJsonParser {
+  fn parse_line(&mut self, line: &str) -> Option<MapEvent> {
+    self.data += line;
+    None
+  }
+
+  fn finalize(&self) -> Option<MapEvent> {
+    let document: Value = serde_json::from_str(&self.data)
+      .map_err(|e| error!("Cannot read GeoJSON: {e}"))
+      .ok()?;
+    let mut layer = Layer::new(self.layer.clone());
+    layer.shapes = self.shapes(&document);
+    Some(MapEvent::Layer(layer))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_features() {
+    let data = r##"{"type": "FeatureCollection", "features": [
+      {"type": "Feature", "properties": {"name": "Berlin", "marker-color": "#ff0000", "marker-symbol": "bus", "population": 3700000},
+       "geometry": {"type": "Point", "coordinates": [13.4, 52.5]}},
+      {"type": "Feature", "properties": null,
+       "geometry": {"type": "MultiLineString", "coordinates": [[[13, 52], [14, 53]], [[10, 50], [11, 51]]]}},
+      {"type": "Feature", "properties": {"fill-opacity": 1, "stroke-width": 5},
+       "geometry": {"type": "Polygon", "coordinates": [[[13, 52], [14, 53], [14, 52], [13, 52]], [[13.5, 52.5], [13.6, 52.6], [13.6, 52.5], [13.5, 52.5]]]}}
+    ]}"##;
+    let mut parser = GeoJsonParser::new().with_color(Color::Green);
+    for line in data.lines() {
+      parser.parse_line(line);
+    }
+    let Some(MapEvent::Layer(layer)) = parser.finalize() else {
+      panic!("no layer");
+    };
+    assert_eq!(layer.id, "GeoJSON");
+    assert_eq!(layer.shapes.len(), 4);
+    assert_eq!(layer.shapes[0].style.color, Color::Red);
+    assert_eq!(layer.shapes[0].label.as_deref(), Some("Berlin"));
+    assert_eq!(layer.shapes[0].icon, Some(Icon::new("bus".to_string())));
+    assert_eq!(
+      layer.shapes[0].properties,
+      vec![
+        ("name".to_string(), "Berlin".to_string()),
+        ("population".to_string(), "3700000".to_string())
+      ]
+    );
+    assert!((layer.shapes[0].coordinates[0].lat - 52.5).abs() < 0.001);
+    assert_eq!(layer.shapes[1].style.color, Color::Green);
+    assert_eq!(layer.shapes[2].coordinates.len(), 2);
+    assert_eq!(
+      (layer.shapes[1].part_of, layer.shapes[2].part_of),
+      (Some(1), Some(1))
+    );
+    assert_eq!(layer.shapes[3].part_of, None);
+    assert_eq!(layer.shapes[3].style.fill, FillStyle::Solid);
+    assert_eq!(layer.shapes[3].style.width, Some(5.));
+    assert_eq!(layer.shapes[3].coordinates.len(), 4);
+  }
+
+  #[test]
+  fn simplestyle() {
+    let data = r##"{"type": "Feature",
+      "properties": {"title": "Park", "description": "Open all day.", "stroke": "#555555",
+                     "fill": "#00f", "fill-opacity": 0.5, "marker-size": "large"},
+      "geometry": {"type": "Polygon", "coordinates": [[[13, 52], [14, 53], [14, 52], [13, 52]]]}}"##;
+    let shapes = GeoJsonParser::new().shapes(&serde_json::from_str(data).unwrap());
+    assert_eq!(shapes[0].style.color, Color::Grey);
+    assert_eq!(shapes[0].style.fill, FillStyle::Transparent);
+    assert_eq!(shapes[0].label.as_deref(), Some("Park"));
+    assert_eq!(shapes[0].description.as_deref(), Some("Open all day."));
+
+    let fill_only = data.replace(r##""stroke": "#555555","##, "");
+    let shapes = GeoJsonParser::new().shapes(&serde_json::from_str(&fill_only).unwrap());
+    assert_eq!(shapes[0].style.color, Color::Blue);
+  }
+
+  #[test]
+  fn read_export() {
+    let mut layer = Layer::new("test".to_string());
+    layer.shapes = vec![Shape::new(vec![
+      Coordinate { lat: 52., lon: 13. },
+      Coordinate { lat: 53., lon: 14. },
+    ])
+    .with_color(Color::DarkRed)
+    .with_label(Some("line".to_string()))
+    .with_description(Some("A line.".to_string()))
+    .with_properties(vec![
+      ("lanes".to_string(), "2".to_string()),
+      ("name".to_string(), "Main Street".to_string()),
+    ])];
+    layer.shapes[0].style.width = Some(5.);
+    layer.shapes.push(
+      Shape::new(vec![Coordinate { lat: 52., lon: 13. }])
+        .with_color(Color::Red)
+        .with_fill(FillStyle::Transparent)
+        .with_radius(Some(250.)),
+    );
+    let document = GeoJsonExporter::default().feature_collection(&[layer.clone()]);
+    let shapes: Vec<Shape> = (GeoJsonParser::new().shapes(&document).into_iter())
+      .map(|shape| shape.with_source(None))
+      .collect();
+    assert_eq!(shapes, layer.shapes);
+  }
+
+  #[test]
+  fn round_trip() {
+    let data = r##"{"type": "FeatureCollection", "features": [
+      {"type": "Feature", "id": "way/42", "properties": {"name": "Main Street", "lanes": 2,
+        "oneway": true, "tags": {"surface": "asphalt"}, "stroke": "#ff0000", "stroke-width": 3},
+       "geometry": {"type": "LineString", "coordinates": [[13.4012345, 52.5123456], [13.5, 52.6]]}},
+      {"type": "Feature", "id": 7, "properties": {"lanes": 4},
+       "geometry": {"type": "MultiPolygon", "coordinates": [
+         [[[13, 52], [14, 53], [14, 52], [13, 52]], [[13.5, 52.5], [13.6, 52.6], [13.6, 52.5], [13.5, 52.5]]],
+         [[[10, 50], [11, 51], [11, 50], [10, 50]]]]}}
+    ]}"##;
+    let document: Value = serde_json::from_str(data).unwrap();
+    let mut layer = Layer::new("roads".to_string());
+    layer.shapes = GeoJsonParser::new().shapes(&document);
+    let exported = GeoJsonExporter::default().feature_collection(&[layer.clone()]);
+    assert_eq!(exported, document);
+
+    layer.shapes[0].style.color = Color::Blue;
+    layer.shapes[0].properties[0].1 = "3".to_string();
+    layer.shapes[1].coordinates[0].lat = 52.1;
+    let exported = GeoJsonExporter::default().feature_collection(&[layer]);
+    let features = &exported["features"];
+    assert_eq!(features[0]["id"], "way/42");
+    assert_eq!(features[0]["properties"]["stroke"], "#0000ff");
+    assert_eq!(features[0]["properties"]["stroke-width"], 3);
+    assert_eq!(features[0]["properties"]["lanes"], "3");
+    assert_eq!(
+      features[0]["properties"]["tags"],
+      document["features"][0]["properties"]["tags"]
+    );
+    assert_eq!(features[0]["geometry"], document["features"][0]["geometry"]);
+    assert_eq!(features[1]["id"], 7);
+    assert_eq!(features[1]["geometry"]["type"], "MultiPolygon");
+    assert_ne!(features[1]["geometry"], document["features"][1]["geometry"]);
+  }
+}
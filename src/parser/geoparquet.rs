@@ -0,0 +1,253 @@
+use std::{
+  io::{BufRead, Cursor, Read},
+  path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use arrow::{
+  array::{Array, BinaryArray, LargeBinaryArray},
+  datatypes::Schema,
+  error::ArrowError,
+  ipc::reader::FileReader,
+  record_batch::RecordBatch,
+  util::display::{ArrayFormatter, FormatOptions},
+};
+use bytes::Bytes;
+use log::error;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde::Deserialize;
+
+use crate::map::map_event::{Color, Layer, MapEvent};
+
+use super::{wkb::parse_wkb, FileParser};
+
+const BATCH_SIZE: usize = 10_000;
+
+/// The `geo` metadata of the schema as defined by the GeoParquet specification.
+#[derive(Deserialize, Debug)]
+struct GeoMetadata {
+  primary_column: String,
+}
+
+type Batches = Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>>>;
+
+fn geometry_column(schema: &Schema) -> String {
+  schema
+    .metadata()
+    .get("geo")
+    .and_then(|geo| serde_json::from_str::<GeoMetadata>(geo).ok())
+    .map_or_else(|| "geometry".to_string(), |geo| geo.primary_column)
+}
+
+/// Parses GeoParquet and Arrow IPC files with WKB encoded geometries.
+/// The other columns are used as label. The file is read in batches, each one is sent as layer.
+#[derive(Debug, Default)]
+pub struct GeoParquetParser {
+  ipc: bool,
+  color: Color,
+  columns: Option<Vec<String>>,
+  max_rows: Option<usize>,
+}
+
+impl GeoParquetParser {
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Returns a parser for `.parquet`, `.arrow`, and `.feather` files.
+  #[must_use]
+  pub fn for_path(path: &Path) -> Option<Self> {
+    match path.extension()?.to_str()?.to_lowercase().as_str() {
+      "parquet" | "geoparquet" => Some(Self::new()),
+      "arrow" | "feather" | "ipc" => Some(Self::new().with_ipc(true)),
+      _ => None,
+    }
+  }
+
+  /// Reads the Arrow IPC file format instead of parquet.
+  #[must_use]
+  pub fn with_ipc(mut self, ipc: bool) -> Self {
+    self.ipc = ipc;
+    self
+  }
+
+  #[must_use]
+  pub fn with_color(mut self, color: Color) -> Self {
+    self.color = color;
+    self
+  }
+
+  /// Only these columns are used for the label. All columns are used if `None`.
+  #[must_use]
+  pub fn with_columns(mut self, columns: Option<Vec<String>>) -> Self {
+    self.columns = columns;
+    self
+  }
+
+  /// Stops reading after this number of rows.
+  #[must_use]
+  pub fn with_max_rows(mut self, max_rows: Option<usize>) -> Self {
+    self.max_rows = max_rows;
+    self
+  }
+
+  fn batches(&self, data: Vec<u8>) -> Result<(String, Batches)> {
+    if self.ipc {
+      let reader = FileReader::try_new(Cursor::new(data), None)?;
+      let geometry = geometry_column(&reader.schema());
+      Ok((geometry, Box::new(reader)))
+    } else {
+      let builder = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(data))?;
+      let geometry = geometry_column(builder.schema());
+      Ok((
+        geometry,
+        Box::new(builder.with_batch_size(BATCH_SIZE).build()?),
+      ))
+    }
+  }
+
+  fn to_layer(&self, batch: &RecordBatch, geometry: &str) -> Result<Layer> {
+    let column = batch
+      .column_by_name(geometry)
+      .ok_or(anyhow!("No geometry column {geometry}."))?;
+    let wkbs: Vec<Option<&[u8]>> =
+      if let Some(array) = column.as_any().downcast_ref::<BinaryArray>() {
+        array.iter().collect()
+      } else if let Some(array) = column.as_any().downcast_ref::<LargeBinaryArray>() {
+        array.iter().collect()
+      } else {
+        return Err(anyhow!(
+          "Geometry column {geometry} is {}, only WKB is supported.",
+          column.data_type()
+        ));
+      };
+
+    let options = FormatOptions::default();
+    let schema = batch.schema();
+    let attributes = schema
+      .fields()
+      .iter()
+      .zip(batch.columns())
+      .filter(|(field, _)| {
+        field.name() != geometry
+          && self
+            .columns
+            .as_ref()
+            .map_or(true, |columns| columns.contains(field.name()))
+      })
+      .map(|(field, array)| {
+        Ok((
+          field.name(),
+          ArrayFormatter::try_new(array.as_ref(), &options)?,
+        ))
+      })
+      .collect::<Result<Vec<_>, ArrowError>>()?;
+
+    let mut layer = Layer::new("GeoParquet".to_string());
+    for (row, wkb) in wkbs.into_iter().enumerate() {
+      let Some(parts) = wkb.and_then(|wkb| parse_wkb(wkb).ok()) else {
+        continue;
+      };
+      let label = attributes
+        .iter()
+        .map(|(name, formatter)| (name, formatter.value(row).to_string()))
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| format!("{name}: {value}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+      let label = (!label.is_empty()).then_some(label);
+      layer.shapes.extend(
+        parts
+          .into_iter()
+          .map(|part| part.into_shape(self.color, label.clone())),
+      );
+    }
+    Ok(layer)
+  }
+}
+
+impl FileParser for GeoParquetParser {
+  fn parse<'a>(
+    &'a mut self,
+    mut file: Box<dyn BufRead>,
+  ) -> Box<dyn Iterator<Item = MapEvent> + '_> {
+    let mut data = vec![];
+    if let Err(e) = file.read_to_end(&mut data) {
+      error!("Cannot read file: {e}");
+      return Box::new(std::iter::empty());
+    }
+    let (geometry, batches) = match self.batches(data) {
+      Ok(batches) => batches,
+      Err(e) => {
+        error!("Cannot read geoparquet: {e}");
+        return Box::new(std::iter::empty());
+      }
+    };
+    let mut remaining = self.max_rows.unwrap_or(usize::MAX);
+    Box::new(batches.map_while(move |batch| {
+      if remaining == 0 {
+        return None;
+      }
+      let batch = batch.map_err(|e| error!("Cannot read batch: {e}")).ok()?;
+      let batch = batch.slice(0, batch.num_rows().min(remaining));
+      remaining -= batch.num_rows();
+      match self.to_layer(&batch, &geometry) {
+        Ok(layer) => Some(MapEvent::Layer(layer)),
+        Err(e) => {
+          error!("{e}");
+          None
+        }
+      }
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use arrow::{
+    array::{ArrayRef, StringArray},
+    datatypes::{DataType, Field},
+    ipc::writer::FileWriter,
+  };
+
+  use super::*;
+
+  #[test]
+  fn parse_ipc() {
+    let point = [
+      1u8, 1, 0, 0, 0, 0xCD, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0x2A, 0x40, 0, 0, 0, 0, 0, 0x40, 0x4A,
+      0x40,
+    ];
+    let schema = Arc::new(Schema::new(vec![
+      Field::new("name", DataType::Utf8, true),
+      Field::new("geometry", DataType::Binary, true),
+    ]));
+    let batch = RecordBatch::try_new(
+      schema.clone(),
+      vec![
+        Arc::new(StringArray::from(vec![Some("Berlin"), None])) as ArrayRef,
+        Arc::new(BinaryArray::from(vec![Some(&point[..]), Some(&point[..])])),
+      ],
+    )
+    .unwrap();
+    let mut data = vec![];
+    let mut writer = FileWriter::try_new(&mut data, &schema).unwrap();
+    writer.write(&batch).unwrap();
+    writer.finish().unwrap();
+    drop(writer);
+
+    let mut parser = GeoParquetParser::new()
+      .with_ipc(true)
+      .with_max_rows(Some(1));
+    let events: Vec<_> = parser.parse(Box::new(Cursor::new(data))).collect();
+    assert_eq!(events.len(), 1);
+    let MapEvent::Layer(layer) = &events[0] else {
+      panic!("Expected a layer.");
+    };
+    assert_eq!(layer.shapes.len(), 1);
+    assert_eq!(layer.shapes[0].label.as_deref(), Some("name: Berlin"));
+  }
+}
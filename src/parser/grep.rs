@@ -1,14 +1,16 @@
-use std::str::FromStr;
+use std::{borrow::Cow, str::FromStr};
 
+use chrono::{DateTime, NaiveDateTime};
 use log::{debug, error};
 use regex::{Regex, RegexBuilder};
 
 use crate::map::{
+  coordinate_format::GridCoordinateFinder,
   coordinates::Coordinate,
-  map_event::{Color, FillStyle, Layer, MapEvent, Shape},
+  map_event::{Color, Dash, FillStyle, Layer, MapEvent, Shape},
 };
 
-use super::Parser;
+use super::{polyline, Parser};
 
 #[allow(clippy::module_name_repetitions)]
 #[derive(Clone, Debug)]
@@ -21,6 +23,38 @@ pub struct GrepParser {
   coord_re: Regex,
   clear_re: Regex,
   label_re: Option<Regex>,
+  /// Composes the label of the named groups of the label pattern, e.g. `{vehicle} @ {speed}km/h`.
+  label_template: Option<String>,
+  hex_color_re: Regex,
+  /// `width=3`, `fill=none`, or `dash=10/5`.
+  style_re: Regex,
+  width: Option<f32>,
+  dash: Option<Dash>,
+  polyline_re: Regex,
+  polyline_precision: Option<u32>,
+  /// `circle 52.5,13.4 r=500m`, a circle with the radius in meters or kilometers.
+  circle_re: Regex,
+  grid: GridCoordinateFinder,
+}
+
+/// Parses RFC 3339 times, times like `2024-01-15 12:00:00` in UTC, and seconds or milliseconds
+/// since the epoch into milliseconds since the epoch.
+fn parse_time(time: &str) -> Option<i64> {
+  if let Ok(time) = DateTime::parse_from_rfc3339(time) {
+    return Some(time.timestamp_millis());
+  }
+  for format in ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"] {
+    if let Ok(time) = NaiveDateTime::parse_from_str(time, format) {
+      return Some(time.and_utc().timestamp_millis());
+    }
+  }
+  let number: i64 = time.parse().ok()?;
+  // Seconds since the epoch stay below 10^11 until the year 5138.
+  Some(if number.abs() < 100_000_000_000 {
+    number * 1000
+  } else {
+    number
+  })
 }
 
 impl Parser for GrepParser {
@@ -34,26 +68,25 @@ impl Parser for GrepParser {
     for l in line.split('\n') {
       self.parse_color(l);
       self.parse_fill(l);
+      self.parse_style(l);
       let label = self.parse_label(l);
+      let time = self.parse_time(l);
+      let (circles, l) = self.parse_circles(l);
+      for (center, radius) in circles {
+        let circle = self.shape(vec![center], label.clone(), time);
+        layer
+          .shapes
+          .push(circle.with_fill(self.fill).with_radius(Some(radius)));
+      }
+      let l = l.as_ref();
+      for coordinates in self.parse_polylines(l) {
+        layer
+          .shapes
+          .push(self.shape(coordinates, label.clone(), time));
+      }
       let coordinates = self.parse_shape(l);
-      match coordinates.len() {
-        0 => (),
-        1 => {
-          layer.shapes.push(
-            Shape::new(coordinates)
-              .with_color(self.color)
-              .with_fill(FillStyle::Solid)
-              .with_label(label),
-          );
-        }
-        _ => {
-          layer.shapes.push(
-            Shape::new(coordinates)
-              .with_color(self.color)
-              .with_fill(self.fill)
-              .with_label(label),
-          );
-        }
+      if !coordinates.is_empty() {
+        layer.shapes.push(self.shape(coordinates, label, time));
       }
     }
 
@@ -94,6 +127,23 @@ impl GrepParser {
       coord_re,
       clear_re,
       label_re: None,
+      label_template: None,
+      hex_color_re: Regex::new(r"(?:^|\s)(#[0-9a-fA-F]{3}(?:[0-9a-fA-F]{3})?)\b").unwrap(),
+      style_re: RegexBuilder::new(r"\b(width|fill|dash)=(\S+)")
+        .case_insensitive(true)
+        .build()
+        .unwrap(),
+      width: None,
+      dash: None,
+      polyline_re: polyline::polyline_regex(),
+      polyline_precision: None,
+      circle_re: RegexBuilder::new(
+        r"\bcircle\s+(-?\d+(?:\.\d*)?)\s*,\s*(-?\d+(?:\.\d*)?)\s+r=(\d+(?:\.\d*)?)((?:km|m)?)\b",
+      )
+      .case_insensitive(true)
+      .build()
+      .unwrap(),
+      grid: GridCoordinateFinder::new(),
     }
   }
 
@@ -111,6 +161,51 @@ impl GrepParser {
     self
   }
 
+  /// Composes labels of the named capture groups of the label pattern, `{name}` is replaced by the
+  /// group. Without a template the groups are joined.
+  #[must_use]
+  pub fn with_label_template(mut self, template: Option<String>) -> Self {
+    self.label_template = template;
+    self
+  }
+
+  /// Sets the precision of encoded polylines found in the lines. `None`, the default, disables
+  /// the detection.
+  #[must_use]
+  pub fn with_polyline_precision(mut self, precision: Option<u32>) -> Self {
+    self.polyline_precision = precision;
+    self
+  }
+
+  fn parse_polylines(&self, line: &str) -> Vec<Vec<Coordinate>> {
+    self
+      .polyline_precision
+      .map(|precision| polyline::find_polylines(&self.polyline_re, line, precision))
+      .unwrap_or_default()
+  }
+
+  /// The circles of the line as center and radius in meters, and the line without them, so their
+  /// centers are not read as points.
+  fn parse_circles<'a>(&self, line: &'a str) -> (Vec<(Coordinate, f32)>, Cow<'a, str>) {
+    let circles = self
+      .circle_re
+      .captures_iter(line)
+      .filter_map(|c| {
+        let (_, [lat, lon, radius, unit]) = c.extract();
+        let factor = if unit.eq_ignore_ascii_case("km") {
+          1000.
+        } else {
+          1.
+        };
+        Some((
+          self.parse_coordinate(lat, lon)?,
+          radius.parse::<f32>().ok()? * factor,
+        ))
+      })
+      .collect();
+    (circles, self.circle_re.replace_all(line, ""))
+  }
+
   fn parse_clear(&self, line: &str) -> Option<MapEvent> {
     self.clear_re.is_match(line).then_some(MapEvent::Clear)
   }
@@ -123,6 +218,51 @@ impl GrepParser {
     }
   }
 
+  /// Reads hex colors like `#ff8800` as the closest color and the style tokens `width=<pixels>`,
+  /// `fill=none|solid|transparent`, and `dash=none|dashed|dotted|10/5`. Like colors they stay for
+  /// the following lines.
+  fn parse_style(&mut self, line: &str) {
+    for (_, [hex]) in self.hex_color_re.captures_iter(line).map(|c| c.extract()) {
+      if let Some(color) = Color::from_hex(hex) {
+        self.color = color;
+      }
+    }
+    for (_, [key, value]) in self.style_re.captures_iter(line).map(|c| c.extract()) {
+      match (key.to_lowercase().as_str(), value.to_lowercase().as_str()) {
+        ("width", width) => match width.parse::<f32>() {
+          Ok(width) if width > 0. => self.width = Some(width),
+          _ => error!("Failed parsing width {width}"),
+        },
+        ("fill", "none") => self.fill = FillStyle::NoFill,
+        ("dash", "none") => self.dash = None,
+        ("dash", dash) => match Dash::from_str(dash) {
+          Ok(dash) => self.dash = Some(dash),
+          Err(()) => error!("Failed parsing dash {dash}"),
+        },
+        // Fill styles are read by parse_fill.
+        _ => (),
+      }
+    }
+  }
+
+  /// A shape in the current style, points are always filled.
+  fn shape(&self, coordinates: Vec<Coordinate>, label: Option<String>, time: Option<i64>) -> Shape {
+    let fill = if coordinates.len() == 1 {
+      FillStyle::Solid
+    } else {
+      self.fill
+    };
+    let timestamps = time.map(|t| vec![t; coordinates.len()]).unwrap_or_default();
+    let mut shape = Shape::new(coordinates)
+      .with_color(self.color)
+      .with_fill(fill)
+      .with_label(label)
+      .with_timestamps(timestamps);
+    shape.style.width = self.width;
+    shape.style.dash = self.dash;
+    shape
+  }
+
   fn parse_fill(&mut self, line: &str) {
     for (_, [fill]) in self.fill_re.captures_iter(line).map(|c| c.extract()) {
       let _ = FillStyle::from_str(fill)
@@ -138,6 +278,9 @@ impl GrepParser {
         coordinates.push(coord);
       }
     }
+    if coordinates.is_empty() {
+      coordinates = self.grid.find(line);
+    }
     coordinates
   }
 
@@ -145,7 +288,22 @@ impl GrepParser {
     if let Some(re) = &self.label_re {
       let res: Vec<_> = re
         .captures_iter(line)
-        .map(|c| c.extract::<1>().1[0])
+        .map(|c| match &self.label_template {
+          Some(template) => re
+            .capture_names()
+            .flatten()
+            .fold(template.clone(), |label, name| {
+              let value = c.name(name).map_or("", |m| m.as_str());
+              label.replace(&format!("{{{name}}}"), value)
+            }),
+          None => c
+            .iter()
+            .skip(1)
+            .flatten()
+            .map(|m| m.as_str())
+            .collect::<Vec<_>>()
+            .join(" "),
+        })
         .collect();
 
       if res.is_empty() {
@@ -158,6 +316,17 @@ impl GrepParser {
     }
   }
 
+  /// The time of the capture group `time` of the label pattern in milliseconds since the epoch.
+  fn parse_time(&self, line: &str) -> Option<i64> {
+    let time = self
+      .label_re
+      .as_ref()?
+      .captures(line)?
+      .name("time")?
+      .as_str();
+    parse_time(time)
+  }
+
   fn parse_coordinate(&self, x: &str, y: &str) -> Option<Coordinate> {
     let lat = match x.parse::<f32>() {
       Ok(v) => v,
@@ -180,3 +349,91 @@ impl GrepParser {
     coordinates.is_valid().then_some(coordinates)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn label_template() {
+    let mut parser = GrepParser::new(false)
+      .with_label_pattern(r"vehicle=(?<vehicle>\w+) speed=(?<speed>\d+) time=(?<time>\S+)")
+      .with_label_template(Some("{vehicle} @ {speed}km/h".to_string()));
+    let line = "vehicle=bus7 speed=42 time=2024-01-15T12:00:00Z at 52.5, 13.4";
+    let Some(MapEvent::Layer(layer)) = parser.parse_line(line) else {
+      panic!("Expected a layer.");
+    };
+    let shape = &layer.shapes[0];
+    assert_eq!(shape.label.as_deref(), Some("bus7 @ 42km/h"));
+    assert_eq!(shape.timestamps, vec![1_705_320_000_000]);
+
+    let mut parser = GrepParser::new(false).with_label_pattern(r"(\w+)=(\d+)");
+    let Some(MapEvent::Layer(layer)) = parser.parse_line("id=7 52.5, 13.4") else {
+      panic!("Expected a layer.");
+    };
+    assert_eq!(layer.shapes[0].label.as_deref(), Some("id 7"));
+  }
+
+  #[test]
+  fn style_tokens() {
+    let mut parser = GrepParser::new(false);
+    let line = "#f00 width=5 dash=10/5 fill=none 52.5, 13.4 52.6, 13.5 52.5, 13.6";
+    let Some(MapEvent::Layer(layer)) = parser.parse_line(line) else {
+      panic!("Expected a layer.");
+    };
+    let style = &layer.shapes[0].style;
+    assert_eq!(style.color, Color::Red);
+    assert_eq!(style.width, Some(5.));
+    assert_eq!(style.dash, Some(Dash { on: 10., off: 5. }));
+    assert_eq!(style.fill, FillStyle::NoFill);
+
+    // The style stays for the next lines until it is changed.
+    let Some(MapEvent::Layer(layer)) = parser.parse_line("dash=none 52.5, 13.4") else {
+      panic!("Expected a layer.");
+    };
+    let style = &layer.shapes[0].style;
+    assert_eq!(
+      (style.color, style.width, style.dash),
+      (Color::Red, Some(5.), None)
+    );
+  }
+
+  #[test]
+  fn times() {
+    assert_eq!(parse_time("2024-01-15 12:00:00.5"), Some(1_705_320_000_500));
+    assert_eq!(parse_time("1705320000"), Some(1_705_320_000_000));
+    assert_eq!(parse_time("1705320000000"), Some(1_705_320_000_000));
+    assert_eq!(parse_time("noon"), None);
+  }
+
+  #[test]
+  fn circles() {
+    let mut parser = GrepParser::new(false);
+    let line = "depot circle 52.5,13.4 r=2.5km circle 52.6, 13.5 r=500 52.7, 13.6";
+    let Some(MapEvent::Layer(layer)) = parser.parse_line(line) else {
+      panic!("Expected a layer.");
+    };
+    let circles: Vec<_> = layer.shapes.iter().map(Shape::circle).collect();
+    assert_eq!(
+      circles,
+      vec![
+        Some((
+          Coordinate {
+            lat: 52.5,
+            lon: 13.4
+          },
+          2500.
+        )),
+        Some((
+          Coordinate {
+            lat: 52.6,
+            lon: 13.5
+          },
+          500.
+        )),
+        None,
+      ]
+    );
+    assert_eq!(layer.shapes[2].coordinates.len(), 1);
+  }
+}
@@ -0,0 +1,426 @@
+use std::{
+  collections::HashMap,
+  hash::{DefaultHasher, Hash, Hasher},
+  io::{BufRead, Cursor, Read},
+  path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use log::error;
+use roxmltree::{Document, Node};
+
+use crate::map::{
+  coordinates::Coordinate,
+  map_event::{Color, FillStyle, ImageOverlay, Layer, MapEvent, Shape},
+};
+
+use super::FileParser;
+
+/// The colors of a style. KML colors are written as aabbggrr.
+#[derive(Debug, Default, Clone, Copy)]
+struct Style {
+  line: Option<Color>,
+  fill: Option<bool>,
+}
+
+fn kml_color(color: &str) -> Option<Color> {
+  let color = color.trim();
+  let (blue, green, red) = (color.get(2..4)?, color.get(4..6)?, color.get(6..8)?);
+  Color::from_hex(&format!("#{red}{green}{blue}"))
+}
+
+fn child<'a>(node: Node<'a, 'a>, name: &str) -> Option<Node<'a, 'a>> {
+  node.children().find(|n| n.has_tag_name(name))
+}
+
+fn child_text<'a>(node: Node<'a, 'a>, name: &str) -> Option<&'a str> {
+  child(node, name)?.text().map(str::trim)
+}
+
+fn style(node: Node) -> Style {
+  let line = child(node, "LineStyle")
+    .or_else(|| child(node, "IconStyle"))
+    .and_then(|s| child_text(s, "color"))
+    .and_then(kml_color);
+  let fill = child(node, "PolyStyle")
+    .and_then(|s| child_text(s, "fill"))
+    .map(|fill| fill != "0");
+  Style { line, fill }
+}
+
+/// `lon,lat[,alt]` tuples separated by whitespace.
+fn coordinates(text: &str) -> Vec<Coordinate> {
+  text
+    .split_whitespace()
+    .filter_map(|tuple| {
+      let mut values = tuple.split(',').map(|v| v.parse::<f32>().ok());
+      let (lon, lat) = (values.next()??, values.next()??);
+      Some(Coordinate { lat, lon }).filter(Coordinate::is_valid)
+    })
+    .collect()
+}
+
+/// The radius in meters of a circle around the point of the placemark, stored as `radius` in its
+/// extended data like circles drawn in Google My Maps and other tools.
+fn radius(placemark: Node) -> Option<f32> {
+  placemark
+    .descendants()
+    .filter(|n| n.has_tag_name("Data") || n.has_tag_name("SimpleData"))
+    .find(|n| {
+      n.attribute("name")
+        .is_some_and(|name| name.eq_ignore_ascii_case("radius"))
+    })
+    .and_then(|n| child_text(n, "value").or_else(|| n.text()))
+    .and_then(|radius| radius.trim().parse::<f32>().ok())
+    .filter(|radius| *radius > 0.)
+}
+
+/// The `Data` and `SimpleData` of the extended data of the placemark besides the radius of circles.
+fn properties(placemark: Node) -> Vec<(String, String)> {
+  placemark
+    .descendants()
+    .filter(|n| n.has_tag_name("Data") || n.has_tag_name("SimpleData"))
+    .filter_map(|n| {
+      let name = n.attribute("name")?;
+      let value = child_text(n, "value").or_else(|| n.text().map(str::trim))?;
+      Some((name.to_string(), value.to_string()))
+    })
+    .filter(|(name, _)| !name.eq_ignore_ascii_case("radius"))
+    .collect()
+}
+
+/// A `gx:Track` with the times of its coordinates if all have one.
+fn track(track: Node) -> Shape {
+  let texts = |name| {
+    track
+      .children()
+      .filter(move |n| n.has_tag_name(name))
+      .map(|n| n.text().unwrap_or_default())
+  };
+  let coords: Vec<Coordinate> = texts("coord")
+    .flat_map(|c| coordinates(&c.split_whitespace().collect::<Vec<_>>().join(",")))
+    .collect();
+  let timestamps = texts("when")
+    .map(|t| {
+      DateTime::parse_from_rfc3339(t.trim())
+        .ok()
+        .map(|t| t.timestamp_millis())
+    })
+    .collect::<Option<Vec<_>>>()
+    .filter(|t| t.len() == coords.len());
+  Shape::new(coords).with_timestamps(timestamps.unwrap_or_default())
+}
+
+/// Reads KML files and KMZ archives, e.g. exports of Google Earth or My Maps. The points, lines,
+/// and polygons of placemarks are drawn with their name as label and the color of their style,
+/// polygons lose their holes. The extended data of placemarks become properties, points with a
+/// `radius` in it are circles. Ground overlays become image overlays if their image is in the
+/// archive or next to the file.
+#[derive(Debug, Default)]
+pub struct KmlParser {
+  kmz: bool,
+  /// The directory relative image paths are resolved in.
+  dir: Option<PathBuf>,
+  color: Color,
+}
+
+impl KmlParser {
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Returns a parser for `.kml` and `.kmz` files.
+  #[must_use]
+  pub fn for_path(path: &Path) -> Option<Self> {
+    let parser = match path.extension()?.to_str()?.to_lowercase().as_str() {
+      "kml" => Self::new(),
+      "kmz" => Self::new().with_kmz(true),
+      _ => return None,
+    };
+    Some(parser.with_dir(path.parent().map(Path::to_path_buf)))
+  }
+
+  /// Reads a zip archive with a KML document and its images instead of plain KML.
+  #[must_use]
+  pub fn with_kmz(mut self, kmz: bool) -> Self {
+    self.kmz = kmz;
+    self
+  }
+
+  #[must_use]
+  pub fn with_dir(mut self, dir: Option<PathBuf>) -> Self {
+    self.dir = dir;
+    self
+  }
+
+  #[must_use]
+  pub fn with_color(mut self, color: Color) -> Self {
+    self.color = color;
+    self
+  }
+
+  /// Extracts the KML document and the images of a KMZ archive. The images are written to a
+  /// directory in the temp dir, because overlays are loaded from files.
+  fn unpack(&mut self, data: &[u8]) -> Result<String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    let dir = std::env::temp_dir().join(format!("mapvas_kmz_{:x}", hasher.finish()));
+
+    let mut document = None;
+    for i in 0..archive.len() {
+      let mut file = archive.by_index(i)?;
+      let Some(name) = file.enclosed_name() else {
+        continue;
+      };
+      if name
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("kml"))
+      {
+        // doc.kml is the main document if there are several.
+        if document.is_none() || name == Path::new("doc.kml") {
+          let mut text = String::new();
+          file.read_to_string(&mut text)?;
+          document = Some(text);
+        }
+      } else if file.is_file() {
+        let path = dir.join(name);
+        if let Some(parent) = path.parent() {
+          std::fs::create_dir_all(parent)?;
+        }
+        std::io::copy(&mut file, &mut std::fs::File::create(path)?)?;
+      }
+    }
+    self.dir = Some(dir);
+    document.ok_or(anyhow!("No KML document in the archive."))
+  }
+
+  fn placemark(&self, placemark: Node, styles: &HashMap<&str, Style>, layer: &mut Layer) {
+    let label = child_text(placemark, "name").map(String::from);
+    let referenced = child_text(placemark, "styleUrl")
+      .and_then(|url| styles.get(url.trim_start_matches('#')))
+      .copied()
+      .unwrap_or_default();
+    let inline = child(placemark, "Style").map(style).unwrap_or_default();
+    let color = inline.line.or(referenced.line).unwrap_or(self.color);
+    let fill = match inline.fill.or(referenced.fill) {
+      Some(false) => FillStyle::NoFill,
+      _ => FillStyle::Transparent,
+    };
+
+    let radius = radius(placemark);
+    let properties = properties(placemark);
+    for geometry in placemark.descendants() {
+      let text = |name| child_text(geometry, name).map(coordinates);
+      let shape = match geometry.tag_name().name() {
+        "Point" if radius.is_some() => {
+          text("coordinates").map(|c| Shape::new(c).with_radius(radius).with_fill(fill))
+        }
+        "Point" | "LineString" => text("coordinates").map(Shape::new),
+        "Polygon" => child(geometry, "outerBoundaryIs")
+          .and_then(|boundary| child(boundary, "LinearRing"))
+          .and_then(|ring| child_text(ring, "coordinates"))
+          .map(|c| Shape::new(coordinates(c)).with_fill(fill)),
+        "Track" => Some(track(geometry)),
+        _ => None,
+      };
+      if let Some(shape) = shape.filter(|s| !s.coordinates.is_empty()) {
+        layer.shapes.push(
+          shape
+            .with_color(color)
+            .with_label(label.clone())
+            .with_properties(properties.clone()),
+        );
+      }
+    }
+  }
+
+  fn ground_overlay(&self, overlay: Node) -> Option<ImageOverlay> {
+    let href = child(overlay, "Icon").and_then(|icon| child_text(icon, "href"))?;
+    let path = match &self.dir {
+      Some(dir) if Path::new(href).is_relative() => dir.join(href),
+      _ => PathBuf::from(href),
+    };
+    let bounds = child(overlay, "LatLonBox")?;
+    let value = |name| child_text(bounds, name)?.parse::<f32>().ok();
+    let image = ImageOverlay::new(
+      path,
+      Coordinate {
+        lat: value("north")?,
+        lon: value("west")?,
+      },
+      Coordinate {
+        lat: value("south")?,
+        lon: value("east")?,
+      },
+    );
+    let alpha = child_text(overlay, "color").and_then(|c| u8::from_str_radix(c.get(..2)?, 16).ok());
+    Some(match alpha {
+      Some(alpha) => image.with_opacity(f32::from(alpha) / 255.),
+      None => image,
+    })
+  }
+
+  fn read(&self, text: &str) -> Result<Vec<MapEvent>> {
+    let document = Document::parse(text)?;
+    let styles: HashMap<&str, Style> = document
+      .descendants()
+      .filter(|n| n.has_tag_name("Style"))
+      .filter_map(|n| Some((n.attribute("id")?, style(n))))
+      .collect();
+    let mut layer = Layer::new("KML".to_string());
+    let mut events = vec![];
+    for node in document.descendants() {
+      match node.tag_name().name() {
+        "Placemark" => self.placemark(node, &styles, &mut layer),
+        "GroundOverlay" => events.extend(self.ground_overlay(node).map(MapEvent::ImageOverlay)),
+        _ => {}
+      }
+    }
+    events.insert(0, MapEvent::Layer(layer));
+    Ok(events)
+  }
+}
+
+impl FileParser for KmlParser {
+  fn parse<'a>(
+    &'a mut self,
+    mut file: Box<dyn BufRead>,
+  ) -> Box<dyn Iterator<Item = MapEvent> + '_> {
+    let mut data = vec![];
+    if let Err(e) = file.read_to_end(&mut data) {
+      error!("Cannot read KML: {e}");
+      return Box::new(std::iter::empty());
+    }
+    let text = if self.kmz {
+      self.unpack(&data)
+    } else {
+      String::from_utf8(data).map_err(Into::into)
+    };
+    match text.and_then(|text| self.read(&text)) {
+      Ok(events) => Box::new(events.into_iter()),
+      Err(e) => {
+        error!("Cannot parse KML: {e}");
+        Box::new(std::iter::empty())
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use super::*;
+
+  const KML: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">
+  <Document>
+    <Style id="red"><LineStyle><color>ff0000ff</color></LineStyle><PolyStyle><fill>0</fill></PolyStyle></Style>
+    <Placemark>
+      <name>Area</name>
+      <styleUrl>#red</styleUrl>
+      <Polygon><outerBoundaryIs><LinearRing>
+        <coordinates>13.4,52.5,0 13.5,52.5,0 13.5,52.6,0 13.4,52.5,0</coordinates>
+      </LinearRing></outerBoundaryIs></Polygon>
+    </Placemark>
+    <Placemark>
+      <MultiGeometry>
+        <Point><coordinates>13.4,52.5</coordinates></Point>
+        <LineString><coordinates>13.4,52.5 13.5,52.6</coordinates></LineString>
+      </MultiGeometry>
+    </Placemark>
+    <Placemark>
+      <gx:Track>
+        <when>2024-01-15T12:00:00Z</when>
+        <when>2024-01-15T12:01:00Z</when>
+        <gx:coord>13.4 52.5 30</gx:coord>
+        <gx:coord>13.5 52.6 35</gx:coord>
+      </gx:Track>
+    </Placemark>
+    <GroundOverlay>
+      <color>80ffffff</color>
+      <Icon><href>images/scan.png</href></Icon>
+      <LatLonBox><north>52.6</north><south>52.5</south><east>13.5</east><west>13.4</west></LatLonBox>
+    </GroundOverlay>
+  </Document>
+</kml>"##;
+
+  fn parse(parser: &mut KmlParser, data: Vec<u8>) -> Vec<MapEvent> {
+    parser.parse(Box::new(Cursor::new(data))).collect()
+  }
+
+  #[test]
+  fn kml() {
+    let mut parser = KmlParser::for_path(Path::new("/data/map.kml")).unwrap();
+    let events = parse(&mut parser, KML.as_bytes().to_vec());
+    let [MapEvent::Layer(layer), MapEvent::ImageOverlay(overlay)] = &events[..] else {
+      panic!("Expected a layer and an overlay: {events:?}");
+    };
+    assert_eq!(layer.shapes.len(), 4);
+    let area = &layer.shapes[0];
+    assert_eq!(area.label.as_deref(), Some("Area"));
+    assert_eq!(area.style.color, Color::Red);
+    assert_eq!(area.style.fill, FillStyle::NoFill);
+    assert_eq!(area.coordinates.len(), 4);
+    assert_eq!(layer.shapes[1].coordinates.len(), 1);
+    assert_eq!(
+      layer.shapes[3].timestamps,
+      vec![1_705_320_000_000, 1_705_320_060_000]
+    );
+    assert_eq!(overlay.path, Path::new("/data/images/scan.png"));
+    assert!((overlay.nw.lat - 52.6).abs() < 1e-5 && (overlay.se.lon - 13.5).abs() < 1e-5);
+    assert!((overlay.opacity - 0.5).abs() < 0.01);
+  }
+
+  #[test]
+  fn kmz() {
+    let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+    let options = zip::write::SimpleFileOptions::default();
+    zip.start_file("doc.kml", options).unwrap();
+    zip.write_all(KML.as_bytes()).unwrap();
+    zip.start_file("images/scan.png", options).unwrap();
+    zip.write_all(b"png").unwrap();
+    let data = zip.finish().unwrap().into_inner();
+
+    let mut parser = KmlParser::for_path(Path::new("map.kmz")).unwrap();
+    let events = parse(&mut parser, data);
+    let Some(MapEvent::ImageOverlay(overlay)) = events.last() else {
+      panic!("Expected an overlay.");
+    };
+    assert_eq!(std::fs::read(&overlay.path).unwrap(), b"png");
+    assert!(parse(&mut parser, b"not a zip".to_vec()).is_empty());
+  }
+
+  #[test]
+  fn circles() {
+    let kml = r#"<kml><Placemark><name>Range</name>
+      <ExtendedData>
+        <Data name="radius"><value>500</value></Data>
+        <Data name="range"><value>long</value></Data>
+      </ExtendedData>
+      <Point><coordinates>13.4,52.5</coordinates></Point>
+    </Placemark></kml>"#;
+    let events = parse(&mut KmlParser::new(), kml.as_bytes().to_vec());
+    let [MapEvent::Layer(layer)] = &events[..] else {
+      panic!("Expected a layer: {events:?}");
+    };
+    assert_eq!(
+      layer.shapes[0].circle(),
+      Some((
+        Coordinate {
+          lat: 52.5,
+          lon: 13.4
+        },
+        500.
+      ))
+    );
+    assert_eq!(layer.shapes[0].style.fill, FillStyle::Transparent);
+    assert_eq!(
+      layer.shapes[0].properties,
+      vec![("range".to_string(), "long".to_string())]
+    );
+  }
+}
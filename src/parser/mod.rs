@@ -1,18 +1,46 @@
+mod activity;
+pub use activity::ActivityParser;
+mod command;
+pub use command::CommandParser;
+mod detect;
+pub use detect::{detect, scores, Format};
+mod geojson;
+pub use geojson::GeoJsonParser;
+mod geoparquet;
+pub use geoparquet::GeoParquetParser;
 mod grep;
 use std::{
   fs::File,
-  io::{BufRead, BufReader},
+  io::{BufRead, BufReader, Read},
   iter::empty,
   path::{Path, PathBuf},
 };
 
 pub use grep::GrepParser;
+pub mod polyline;
+pub use polyline::PolylineParser;
 mod random;
 pub use random::RandomParser;
+mod kml;
+pub use kml::KmlParser;
+mod nmea;
+pub use nmea::NmeaParser;
+mod osm;
+pub use osm::{OsmParser, TagFilter};
+mod plugin;
+pub use plugin::{plugin_layers, plugins, PluginParser};
+mod shapefile;
+pub use shapefile::ShapefileParser;
 mod tt_json;
 pub use tt_json::TTJsonParser;
+pub mod wkb;
 
-use crate::map::map_event::MapEvent;
+use log::error;
+
+use crate::{
+  map::map_event::{Color, MapEvent, RenderMode},
+  recent::RecentFile,
+};
 
 /// An interface for input parsers.
 pub trait Parser {
@@ -64,10 +92,42 @@ where
   }
 }
 
+/// Chooses a parser for file formats that are recognized by their extension, then plugins for the
+/// extension. Returns `None` for files that should be handled by a line based parser.
+#[must_use]
+pub fn parser_for_path(path: &Path, color: Color) -> Option<Box<dyn FileParser>> {
+  let extension = path.extension()?.to_str()?.to_lowercase();
+  match extension.as_str() {
+    "nmea" => Some(Box::new(NmeaParser::new().with_color(color))),
+    "geojson" => Some(Box::new(GeoJsonParser::new().with_color(color))),
+    "shp" => Some(Box::new(
+      ShapefileParser::new().with_path(path).with_color(color),
+    )),
+    "parquet" | "geoparquet" | "arrow" | "feather" | "ipc" => {
+      GeoParquetParser::for_path(path).map(|p| Box::new(p.with_color(color)) as Box<dyn FileParser>)
+    }
+    "kml" | "kmz" => {
+      KmlParser::for_path(path).map(|p| Box::new(p.with_color(color)) as Box<dyn FileParser>)
+    }
+    "fit" | "tcx" => {
+      ActivityParser::for_path(path).map(|p| Box::new(p.with_color(color)) as Box<dyn FileParser>)
+    }
+    _ => OsmParser::for_path(path)
+      .map(|p| Box::new(p.with_color(color)) as Box<dyn FileParser>)
+      .or_else(|| PluginParser::for_path(path).map(|p| Box::new(p) as Box<dyn FileParser>)),
+  }
+}
+
+/// The number of bytes the format of a file is recognized by.
+const DETECTION_SAMPLE: u64 = 8192;
+
 /// Encapsulates file reading and choosing the correct parser for a file.
 pub struct AutoFileParser {
   path: PathBuf,
   parser: Box<dyn FileParser>,
+  /// How the layers are drawn, set by the `--style` of a recent file.
+  render_mode: Option<RenderMode>,
+  geodesic: bool,
 }
 
 impl AutoFileParser {
@@ -76,29 +136,116 @@ impl AutoFileParser {
     Self {
       parser: Self::get_parser(&path),
       path,
+      render_mode: None,
+      geodesic: false,
     }
   }
 
-  fn get_parser(_path: &Path) -> Box<dyn FileParser> {
-    Box::new(GrepParser::new(false))
+  /// The parser for a recent file that applies the stored parser and style options of mapcat
+  /// like `--parser nmea`, `--color red`, or `--style heatmap`, so the file is drawn again the
+  /// same way.
+  #[must_use]
+  pub fn reopened(file: &RecentFile) -> Self {
+    let color = file
+      .option("--color")
+      .and_then(|color| color.parse().ok())
+      .unwrap_or_default();
+    let precision = file
+      .option("--polyline-precision")
+      .and_then(|precision| precision.parse().ok())
+      .unwrap_or(5);
+    let grep = || -> Box<dyn FileParser> {
+      let mut grep = GrepParser::new(file.flag("--invert-coordinates"))
+        .with_color(color)
+        .with_label_template(file.option("--label").map(String::from))
+        .with_polyline_precision(file.flag("--grep-polylines").then_some(precision));
+      if let Some(pattern) = file.option("--label-pattern") {
+        grep = grep.with_label_pattern(pattern);
+      }
+      Box::new(grep)
+    };
+    let path = &file.path;
+    let parser = match (file.option("--parser-cmd"), file.option("--parser")) {
+      (Some(command), _) => Box::new(CommandParser::new(command.to_string()).with_color(color)),
+      (None, None | Some("auto")) => parser_for_path(path, color)
+        .or_else(|| {
+          let prefer = file.option("--prefer").and_then(|f| f.parse().ok());
+          Self::detected_parser(path, color, prefer)
+        })
+        .unwrap_or_else(grep),
+      (None, Some("grep")) => grep(),
+      (None, Some("polyline")) => Box::new(
+        PolylineParser::new()
+          .with_precision(precision)
+          .with_color(color),
+      ),
+      (None, Some(name)) => match name.parse::<Format>() {
+        Ok(format) => format.parser(color),
+        Err(()) => match PluginParser::named(name) {
+          Some(plugin) => Box::new(plugin),
+          None => {
+            error!("Unknown parser: {name}. Falling back to grep.");
+            grep()
+          }
+        },
+      },
+    };
+    Self {
+      path: path.clone(),
+      parser,
+      render_mode: file.option("--style").and_then(|style| style.parse().ok()),
+      geodesic: file.flag("--geodesic"),
+    }
+  }
+
+  fn get_parser(path: &Path) -> Box<dyn FileParser> {
+    parser_for_path(path, Color::default())
+      .or_else(|| Self::detected_parser(path, Color::default(), None))
+      .unwrap_or_else(|| Box::new(GrepParser::new(false)))
+  }
+
+  /// The parser for the format recognized by the start of the file.
+  fn detected_parser(
+    path: &Path,
+    color: Color,
+    prefer: Option<Format>,
+  ) -> Option<Box<dyn FileParser>> {
+    let mut start = vec![];
+    File::open(path)
+      .ok()?
+      .take(DETECTION_SAMPLE)
+      .read_to_end(&mut start)
+      .ok()?;
+    detect(&start, prefer).map(|format| format.parser(color))
   }
 
   pub fn parse(&mut self) -> Box<dyn Iterator<Item = MapEvent> + '_> {
     let f = File::open(self.path.clone());
-    if let Ok(f) = f {
-      let read = BufReader::new(f);
-      self.parser.parse(Box::new(read))
-    } else {
-      Box::new(empty())
-    }
+    let Ok(f) = f else {
+      return Box::new(empty());
+    };
+    let (render_mode, geodesic) = (self.render_mode, self.geodesic);
+    let read = BufReader::new(f);
+    Box::new(
+      self
+        .parser
+        .parse(Box::new(read))
+        .map(move |event| match event {
+          MapEvent::Layer(mut layer) => {
+            if let Some(render_mode) = render_mode {
+              layer.render_mode = render_mode;
+            }
+            MapEvent::Layer(layer.with_geodesic(geodesic))
+          }
+          event => event,
+        }),
+    )
   }
 }
 
 #[cfg(test)]
 mod tests {
-  use crate::parser::FileParser;
-
-  use super::GrepParser;
+  use super::*;
 
   #[test]
   fn parse() {
@@ -111,4 +258,26 @@ mod tests {
     let parsed: Vec<_> = parser.parse(read).collect();
     assert_eq!(parsed.len(), 2);
   }
+
+  #[test]
+  fn reopened() {
+    let path = std::env::temp_dir().join(format!("mapvas_reopened_{}.txt", std::process::id()));
+    std::fs::write(&path, "52.0, 10.0\n53.0, 11.0\n").unwrap();
+    let file = RecentFile {
+      path: path.clone(),
+      options: ["--parser", "grep", "--color", "red", "--style", "heatmap"]
+        .map(String::from)
+        .to_vec(),
+    };
+    let parsed: Vec<_> = AutoFileParser::reopened(&file).parse().collect();
+    assert_eq!(parsed.len(), 2);
+    for event in parsed {
+      let MapEvent::Layer(layer) = event else {
+        panic!("Not a layer: {event:?}");
+      };
+      assert_eq!(layer.render_mode, RenderMode::Heatmap);
+      assert_eq!(layer.shapes[0].style.color, Color::Red);
+    }
+    std::fs::remove_file(path).unwrap();
+  }
 }
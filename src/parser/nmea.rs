@@ -0,0 +1,210 @@
+use std::io::BufRead;
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use log::debug;
+
+use crate::map::{
+  coordinates::Coordinate,
+  map_event::{Color, FillStyle, Layer, MapEvent, Shape},
+};
+
+use super::FileParser;
+
+/// A position of a GPS receiver.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Fix {
+  coordinate: Coordinate,
+  time: Option<NaiveTime>,
+}
+
+/// Verifies the checksum after the `*` if there is one and returns the fields of the sentence.
+fn fields(line: &str) -> Option<Vec<&str>> {
+  let sentence = line.trim().strip_prefix('$')?;
+  let (data, checksum) = match sentence.split_once('*') {
+    Some((data, checksum)) => (data, Some(checksum)),
+    None => (sentence, None),
+  };
+  if let Some(checksum) = checksum {
+    let expected = u8::from_str_radix(checksum.get(..2)?, 16).ok()?;
+    if data.bytes().fold(0, |acc, b| acc ^ b) != expected {
+      debug!("Wrong checksum: {line}");
+      return None;
+    }
+  }
+  Some(data.split(',').collect())
+}
+
+/// Parses `ddmm.mmmm` or `dddmm.mmmm` with a hemisphere into decimal degrees.
+fn degrees(value: &str, hemisphere: &str) -> Option<f32> {
+  let dot = value.find('.').unwrap_or(value.len());
+  let split = dot.checked_sub(2)?;
+  let degrees: f32 = value.get(..split)?.parse().ok()?;
+  let minutes: f32 = value.get(split..)?.parse().ok()?;
+  let value = degrees + minutes / 60.;
+  match hemisphere {
+    "N" | "E" => Some(value),
+    "S" | "W" => Some(-value),
+    _ => None,
+  }
+}
+
+/// Parses `hhmmss.ss`.
+fn time(value: &str) -> Option<NaiveTime> {
+  NaiveTime::parse_from_str(value, "%H%M%S%.f").ok()
+}
+
+fn coordinate(lat: &str, ns: &str, lon: &str, ew: &str) -> Option<Coordinate> {
+  let coordinate = Coordinate {
+    lat: degrees(lat, ns)?,
+    lon: degrees(lon, ew)?,
+  };
+  coordinate.is_valid().then_some(coordinate)
+}
+
+/// Parses NMEA 0183 output of GPS receivers, e.g. from `gpsd` or a serial device.
+/// GGA, RMC, and GLL sentences move a point on the layer `NMEA position` and extend the track on
+/// the layer `NMEA track`. The date is taken from RMC sentences.
+#[derive(Debug, Default)]
+pub struct NmeaParser {
+  color: Color,
+  date: Option<NaiveDate>,
+  last: Option<Fix>,
+}
+
+impl NmeaParser {
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  #[must_use]
+  pub fn with_color(mut self, color: Color) -> Self {
+    self.color = color;
+    self
+  }
+
+  fn parse_fix(&mut self, line: &str) -> Option<Fix> {
+    let fields = fields(line)?;
+    let sentence_type = fields.first()?.get(2..)?;
+    let field = |i: usize| fields.get(i).copied().unwrap_or_default();
+    match sentence_type {
+      "GGA" if field(6) != "0" => Some(Fix {
+        coordinate: coordinate(field(2), field(3), field(4), field(5))?,
+        time: time(field(1)),
+      }),
+      "RMC" if field(2) == "A" => {
+        if let Ok(date) = NaiveDate::parse_from_str(field(9), "%d%m%y") {
+          self.date = Some(date);
+        }
+        Some(Fix {
+          coordinate: coordinate(field(3), field(4), field(5), field(6))?,
+          time: time(field(1)),
+        })
+      }
+      "GLL" if field(6) != "V" => Some(Fix {
+        coordinate: coordinate(field(1), field(2), field(3), field(4))?,
+        time: time(field(5)),
+      }),
+      _ => None,
+    }
+  }
+
+  /// Milliseconds since the epoch. Only the time of the day is known before the first RMC.
+  fn timestamp(&self, time: NaiveTime) -> i64 {
+    self
+      .date
+      .unwrap_or_default()
+      .and_time(time)
+      .and_utc()
+      .timestamp_millis()
+  }
+
+  fn parse_line(&mut self, line: &str) -> Vec<MapEvent> {
+    let Some(fix) = self.parse_fix(line) else {
+      return vec![];
+    };
+    // Several sentences per epoch report the same position.
+    if self.last == Some(fix) {
+      return vec![];
+    }
+    let timestamp = fix.time.map(|t| self.timestamp(t));
+    let label = timestamp
+      .and_then(DateTime::<Utc>::from_timestamp_millis)
+      .map(|t| t.format("%Y-%m-%d %H:%M:%S%.3f UTC").to_string());
+
+    let mut events = vec![];
+    if let Some(last) = self.last.replace(fix) {
+      let timestamps = match (last.time, fix.time) {
+        (Some(t1), Some(t2)) => vec![self.timestamp(t1), self.timestamp(t2)],
+        _ => vec![],
+      };
+      let mut track = Layer::new("NMEA track".to_string());
+      track.shapes.push(
+        Shape::new(vec![last.coordinate, fix.coordinate])
+          .with_color(self.color)
+          .with_timestamps(timestamps),
+      );
+      events.push(MapEvent::Layer(track));
+    }
+
+    let mut position = Layer::new("NMEA position".to_string()).with_replace(true);
+    position.shapes.push(
+      Shape::new(vec![fix.coordinate])
+        .with_color(self.color)
+        .with_fill(FillStyle::Solid)
+        .with_label(label)
+        .with_timestamps(timestamp.into_iter().collect()),
+    );
+    events.push(MapEvent::Layer(position));
+    events
+  }
+}
+
+impl FileParser for NmeaParser {
+  fn parse<'a>(
+    &'a mut self,
+    mut file: Box<dyn BufRead>,
+  ) -> Box<dyn Iterator<Item = MapEvent> + '_> {
+    let mut buf = String::new();
+    Box::new(
+      std::iter::from_fn(move || {
+        buf.clear();
+        match file.read_line(&mut buf) {
+          Ok(l) if l > 0 => Some(self.parse_line(&buf)),
+          _ => None,
+        }
+      })
+      .flatten(),
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_sentences() {
+    let mut parser = NmeaParser::new();
+    let gga = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+    let events = parser.parse_line(gga);
+    assert_eq!(events.len(), 1);
+    let MapEvent::Layer(layer) = &events[0] else {
+      panic!("Expected a layer.");
+    };
+    assert!(layer.replace);
+    let c = layer.shapes[0].coordinates[0];
+    assert!((c.lat - 48.1173).abs() < 1e-4 && (c.lon - 11.516_667).abs() < 1e-4);
+
+    let rmc = "$GPRMC,123520,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*60";
+    let events = parser.parse_line(rmc);
+    assert_eq!(events.len(), 2);
+    let MapEvent::Layer(track) = &events[0] else {
+      panic!("Expected a layer.");
+    };
+    assert_eq!(track.shapes[0].timestamps[1], 764_426_120_000);
+
+    assert!(parser.parse_line("$GPGGA,123519,4807.038,N*00").is_empty());
+    assert!(parser.parse_line("no nmea").is_empty());
+  }
+}
@@ -0,0 +1,592 @@
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  fs::File,
+  io::{BufRead, Cursor, Read},
+  path::Path,
+  str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+use log::error;
+use osmpbf::{Element, ElementReader, RelMemberType};
+use serde::Deserialize;
+
+use crate::map::{
+  coordinates::Coordinate,
+  map_event::{Color, FillStyle, Layer, MapEvent, Shape},
+};
+
+use super::FileParser;
+
+type Tags = Vec<(String, String)>;
+
+/// Keys that make a closed way an area instead of a closed line.
+const AREA_KEYS: [&str; 7] = [
+  "building", "landuse", "leisure", "natural", "water", "amenity", "place",
+];
+
+/// Selects OSM elements by a tag. `key=value` matches exactly, `key=*` and `key` match any value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagFilter {
+  key: String,
+  value: Option<String>,
+}
+
+impl TagFilter {
+  fn matches(&self, tags: &Tags) -> bool {
+    tags
+      .iter()
+      .any(|(k, v)| *k == self.key && self.value.as_ref().map_or(true, |value| value == v))
+  }
+}
+
+impl FromStr for TagFilter {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (key, value) = s.split_once('=').unwrap_or((s, "*"));
+    let key = key.trim();
+    if key.is_empty() {
+      return Err(format!("Filter {s} is not of the form key=value."));
+    }
+    let value = value.trim();
+    Ok(Self {
+      key: key.to_string(),
+      value: (value != "*").then(|| value.to_string()),
+    })
+  }
+}
+
+/// The OSM JSON format as returned by Overpass with `[out:json]`.
+#[derive(Deserialize, Debug)]
+struct JsonOsm {
+  elements: Vec<JsonElement>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum JsonElement {
+  Node {
+    id: i64,
+    lat: Option<f32>,
+    lon: Option<f32>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+  },
+  Way {
+    id: i64,
+    #[serde(default)]
+    nodes: Vec<i64>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+  },
+  Relation {
+    #[serde(default)]
+    members: Vec<JsonMember>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+  },
+  #[serde(other)]
+  Other,
+}
+
+#[derive(Deserialize, Debug)]
+struct JsonMember {
+  #[serde(rename = "type")]
+  member_type: String,
+  #[serde(rename = "ref")]
+  id: i64,
+  #[serde(default)]
+  role: String,
+}
+
+/// The raw elements of an OSM file.
+#[derive(Debug, Default)]
+struct OsmData {
+  nodes: HashMap<i64, Coordinate>,
+  tagged_nodes: Vec<(i64, Tags)>,
+  ways: Vec<(i64, Vec<i64>, Tags)>,
+  relations: Vec<(Vec<(i64, String)>, Tags)>,
+}
+
+impl OsmData {
+  fn add_node(&mut self, id: i64, coordinate: Coordinate, tags: Tags) {
+    self.nodes.insert(id, coordinate);
+    if !tags.is_empty() {
+      self.tagged_nodes.push((id, tags));
+    }
+  }
+
+  fn from_xml(data: &str) -> Result<Self> {
+    fn id(n: &roxmltree::Node) -> Result<i64> {
+      Ok(
+        n.attribute("id")
+          .or(n.attribute("ref"))
+          .ok_or(anyhow!("Element without id."))?
+          .parse()?,
+      )
+    }
+    fn tags(n: &roxmltree::Node) -> Tags {
+      n.children()
+        .filter(|c| c.has_tag_name("tag"))
+        .filter_map(|c| Some((c.attribute("k")?.to_string(), c.attribute("v")?.to_string())))
+        .collect()
+    }
+
+    let document = roxmltree::Document::parse(data)?;
+    let mut osm = Self::default();
+    for element in document.root_element().children() {
+      match element.tag_name().name() {
+        "node" => {
+          let coordinate = |name| -> Result<f32> {
+            Ok(
+              element
+                .attribute(name)
+                .ok_or(anyhow!("Node without {name}."))?
+                .parse()?,
+            )
+          };
+          osm.add_node(
+            id(&element)?,
+            Coordinate {
+              lat: coordinate("lat")?,
+              lon: coordinate("lon")?,
+            },
+            tags(&element),
+          );
+        }
+        "way" => {
+          let refs = element
+            .children()
+            .filter(|c| c.has_tag_name("nd"))
+            .map(|c| id(&c))
+            .collect::<Result<_>>()?;
+          osm.ways.push((id(&element)?, refs, tags(&element)));
+        }
+        "relation" => {
+          let members = element
+            .children()
+            .filter(|c| c.has_tag_name("member") && c.attribute("type") == Some("way"))
+            .map(|c| Ok((id(&c)?, c.attribute("role").unwrap_or("").to_string())))
+            .collect::<Result<_>>()?;
+          osm.relations.push((members, tags(&element)));
+        }
+        _ => (),
+      }
+    }
+    Ok(osm)
+  }
+
+  fn from_json(data: &str) -> Result<Self> {
+    let json: JsonOsm = serde_json::from_str(data)?;
+    let mut osm = Self::default();
+    for element in json.elements {
+      match element {
+        JsonElement::Node { id, lat, lon, tags } => {
+          if let (Some(lat), Some(lon)) = (lat, lon) {
+            osm.add_node(id, Coordinate { lat, lon }, tags.into_iter().collect());
+          }
+        }
+        JsonElement::Way { id, nodes, tags } => {
+          osm.ways.push((id, nodes, tags.into_iter().collect()));
+        }
+        JsonElement::Relation { members, tags } => {
+          let members = members
+            .into_iter()
+            .filter(|m| m.member_type == "way")
+            .map(|m| (m.id, m.role))
+            .collect();
+          osm.relations.push((members, tags.into_iter().collect()));
+        }
+        JsonElement::Other => (),
+      }
+    }
+    Ok(osm)
+  }
+
+  #[allow(clippy::cast_possible_truncation)]
+  fn from_pbf(data: Vec<u8>) -> Result<Self> {
+    fn to_tags<'a>(tags: impl Iterator<Item = (&'a str, &'a str)>) -> Tags {
+      tags.map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    let mut osm = Self::default();
+    ElementReader::new(Cursor::new(data)).for_each(|element| match element {
+      Element::Node(n) => osm.add_node(
+        n.id(),
+        Coordinate {
+          lat: n.lat() as f32,
+          lon: n.lon() as f32,
+        },
+        to_tags(n.tags()),
+      ),
+      Element::DenseNode(n) => osm.add_node(
+        n.id(),
+        Coordinate {
+          lat: n.lat() as f32,
+          lon: n.lon() as f32,
+        },
+        to_tags(n.tags()),
+      ),
+      Element::Way(w) => osm
+        .ways
+        .push((w.id(), w.refs().collect(), to_tags(w.tags()))),
+      Element::Relation(r) => {
+        let members = r
+          .members()
+          .filter(|m| m.member_type == RelMemberType::Way)
+          .map(|m| (m.member_id, m.role().unwrap_or("").to_string()))
+          .collect();
+        osm.relations.push((members, to_tags(r.tags())));
+      }
+    })?;
+    Ok(osm)
+  }
+}
+
+fn label(tags: &Tags) -> Option<String> {
+  (!tags.is_empty()).then(|| {
+    tags
+      .iter()
+      .map(|(k, v)| format!("{k}: {v}"))
+      .collect::<Vec<_>>()
+      .join(", ")
+  })
+}
+
+fn is_area(tags: &Tags) -> bool {
+  match tags.iter().find(|(k, _)| k == "area") {
+    Some((_, v)) => v == "yes",
+    None => tags.iter().any(|(k, _)| AREA_KEYS.contains(&k.as_str())),
+  }
+}
+
+/// Joins the ways of a multipolygon into rings by connecting their end points.
+fn join_rings(mut ways: Vec<Vec<Coordinate>>) -> Vec<Vec<Coordinate>> {
+  let mut rings = vec![];
+  while let Some(mut ring) = ways.pop() {
+    while ring.len() > 1 && ring.first() != ring.last() {
+      let Some(next) = ways
+        .iter()
+        .position(|w| w.first() == ring.last() || w.last() == ring.last())
+      else {
+        break;
+      };
+      let mut way = ways.swap_remove(next);
+      if way.first() != ring.last() {
+        way.reverse();
+      }
+      ring.extend(way.into_iter().skip(1));
+    }
+    rings.push(ring);
+  }
+  rings
+}
+
+/// If the file starts with the `OSMHeader` block of OSM PBF files: the length of the block header
+/// and its type as first protobuf field.
+fn is_osm_pbf(path: &Path) -> bool {
+  let mut start = [0; 15];
+  File::open(path)
+    .and_then(|mut file| file.read_exact(&mut start))
+    .is_ok()
+    && start[4..] == *b"\x0a\x09OSMHeader"
+}
+
+/// Parses OpenStreetMap XML (`.osm`), JSON, and PBF (`.osm.pbf`) files.
+/// Ways become lines or areas, multipolygon relations are joined into polygons,
+/// and tagged nodes become points. The tags are used as label.
+#[derive(Debug, Default)]
+pub struct OsmParser {
+  pbf: bool,
+  filter: Option<TagFilter>,
+  color: Color,
+  layer: Option<String>,
+}
+
+impl OsmParser {
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Returns a parser for the format of the file if it is an OSM file. `.pbf` files are
+  /// recognized by their header, as vector tiles use the extension as well.
+  #[must_use]
+  pub fn for_path(path: &Path) -> Option<Self> {
+    let name = path.file_name()?.to_str()?.to_lowercase();
+    if name.ends_with(".pbf") && is_osm_pbf(path) {
+      Some(Self::new().with_pbf(true))
+    } else if name.ends_with(".osm") {
+      Some(Self::new())
+    } else {
+      None
+    }
+  }
+
+  #[must_use]
+  pub fn with_pbf(mut self, pbf: bool) -> Self {
+    self.pbf = pbf;
+    self
+  }
+
+  #[must_use]
+  pub fn with_filter(mut self, filter: Option<TagFilter>) -> Self {
+    self.filter = filter;
+    self
+  }
+
+  #[must_use]
+  pub fn with_color(mut self, color: Color) -> Self {
+    self.color = color;
+    self
+  }
+
+  /// Sets the id of the created layer. Defaults to `OSM`.
+  #[must_use]
+  pub fn with_layer(mut self, layer: &str) -> Self {
+    self.layer = Some(layer.to_string());
+    self
+  }
+
+  fn selected(&self, tags: &Tags) -> bool {
+    !tags.is_empty() && self.filter.as_ref().map_or(true, |f| f.matches(tags))
+  }
+
+  fn shape(&self, coordinates: Vec<Coordinate>, fill: FillStyle, tags: &Tags) -> Shape {
+    Shape::new(coordinates)
+      .with_color(self.color)
+      .with_fill(fill)
+      .with_label(label(tags))
+  }
+
+  fn to_layer(&self, osm: &OsmData) -> Layer {
+    let mut layer = Layer::new(self.layer.clone().unwrap_or_else(|| "OSM".to_string()));
+    let coordinates = |refs: &[i64]| -> Vec<Coordinate> {
+      refs
+        .iter()
+        .filter_map(|r| osm.nodes.get(r))
+        .copied()
+        .collect()
+    };
+    let ways: HashMap<i64, &Vec<i64>> = osm.ways.iter().map(|(id, refs, _)| (*id, refs)).collect();
+
+    for (id, tags) in &osm.tagged_nodes {
+      if self.selected(tags) {
+        if let Some(c) = osm.nodes.get(id) {
+          layer
+            .shapes
+            .push(self.shape(vec![*c], FillStyle::Solid, tags));
+        }
+      }
+    }
+
+    // Ways that are drawn as part of a multipolygon are not drawn again.
+    let mut drawn = HashSet::new();
+    for (members, tags) in &osm.relations {
+      if !self.selected(tags) {
+        continue;
+      }
+      let member_ways = members
+        .iter()
+        .filter_map(|(id, role)| ways.get(id).map(|refs| (*id, role, coordinates(refs))));
+      if tags.iter().any(|(k, v)| k == "type" && v == "multipolygon") {
+        let mut outer = vec![];
+        let mut inner = vec![];
+        for (id, role, way) in member_ways {
+          drawn.insert(id);
+          if role == "inner" {
+            inner.push(way);
+          } else {
+            outer.push(way);
+          }
+        }
+        // Holes are drawn as outlines without fill.
+        let rings: Vec<_> = join_rings(outer)
+          .into_iter()
+          .map(|ring| (ring, FillStyle::Transparent))
+          .chain(
+            join_rings(inner)
+              .into_iter()
+              .map(|ring| (ring, FillStyle::NoFill)),
+          )
+          .collect();
+        // The rings stay one feature.
+        let part_of = (rings.len() > 1).then_some(layer.shapes.len());
+        for (ring, fill) in rings {
+          layer
+            .shapes
+            .push(self.shape(ring, fill, tags).with_part_of(part_of));
+        }
+      } else {
+        for (_, _, way) in member_ways {
+          layer.shapes.push(self.shape(way, FillStyle::NoFill, tags));
+        }
+      }
+    }
+
+    for (id, refs, tags) in &osm.ways {
+      if drawn.contains(id) || !self.selected(tags) {
+        continue;
+      }
+      let closed = refs.len() > 2 && refs.first() == refs.last();
+      let fill = if closed && is_area(tags) {
+        FillStyle::Transparent
+      } else {
+        FillStyle::NoFill
+      };
+      layer.shapes.push(self.shape(coordinates(refs), fill, tags));
+    }
+
+    layer.shapes.retain(|s| !s.coordinates.is_empty());
+    layer
+  }
+
+  fn read(&self, data: Vec<u8>) -> Result<OsmData> {
+    if self.pbf {
+      OsmData::from_pbf(data)
+    } else {
+      let data = String::from_utf8(data)?;
+      if data.trim_start().starts_with('{') {
+        OsmData::from_json(&data)
+      } else {
+        OsmData::from_xml(&data)
+      }
+    }
+  }
+}
+
+impl FileParser for OsmParser {
+  fn parse<'a>(
+    &'a mut self,
+    mut file: Box<dyn BufRead>,
+  ) -> Box<dyn Iterator<Item = MapEvent> + '_> {
+    let mut data = vec![];
+    if let Err(e) = file.read_to_end(&mut data) {
+      error!("Cannot read osm file: {e}");
+      return Box::new(std::iter::empty());
+    }
+    match self.read(data) {
+      Ok(osm) => Box::new(std::iter::once(MapEvent::Layer(self.to_layer(&osm)))),
+      Err(e) => {
+        error!("Cannot parse osm file: {e}");
+        Box::new(std::iter::empty())
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DATA: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="50.0" lon="6.0"/>
+  <node id="2" lat="50.0" lon="7.0"/>
+  <node id="3" lat="51.0" lon="7.0">
+    <tag k="amenity" v="cafe"/>
+  </node>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <tag k="highway" v="primary"/>
+  </way>
+  <way id="11">
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <nd ref="1"/>
+  </way>
+  <relation id="20">
+    <member type="way" ref="10" role="outer"/>
+    <member type="way" ref="11" role="outer"/>
+    <tag k="type" v="multipolygon"/>
+    <tag k="landuse" v="forest"/>
+  </relation>
+</osm>"#;
+
+  #[test]
+  fn parse_xml() {
+    let osm = OsmData::from_xml(DATA).unwrap();
+    let layer = OsmParser::new().to_layer(&osm);
+    // The cafe and the forest. The highway is drawn as part of the forest.
+    assert_eq!(layer.shapes.len(), 2);
+    assert_eq!(layer.shapes[1].coordinates.len(), 4);
+    assert_eq!(layer.shapes[1].style.fill, FillStyle::Transparent);
+
+    let filter = TagFilter::from_str("amenity=*").unwrap();
+    let layer = OsmParser::new().with_filter(Some(filter)).to_layer(&osm);
+    assert_eq!(layer.shapes.len(), 1);
+  }
+
+  #[test]
+  fn multipolygon_holes() {
+    let data = r#"<osm version="0.6">
+  <node id="1" lat="50.0" lon="6.0"/>
+  <node id="2" lat="50.0" lon="7.0"/>
+  <node id="3" lat="51.0" lon="7.0"/>
+  <node id="4" lat="50.2" lon="6.8"/>
+  <node id="5" lat="50.2" lon="6.9"/>
+  <node id="6" lat="50.4" lon="6.9"/>
+  <way id="10"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="1"/></way>
+  <way id="11"><nd ref="4"/><nd ref="5"/><nd ref="6"/><nd ref="4"/></way>
+  <relation id="20">
+    <member type="way" ref="10" role="outer"/>
+    <member type="way" ref="11" role="inner"/>
+    <tag k="type" v="multipolygon"/>
+    <tag k="landuse" v="meadow"/>
+  </relation>
+</osm>"#;
+    let layer = OsmParser::new().to_layer(&OsmData::from_xml(data).unwrap());
+    assert_eq!(layer.shapes.len(), 2);
+    assert_eq!(layer.shapes[0].style.fill, FillStyle::Transparent);
+    assert_eq!(layer.shapes[1].style.fill, FillStyle::NoFill);
+    assert_eq!(layer.shapes[0].part_of, Some(0));
+    assert_eq!(layer.shapes[1].part_of, Some(0));
+  }
+
+  #[test]
+  fn pbf_header() {
+    let dir = std::env::temp_dir();
+    let osm = dir.join(format!("mapvas_{}.osm.pbf", std::process::id()));
+    let mut header = vec![0, 0, 0, 13];
+    header.extend(b"\x0a\x09OSMHeader\x18\x10");
+    std::fs::write(&osm, &header).unwrap();
+    assert!(OsmParser::for_path(&osm).is_some_and(|p| p.pbf));
+    // A vector tile.
+    let tile = dir.join(format!("mapvas_{}_tile.pbf", std::process::id()));
+    std::fs::write(&tile, b"\x1a\x20\x0a\x05water\x28\x80\x20\x78\x02").unwrap();
+    assert!(OsmParser::for_path(&tile).is_none());
+    let _ = std::fs::remove_file(osm);
+    let _ = std::fs::remove_file(tile);
+  }
+
+  #[test]
+  fn parse_json() {
+    let data = r#"{"elements": [
+      {"type": "node", "id": 1, "lat": 50.0, "lon": 6.0},
+      {"type": "node", "id": 2, "lat": 51.0, "lon": 7.0, "tags": {"amenity": "cafe"}},
+      {"type": "way", "id": 3, "nodes": [1, 2], "tags": {"highway": "primary"}},
+      {"type": "area", "id": 4}
+    ]}"#;
+    let osm = OsmData::from_json(data).unwrap();
+    let layer = OsmParser::new().with_layer("Overpass").to_layer(&osm);
+    assert_eq!(layer.id, "Overpass");
+    assert_eq!(layer.shapes.len(), 2);
+    assert_eq!(layer.shapes[1].coordinates.len(), 2);
+  }
+
+  #[test]
+  fn tag_filter() {
+    let tags = vec![("highway".to_string(), "primary".to_string())];
+    assert!(TagFilter::from_str("highway=*").unwrap().matches(&tags));
+    assert!(TagFilter::from_str("highway").unwrap().matches(&tags));
+    assert!(TagFilter::from_str("highway=primary")
+      .unwrap()
+      .matches(&tags));
+    assert!(!TagFilter::from_str("highway=service")
+      .unwrap()
+      .matches(&tags));
+    assert!(TagFilter::from_str("=x").is_err());
+  }
+}
@@ -0,0 +1,324 @@
+use std::{
+  path::{Path, PathBuf},
+  sync::OnceLock,
+};
+
+use anyhow::{anyhow, Result};
+use log::error;
+use rhai::{serde::from_dynamic, Array, Dynamic, Engine, FuncArgs, Scope, AST};
+
+use crate::config;
+use crate::map::{
+  map_event::{Layer, MapEvent, Shape},
+  script::register_shapes,
+};
+
+use super::Parser;
+
+/// The directory with the plugins, given by `MAPVAS_PLUGINS`, by default `plugins` in the
+/// [config directory](config::dir).
+fn directory() -> Option<PathBuf> {
+  config::path("MAPVAS_PLUGINS", "plugins")
+}
+
+/// The `.rhai` files in the plugin directory.
+#[must_use]
+pub fn plugins() -> Vec<PathBuf> {
+  let Some(entries) = directory().and_then(|dir| std::fs::read_dir(dir).ok()) else {
+    return vec![];
+  };
+  let mut plugins: Vec<PathBuf> = entries
+    .filter_map(|entry| entry.ok().map(|e| e.path()))
+    .filter(|path| path.extension().is_some_and(|e| e == "rhai"))
+    .collect();
+  plugins.sort();
+  plugins
+}
+
+/// The functions a plugin defines, looked up once when it is compiled.
+#[derive(Debug, Clone, Copy)]
+struct Functions {
+  parse_line: bool,
+  parse: bool,
+  layer: bool,
+  extensions: bool,
+}
+
+impl Functions {
+  fn of(ast: &AST) -> Self {
+    let defines = |function: &str| ast.iter_functions().any(|f| f.name == function);
+    Self {
+      parse_line: defines("parse_line"),
+      parse: defines("parse"),
+      layer: defines("layer"),
+      extensions: defines("extensions"),
+    }
+  }
+
+  fn parses(self) -> bool {
+    self.parse_line || self.parse
+  }
+}
+
+/// A plugin of the plugin directory as it was found when first needed.
+struct Plugin {
+  name: String,
+  script: String,
+  functions: Functions,
+  extensions: Vec<String>,
+}
+
+impl Plugin {
+  /// A parser running the plugin. Compiled rhai scripts cannot be moved to the thread reading a
+  /// file, so only the chosen plugin is compiled again there.
+  fn parser(&self) -> Option<PluginParser> {
+    PluginParser::compile(self.name.clone(), &self.script)
+      .map_err(|e| error!("Cannot load plugin {}: {e}", self.name))
+      .ok()
+  }
+}
+
+/// The plugins of the plugin directory, which are read and compiled once.
+fn loaded() -> &'static [Plugin] {
+  static PLUGINS: OnceLock<Vec<Plugin>> = OnceLock::new();
+  PLUGINS.get_or_init(|| {
+    plugins()
+      .into_iter()
+      .filter_map(|path| {
+        let name = path.file_stem()?.to_str()?.to_string();
+        let script = std::fs::read_to_string(&path)
+          .map_err(|e| error!("Cannot read plugin {path:?}: {e}"))
+          .ok()?;
+        let parser = PluginParser::compile(name, &script)
+          .map_err(|e| error!("Cannot load plugin {path:?}: {e}"))
+          .ok()?;
+        Some(Plugin {
+          extensions: parser.extensions(),
+          name: parser.name,
+          script,
+          functions: parser.functions,
+        })
+      })
+      .collect()
+  })
+}
+
+/// The layers of the plugins defining `layer()`, which the map draws when it starts.
+#[must_use]
+pub fn plugin_layers() -> Vec<MapEvent> {
+  loaded()
+    .iter()
+    .filter(|plugin| plugin.functions.layer)
+    .filter_map(Plugin::parser)
+    .filter_map(|parser| parser.layer())
+    .collect()
+}
+
+/// A parser or layer written as [rhai](https://rhai.rs) script, named after its file.
+/// The script defines `parse_line(line)` to read the input line by line or `parse(text)` to read
+/// it at once. Both return a shape, an array of shapes, or nothing, built with `shape` and
+/// `point`. An optional `extensions()` returns the file extensions the parser is chosen for.
+/// `layer()` returns shapes the same way, which are drawn when the map starts.
+pub struct PluginParser {
+  name: String,
+  engine: Engine,
+  ast: AST,
+  functions: Functions,
+  /// The input collected for `parse`.
+  data: String,
+}
+
+impl PluginParser {
+  /// Compiles a plugin.
+  ///
+  /// # Errors
+  /// If the script does not compile or defines neither `parse_line`, `parse`, nor `layer`.
+  pub fn compile(name: String, script: &str) -> Result<Self> {
+    let mut engine = Engine::new();
+    register_shapes(&mut engine);
+    let ast = engine.compile(script)?;
+    let functions = Functions::of(&ast);
+    if !functions.parses() && !functions.layer {
+      return Err(anyhow!(
+        "Plugin {name} defines neither parse_line, parse, nor layer."
+      ));
+    }
+    Ok(Self {
+      name,
+      engine,
+      ast,
+      functions,
+      data: String::new(),
+    })
+  }
+
+  /// Reads and compiles the plugin file.
+  ///
+  /// # Errors
+  /// If the file cannot be read or compiled.
+  pub fn load(path: &Path) -> Result<Self> {
+    let name = path
+      .file_stem()
+      .and_then(|stem| stem.to_str())
+      .ok_or(anyhow!("Invalid plugin file {path:?}"))?;
+    Self::compile(name.to_string(), &std::fs::read_to_string(path)?)
+  }
+
+  /// The parsing plugin with the name from the plugin directory.
+  #[must_use]
+  pub fn named(name: &str) -> Option<Self> {
+    loaded()
+      .iter()
+      .find(|plugin| plugin.name == name && plugin.functions.parses())?
+      .parser()
+  }
+
+  /// The first parsing plugin from the plugin directory handling the extension of the file.
+  #[must_use]
+  pub fn for_path(path: &Path) -> Option<Self> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    loaded()
+      .iter()
+      .find(|plugin| plugin.functions.parses() && plugin.extensions.contains(&extension))?
+      .parser()
+  }
+
+  fn extensions(&self) -> Vec<String> {
+    if !self.functions.extensions {
+      return vec![];
+    }
+    self
+      .engine
+      .call_fn::<Array>(&mut Scope::new(), &self.ast, "extensions", ())
+      .map(|extensions| {
+        extensions
+          .into_iter()
+          .map(|e| e.to_string().to_lowercase())
+          .collect()
+      })
+      .unwrap_or_default()
+  }
+
+  /// The layer returned by `layer()`, if the plugin defines it.
+  #[must_use]
+  pub fn layer(&self) -> Option<MapEvent> {
+    if !self.functions.layer {
+      return None;
+    }
+    self.call("layer", ())
+  }
+
+  /// Calls the function of the script and reads the shapes it returns.
+  fn call(&self, function: &str, arguments: impl FuncArgs) -> Option<MapEvent> {
+    let result = self
+      .engine
+      .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, function, arguments)
+      .map_err(|e| e.to_string())
+      .and_then(|value| shapes(&value).map_err(|e| e.to_string()));
+    match result {
+      Ok(shapes) if shapes.is_empty() => None,
+      Ok(shapes) => {
+        let mut layer = Layer::new(self.name.clone());
+        layer.shapes = shapes;
+        Some(MapEvent::Layer(layer))
+      }
+      Err(e) => {
+        error!("Plugin {} failed: {e}", self.name);
+        None
+      }
+    }
+  }
+}
+
+fn shapes(value: &Dynamic) -> Result<Vec<Shape>, Box<rhai::EvalAltResult>> {
+  if value.is_unit() {
+    Ok(vec![])
+  } else if value.is_array() {
+    from_dynamic(value)
+  } else {
+    Ok(vec![from_dynamic(value)?])
+  }
+}
+
+impl Parser for PluginParser {
+  fn parse_line(&mut self, line: &str) -> Option<MapEvent> {
+    if self.functions.parse_line {
+      self.call("parse_line", (line.trim_end().to_string(),))
+    } else {
+      self.data += line;
+      None
+    }
+  }
+
+  fn finalize(&self) -> Option<MapEvent> {
+    if self.functions.parse_line || !self.functions.parse {
+      return None;
+    }
+    self.call("parse", (self.data.clone(),))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn line_plugin() {
+    let script = r#"
+      fn extensions() { ["pos"] }
+      fn parse_line(line) {
+        let values = line.split(";");
+        if values.len() != 3 { return; }
+        let s = shape([point(parse_float(values[1]), parse_float(values[2]))]);
+        s.label = values[0];
+        s
+      }
+    "#;
+    let mut parser = PluginParser::compile("positions".to_string(), script).unwrap();
+    assert_eq!(parser.extensions(), vec!["pos"]);
+    assert!(parser.parse_line("header").is_none());
+    let Some(MapEvent::Layer(layer)) = parser.parse_line("Berlin;52.5;13.4\n") else {
+      panic!("no layer");
+    };
+    assert_eq!(layer.id, "positions");
+    assert_eq!(layer.shapes[0].label.as_deref(), Some("Berlin"));
+    assert!((layer.shapes[0].coordinates[0].lon - 13.4).abs() < 0.001);
+    assert!(parser.finalize().is_none());
+  }
+
+  #[test]
+  fn document_plugin() {
+    let script = r"
+      fn parse(text) {
+        let coordinates = [];
+        for line in text.split() {
+          let values = line.split(',');
+          coordinates.push(point(parse_float(values[0]), parse_float(values[1])));
+        }
+        [shape(coordinates)]
+      }
+    ";
+    let mut parser = PluginParser::compile("track".to_string(), script).unwrap();
+    assert!(parser.parse_line("52.5,13.4\n").is_none());
+    assert!(parser.parse_line("52.6,13.5\n").is_none());
+    let Some(MapEvent::Layer(layer)) = parser.finalize() else {
+      panic!("no layer");
+    };
+    assert_eq!(layer.shapes[0].coordinates.len(), 2);
+    assert!(PluginParser::compile("empty".to_string(), "fn other() {}").is_err());
+  }
+
+  #[test]
+  fn layer_plugin() {
+    let script = "fn layer() { [shape([point(52.5, 13.4), point(52.6, 13.5)])] }";
+    let parser = PluginParser::compile("stations".to_string(), script).unwrap();
+    let Some(MapEvent::Layer(layer)) = parser.layer() else {
+      panic!("no layer");
+    };
+    assert_eq!(layer.id, "stations");
+    assert_eq!(layer.shapes[0].coordinates.len(), 2);
+    assert!(parser.finalize().is_none());
+    let parser = PluginParser::compile("track".to_string(), "fn parse(text) {}").unwrap();
+    assert!(parser.layer().is_none());
+  }
+}
@@ -0,0 +1,449 @@
+use std::{
+  io::{BufRead, Read},
+  path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use log::{debug, error};
+
+use crate::map::{
+  coordinates::Coordinate,
+  map_event::{Color, FillStyle, Layer, MapEvent, Shape},
+};
+
+use super::FileParser;
+
+const HEADER_SIZE: usize = 100;
+
+/// Parses ESRI shapefiles with points, polylines, and polygons in WGS84.
+/// The attributes of the `.dbf` sidecar file are used as labels if the path of the `.shp` is known.
+#[derive(Debug, Default)]
+pub struct ShapefileParser {
+  path: Option<PathBuf>,
+  color: Color,
+}
+
+fn read_i32_be(data: &[u8], offset: usize) -> Result<i32> {
+  data
+    .get(offset..offset + 4)
+    .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    .ok_or(anyhow!("Unexpected end of shapefile at {offset}."))
+}
+
+fn read_i32_le(data: &[u8], offset: usize) -> Result<i32> {
+  data
+    .get(offset..offset + 4)
+    .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    .ok_or(anyhow!("Unexpected end of shapefile at {offset}."))
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn read_point(data: &[u8], offset: usize) -> Result<Coordinate> {
+  let value = |o: usize| -> Result<f64> {
+    data
+      .get(o..o + 8)
+      .map(|b| f64::from_le_bytes(b.try_into().expect("slice has 8 bytes")))
+      .ok_or(anyhow!("Unexpected end of shapefile at {o}."))
+  };
+  Ok(Coordinate {
+    lat: value(offset + 8)? as f32,
+    lon: value(offset)? as f32,
+  })
+}
+
+/// The geometry of one record. Polygons and polylines can consist of several parts.
+#[derive(Debug, PartialEq)]
+enum Geometry {
+  Null,
+  Points(Vec<Coordinate>),
+  Lines(Vec<Vec<Coordinate>>),
+  Polygons(Vec<Vec<Coordinate>>),
+}
+
+/// A count or index of a record, which must not be negative.
+fn read_count(data: &[u8], offset: usize) -> Result<usize> {
+  usize::try_from(read_i32_le(data, offset)?).map_err(|_| anyhow!("Negative count at {offset}."))
+}
+
+/// The end of `count` items of `size` bytes from `offset`, if the record contains them.
+fn end_of(data: &[u8], offset: usize, count: usize, size: usize) -> Result<usize> {
+  count
+    .checked_mul(size)
+    .and_then(|length| length.checked_add(offset))
+    .filter(|end| *end <= data.len())
+    .ok_or(anyhow!("Record too short for {count} items at {offset}."))
+}
+
+fn read_parts(data: &[u8]) -> Result<Vec<Vec<Coordinate>>> {
+  // Layout after the shape type: bounding box (32 bytes), number of parts, number of points,
+  // part start indices, points.
+  let num_parts = read_count(data, 36)?;
+  let num_points = read_count(data, 40)?;
+  let points_offset = end_of(data, 44, num_parts, 4)?;
+  end_of(data, points_offset, num_points, 16)?;
+  let mut starts = (0..num_parts)
+    .map(|i| read_count(data, 44 + 4 * i))
+    .collect::<Result<Vec<_>>>()?;
+  starts.push(num_points);
+  if starts.windows(2).any(|w| w[0] > w[1]) {
+    return Err(anyhow!("Invalid part start indices."));
+  }
+  starts
+    .windows(2)
+    .map(|w| {
+      (w[0]..w[1])
+        .map(|i| read_point(data, points_offset + 16 * i))
+        .collect()
+    })
+    .collect()
+}
+
+/// Parses the content of one record starting with the shape type.
+fn read_geometry(data: &[u8]) -> Result<Geometry> {
+  let shape_type = read_i32_le(data, 0)?;
+  // The Z and M variants extend the 2D layout, so they are read the same way.
+  match shape_type {
+    0 => Ok(Geometry::Null),
+    1 | 11 | 21 => Ok(Geometry::Points(vec![read_point(data, 4)?])),
+    3 | 13 | 23 => Ok(Geometry::Lines(read_parts(data)?)),
+    5 | 15 | 25 => Ok(Geometry::Polygons(read_parts(data)?)),
+    8 | 18 | 28 => {
+      let num_points = read_count(data, 36)?;
+      end_of(data, 40, num_points, 16)?;
+      Ok(Geometry::Points(
+        (0..num_points)
+          .map(|i| read_point(data, 40 + 16 * i))
+          .collect::<Result<_>>()?,
+      ))
+    }
+    t => Err(anyhow!("Unsupported shape type {t}.")),
+  }
+}
+
+fn read_geometries(data: &[u8]) -> Result<Vec<Geometry>> {
+  if read_i32_be(data, 0)? != 9994 {
+    return Err(anyhow!("Not a shapefile."));
+  }
+  let mut geometries = vec![];
+  let mut offset = HEADER_SIZE;
+  while offset + 8 <= data.len() {
+    // The content length is given in 16 bit words.
+    let words = usize::try_from(read_i32_be(data, offset + 4)?)
+      .map_err(|_| anyhow!("Negative record length at {offset}."))?;
+    let end =
+      end_of(data, offset + 8, words, 2).map_err(|_| anyhow!("Truncated record at {offset}."))?;
+    geometries.push(read_geometry(&data[offset + 8..end])?);
+    offset = end;
+  }
+  Ok(geometries)
+}
+
+/// If the ring is the hole of a polygon. Shapefiles store outer rings clockwise and holes
+/// counterclockwise.
+fn is_hole(ring: &[Coordinate]) -> bool {
+  let twice_area: f32 = ring
+    .iter()
+    .zip(ring.iter().cycle().skip(1))
+    .map(|(a, b)| (b.lon - a.lon) * (b.lat + a.lat))
+    .sum();
+  twice_area < 0.
+}
+
+/// Reads the records of a dBASE file as lists of field names and trimmed values.
+fn read_dbf(data: &[u8]) -> Result<Vec<Vec<(String, String)>>> {
+  let short = |o: usize| -> Result<usize> {
+    data
+      .get(o..o + 2)
+      .map(|b| usize::from(u16::from_le_bytes([b[0], b[1]])))
+      .ok_or(anyhow!("Unexpected end of dbf file."))
+  };
+  let num_records = usize::try_from(read_i32_le(data, 4)?)?;
+  let header_length = short(8)?;
+  let record_length = short(10)?;
+
+  let mut fields = vec![];
+  let mut offset = 32;
+  while offset + 32 <= header_length && data.get(offset) != Some(&0x0D) {
+    let descriptor = &data[offset..offset + 32];
+    let name_end = descriptor[..11].iter().position(|&b| b == 0).unwrap_or(11);
+    let name = String::from_utf8_lossy(&descriptor[..name_end]).to_string();
+    fields.push((name, usize::from(descriptor[16])));
+    offset += 32;
+  }
+
+  (0..num_records)
+    .map(|r| {
+      let start = header_length + r * record_length;
+      let record = data
+        .get(start..start + record_length)
+        .ok_or(anyhow!("Truncated dbf record {r}."))?;
+      // The first byte is the deletion flag.
+      let mut position = 1;
+      let mut values = vec![];
+      for (name, length) in &fields {
+        let value = record
+          .get(position..position + length)
+          .map(|v| String::from_utf8_lossy(v).trim().to_string())
+          .unwrap_or_default();
+        values.push((name.clone(), value));
+        position += length;
+      }
+      Ok(values)
+    })
+    .collect()
+}
+
+impl ShapefileParser {
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Sets the path of the `.shp` file to find the attributes in the `.dbf` file next to it.
+  #[must_use]
+  pub fn with_path(mut self, path: &Path) -> Self {
+    self.path = Some(path.to_path_buf());
+    self
+  }
+
+  #[must_use]
+  pub fn with_color(mut self, color: Color) -> Self {
+    self.color = color;
+    self
+  }
+
+  fn labels(&self) -> Vec<Option<String>> {
+    let Some(dbf) = self.path.as_ref().map(|p| p.with_extension("dbf")) else {
+      return vec![];
+    };
+    let records = std::fs::read(&dbf)
+      .map_err(anyhow::Error::from)
+      .and_then(|data| read_dbf(&data));
+    match records {
+      Ok(records) => records
+        .into_iter()
+        .map(|fields| {
+          let label = fields
+            .into_iter()
+            .filter(|(_, v)| !v.is_empty())
+            .map(|(k, v)| format!("{k}: {v}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+          (!label.is_empty()).then_some(label)
+        })
+        .collect(),
+      Err(e) => {
+        debug!("No attributes from {dbf:?}: {e}");
+        vec![]
+      }
+    }
+  }
+
+  fn to_layer(&self, geometries: Vec<Geometry>) -> Layer {
+    let labels = self.labels();
+    let mut layer = Layer::new("Shapefile".to_string());
+    for (i, geometry) in geometries.into_iter().enumerate() {
+      let label = labels.get(i).cloned().flatten();
+      let parts: Vec<(Vec<Coordinate>, FillStyle)> = match geometry {
+        Geometry::Null => continue,
+        Geometry::Points(points) => points
+          .into_iter()
+          .map(|p| (vec![p], FillStyle::NoFill))
+          .collect(),
+        Geometry::Lines(lines) => lines.into_iter().map(|l| (l, FillStyle::NoFill)).collect(),
+        // Holes are drawn as outlines without fill.
+        Geometry::Polygons(rings) => rings
+          .into_iter()
+          .map(|ring| {
+            let fill = if is_hole(&ring) {
+              FillStyle::NoFill
+            } else {
+              FillStyle::Transparent
+            };
+            (ring, fill)
+          })
+          .collect(),
+      };
+      let parts: Vec<_> = parts.into_iter().filter(|(p, _)| !p.is_empty()).collect();
+      // The parts of a record stay one feature.
+      let part_of = (parts.len() > 1).then_some(layer.shapes.len());
+      for (part, fill) in parts {
+        layer.shapes.push(
+          Shape::new(part)
+            .with_color(self.color)
+            .with_fill(fill)
+            .with_label(label.clone())
+            .with_part_of(part_of),
+        );
+      }
+    }
+    layer
+  }
+}
+
+impl FileParser for ShapefileParser {
+  fn parse<'a>(
+    &'a mut self,
+    mut file: Box<dyn BufRead>,
+  ) -> Box<dyn Iterator<Item = MapEvent> + '_> {
+    let mut data = vec![];
+    if let Err(e) = file.read_to_end(&mut data) {
+      error!("Cannot read shapefile: {e}");
+      return Box::new(std::iter::empty());
+    }
+    match read_geometries(&data) {
+      Ok(geometries) => Box::new(std::iter::once(MapEvent::Layer(self.to_layer(geometries)))),
+      Err(e) => {
+        error!("Cannot parse shapefile: {e}");
+        Box::new(std::iter::empty())
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn record(number: i32, content: &[u8]) -> Vec<u8> {
+    let mut r = vec![];
+    r.extend(number.to_be_bytes());
+    r.extend(i32::try_from(content.len() / 2).unwrap().to_be_bytes());
+    r.extend(content);
+    r
+  }
+
+  #[test]
+  fn parse_point_and_line() {
+    let mut data = vec![0u8; HEADER_SIZE];
+    data[..4].copy_from_slice(&9994i32.to_be_bytes());
+
+    let mut point = vec![];
+    point.extend(1i32.to_le_bytes());
+    point.extend(13.4f64.to_le_bytes());
+    point.extend(52.5f64.to_le_bytes());
+    data.extend(record(1, &point));
+
+    let mut line = vec![];
+    line.extend(3i32.to_le_bytes());
+    line.extend([0u8; 32]);
+    line.extend(1i32.to_le_bytes());
+    line.extend(2i32.to_le_bytes());
+    line.extend(0i32.to_le_bytes());
+    for v in [6.9f64, 50.9, 13.4, 52.5] {
+      line.extend(v.to_le_bytes());
+    }
+    data.extend(record(2, &line));
+
+    let geometries = read_geometries(&data).unwrap();
+    assert_eq!(
+      geometries,
+      vec![
+        Geometry::Points(vec![Coordinate {
+          lat: 52.5,
+          lon: 13.4
+        }]),
+        Geometry::Lines(vec![vec![
+          Coordinate {
+            lat: 50.9,
+            lon: 6.9
+          },
+          Coordinate {
+            lat: 52.5,
+            lon: 13.4
+          }
+        ]]),
+      ]
+    );
+
+    let mut parser = ShapefileParser::new();
+    let events: Vec<_> = parser.parse(Box::new(std::io::Cursor::new(data))).collect();
+    assert_eq!(events.len(), 1);
+  }
+
+  fn polygon(rings: &[&[(f64, f64)]]) -> Vec<u8> {
+    let mut polygon = vec![];
+    polygon.extend(5i32.to_le_bytes());
+    polygon.extend([0u8; 32]);
+    polygon.extend(i32::try_from(rings.len()).unwrap().to_le_bytes());
+    let points = rings.concat();
+    polygon.extend(i32::try_from(points.len()).unwrap().to_le_bytes());
+    let mut start = 0;
+    for ring in rings {
+      polygon.extend(i32::try_from(start).unwrap().to_le_bytes());
+      start += ring.len();
+    }
+    for (lon, lat) in points {
+      polygon.extend(lon.to_le_bytes());
+      polygon.extend(lat.to_le_bytes());
+    }
+    polygon
+  }
+
+  #[test]
+  fn polygon_holes() {
+    let outer = [(0., 0.), (0., 10.), (10., 10.), (10., 0.), (0., 0.)];
+    let hole = [(2., 2.), (8., 2.), (8., 8.), (2., 8.), (2., 2.)];
+    let geometry = read_geometry(&polygon(&[&outer, &hole])).unwrap();
+    let layer = ShapefileParser::new().to_layer(vec![geometry]);
+    assert_eq!(layer.shapes.len(), 2);
+    assert_eq!(layer.shapes[0].style.fill, FillStyle::Transparent);
+    assert_eq!(layer.shapes[1].style.fill, FillStyle::NoFill);
+    assert_eq!(layer.shapes[1].part_of, Some(0));
+  }
+
+  #[test]
+  fn invalid_records() {
+    let outer = [(0., 0.), (0., 10.), (10., 10.), (0., 0.)];
+    let valid = polygon(&[&outer]);
+    assert!(read_geometry(&valid).is_ok());
+    let with = |offset: usize, value: i32| {
+      let mut data = valid.clone();
+      data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+      data
+    };
+    // Negative and too large counts of parts and points.
+    assert!(read_geometry(&with(36, -1)).is_err());
+    assert!(read_geometry(&with(36, i32::MAX)).is_err());
+    assert!(read_geometry(&with(40, -1)).is_err());
+    assert!(read_geometry(&with(40, i32::MAX)).is_err());
+    // A part starting after the last point.
+    assert!(read_geometry(&with(44, 9)).is_err());
+    assert!(read_geometry(&valid[..valid.len() - 1]).is_err());
+
+    let mut multipoint = vec![];
+    multipoint.extend(8i32.to_le_bytes());
+    multipoint.extend([0u8; 32]);
+    multipoint.extend(i32::MAX.to_le_bytes());
+    assert!(read_geometry(&multipoint).is_err());
+
+    let mut data = vec![0u8; HEADER_SIZE];
+    data[..4].copy_from_slice(&9994i32.to_be_bytes());
+    data.extend(1i32.to_be_bytes());
+    data.extend((-2i32).to_be_bytes());
+    assert!(read_geometries(&data).is_err());
+    let length = data.len() - 4;
+    data[length..].copy_from_slice(&i32::MAX.to_be_bytes());
+    assert!(read_geometries(&data).is_err());
+  }
+
+  #[test]
+  fn parse_dbf() {
+    let mut data = vec![0u8; 32];
+    data[4..8].copy_from_slice(&1i32.to_le_bytes());
+    data[8..10].copy_from_slice(&65u16.to_le_bytes());
+    data[10..12].copy_from_slice(&7u16.to_le_bytes());
+    let mut field = [0u8; 32];
+    field[..4].copy_from_slice(b"NAME");
+    field[11] = b'C';
+    field[16] = 6;
+    data.extend(field);
+    data.push(0x0D);
+    data.extend(b" Koln  ");
+
+    assert_eq!(
+      read_dbf(&data).unwrap(),
+      vec![vec![("NAME".to_string(), "Koln".to_string())]]
+    );
+  }
+}
@@ -0,0 +1,225 @@
+use anyhow::{anyhow, Result};
+
+use crate::map::{
+  coordinates::Coordinate,
+  map_event::{Color, FillStyle, Shape},
+};
+
+/// The drawable parts of a geometry. Multi geometries and collections are split into their parts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Part {
+  Point(Coordinate),
+  Line(Vec<Coordinate>),
+  /// The outer ring of a polygon.
+  Ring(Vec<Coordinate>),
+  /// An inner ring of a polygon, which is drawn as outline without fill.
+  Hole(Vec<Coordinate>),
+}
+
+impl Part {
+  #[must_use]
+  pub fn into_shape(self, color: Color, label: Option<String>) -> Shape {
+    let (coordinates, fill) = match self {
+      Part::Point(c) => (vec![c], FillStyle::Solid),
+      Part::Line(coordinates) => (coordinates, FillStyle::NoFill),
+      Part::Ring(coordinates) => (coordinates, FillStyle::Transparent),
+      Part::Hole(coordinates) => (coordinates, FillStyle::NoFill),
+    };
+    Shape::new(coordinates)
+      .with_color(color)
+      .with_fill(fill)
+      .with_label(label)
+  }
+}
+
+struct WkbReader<'a> {
+  data: &'a [u8],
+  offset: usize,
+}
+
+impl WkbReader<'_> {
+  fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+    let bytes = self
+      .data
+      .get(self.offset..self.offset + N)
+      .ok_or(anyhow!("Unexpected end of WKB at {}.", self.offset))?;
+    self.offset += N;
+    Ok(bytes.try_into().expect("slice has N bytes"))
+  }
+
+  fn u32(&mut self, big_endian: bool) -> Result<u32> {
+    let bytes = self.bytes::<4>()?;
+    Ok(if big_endian {
+      u32::from_be_bytes(bytes)
+    } else {
+      u32::from_le_bytes(bytes)
+    })
+  }
+
+  fn f64(&mut self, big_endian: bool) -> Result<f64> {
+    let bytes = self.bytes::<8>()?;
+    Ok(if big_endian {
+      f64::from_be_bytes(bytes)
+    } else {
+      f64::from_le_bytes(bytes)
+    })
+  }
+
+  /// Reads x and y and skips z and m values.
+  #[allow(clippy::cast_possible_truncation)]
+  fn coordinate(&mut self, big_endian: bool, dimensions: usize) -> Result<Option<Coordinate>> {
+    let lon = self.f64(big_endian)?;
+    let lat = self.f64(big_endian)?;
+    for _ in 2..dimensions {
+      self.f64(big_endian)?;
+    }
+    // Empty points are encoded as NaN.
+    let coordinate = Coordinate {
+      lat: lat as f32,
+      lon: lon as f32,
+    };
+    Ok((!lat.is_nan() && !lon.is_nan()).then_some(coordinate))
+  }
+
+  fn coordinates(&mut self, big_endian: bool, dimensions: usize) -> Result<Vec<Coordinate>> {
+    let count = self.u32(big_endian)?;
+    let mut coordinates = vec![];
+    for _ in 0..count {
+      coordinates.extend(self.coordinate(big_endian, dimensions)?);
+    }
+    Ok(coordinates)
+  }
+
+  fn geometry(&mut self, parts: &mut Vec<Part>) -> Result<()> {
+    let big_endian = match self.bytes::<1>()?[0] {
+      0 => true,
+      1 => false,
+      b => return Err(anyhow!("Invalid byte order {b}.")),
+    };
+    let mut geometry_type = self.u32(big_endian)?;
+    // Extended WKB as used by PostGIS has flags for z, m, and an srid.
+    let mut dimensions = 2;
+    for flag in [0x8000_0000, 0x4000_0000] {
+      if geometry_type & flag != 0 {
+        dimensions += 1;
+      }
+    }
+    if geometry_type & 0x2000_0000 != 0 {
+      self.u32(big_endian)?;
+    }
+    geometry_type &= 0x0FFF_FFFF;
+    // ISO WKB encodes z and m as 1000, 2000, 3000 added to the type.
+    dimensions += match geometry_type / 1000 {
+      1 | 2 => 1,
+      3 => 2,
+      _ => 0,
+    };
+    match geometry_type % 1000 {
+      1 => parts.extend(self.coordinate(big_endian, dimensions)?.map(Part::Point)),
+      2 => parts.push(Part::Line(self.coordinates(big_endian, dimensions)?)),
+      3 => {
+        for ring in 0..self.u32(big_endian)? {
+          let coordinates = self.coordinates(big_endian, dimensions)?;
+          parts.push(match ring {
+            0 => Part::Ring(coordinates),
+            _ => Part::Hole(coordinates),
+          });
+        }
+      }
+      4..=7 => {
+        for _ in 0..self.u32(big_endian)? {
+          self.geometry(parts)?;
+        }
+      }
+      t => return Err(anyhow!("Unsupported geometry type {t}.")),
+    }
+    Ok(())
+  }
+}
+
+/// Parses well-known binary in the ISO and the extended PostGIS flavor.
+///
+/// # Errors
+/// If the data is no valid WKB.
+pub fn parse_wkb(data: &[u8]) -> Result<Vec<Part>> {
+  let mut parts = vec![];
+  WkbReader { data, offset: 0 }.geometry(&mut parts)?;
+  Ok(parts)
+}
+
+/// Parses hex encoded WKB as returned by PostGIS for geometry columns.
+///
+/// # Errors
+/// If the string is not hex encoded WKB.
+pub fn parse_hex_wkb(hex: &str) -> Result<Vec<Part>> {
+  let hex = hex.trim();
+  let data = (0..hex.len())
+    .step_by(2)
+    .map(|i| {
+      hex
+        .get(i..i + 2)
+        .and_then(|b| u8::from_str_radix(b, 16).ok())
+        .ok_or(anyhow!("Invalid hex at {i}."))
+    })
+    .collect::<Result<Vec<_>>>()?;
+  parse_wkb(&data)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse() {
+    // POINT(13.4 52.5)
+    let point = parse_hex_wkb("0101000000CDCCCCCCCCCC2A400000000000404A40").unwrap();
+    assert_eq!(
+      point,
+      vec![Part::Point(Coordinate {
+        lat: 52.5,
+        lon: 13.4
+      })]
+    );
+
+    // SRID=4326;MULTILINESTRING((1 2,3 4),(5 6,7 8)) in big endian extended WKB.
+    let lines = parse_hex_wkb(concat!(
+      "0020000005000010E600000002",
+      "000000000200000002",
+      "3FF0000000000000400000000000000040080000000000004010000000000000",
+      "000000000200000002",
+      "40140000000000004018000000000000401C0000000000004020000000000000",
+    ))
+    .unwrap();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(
+      lines[1],
+      Part::Line(vec![
+        Coordinate { lat: 6., lon: 5. },
+        Coordinate { lat: 8., lon: 7. }
+      ])
+    );
+
+    // POLYGON((0 0,4 0,4 4,0 0),(1 1,2 1,2 2,1 1))
+    let polygon = parse_hex_wkb(concat!(
+      "00000000030000000200000004",
+      "0000000000000000000000000000000040100000000000000000000000000000",
+      "4010000000000000401000000000000000000000000000000000000000000000",
+      "00000004",
+      "3FF00000000000003FF000000000000040000000000000003FF0000000000000",
+      "400000000000000040000000000000003FF00000000000003FF0000000000000",
+    ))
+    .unwrap();
+    assert!(matches!(polygon[0], Part::Ring(_)));
+    assert!(matches!(polygon[1], Part::Hole(_)));
+    assert_eq!(
+      polygon[1]
+        .clone()
+        .into_shape(Color::default(), None)
+        .style
+        .fill,
+      FillStyle::NoFill
+    );
+
+    assert!(parse_wkb(&[1, 9, 0, 0, 0]).is_err());
+  }
+}
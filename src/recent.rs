@@ -0,0 +1,124 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+/// How many files are remembered.
+const MAX_RECENT: usize = 20;
+
+/// A file that was drawn with the mapcat options used for it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecentFile {
+  pub path: PathBuf,
+  /// Options like `--parser nmea` to parse and draw the file again the same way.
+  #[serde(default)]
+  pub options: Vec<String>,
+}
+
+impl RecentFile {
+  /// The value of a stored option like `--parser`.
+  #[must_use]
+  pub fn option(&self, name: &str) -> Option<&str> {
+    let i = self.options.iter().position(|option| option == name)?;
+    self.options.get(i + 1).map(String::as_str)
+  }
+
+  /// If a stored flag like `--geodesic` is set.
+  #[must_use]
+  pub fn flag(&self, name: &str) -> bool {
+    self.options.iter().any(|option| option == name)
+  }
+}
+
+/// The recently drawn files, newest first. They are stored in the file given by
+/// `MAPVAS_RECENT`, by default `recent.json` in the [config directory](config::dir).
+#[derive(Debug, Default)]
+pub struct RecentFiles {
+  path: Option<PathBuf>,
+  pub files: Vec<RecentFile>,
+}
+
+impl RecentFiles {
+  /// Reads the stored list. A missing or broken list is empty.
+  #[must_use]
+  pub fn load() -> Self {
+    let path = config::path("MAPVAS_RECENT", "recent.json");
+    let files = config::load(path.as_deref());
+    Self { path, files }
+  }
+
+  /// Moves the file to the front of the list.
+  pub fn add(&mut self, path: PathBuf, options: Vec<String>) {
+    self.files.retain(|f| f.path != path);
+    self.files.insert(0, RecentFile { path, options });
+    self.files.truncate(MAX_RECENT);
+  }
+
+  /// The n-th most recent file, starting at 1 as shown by [`RecentFiles::numbered`].
+  #[must_use]
+  pub fn get(&self, n: usize) -> Option<&RecentFile> {
+    n.checked_sub(1).and_then(|i| self.files.get(i))
+  }
+
+  /// The list with one numbered line per file.
+  #[must_use]
+  pub fn numbered(&self) -> Vec<String> {
+    self
+      .files
+      .iter()
+      .enumerate()
+      .map(|(i, f)| format!("{} {} {}", i + 1, f.path.display(), f.options.join(" ")))
+      .map(|line| line.trim_end().to_string())
+      .collect()
+  }
+
+  /// Writes the list back.
+  ///
+  /// # Errors
+  /// If the list cannot be written.
+  pub fn save(&self) -> Result<()> {
+    if let Some(path) = &self.path {
+      config::save(path, &self.files)?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn newest_first() {
+    let mut recent = RecentFiles::default();
+    recent.add("a.gpx".into(), vec![]);
+    recent.add("b.txt".into(), vec!["--parser".into(), "nmea".into()]);
+    recent.add("a.gpx".into(), vec!["--color".into(), "red".into()]);
+    assert_eq!(recent.files.len(), 2);
+    assert_eq!(recent.get(1).unwrap().path, PathBuf::from("a.gpx"));
+    assert!(recent.get(0).is_none());
+    assert_eq!(
+      recent.numbered(),
+      vec!["1 a.gpx --color red", "2 b.txt --parser nmea"]
+    );
+    for i in 0..30 {
+      recent.add(format!("{i}.txt").into(), vec![]);
+    }
+    assert_eq!(recent.files.len(), MAX_RECENT);
+  }
+
+  #[test]
+  fn options() {
+    let file = RecentFile {
+      path: "b.txt".into(),
+      options: vec!["--parser".into(), "nmea".into(), "--geodesic".into()],
+    };
+    assert_eq!(file.option("--parser"), Some("nmea"));
+    assert_eq!(file.option("--geodesic"), None);
+    assert_eq!(file.option("--color"), None);
+    assert!(file.flag("--geodesic"));
+    assert!(!file.flag("--invert-coordinates"));
+  }
+}
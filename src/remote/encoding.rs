@@ -0,0 +1,151 @@
+use std::{io::Write, str::FromStr};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use super::to_ndjson;
+use crate::map::map_event::MapEvent;
+
+/// How the events for `POST /batch` are encoded, told by the content type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+  /// Newline delimited json.
+  #[default]
+  Json,
+  /// Concatenated MessagePack values, much smaller and faster for many coordinates.
+  MessagePack,
+}
+
+impl FromStr for Encoding {
+  type Err = ();
+  fn from_str(input: &str) -> Result<Encoding, Self::Err> {
+    match input.to_lowercase().as_str() {
+      "json" | "ndjson" => Ok(Encoding::Json),
+      "msgpack" | "messagepack" => Ok(Encoding::MessagePack),
+      _ => Err(()),
+    }
+  }
+}
+
+impl Encoding {
+  #[must_use]
+  pub fn content_type(self) -> &'static str {
+    match self {
+      Encoding::Json => "application/x-ndjson",
+      Encoding::MessagePack => "application/msgpack",
+    }
+  }
+
+  /// The encoding of a content type, json for unknown ones.
+  #[must_use]
+  pub fn from_content_type(content_type: &str) -> Self {
+    match content_type.split(';').next().map(str::trim) {
+      Some("application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack") => {
+        Encoding::MessagePack
+      }
+      _ => Encoding::Json,
+    }
+  }
+
+  /// # Errors
+  /// If an event cannot be serialized.
+  pub fn encode(self, events: &[MapEvent]) -> Result<Vec<u8>> {
+    match self {
+      Encoding::Json => Ok(to_ndjson(events)?),
+      Encoding::MessagePack => {
+        let mut bytes = vec![];
+        for event in events {
+          // Structs are written as maps so skipped and default fields work.
+          rmp_serde::encode::write_named(&mut bytes, event)?;
+        }
+        Ok(bytes)
+      }
+    }
+  }
+}
+
+/// Reads events written by [`Encoding::encode`] with [`Encoding::MessagePack`].
+///
+/// # Errors
+/// If the bytes are no complete events.
+pub fn from_msgpack(bytes: &[u8]) -> Result<Vec<MapEvent>> {
+  let mut deserializer = rmp_serde::Deserializer::new(bytes);
+  let mut events = vec![];
+  while !deserializer.get_ref().is_empty() {
+    events.push(MapEvent::deserialize(&mut deserializer)?);
+  }
+  Ok(events)
+}
+
+/// The compression of requests to the map, told by the content encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+  Gzip,
+  Zstd,
+}
+
+impl FromStr for Compression {
+  type Err = ();
+  fn from_str(input: &str) -> Result<Compression, Self::Err> {
+    match input.to_lowercase().as_str() {
+      "gzip" => Ok(Compression::Gzip),
+      "zstd" => Ok(Compression::Zstd),
+      _ => Err(()),
+    }
+  }
+}
+
+impl Compression {
+  #[must_use]
+  pub fn content_encoding(self) -> &'static str {
+    match self {
+      Compression::Gzip => "gzip",
+      Compression::Zstd => "zstd",
+    }
+  }
+
+  /// # Errors
+  /// If the compression fails.
+  pub fn compress(self, bytes: &[u8]) -> Result<Vec<u8>> {
+    match self {
+      Compression::Gzip => {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+        encoder.write_all(bytes)?;
+        Ok(encoder.finish()?)
+      }
+      Compression::Zstd => Ok(zstd::encode_all(bytes, 3)?),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::map::coordinates::Coordinate;
+  use crate::map::map_event::{Layer, Shape, View};
+
+  #[test]
+  fn msgpack_round_trip() {
+    let center = Coordinate {
+      lat: 52.5,
+      lon: 13.4,
+    };
+    let mut layer = Layer::new("test".to_string());
+    layer
+      .shapes
+      .push(Shape::new(vec![center]).with_label(Some("Berlin".to_string())));
+    let events = vec![
+      MapEvent::Layer(layer),
+      MapEvent::SetView(View::Center { center, zoom: None }),
+      MapEvent::Focus,
+    ];
+    let bytes = Encoding::MessagePack.encode(&events).unwrap();
+    assert!(bytes.len() < Encoding::Json.encode(&events).unwrap().len());
+    assert_eq!(from_msgpack(&bytes).unwrap(), events);
+    assert!(from_msgpack(&bytes[..bytes.len() - 1]).is_err());
+    assert_eq!(
+      Encoding::from_content_type("application/msgpack; charset=binary"),
+      Encoding::MessagePack
+    );
+  }
+}
@@ -0,0 +1,229 @@
+use std::{net::SocketAddr, pin::Pin, str::FromStr};
+
+use axum::http::StatusCode;
+use futures::{Stream, StreamExt};
+use log::error;
+use tonic::{Request, Response, Status};
+
+use super::{render_png, Remote, RemoteConfig};
+use crate::map::{
+  coordinates::Coordinate,
+  map_event::{Color, FillStyle, Layer, MapEvent, MapUpdate, Shape, View},
+};
+
+/// The code generated from `proto/mapvas.proto`.
+#[allow(clippy::pedantic)]
+pub mod proto {
+  tonic::include_proto!("mapvas");
+}
+
+use proto::{
+  map_server::{Map, MapServer},
+  map_update::{LayerAdded, LayerCleared, Update},
+  AddGeometriesRequest, AddGeometriesResponse, ClearRequest, Empty, FocusRequest,
+  ScreenshotRequest, ScreenshotResponse, StreamEventsRequest,
+};
+
+impl From<proto::Coordinate> for Coordinate {
+  fn from(c: proto::Coordinate) -> Self {
+    Coordinate {
+      lat: c.lat,
+      lon: c.lon,
+    }
+  }
+}
+
+impl From<Coordinate> for proto::Coordinate {
+  fn from(c: Coordinate) -> Self {
+    proto::Coordinate {
+      lat: c.lat,
+      lon: c.lon,
+    }
+  }
+}
+
+impl From<MapUpdate> for proto::MapUpdate {
+  fn from(update: MapUpdate) -> Self {
+    let update = match update {
+      MapUpdate::LayerAdded { id, shapes } => Update::LayerAdded(LayerAdded {
+        id,
+        shapes: shapes as u64,
+      }),
+      MapUpdate::Cleared => Update::Cleared(Empty {}),
+      MapUpdate::LayerCleared { id } => Update::LayerCleared(LayerCleared { id }),
+      MapUpdate::Focus => Update::Focus(Empty {}),
+      MapUpdate::View { camera } => Update::View(proto::Camera {
+        center: Some(camera.center.into()),
+        zoom: camera.zoom,
+        rotation: camera.rotation,
+      }),
+      MapUpdate::Screenshot { path } => Update::Screenshot(path.display().to_string()),
+      MapUpdate::Exported { path } => Update::Exported(path.display().to_string()),
+    };
+    proto::MapUpdate {
+      update: Some(update),
+    }
+  }
+}
+
+fn to_shape(geometry: proto::Geometry) -> Result<Shape, Status> {
+  let mut shape = Shape::new(
+    geometry
+      .coordinates
+      .into_iter()
+      .map(Coordinate::from)
+      .collect(),
+  )
+  .with_label(geometry.label);
+  if !geometry.color.is_empty() {
+    let color = Color::from_str(&geometry.color)
+      .map_err(|()| Status::invalid_argument(format!("Unknown color: {}", geometry.color)))?;
+    shape = shape.with_color(color);
+  }
+  if !geometry.fill.is_empty() {
+    let fill = FillStyle::from_str(&geometry.fill)
+      .map_err(|()| Status::invalid_argument(format!("Unknown fill: {}", geometry.fill)))?;
+    shape = shape.with_fill(fill);
+  }
+  Ok(shape)
+}
+
+/// The gRPC service sending its requests to the map like the http endpoints.
+pub struct MapService {
+  remote: Remote,
+}
+
+impl MapService {
+  async fn send(&self, event: MapEvent) -> Result<(), Status> {
+    self
+      .remote
+      .sender
+      .send(event)
+      .await
+      .map_err(|_| Status::unavailable("The map is closed."))
+  }
+}
+
+#[tonic::async_trait]
+impl Map for MapService {
+  async fn add_geometries(
+    &self,
+    request: Request<AddGeometriesRequest>,
+  ) -> Result<Response<AddGeometriesResponse>, Status> {
+    let request = request.into_inner();
+    let mut layer = Layer::new(request.layer).with_replace(request.replace);
+    layer.shapes = request
+      .geometries
+      .into_iter()
+      .map(to_shape)
+      .collect::<Result<_, _>>()?;
+    let count = layer.shapes.len() as u64;
+    self.send(MapEvent::Layer(layer)).await?;
+    Ok(Response::new(AddGeometriesResponse { count }))
+  }
+
+  async fn clear(&self, request: Request<ClearRequest>) -> Result<Response<Empty>, Status> {
+    let event = match request.into_inner().layer {
+      Some(layer) => MapEvent::ClearLayer(layer),
+      None => MapEvent::Clear,
+    };
+    self.send(event).await?;
+    Ok(Response::new(Empty {}))
+  }
+
+  async fn focus(&self, request: Request<FocusRequest>) -> Result<Response<Empty>, Status> {
+    let event = match request.into_inner().bbox {
+      Some(proto::BoundingBox {
+        corner1: Some(a),
+        corner2: Some(b),
+      }) => MapEvent::SetView(View::BoundingBox {
+        bbox: [a.lat, a.lon, b.lat, b.lon],
+      }),
+      Some(_) => {
+        return Err(Status::invalid_argument(
+          "The bounding box needs two corners.",
+        ))
+      }
+      None => MapEvent::Focus,
+    };
+    self.send(event).await?;
+    Ok(Response::new(Empty {}))
+  }
+
+  async fn screenshot(
+    &self,
+    request: Request<ScreenshotRequest>,
+  ) -> Result<Response<ScreenshotResponse>, Status> {
+    let request = request.into_inner();
+    let png = render_png(&self.remote, None, request.width, request.height)
+      .await
+      .map_err(|code| match code {
+        StatusCode::SERVICE_UNAVAILABLE => Status::unavailable("The map is closed."),
+        StatusCode::GATEWAY_TIMEOUT => Status::deadline_exceeded("The map took too long."),
+        _ => Status::internal("Cannot encode the screenshot."),
+      })?;
+    Ok(Response::new(ScreenshotResponse { png }))
+  }
+
+  type StreamEventsStream = Pin<Box<dyn Stream<Item = Result<proto::MapUpdate, Status>> + Send>>;
+
+  async fn stream_events(
+    &self,
+    _request: Request<StreamEventsRequest>,
+  ) -> Result<Response<Self::StreamEventsStream>, Status> {
+    let stream = self.remote.update_stream().map(|update| Ok(update.into()));
+    Ok(Response::new(Box::pin(stream)))
+  }
+}
+
+/// Serves the gRPC api on its own address. With a token in the config the requests need it as
+/// `authorization: Bearer <token>` metadata.
+pub async fn serve(address: SocketAddr, remote: Remote, config: &RemoteConfig) {
+  let config = config.clone();
+  let service = MapServer::with_interceptor(MapService { remote }, move |request: Request<()>| {
+    let given = request
+      .metadata()
+      .get("authorization")
+      .and_then(|value| value.to_str().ok());
+    if config.authorizes(given) {
+      Ok(request)
+    } else {
+      Err(Status::unauthenticated("Missing or wrong token."))
+    }
+  });
+  if let Err(e) = tonic::transport::Server::builder()
+    .add_service(service)
+    .serve(address)
+    .await
+  {
+    error!("Cannot serve gRPC on {address}: {e}");
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn geometry() {
+    let geometry = proto::Geometry {
+      coordinates: vec![proto::Coordinate {
+        lat: 52.5,
+        lon: 13.4,
+      }],
+      color: "red".to_string(),
+      fill: String::new(),
+      label: Some("Berlin".to_string()),
+    };
+    let shape = to_shape(geometry.clone()).unwrap();
+    assert_eq!(shape.style.color, Color::Red);
+    assert_eq!(shape.label.as_deref(), Some("Berlin"));
+    assert_eq!(shape.coordinates.len(), 1);
+
+    let unknown = proto::Geometry {
+      fill: "dotted".to_string(),
+      ..geometry
+    };
+    assert!(to_shape(unknown).is_err());
+  }
+}
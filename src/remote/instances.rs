@@ -0,0 +1,76 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::Result;
+
+use crate::config;
+
+/// The addresses of the named mapvas instances. They are stored in the file given by
+/// `MAPVAS_INSTANCES`, by default `instances.json` in the [config directory](config::dir).
+/// Instances that ended are not removed in every case, so an address may be unreachable.
+#[derive(Debug, Default)]
+pub struct Instances {
+  pub addresses: BTreeMap<String, String>,
+}
+
+/// The file the instances are stored in.
+fn path() -> Option<PathBuf> {
+  config::path("MAPVAS_INSTANCES", "instances.json")
+}
+
+impl Instances {
+  /// Reads the stored instances. A missing or broken file has no instances.
+  #[must_use]
+  pub fn load() -> Self {
+    Self {
+      addresses: config::load(path().as_deref()),
+    }
+  }
+
+  /// Changes the stored instances while holding a lock, so instances starting at the same time
+  /// do not drop the registrations of each other.
+  ///
+  /// # Errors
+  /// If the file cannot be locked or written.
+  pub fn update(change: impl FnOnce(&mut Self)) -> Result<()> {
+    if let Some(path) = path() {
+      config::update(&path, |addresses| {
+        let mut instances = Self {
+          addresses: std::mem::take(addresses),
+        };
+        change(&mut instances);
+        *addresses = instances.addresses;
+      })?;
+    }
+    Ok(())
+  }
+
+  pub fn register(&mut self, name: &str, address: String) {
+    self.addresses.insert(name.to_string(), address);
+  }
+
+  pub fn unregister(&mut self, name: &str) {
+    self.addresses.remove(name);
+  }
+
+  #[must_use]
+  pub fn address(&self, name: &str) -> Option<&str> {
+    self.addresses.get(name).map(String::as_str)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn register() {
+    let mut instances = Instances::default();
+    instances.register("work", "127.0.0.1:12345".into());
+    instances.register("debug", "127.0.0.1:40000".into());
+    instances.register("work", "127.0.0.1:41000".into());
+    assert_eq!(instances.address("work"), Some("127.0.0.1:41000"));
+    instances.unregister("debug");
+    assert!(instances.address("debug").is_none());
+    assert_eq!(instances.addresses.len(), 1);
+  }
+}
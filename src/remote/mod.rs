@@ -1,10 +1,286 @@
-use axum::{extract::State, Json};
-use tokio::sync::mpsc::Sender;
+use std::{
+  convert::Infallible,
+  io::Cursor,
+  net::IpAddr,
+  path::{Path, PathBuf},
+  str::FromStr,
+  time::Duration,
+};
 
-use crate::map::map_event::MapEvent;
+use axum::{
+  body::Body,
+  extract::{FromRef, Path as UrlPath, Query, Request, State},
+  http::{header, HeaderMap, StatusCode},
+  middleware::Next,
+  response::{
+    sse::{Event, KeepAlive, Sse},
+    IntoResponse, Response,
+  },
+  Json,
+};
+use futures::{Stream, StreamExt};
+use image::{imageops::FilterType, ImageOutputFormat};
+use log::error;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use subtle::ConstantTimeEq;
+use tokio::sync::{
+  broadcast::{self, error::RecvError},
+  mpsc::Sender,
+};
+
+use self::encoding::{from_msgpack, Compression, Encoding};
+use crate::{
+  config,
+  map::{
+    coordinates::Coordinate,
+    map_event::{ExportFormat, MapEvent, MapUpdate, View},
+  },
+};
+
+pub mod encoding;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod instances;
+pub mod socket;
 
 pub const DEFAULT_PORT: u16 = 12345;
 
+/// The largest MessagePack batch and the longest line of a json batch in bytes by default.
+const DEFAULT_MAX_BATCH_SIZE: usize = 64 << 20;
+
+/// Where the remote api listens and the token it requires, by default `127.0.0.1:12345` without
+/// a token. `MAPVAS_ADDRESS` and `MAPVAS_TOKEN` change them for mapvas and mapcat. The token can
+/// also be kept in the file `MAPVAS_TOKEN_FILE`, by default `token` in the
+/// [config directory](config::dir), so it does not show up in the environment or process list.
+/// With a socket from `MAPVAS_SOCKET` mapcat sends its events there instead, see [`socket`].
+/// `MAPVAS_INSTANCE` names the instance, see [`instances`]. `MAPVAS_MAX_BATCH_MB` limits the
+/// batches the map accepts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteConfig {
+  /// A host and port like `0.0.0.0:12345` or `mapvas.local:12345`.
+  pub address: String,
+  pub token: Option<String>,
+  pub socket: Option<PathBuf>,
+  pub instance: Option<String>,
+  /// How mapcat encodes its events, set by `MAPVAS_ENCODING`.
+  pub encoding: Encoding,
+  /// How mapcat compresses its events, set by `MAPVAS_COMPRESSION`.
+  pub compression: Option<Compression>,
+  /// The largest MessagePack batch and the longest line of a json batch in bytes.
+  pub max_batch_size: usize,
+}
+
+impl Default for RemoteConfig {
+  fn default() -> Self {
+    Self {
+      address: format!("127.0.0.1:{DEFAULT_PORT}"),
+      token: None,
+      socket: None,
+      instance: None,
+      encoding: Encoding::default(),
+      compression: None,
+      max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+    }
+  }
+}
+
+impl RemoteConfig {
+  #[must_use]
+  pub fn from_env() -> Self {
+    Self::default()
+      .with_address(std::env::var("MAPVAS_ADDRESS").ok())
+      .with_token(token_file(
+        config::path("MAPVAS_TOKEN_FILE", "token").as_deref(),
+      ))
+      .with_token(std::env::var("MAPVAS_TOKEN").ok())
+      .with_socket(std::env::var_os("MAPVAS_SOCKET").map(PathBuf::from))
+      .with_instance(std::env::var("MAPVAS_INSTANCE").ok())
+      .with_encoding(std::env::var("MAPVAS_ENCODING").ok())
+      .with_compression(std::env::var("MAPVAS_COMPRESSION").ok())
+      .with_max_batch_size(std::env::var("MAPVAS_MAX_BATCH_MB").ok())
+  }
+
+  #[must_use]
+  pub fn with_address(mut self, address: Option<String>) -> Self {
+    if let Some(address) = address.filter(|a| !a.is_empty()) {
+      self.address = address;
+    }
+    self
+  }
+
+  #[must_use]
+  pub fn with_token(mut self, token: Option<String>) -> Self {
+    if let Some(token) = token.filter(|t| !t.is_empty()) {
+      self.token = Some(token);
+    }
+    self
+  }
+
+  #[must_use]
+  pub fn with_socket(mut self, socket: Option<PathBuf>) -> Self {
+    if let Some(socket) = socket.filter(|s| !s.as_os_str().is_empty()) {
+      self.socket = Some(socket::location(socket));
+    }
+    self
+  }
+
+  #[must_use]
+  pub fn with_instance(mut self, instance: Option<String>) -> Self {
+    if let Some(instance) = instance.filter(|i| !i.is_empty()) {
+      self.instance = Some(instance);
+    }
+    self
+  }
+
+  #[must_use]
+  pub fn with_encoding(mut self, encoding: Option<String>) -> Self {
+    if let Some(encoding) = encoding {
+      match Encoding::from_str(&encoding) {
+        Ok(encoding) => self.encoding = encoding,
+        Err(()) => error!("Unknown encoding: {encoding}."),
+      }
+    }
+    self
+  }
+
+  #[must_use]
+  pub fn with_compression(mut self, compression: Option<String>) -> Self {
+    if let Some(compression) = compression {
+      match Compression::from_str(&compression) {
+        Ok(compression) => self.compression = Some(compression),
+        Err(()) => error!("Unknown compression: {compression}."),
+      }
+    }
+    self
+  }
+
+  /// Sets the size limit of batches in megabytes.
+  #[must_use]
+  pub fn with_max_batch_size(mut self, megabytes: Option<String>) -> Self {
+    if let Some(megabytes) = megabytes {
+      match megabytes.trim().parse::<usize>() {
+        Ok(megabytes) if megabytes > 0 => self.max_batch_size = megabytes << 20,
+        _ => error!("Invalid batch size: {megabytes} MB."),
+      }
+    }
+    self
+  }
+
+  /// The config with the address under which the named instance is registered, `None` if it is
+  /// not registered.
+  #[must_use]
+  pub fn resolved(&self) -> Option<Self> {
+    let Some(name) = &self.instance else {
+      return Some(self.clone());
+    };
+    let address = instances::Instances::load().address(name)?.to_string();
+    Some(Self {
+      address,
+      ..self.clone()
+    })
+  }
+
+  /// The url of an endpoint like `/screenshot`.
+  #[must_use]
+  pub fn url(&self, path: &str) -> String {
+    format!("http://{}{path}", self.address)
+  }
+
+  /// The value of the `Authorization` header if a token is set.
+  #[must_use]
+  pub fn authorization(&self) -> Option<String> {
+    self.token.as_ref().map(|token| format!("Bearer {token}"))
+  }
+
+  /// If the value of the `Authorization` header carries the token, always without a token. It is
+  /// compared in constant time, so the response time tells nothing about the token.
+  #[must_use]
+  pub fn authorizes(&self, given: Option<&str>) -> bool {
+    self.authorization().map_or(true, |expected| {
+      given.is_some_and(|given| bool::from(given.as_bytes().ct_eq(expected.as_bytes())))
+    })
+  }
+
+  /// If the address is only reachable from this machine.
+  #[must_use]
+  pub fn is_local(&self) -> bool {
+    let host = self
+      .address
+      .rsplit_once(':')
+      .map_or(self.address.as_str(), |(host, _)| host)
+      .trim_start_matches('[')
+      .trim_end_matches(']');
+    host == "localhost" || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+  }
+}
+
+/// The token kept in the file, without surrounding whitespace.
+fn token_file(path: Option<&Path>) -> Option<String> {
+  let token = std::fs::read_to_string(path?).ok()?;
+  Some(token.trim().to_string())
+}
+
+/// Rejects requests without the configured bearer token.
+pub async fn authorize(
+  State(config): State<RemoteConfig>,
+  request: Request,
+  next: Next,
+) -> Response {
+  let given = request
+    .headers()
+    .get(header::AUTHORIZATION)
+    .and_then(|value| value.to_str().ok());
+  if config.authorizes(given) {
+    next.run(request).await
+  } else {
+    StatusCode::UNAUTHORIZED.into_response()
+  }
+}
+
+/// How long the screenshot endpoint waits for the map to render.
+const SCREENSHOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The state of the remote api: the events to the map and the changes of the map.
+#[derive(Clone)]
+pub struct Remote {
+  pub sender: Sender<MapEvent>,
+  pub updates: broadcast::Sender<MapUpdate>,
+  /// The largest MessagePack batch and the longest line of a json batch in bytes.
+  pub max_batch_size: usize,
+}
+
+impl FromRef<Remote> for Sender<MapEvent> {
+  fn from_ref(remote: &Remote) -> Self {
+    remote.sender.clone()
+  }
+}
+
+impl Remote {
+  /// The changes of the map from now on. Subscribers that are too slow miss updates.
+  pub fn update_stream(&self) -> impl Stream<Item = MapUpdate> {
+    futures::stream::unfold(self.updates.subscribe(), |mut receiver| async move {
+      loop {
+        match receiver.recv().await {
+          Ok(update) => return Some((update, receiver)),
+          Err(RecvError::Lagged(_)) => continue,
+          Err(RecvError::Closed) => return None,
+        }
+      }
+    })
+  }
+}
+
+/// Streams the changes of the map as server-sent events with the json of a [`MapUpdate`].
+pub async fn events(
+  State(remote): State<Remote>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+  let stream = remote
+    .update_stream()
+    .map(|update| Ok(Event::default().data(serde_json::to_string(&update).unwrap_or_default())));
+  Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
 pub async fn serve_axum(
   State(sender): State<Sender<MapEvent>>,
   Json(event): Json<MapEvent>,
@@ -12,3 +288,486 @@ pub async fn serve_axum(
   let _ = sender.send(event).await;
   42.to_string()
 }
+
+/// Newline delimited json of the events, the body of `POST /batch`.
+///
+/// # Errors
+/// If an event cannot be serialized.
+pub fn to_ndjson(events: &[MapEvent]) -> serde_json::Result<Vec<u8>> {
+  let mut lines = vec![];
+  for event in events {
+    serde_json::to_writer(&mut lines, event)?;
+    lines.push(b'\n');
+  }
+  Ok(lines)
+}
+
+/// Takes the complete lines from the start of the buffer and parses them as events, with `rest`
+/// also the incomplete last line. Empty lines are skipped.
+fn take_events(buffer: &mut Vec<u8>, rest: bool) -> serde_json::Result<Vec<MapEvent>> {
+  let end = if rest {
+    buffer.len()
+  } else {
+    buffer
+      .iter()
+      .rposition(|&b| b == b'\n')
+      .map_or(0, |i| i + 1)
+  };
+  buffer
+    .drain(..end)
+    .as_slice()
+    .split(|&b| b == b'\n')
+    .filter(|line| !line.trim_ascii().is_empty())
+    .map(serde_json::from_slice)
+    .collect()
+}
+
+/// Receives many events at once as newline delimited json, or as MessagePack with the content type
+/// `application/msgpack`. Answers with the number of events.
+pub async fn batch(
+  State(remote): State<Remote>,
+  headers: HeaderMap,
+  body: Body,
+) -> Result<String, (StatusCode, String)> {
+  let (sender, max) = (remote.sender, remote.max_batch_size);
+  let encoding = headers
+    .get(header::CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+    .map_or(Encoding::Json, Encoding::from_content_type);
+  match encoding {
+    Encoding::Json => json_batch(&sender, body, max).await,
+    Encoding::MessagePack => {
+      let bytes = axum::body::to_bytes(body, max).await.map_err(|e| {
+        error!("Cannot read batch: {e}");
+        (StatusCode::BAD_REQUEST, e.to_string())
+      })?;
+      let events = from_msgpack(&bytes).map_err(|e| {
+        error!("Cannot parse batch: {e}");
+        (StatusCode::BAD_REQUEST, e.to_string())
+      })?;
+      let count = events.len();
+      for event in events {
+        let _ = sender.send(event).await;
+      }
+      Ok(count.to_string())
+    }
+  }
+}
+
+/// The events are parsed and sent to the map while the body streams in, so the events before a
+/// broken line are drawn anyway.
+async fn json_batch(
+  sender: &Sender<MapEvent>,
+  body: Body,
+  max: usize,
+) -> Result<String, (StatusCode, String)> {
+  let mut stream = body.into_data_stream();
+  let mut buffer = vec![];
+  let mut count = 0;
+  loop {
+    let chunk = stream.next().await.transpose().map_err(|e| {
+      error!("Cannot read batch: {e}");
+      (StatusCode::BAD_REQUEST, e.to_string())
+    })?;
+    let rest = chunk.is_none();
+    if let Some(chunk) = chunk {
+      buffer.extend_from_slice(&chunk);
+    }
+    let events = take_events(&mut buffer, rest).map_err(|e| {
+      error!("Cannot parse batch after {count} events: {e}");
+      (StatusCode::BAD_REQUEST, format!("event {}: {e}", count + 1))
+    })?;
+    for event in events {
+      let _ = sender.send(event).await;
+      count += 1;
+    }
+    if buffer.len() > max {
+      return Err((
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!("event {}: longer than {max} bytes", count + 1),
+      ));
+    }
+    if rest {
+      return Ok(count.to_string());
+    }
+  }
+}
+
+/// The destination of the fly-to endpoint. The zoom level is kept if it is not given.
+#[derive(Debug, Deserialize)]
+pub struct FlyToParams {
+  lat: f32,
+  lon: f32,
+  zoom: Option<f32>,
+}
+
+/// Moves the camera to the position in an animation.
+pub async fn fly_to(
+  State(sender): State<Sender<MapEvent>>,
+  Query(params): Query<FlyToParams>,
+) -> StatusCode {
+  let center = Coordinate {
+    lat: params.lat,
+    lon: params.lon,
+  };
+  if !center.is_valid() {
+    return StatusCode::BAD_REQUEST;
+  }
+  set_view(
+    State(sender),
+    Json(View::Center {
+      center,
+      zoom: params.zoom,
+    }),
+  )
+  .await
+}
+
+/// Moves the camera to a `{"center": {"lat": 52.5, "lon": 13.4}, "zoom": 14}` or
+/// `{"bbox": [52.6, 13.3, 52.4, 13.5]}` payload.
+pub async fn set_view(
+  State(sender): State<Sender<MapEvent>>,
+  Json(view): Json<View>,
+) -> StatusCode {
+  match sender.send(MapEvent::SetView(view)).await {
+    Ok(()) => StatusCode::OK,
+    Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+  }
+}
+
+/// The largest width and height of a screenshot in pixels.
+const MAX_SCREENSHOT_SIZE: u32 = 8192;
+
+/// The optional area and output size of the screenshot endpoint. A `bbox` like
+/// `52.6,13.3,52.4,13.5` shows the area before the screenshot is taken. If only one size is given
+/// the aspect ratio is kept, both are at most [`MAX_SCREENSHOT_SIZE`].
+#[derive(Debug, Deserialize)]
+pub struct ScreenshotParams {
+  bbox: Option<String>,
+  width: Option<u32>,
+  height: Option<u32>,
+}
+
+fn temporary_path(extension: &str) -> PathBuf {
+  let nanos = std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_nanos();
+  std::env::temp_dir().join(format!("mapvas_{}_{nanos}.{extension}", std::process::id()))
+}
+
+/// Waits until the map has published that it wrote the screenshot or export to the path.
+async fn written(updates: &mut broadcast::Receiver<MapUpdate>, path: &Path) -> bool {
+  loop {
+    match updates.recv().await {
+      Ok(MapUpdate::Screenshot { path: written } | MapUpdate::Exported { path: written })
+        if written == path =>
+      {
+        return true
+      }
+      Ok(_) | Err(RecvError::Lagged(_)) => continue,
+      Err(RecvError::Closed) => return false,
+    }
+  }
+}
+
+/// Renders the current map or the view as png, scaled to the width and height if they are given.
+pub(crate) async fn render_png(
+  remote: &Remote,
+  view: Option<View>,
+  width: Option<u32>,
+  height: Option<u32>,
+) -> Result<Vec<u8>, StatusCode> {
+  let mut updates = remote.updates.subscribe();
+  let path = temporary_path("png");
+  let events = view
+    .map(MapEvent::SetView)
+    .into_iter()
+    .chain([MapEvent::Screenshot(path.clone())]);
+  for event in events {
+    if remote.sender.send(event).await.is_err() {
+      return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+  }
+  match tokio::time::timeout(SCREENSHOT_TIMEOUT, written(&mut updates, &path)).await {
+    Ok(true) => (),
+    Ok(false) => return Err(StatusCode::SERVICE_UNAVAILABLE),
+    Err(_) => {
+      error!("Screenshot {path:?} was not written in time.");
+      return Err(StatusCode::GATEWAY_TIMEOUT);
+    }
+  }
+  let img = image::open(&path);
+  let _ = std::fs::remove_file(&path);
+  let img = img.map_err(|e| {
+    error!("Cannot read screenshot {path:?}: {e}");
+    StatusCode::INTERNAL_SERVER_ERROR
+  })?;
+
+  let (width, height) = (
+    width.map(|w| w.clamp(1, MAX_SCREENSHOT_SIZE)),
+    height.map(|h| h.clamp(1, MAX_SCREENSHOT_SIZE)),
+  );
+  let img = match (width, height) {
+    (Some(width), Some(height)) => img.resize_exact(width, height, FilterType::Triangle),
+    (Some(width), None) => img.resize(width, MAX_SCREENSHOT_SIZE, FilterType::Triangle),
+    (None, Some(height)) => img.resize(MAX_SCREENSHOT_SIZE, height, FilterType::Triangle),
+    (None, None) => img,
+  };
+
+  let mut png = Cursor::new(vec![]);
+  img
+    .write_to(&mut png, ImageOutputFormat::Png)
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+  Ok(png.into_inner())
+}
+
+/// Renders the current map, or the area of `bbox`, and returns it as png.
+pub async fn screenshot(
+  State(remote): State<Remote>,
+  Query(params): Query<ScreenshotParams>,
+) -> impl IntoResponse {
+  let view = match params.bbox.as_deref().map(str::parse) {
+    None => None,
+    Some(Ok(view @ View::BoundingBox { .. })) => Some(view),
+    Some(_) => return Err(StatusCode::BAD_REQUEST),
+  };
+  let png = render_png(&remote, view, params.width, params.height).await?;
+  Ok::<_, StatusCode>(([(header::CONTENT_TYPE, "image/png")], png))
+}
+
+/// Waits until the map has exported its layers and reads the features.
+async fn current_features(remote: &Remote) -> Result<Vec<Value>, StatusCode> {
+  let mut updates = remote.updates.subscribe();
+  let path = temporary_path("geojson");
+  let event = MapEvent::Export {
+    format: ExportFormat::GeoJson,
+    path: path.clone(),
+  };
+  if remote.sender.send(event).await.is_err() {
+    return Err(StatusCode::SERVICE_UNAVAILABLE);
+  }
+  let exported = tokio::time::timeout(SCREENSHOT_TIMEOUT, written(&mut updates, &path)).await;
+  let json = match exported {
+    Ok(true) => tokio::fs::read_to_string(&path).await.ok(),
+    Ok(false) | Err(_) => None,
+  };
+  let _ = tokio::fs::remove_file(&path).await;
+  match exported {
+    Ok(true) => (),
+    Ok(false) => return Err(StatusCode::SERVICE_UNAVAILABLE),
+    Err(_) => {
+      error!("Export {path:?} was not written in time.");
+      return Err(StatusCode::GATEWAY_TIMEOUT);
+    }
+  }
+  let features = json
+    .and_then(|json| serde_json::from_str::<Value>(&json).ok())
+    .and_then(|mut collection| collection.get_mut("features").map(Value::take));
+  match features {
+    Some(Value::Array(features)) => Ok(features),
+    _ => {
+      error!("Cannot read export {path:?}.");
+      Err(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+  }
+}
+
+fn layer_of(feature: &Value) -> Option<&str> {
+  feature["properties"]["layer"].as_str()
+}
+
+/// Lists the ids of the layers with their number of features.
+pub async fn layers(State(remote): State<Remote>) -> Result<Json<Value>, StatusCode> {
+  let features = current_features(&remote).await?;
+  let mut counts = std::collections::BTreeMap::<&str, usize>::new();
+  for id in features.iter().filter_map(layer_of) {
+    *counts.entry(id).or_default() += 1;
+  }
+  let layers: Vec<Value> = counts
+    .into_iter()
+    .map(|(id, count)| json!({"id": id, "features": count}))
+    .collect();
+  Ok(Json(json!(layers)))
+}
+
+/// Removes a layer and keeps the others.
+pub async fn delete_layer(
+  State(sender): State<Sender<MapEvent>>,
+  UrlPath(id): UrlPath<String>,
+) -> StatusCode {
+  match sender.send(MapEvent::ClearLayer(id)).await {
+    Ok(()) => StatusCode::OK,
+    Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+  }
+}
+
+/// Paging and filtering of the geometries endpoint.
+#[derive(Debug, Deserialize)]
+pub struct GeometryParams {
+  #[serde(default)]
+  offset: usize,
+  limit: Option<usize>,
+  /// Only features with a position inside `lat1,lon1,lat2,lon2`.
+  bbox: Option<String>,
+}
+
+/// Default number of features per page.
+const PAGE_SIZE: usize = 1000;
+
+fn parse_bbox(bbox: &str) -> Option<[f64; 4]> {
+  let values: Vec<f64> = bbox
+    .split(',')
+    .map(|v| v.trim().parse().ok())
+    .collect::<Option<_>>()?;
+  let [lat1, lon1, lat2, lon2] = values[..] else {
+    return None;
+  };
+  Some([
+    lat1.min(lat2),
+    lon1.min(lon2),
+    lat1.max(lat2),
+    lon1.max(lon2),
+  ])
+}
+
+/// Whether one of the `[lon, lat]` positions of a GeoJSON geometry is in the bounding box.
+fn intersects(coordinates: &Value, [south, west, north, east]: [f64; 4]) -> bool {
+  match coordinates.as_array().map(Vec::as_slice) {
+    Some([lon, lat, ..]) if lon.is_number() => {
+      let (lon, lat) = (
+        lon.as_f64().unwrap_or_default(),
+        lat.as_f64().unwrap_or_default(),
+      );
+      (south..=north).contains(&lat) && (west..=east).contains(&lon)
+    }
+    Some(children) => children
+      .iter()
+      .any(|c| intersects(c, [south, west, north, east])),
+    None => false,
+  }
+}
+
+/// Returns the features of a layer as GeoJSON `FeatureCollection`. `total` is the number of
+/// matching features on all pages.
+pub async fn layer_geometries(
+  State(remote): State<Remote>,
+  UrlPath(id): UrlPath<String>,
+  Query(params): Query<GeometryParams>,
+) -> Result<Json<Value>, StatusCode> {
+  let bbox = match params.bbox.as_deref().map(parse_bbox) {
+    Some(None) => return Err(StatusCode::BAD_REQUEST),
+    Some(bbox) => bbox,
+    None => None,
+  };
+  let features = current_features(&remote).await?;
+  let in_layer: Vec<Value> = features
+    .into_iter()
+    .filter(|f| layer_of(f) == Some(id.as_str()))
+    .collect();
+  if in_layer.is_empty() {
+    return Err(StatusCode::NOT_FOUND);
+  }
+  let matching: Vec<Value> = in_layer
+    .into_iter()
+    .filter(|f| bbox.map_or(true, |bbox| intersects(&f["geometry"]["coordinates"], bbox)))
+    .collect();
+  let total = matching.len();
+  let page: Vec<Value> = matching
+    .into_iter()
+    .skip(params.offset)
+    .take(params.limit.unwrap_or(PAGE_SIZE))
+    .collect();
+  Ok(Json(
+    json!({"type": "FeatureCollection", "features": page, "total": total}),
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bbox_filter() {
+    let bbox = parse_bbox("53,13,52,14").unwrap();
+    assert_eq!(bbox, [52., 13., 53., 14.]);
+    assert!(intersects(&json!([13.4, 52.5]), bbox));
+    assert!(!intersects(&json!([10., 52.5]), bbox));
+    assert!(intersects(&json!([[10., 50.], [13.5, 52.1]]), bbox));
+    assert!(intersects(
+      &json!([[[10., 50.], [13.5, 52.1], [10., 50.]]]),
+      bbox
+    ));
+    assert!(parse_bbox("52,13").is_none());
+  }
+
+  #[test]
+  fn ndjson() {
+    let mut buffer = to_ndjson(&[MapEvent::Focus, MapEvent::Clear]).unwrap();
+    buffer.extend_from_slice(b"\n{\"ClearLayer\":");
+    let events = take_events(&mut buffer, false).unwrap();
+    assert_eq!(events, vec![MapEvent::Focus, MapEvent::Clear]);
+    assert_eq!(buffer, b"{\"ClearLayer\":");
+
+    buffer.extend_from_slice(b"\"test\"}");
+    let events = take_events(&mut buffer, true).unwrap();
+    assert_eq!(events, vec![MapEvent::ClearLayer("test".to_string())]);
+    assert!(buffer.is_empty());
+
+    assert!(take_events(&mut b"{}\n".to_vec(), false).is_err());
+  }
+
+  #[tokio::test]
+  async fn oversized_line() {
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(8);
+    let mut body = to_ndjson(&[MapEvent::Focus]).unwrap();
+    body.extend_from_slice(&[b' '; 100]);
+    let result = json_batch(&sender, Body::from(body), 64).await;
+    assert_eq!(result.unwrap_err().0, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(receiver.try_recv().ok(), Some(MapEvent::Focus));
+
+    let body = to_ndjson(&[MapEvent::Focus, MapEvent::Clear]).unwrap();
+    assert_eq!(
+      json_batch(&sender, Body::from(body), 64).await.unwrap(),
+      "2"
+    );
+  }
+
+  #[test]
+  fn remote_config() {
+    let config = RemoteConfig::default();
+    assert!(config.is_local());
+    assert_eq!(config.url("/layers"), "http://127.0.0.1:12345/layers");
+    assert!(config.authorization().is_none());
+
+    let config = config
+      .with_address(Some("0.0.0.0:8080".into()))
+      .with_token(Some("secret".into()));
+    assert!(!config.is_local());
+    assert_eq!(config.authorization().as_deref(), Some("Bearer secret"));
+    assert!(config.authorizes(Some("Bearer secret")));
+    assert!(!config.authorizes(Some("Bearer secreT")));
+    assert!(!config.authorizes(Some("Bearer secret2")));
+    assert!(!config.authorizes(None));
+    assert!(RemoteConfig::default().authorizes(None));
+
+    let path = std::env::temp_dir().join(format!("mapvas_token_{}", std::process::id()));
+    std::fs::write(&path, "secret\n").unwrap();
+    assert_eq!(token_file(Some(&path)).as_deref(), Some("secret"));
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(token_file(Some(&path)), None);
+    assert!(config
+      .clone()
+      .with_address(Some("[::1]:8080".into()))
+      .is_local());
+    assert_eq!(config.max_batch_size, 64 << 20);
+    let config = config.with_max_batch_size(Some("8".into()));
+    assert_eq!(config.max_batch_size, 8 << 20);
+    assert_eq!(
+      config
+        .with_max_batch_size(Some("none".into()))
+        .max_batch_size,
+      8 << 20
+    );
+  }
+}
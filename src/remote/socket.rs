@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use log::error;
+use tokio::{
+  io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+  sync::mpsc::Sender,
+};
+
+use super::to_ndjson;
+use crate::map::map_event::MapEvent;
+
+/// Where the socket lies. A relative path of a Unix socket lies in `$XDG_RUNTIME_DIR`, which only
+/// the user can access.
+#[must_use]
+pub fn location(path: PathBuf) -> PathBuf {
+  #[cfg(unix)]
+  if path.is_relative() {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+      return PathBuf::from(dir).join(path);
+    }
+  }
+  path
+}
+
+/// Listens on a Unix domain socket, or a named pipe like `\\.\pipe\mapvas` on Windows, for
+/// events sent by [`send`]. Every line is the json of one [`MapEvent`]. Only the user running the
+/// map can connect to the Unix socket.
+///
+/// # Errors
+/// If the socket cannot be created or another map already listens on it.
+#[cfg(unix)]
+pub async fn serve(path: &Path, sender: Sender<MapEvent>) -> Result<()> {
+  use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+  use tokio::net::UnixListener;
+
+  if is_reachable(path).await {
+    return Err(anyhow!("Another map listens on {}.", path.display()));
+  }
+  // A socket left behind by a map that did not shut down properly.
+  if std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+    std::fs::remove_file(path)?;
+  }
+  let listener = UnixListener::bind(path)?;
+  std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+  loop {
+    let (stream, _) = listener.accept().await?;
+    tokio::spawn(read_events(stream, sender.clone()));
+  }
+}
+
+/// Listens on a Unix domain socket, or a named pipe like `\\.\pipe\mapvas` on Windows, for
+/// events sent by [`send`]. Every line is the json of one [`MapEvent`].
+///
+/// # Errors
+/// If the pipe cannot be created or another map already listens on it.
+#[cfg(windows)]
+pub async fn serve(path: &Path, sender: Sender<MapEvent>) -> Result<()> {
+  use tokio::net::windows::named_pipe::ServerOptions;
+
+  let mut server = ServerOptions::new()
+    .first_pipe_instance(true)
+    .create(path)
+    .map_err(|e| anyhow!("Cannot listen on {}: {e}", path.display()))?;
+  loop {
+    server.connect().await?;
+    let client = std::mem::replace(&mut server, ServerOptions::new().create(path)?);
+    tokio::spawn(read_events(client, sender.clone()));
+  }
+}
+
+async fn read_events(stream: impl AsyncRead + Unpin, sender: Sender<MapEvent>) {
+  let mut lines = BufReader::new(stream).lines();
+  while let Ok(Some(line)) = lines.next_line().await {
+    match serde_json::from_str(&line) {
+      Ok(event) => {
+        let _ = sender.send(event).await;
+      }
+      Err(e) => error!("Cannot read event from socket: {e}"),
+    }
+  }
+}
+
+#[cfg(unix)]
+async fn connect(path: &Path) -> std::io::Result<impl AsyncWrite + Unpin> {
+  tokio::net::UnixStream::connect(path).await
+}
+
+#[cfg(windows)]
+async fn connect(path: &Path) -> std::io::Result<impl AsyncWrite + Unpin> {
+  tokio::net::windows::named_pipe::ClientOptions::new().open(path)
+}
+
+/// If a map listens on the socket.
+pub async fn is_reachable(path: &Path) -> bool {
+  connect(path).await.is_ok()
+}
+
+/// Sends the events to the map listening on the socket.
+///
+/// # Errors
+/// If no map listens on the socket.
+pub async fn send(path: &Path, events: &[MapEvent]) -> Result<()> {
+  let mut stream = connect(path)
+    .await
+    .map_err(|e| anyhow!("Cannot connect to {}: {e}", path.display()))?;
+  stream.write_all(&to_ndjson(events)?).await?;
+  stream.shutdown().await?;
+  Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use std::os::unix::fs::PermissionsExt;
+
+  use super::*;
+  use crate::remote::temporary_path;
+
+  #[tokio::test]
+  async fn send_over_socket() {
+    let path = temporary_path("sock");
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(10);
+    let server = tokio::spawn({
+      let path = path.clone();
+      async move { serve(&path, sender).await }
+    });
+    while !is_reachable(&path).await {
+      tokio::task::yield_now().await;
+    }
+    let permissions = std::fs::metadata(&path).unwrap().permissions();
+    assert_eq!(permissions.mode() & 0o777, 0o600);
+    send(&path, &[MapEvent::Focus, MapEvent::Clear])
+      .await
+      .unwrap();
+    assert_eq!(receiver.recv().await, Some(MapEvent::Focus));
+    assert_eq!(receiver.recv().await, Some(MapEvent::Clear));
+    assert!(serve(&path, tokio::sync::mpsc::channel(1).0).await.is_err());
+    server.abort();
+    let _ = std::fs::remove_file(path);
+  }
+
+  #[test]
+  fn locations() {
+    let absolute = PathBuf::from("/tmp/mapvas.sock");
+    assert_eq!(location(absolute.clone()), absolute);
+    let relative = location(PathBuf::from("mapvas.sock"));
+    assert!(relative.ends_with("mapvas.sock"));
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+      assert_eq!(relative, PathBuf::from(dir).join("mapvas.sock"));
+    }
+  }
+}